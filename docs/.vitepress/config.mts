@@ -76,6 +76,7 @@ export default defineConfig({
             items: [
               { text: "libSQL (SQLite)", link: "/docs/database/libsql" },
               { text: "Postgres", link: "/docs/database/postgres" },
+              { text: "Backups", link: "/docs/database/backups" },
             ],
           },
           {
@@ -226,7 +226,7 @@ async fn create_team(libsql: Arc<LibSQL>, db: Connection, location_url: &str) ->
 
     let team = db.get_team_from_id(team_id).await?;
     let now = Utc::now();
-    db.set_team_division(team_id, &team.division_id, division_id, now)
+    db.set_team_division(team_id, &team.division_id, division_id, now, None)
         .await?;
     set_user_to_bot(libsql.clone(), user_id).await?;
 
@@ -1,9 +1,22 @@
-use anyhow::{anyhow, Result};
+use std::path::PathBuf;
+
+use anyhow::{anyhow, Context, Result};
 use clap::Subcommand;
 use rand::{
     distributions::{Alphanumeric, DistString},
     thread_rng,
 };
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio_stream::wrappers::ReceiverStream;
+
+use crate::{
+    get_client,
+    grpc::proto::{
+        upload_provider_selection::Provider, BackupRequest, CollectUploadGarbageRequest,
+        ListDivisionApprovalsRequest, MigrateUploadsRequest, RestoreRequest,
+        SetDivisionApprovalRequest, UploadProviderSelection,
+    },
+};
 
 #[derive(Subcommand, Debug)]
 pub enum AdminCommand {
@@ -11,6 +24,18 @@ pub enum AdminCommand {
     Apply(ApplyCommand),
     /// Generate an random API key with an embedded URL to use as the root_api_key in a Rhombus config.
     GenerateApiKey(GenerateApiKeyCommand),
+    /// Export the entire Rhombus instance, including uploaded files, to an archive. Only supported with the libSQL database.
+    Backup(BackupCommand),
+    /// Restore an archive created with `backup` into a fresh Rhombus instance. Requires the root API key. Only supported with the libSQL database.
+    Restore(RestoreCommand),
+    /// Copy all uploaded files from one upload provider to another and rewrite attachment links. Requires the root API key.
+    MigrateUploads(MigrateUploadsCommand),
+    /// Delete uploaded files which are no longer referenced by any challenge. Requires the root API key.
+    GcUploads(GcUploadsCommand),
+    /// List users waiting for approval to join divisions which require manual approval.
+    DivisionApprovals(DivisionApprovalsCommand),
+    /// Approve or deny a user for a division which requires manual approval.
+    ApproveDivision(ApproveDivisionCommand),
 }
 
 impl AdminCommand {
@@ -20,6 +45,18 @@ impl AdminCommand {
             AdminCommand::GenerateApiKey(generate_api_key_command) => {
                 generate_api_key_command.run().await
             }
+            AdminCommand::Backup(backup_command) => backup_command.run().await,
+            AdminCommand::Restore(restore_command) => restore_command.run().await,
+            AdminCommand::MigrateUploads(migrate_uploads_command) => {
+                migrate_uploads_command.run().await
+            }
+            AdminCommand::GcUploads(gc_uploads_command) => gc_uploads_command.run().await,
+            AdminCommand::DivisionApprovals(division_approvals_command) => {
+                division_approvals_command.run().await
+            }
+            AdminCommand::ApproveDivision(approve_division_command) => {
+                approve_division_command.run().await
+            }
         }
     }
 }
@@ -56,3 +93,249 @@ impl ApplyCommand {
         Err(anyhow!("todo"))
     }
 }
+
+/// Size of the chunks an archive is streamed to the server in
+const RESTORE_CHUNK_SIZE: usize = 1024 * 1024;
+
+#[derive(clap::Parser, Debug)]
+pub struct BackupCommand {
+    /// File to write the archive to
+    #[arg(short, long, default_value = "rhombus-backup.tar")]
+    output: PathBuf,
+}
+
+impl BackupCommand {
+    pub async fn run(&self) -> Result<()> {
+        let mut client = get_client().await?;
+
+        let mut stream = client.backup(BackupRequest {}).await?.into_inner();
+
+        let mut file = tokio::fs::File::create(&self.output)
+            .await
+            .with_context(|| format!("failed to create {}", self.output.display()))?;
+        let mut written = 0;
+        while let Some(reply) = stream.message().await? {
+            file.write_all(&reply.chunk)
+                .await
+                .with_context(|| format!("failed to write {}", self.output.display()))?;
+            written += reply.chunk.len();
+        }
+        file.flush()
+            .await
+            .with_context(|| format!("failed to write {}", self.output.display()))?;
+
+        println!(
+            "✓ Wrote backup ({} bytes) to {}",
+            written,
+            self.output.display()
+        );
+
+        Ok(())
+    }
+}
+
+#[derive(clap::Parser, Debug)]
+pub struct RestoreCommand {
+    /// Archive created with `rhombus-cli admin backup`
+    input: PathBuf,
+}
+
+impl RestoreCommand {
+    pub async fn run(&self) -> Result<()> {
+        let mut file = tokio::fs::File::open(&self.input)
+            .await
+            .with_context(|| format!("failed to read {}", self.input.display()))?;
+
+        // read the archive in a separate task so a read error can be reported instead of
+        // the server complaining about a truncated archive
+        let (tx, rx) = tokio::sync::mpsc::channel(4);
+        let reader = tokio::spawn(async move {
+            loop {
+                let mut chunk = vec![0; RESTORE_CHUNK_SIZE];
+                let n = file.read(&mut chunk).await?;
+                if n == 0 {
+                    break;
+                }
+                chunk.truncate(n);
+                if tx.send(RestoreRequest { chunk }).await.is_err() {
+                    break;
+                }
+            }
+            Ok::<_, std::io::Error>(())
+        });
+
+        let mut client = get_client().await?;
+        let result = client.restore(ReceiverStream::new(rx)).await;
+
+        reader
+            .await?
+            .with_context(|| format!("failed to read {}", self.input.display()))?;
+        result?;
+        println!("✓ Restored backup from {}", self.input.display());
+
+        Ok(())
+    }
+}
+
+#[derive(clap::ValueEnum, Clone, Debug)]
+pub enum UploadProviderKind {
+    Local,
+    Database,
+    S3,
+}
+
+fn upload_provider_selection(
+    kind: &UploadProviderKind,
+    folder: &Option<String>,
+) -> Result<UploadProviderSelection> {
+    let provider = match kind {
+        UploadProviderKind::Local => Provider::Local(
+            folder
+                .clone()
+                .ok_or_else(|| anyhow!("a folder is required for the local upload provider"))?,
+        ),
+        UploadProviderKind::Database => Provider::Database(()),
+        UploadProviderKind::S3 => Provider::S3(()),
+    };
+    Ok(UploadProviderSelection {
+        provider: Some(provider),
+    })
+}
+
+#[derive(clap::Parser, Debug)]
+pub struct MigrateUploadsCommand {
+    /// Upload provider the files are currently stored in
+    #[arg(long)]
+    from: UploadProviderKind,
+    /// Folder on the server, when migrating from a local upload provider
+    #[arg(long)]
+    from_folder: Option<String>,
+    /// Upload provider to copy the files to. Defaults to the provider the server is currently using
+    #[arg(long)]
+    to: Option<UploadProviderKind>,
+    /// Folder on the server, when migrating to a local upload provider
+    #[arg(long)]
+    to_folder: Option<String>,
+}
+
+impl MigrateUploadsCommand {
+    pub async fn run(&self) -> Result<()> {
+        let from = upload_provider_selection(&self.from, &self.from_folder)?;
+        let to = self
+            .to
+            .as_ref()
+            .map(|to| upload_provider_selection(to, &self.to_folder))
+            .transpose()?;
+
+        let mut client = get_client().await?;
+        let migrated = client
+            .migrate_uploads(MigrateUploadsRequest {
+                from: Some(from),
+                to,
+            })
+            .await?
+            .into_inner()
+            .migrated;
+        println!("✓ Migrated {} uploaded files", migrated);
+
+        Ok(())
+    }
+}
+
+#[derive(clap::Parser, Debug)]
+pub struct GcUploadsCommand {
+    /// Only list the files which would be deleted
+    #[arg(long)]
+    dry_run: bool,
+}
+
+impl GcUploadsCommand {
+    pub async fn run(&self) -> Result<()> {
+        let mut client = get_client().await?;
+        let orphaned = client
+            .collect_upload_garbage(CollectUploadGarbageRequest {
+                dry_run: self.dry_run,
+            })
+            .await?
+            .into_inner()
+            .orphaned;
+
+        for url in &orphaned {
+            println!("{}", url);
+        }
+        if self.dry_run {
+            println!("✓ Found {} orphaned files", orphaned.len());
+        } else {
+            println!("✓ Deleted {} orphaned files", orphaned.len());
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(clap::Parser, Debug)]
+pub struct DivisionApprovalsCommand {
+    /// Also list users who have already been approved or denied
+    #[arg(long)]
+    all: bool,
+}
+
+impl DivisionApprovalsCommand {
+    pub async fn run(&self) -> Result<()> {
+        let mut client = get_client().await?;
+        let approvals = client
+            .list_division_approvals(ListDivisionApprovalsRequest {
+                include_decided: self.all,
+            })
+            .await?
+            .into_inner()
+            .approvals;
+
+        for approval in &approvals {
+            let status = match approval.approved {
+                None => "pending",
+                Some(true) => "approved",
+                Some(false) => "denied",
+            };
+            println!(
+                "{}\t{} ({})\t{}",
+                approval.division_id, approval.user_name, approval.user_id, status
+            );
+        }
+        println!("✓ Found {} division approvals", approvals.len());
+
+        Ok(())
+    }
+}
+
+#[derive(clap::Parser, Debug)]
+pub struct ApproveDivisionCommand {
+    /// Id of the division
+    division_id: String,
+    /// Id of the user
+    user_id: i64,
+    /// Deny the user instead of approving them
+    #[arg(long)]
+    deny: bool,
+}
+
+impl ApproveDivisionCommand {
+    pub async fn run(&self) -> Result<()> {
+        let mut client = get_client().await?;
+        client
+            .set_division_approval(SetDivisionApprovalRequest {
+                division_id: self.division_id.clone(),
+                user_id: self.user_id,
+                approved: !self.deny,
+            })
+            .await?;
+
+        if self.deny {
+            println!("✓ Denied user {} for {}", self.user_id, self.division_id);
+        } else {
+            println!("✓ Approved user {} for {}", self.user_id, self.division_id);
+        }
+
+        Ok(())
+    }
+}
@@ -93,7 +93,6 @@ impl Interceptor for AuthInterceptor {
 
 type Client = RhombusClient<InterceptedService<Channel, AuthInterceptor>>;
 
-#[allow(dead_code)]
 /// Load the rhombus-cli.yaml config file and connect to the grpc server to which it refers
 async fn get_client() -> Result<Client> {
     let secret_config = read_secret_config()?;
@@ -114,6 +113,8 @@ async fn connect(url: &str, key: &str) -> Result<Client> {
         .connect()
         .await
         .with_context(|| format!("failed to connect to grpc server '{}'", url))?;
-    let client = RhombusClient::with_interceptor(channel, AuthInterceptor { auth_token });
+    let client = RhombusClient::with_interceptor(channel, AuthInterceptor { auth_token })
+        .max_decoding_message_size(usize::MAX)
+        .max_encoding_message_size(usize::MAX);
     Ok(client)
 }
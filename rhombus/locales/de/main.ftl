@@ -2,6 +2,7 @@ sign-in = Anmelden
 scoreboard = Anzeigetafel
 team = Team
 account = Konto
+tickets = Tickets
 challenges = Challenges
 
 account-description = Verwalten Sie Ihre individuellen Kontoeinstellungen. Sehen Sie sich
@@ -17,16 +18,111 @@ account-discord-integration-join-server-description =
     Tritt dem offiziellen Discord-Server bei, um wichtige Ankündigungen zu erhalten
     und mit anderen Teilnehmern zu chatten.
 
+account-sign-in-methods = Anmeldemethoden
+account-sign-in-methods-description = Verknüpfe andere Konten, um dich mit jedem davon anzumelden. Die letzte Anmeldemethode kann nicht entfernt werden.
+account-sign-in-methods-linked = Verknüpft
+account-sign-in-methods-link = Verknüpfen
+account-sign-in-methods-unlink = Trennen
+account-sign-in-methods-verify-email = Bestätige unten eine E-Mail
+account-sign-in-methods-set-password = Neues Passwort...
+account-sign-in-methods-current-password = Aktuelles Passwort...
+account-sign-in-methods-change-password = Ändern
+account-sign-in-methods-add-passkey = Füge unten einen Passkey hinzu
+account-sessions = Sitzungen
+account-sessions-description = Geräte, die derzeit bei deinem Konto angemeldet sind
+account-sessions-current = dieses Gerät
+account-sessions-unknown-device = Unbekanntes Gerät
+account-sessions-last-seen = zuletzt gesehen { $time }
+account-sessions-revoke = Diese Sitzung abmelden
+account-sessions-revoke-all = Überall abmelden
+account-sessions-revoke-all-confirm = Von allen Sitzungen abmelden, einschließlich dieser?
+account-passkeys = Passkeys
+account-passkeys-description = Melde dich mit Fingerabdruck, Gesicht, Bildschirmsperre oder einem Sicherheitsschlüssel an
+account-passkeys-name = Name des Passkeys...
+account-passkeys-add = Passkey hinzufügen
+account-passkeys-delete = Passkey löschen
+account-passkeys-last-used = zuletzt verwendet { $time }
+account-passkeys-never-used = nie verwendet
+
+password-reset = Passwort zurücksetzen
+password-reset-request-description = Gib eine bestätigte E-Mail deines Kontos ein und wir senden dir einen Link, um ein neues Passwort zu wählen.
+password-reset-description = Wähle ein neues Passwort für { $name }.
+password-reset-send = Link senden
+password-reset-submit = Passwort setzen
+password-reset-forgot = Passwort vergessen?
+
+passkey-signin = Mit einem Passkey anmelden
+passkey-error = Überprüfung des Passkeys fehlgeschlagen
+passkey-error-unsupported = Passkeys werden von diesem Browser oder Authenticator nicht unterstützt
+
+two-factor = Zwei-Faktor-Authentifizierung
+two-factor-description = Fordere zusätzlich zu deiner Anmeldemethode einen Code aus einer Authenticator-App an
+two-factor-disabled = Die Zwei-Faktor-Authentifizierung ist nicht aktiviert.
+two-factor-enabled = Die Zwei-Faktor-Authentifizierung ist aktiviert. Du hast { $recovery_codes } unbenutzte Wiederherstellungscodes.
+two-factor-set-up = Einrichten
+two-factor-setup-description = Scanne den QR-Code mit deiner Authenticator-App oder gib das Geheimnis manuell ein und gib dann den angezeigten Code ein.
+two-factor-qr-code = Authenticator-QR-Code
+two-factor-secret = Geheimnis
+two-factor-enable = Aktivieren
+two-factor-disable = Deaktivieren
+two-factor-regenerate-recovery-codes = Neue Wiederherstellungscodes
+two-factor-code-or-recovery = Code oder Wiederherstellungscode...
+two-factor-verify = Bestätigen
+two-factor-cancel = Abbrechen
+two-factor-continue = Weiter
+two-factor-signin-description = Gib den Code aus deiner Authenticator-App oder einen deiner Wiederherstellungscodes ein.
+two-factor-signin-enroll-description = Dein Konto erfordert Zwei-Faktor-Authentifizierung. Scanne den QR-Code mit deiner Authenticator-App und gib den angezeigten Code ein.
+two-factor-recovery-codes = Wiederherstellungscodes
+two-factor-recovery-codes-description = Bewahre diese Codes sicher auf. Jeder kann einmal zur Anmeldung verwendet werden, falls du deinen Authenticator verlierst. Sie werden nicht erneut angezeigt.
+
+register = Registrieren
+register-prompt = Noch kein Konto?
+
+ldap-signin = Mit { $name } anmelden
+
 team-success-set-team-name = Teamname erfolgreich gesetzt
 team-error-name-length = Teamname muss zwischen 3 und 30 Zeichen lang sein
 team-error-name-taken = Teamname bereits vergeben
+team-transfer-ownership = Zum Teamleiter machen
+team-transfer-ownership-confirm = Dieses Mitglied zum Teamleiter machen? Du kannst das Team danach nicht mehr verwalten.
+team-disband = Team auflösen
+team-disband-description = Alle anderen Mitglieder kehren in ihr eigenes Team zurück. Ihre Lösungen werden mitgenommen.
+team-disband-confirm = Team auflösen? Alle anderen Mitglieder werden entfernt.
+team-invites = Einladungen
+team-invites-description = Spieler einladen und Beitrittsanfragen verwalten (nur Teamleiter)
+team-invite-email = Per E-Mail einladen
+team-invite-email-description = Einen Einladungslink an eine E-Mail-Adresse senden
+team-invite-email-placeholder = spieler@example.com
+team-invite-send = Einladung senden
+team-invite-sent = Einladung gesendet
+team-invite-user = In dein Team einladen
+team-invite-revoke = Einladung zurückziehen
+team-invite-accept = Team beitreten
+team-invite-decline = Einladung ablehnen
+team-invites-pending = Offene Einladungen
+team-invites-pending-description = Einladungen, die noch nicht angenommen wurden
+team-invites-pending-empty = Keine offenen Einladungen
+team-incoming-invites = Einladungen
+team-incoming-invites-description = Teams, die dich eingeladen haben
+team-join-request = Beitritt anfragen
+team-join-requested = Beitritt zum Team angefragt
+team-join-requests = Beitrittsanfragen
+team-join-requests-description = Spieler, die deinem Team beitreten möchten
+team-join-requests-empty = Keine offenen Beitrittsanfragen
+team-join-request-approve = Beitrittsanfrage annehmen
+team-join-request-deny = Beitrittsanfrage ablehnen
+team-error-invite-email = Ungültige E-Mail-Adresse
+team-error-join-request-existing-team = Dieser Spieler muss zuerst sein aktuelles Team verlassen
+team-error-join-request-full = Dein Team ist bereits voll
+team-error-join-request-ineligible = Diese Person ist nicht für die Division deines Teams berechtigt
+team-division-eligibility-requested = Dein Team wurde zur Freigabe eingereicht.
 
 account-check-email = Überprüfen Sie Ihre E-Mail auf einen Bestätigungslink
 account-error-email-length = E-Mail-Adresse muss zwischen 1 und 255 Zeichen lang sein
 account-error-email-already-added = E-Mail-Adresse bereits hinzugefügt
 account-error-verification-email = Fehler beim Senden der Bestätigungs-E-Mail
 
-challenges-ticket-submitted = Ticket eingereicht. Die Autor wird sich in Kürze bei Ihnen anworten.
+challenges-ticket-submitted = Ticket eingereicht. Sie können die Unterhaltung auf der Ticketseite verfolgen.
 challenges-challenge-solved = Challenge gelöst
 challenges-error-ticket-too-long = Ticket ist zu lang
 challenges-error-incorrect-flag = Falsche Flag
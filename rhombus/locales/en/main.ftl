@@ -2,6 +2,7 @@ sign-in = Sign In
 scoreboard = Scoreboard
 team = Team
 account = Account
+tickets = Tickets
 challenges = Challenges
 
 account-description = Manage your individual account settings. View
@@ -17,9 +18,68 @@ account-discord-integration-join-server-description =
     Join the official Discord server to get important announcements
     and chat with other competitors.
 
+account-sign-in-methods = Sign-in Methods
+account-sign-in-methods-description = Link other accounts to sign in with any of them. Your last sign-in method cannot be removed.
+account-sign-in-methods-linked = Linked
+account-sign-in-methods-link = Link
+account-sign-in-methods-unlink = Unlink
+account-sign-in-methods-verify-email = Verify an email below
+account-sign-in-methods-set-password = New password...
+account-sign-in-methods-current-password = Current password...
+account-sign-in-methods-change-password = Change
+account-sign-in-methods-add-passkey = Add a passkey below
+account-sessions = Sessions
+account-sessions-description = Devices currently signed in to your account
+account-sessions-current = this device
+account-sessions-unknown-device = Unknown device
+account-sessions-last-seen = last seen { $time }
+account-sessions-revoke = Sign out this session
+account-sessions-revoke-all = Sign out everywhere
+account-sessions-revoke-all-confirm = Sign out of all sessions, including this one?
+account-passkeys = Passkeys
+account-passkeys-description = Sign in with your device's fingerprint, face, screen lock or a security key
+account-passkeys-name = Passkey name...
+account-passkeys-add = Add passkey
+account-passkeys-delete = Delete passkey
+account-passkeys-last-used = last used { $time }
+account-passkeys-never-used = never used
+
 team-success-set-team-name = Set team name successfully
 team-error-name-length = Team name must be between 3 and 30 characters
 team-error-name-taken = Team name already taken
+team-transfer-ownership = Make team owner
+team-transfer-ownership-confirm = Make this member the team owner? You will no longer be able to manage the team.
+team-disband = Disband team
+team-disband-description = Return every other member to their own team. Their solves move with them.
+team-disband-confirm = Disband the team? All other members will be removed.
+team-invites = Invitations
+team-invites-description = Invite players and manage requests to join your team (owner only)
+team-invite-email = Invite by email
+team-invite-email-description = Send an invitation link to an email address
+team-invite-email-placeholder = player@example.com
+team-invite-send = Send invite
+team-invite-sent = Invitation sent
+team-invite-user = Invite to your team
+team-invite-revoke = Revoke invitation
+team-invite-accept = Join team
+team-invite-decline = Decline invitation
+team-invites-pending = Pending invitations
+team-invites-pending-description = Invitations which have not been accepted yet
+team-invites-pending-empty = No pending invitations
+team-incoming-invites = Invitations
+team-incoming-invites-description = Teams which have invited you to join them
+team-join-request = Request to join
+team-join-requested = Requested to join the team
+team-join-requests = Join requests
+team-join-requests-description = Players asking to join your team
+team-join-requests-empty = No pending join requests
+team-join-request-approve = Approve join request
+team-join-request-deny = Deny join request
+team-error-invite-email = Invalid email address
+team-error-join-request-existing-team = This player must leave their current team first
+team-error-join-request-full = Your team is already full
+team-error-join-request-ineligible = This user is not eligible for your team's division
+team-division-eligibility-requested = Your team has been submitted for approval.
 team-members-unlimited = {$count -> 
     [one] {$count} member
     *[other] {$count} members
@@ -42,13 +102,76 @@ account-error-email-length = Email must be between 1 and 255 characters
 account-error-email-already-added = Email already added
 account-error-verification-email = Failed to send verification email. (The email may already be added to another account).
 account-error-signin-email = Failed to send sign in email
-account-error-invalid-credentials = Invalid password
+account-error-invalid-credentials = Invalid username or password
+account-error-email-unconfirmed = Confirm your email with the link we sent before signing in
+account-error-ldap = Failed to sign in with the directory, try again later
+account-error-registration-closed = Registration is closed. Ask your team for an invite link
+account-error-username-length = Username must be between { $min } and { $max } characters
+account-error-username-invalid = Username contains characters which are not allowed
+account-error-email-in-use = Email is already used by another account
+account-check-email-registration = Check your email to confirm your account
 account-success-set-account-name = Set account name successfully
 account-error-name-length = Account name must be between 3 and 30 characters
 account-error-password-length = Password must be at least 8 characters
 account-error-name-taken = Account name already taken
+account-error-last-sign-in-method = You cannot remove your last sign-in method
+account-error-password-name-taken = Another account with your name already signs in with a password. Change your name first.
+account-success-set-password = Password set
+account-error-incorrect-password = Current password is incorrect
+account-success-change-password = Password changed
+account-check-email-password-reset = If an account with a password uses this email, a reset link has been sent to it
+account-error-password-reset-expired = This password reset link is invalid or has expired
+
+password-reset = Reset Password
+password-reset-request-description = Enter a verified email of your account and we will send you a link to choose a new password.
+password-reset-description = Choose a new password for { $name }.
+password-reset-send = Send reset link
+password-reset-submit = Set password
+password-reset-forgot = Forgot password?
+
+passkey-signin = Sign in with a passkey
+passkey-error = Passkey verification failed
+passkey-error-unsupported = Passkeys are not supported by this browser or authenticator
+passkey-error-unknown = This passkey is not registered to any account
+account-error-passkey-name = Passkey name must be at most 64 characters
+account-success-add-passkey = Passkey added
+account-success-delete-passkey = Passkey deleted
+
+two-factor = Two-Factor Authentication
+two-factor-description = Require a code from an authenticator app in addition to your sign in method
+two-factor-disabled = Two-factor authentication is not enabled.
+two-factor-enabled = Two-factor authentication is enabled. You have { $recovery_codes } unused recovery codes.
+two-factor-set-up = Set up
+two-factor-setup-description = Scan the QR code with your authenticator app, or enter the secret manually, then enter the code it shows.
+two-factor-qr-code = Authenticator QR code
+two-factor-secret = Secret
+two-factor-enable = Enable
+two-factor-disable = Disable
+two-factor-regenerate-recovery-codes = New recovery codes
+two-factor-code-or-recovery = Code or recovery code...
+two-factor-verify = Verify
+two-factor-cancel = Cancel
+two-factor-continue = Continue
+two-factor-signin-description = Enter the code from your authenticator app, or one of your recovery codes.
+two-factor-signin-enroll-description = Your account requires two-factor authentication. Scan the QR code with your authenticator app and enter the code it shows.
+two-factor-recovery-codes = Recovery Codes
+two-factor-recovery-codes-description = Store these codes somewhere safe. Each can be used once to sign in if you lose your authenticator. They will not be shown again.
+two-factor-success-enabled = Two-factor authentication enabled
+two-factor-success-disabled = Two-factor authentication disabled
+two-factor-error-invalid-code = Invalid code
+two-factor-error-too-many-attempts = Too many invalid codes. Sign in again to try more.
+two-factor-error-already-enabled = Two-factor authentication is already enabled
+two-factor-error-not-set-up = Two-factor authentication is not set up
+two-factor-error-required = Two-factor authentication is required for admins
+
+register = Register
+register-prompt = Don't have an account?
+
+ldap-signin = Sign In with { $name }
+
+proof-of-work-error = Verification failed, please try again
 
-challenges-ticket-submitted = Ticket submitted. The author will get back to you shortly.
+challenges-ticket-submitted = Ticket submitted. Follow the conversation on the tickets page.
 challenges-challenge-solved = Challenge solved
 challenges-error-ticket-too-long = Ticket is too long
 challenges-error-incorrect-flag = Incorrect flag
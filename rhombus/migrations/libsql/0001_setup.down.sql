@@ -1,6 +1,18 @@
 DROP TABLE IF EXISTS rhombus_challenge;
 DROP TABLE IF EXISTS rhombus_user;
 DROP TABLE IF EXISTS rhombus_email;
+DROP TABLE IF EXISTS rhombus_user_identity;
+DROP TABLE IF EXISTS rhombus_password_reset;
+DROP TABLE IF EXISTS rhombus_user_totp;
+DROP TABLE IF EXISTS rhombus_user_recovery_code;
+DROP TABLE IF EXISTS rhombus_user_passkey;
+DROP TABLE IF EXISTS rhombus_session;
+DROP TABLE IF EXISTS rhombus_team_invite;
+DROP TABLE IF EXISTS rhombus_team_join_request;
+DROP TABLE IF EXISTS rhombus_team_division_history;
+DROP TABLE IF EXISTS rhombus_division_approval;
+DROP TABLE IF EXISTS rhombus_ticket_tag;
+DROP TABLE IF EXISTS rhombus_ticket_message;
 DROP TABLE IF EXISTS rhombus_team;
 DROP TABLE IF EXISTS rhombus_track;
 DROP TABLE IF EXISTS rhombus_track_ip;
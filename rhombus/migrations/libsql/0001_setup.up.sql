@@ -114,6 +114,69 @@ CREATE TABLE IF NOT EXISTS rhombus_email (
     FOREIGN KEY (user_id) REFERENCES rhombus_user(id) ON DELETE CASCADE
 );
 
+-- Accounts on external identity providers (e.g. OpenID Connect) which can sign in as a user
+CREATE TABLE IF NOT EXISTS rhombus_user_identity (
+    provider TEXT NOT NULL,
+    subject TEXT NOT NULL,
+    user_id INTEGER NOT NULL,
+    PRIMARY KEY (provider, subject),
+    FOREIGN KEY (user_id) REFERENCES rhombus_user(id) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS rhombus_user_identity_user_id ON rhombus_user_identity(user_id);
+
+-- TOTP two-factor authentication, pending until the first code is verified
+CREATE TABLE IF NOT EXISTS rhombus_user_totp (
+    user_id INTEGER PRIMARY KEY NOT NULL,
+    secret TEXT NOT NULL,
+    enabled INTEGER NOT NULL DEFAULT(FALSE), -- 0 or 1
+    last_used_step INTEGER,
+    FOREIGN KEY (user_id) REFERENCES rhombus_user(id) ON DELETE CASCADE
+);
+
+CREATE TABLE IF NOT EXISTS rhombus_user_recovery_code (
+    user_id INTEGER NOT NULL,
+    code_hash TEXT NOT NULL,
+    PRIMARY KEY (user_id, code_hash),
+    FOREIGN KEY (user_id) REFERENCES rhombus_user(id) ON DELETE CASCADE
+);
+
+-- WebAuthn credentials, identified by the base64url credential id
+CREATE TABLE IF NOT EXISTS rhombus_user_passkey (
+    id TEXT PRIMARY KEY NOT NULL,
+    user_id INTEGER NOT NULL,
+    name TEXT NOT NULL,
+    public_key BLOB NOT NULL,
+    algorithm INTEGER NOT NULL,
+    sign_count INTEGER NOT NULL DEFAULT(0),
+    created_at INTEGER NOT NULL DEFAULT(strftime('%s', 'now')),
+    last_used_at INTEGER,
+    FOREIGN KEY (user_id) REFERENCES rhombus_user(id) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS rhombus_user_passkey_user_id ON rhombus_user_passkey(user_id);
+
+-- Server side record of every sign in, so sessions can be listed and revoked
+CREATE TABLE IF NOT EXISTS rhombus_session (
+    id TEXT PRIMARY KEY NOT NULL,
+    user_id INTEGER NOT NULL,
+    ip BLOB,
+    user_agent TEXT,
+    created_at INTEGER NOT NULL DEFAULT(strftime('%s', 'now')),
+    last_seen_at INTEGER NOT NULL DEFAULT(strftime('%s', 'now')),
+    expires INTEGER NOT NULL,
+    FOREIGN KEY (user_id) REFERENCES rhombus_user(id) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS rhombus_session_user_id ON rhombus_session(user_id);
+
+CREATE TRIGGER IF NOT EXISTS rhombus_session_autodelete
+    BEFORE INSERT ON rhombus_session
+BEGIN
+    DELETE FROM rhombus_session
+    WHERE expires < strftime('%s', 'now');
+END;
+
 CREATE TABLE IF NOT EXISTS rhombus_email_signin (
     email TEXT NOT NULL,
     code TEXT UNIQUE,
@@ -128,6 +191,21 @@ BEGIN
     WHERE expires < strftime('%s', 'now');
 END;
 
+CREATE TABLE IF NOT EXISTS rhombus_password_reset (
+    user_id INTEGER NOT NULL,
+    code TEXT UNIQUE,
+    expires INTEGER NOT NULL DEFAULT(strftime('%s', 'now', '+30 minutes')),
+    PRIMARY KEY (user_id),
+    FOREIGN KEY (user_id) REFERENCES rhombus_user(id) ON DELETE CASCADE
+);
+
+CREATE TRIGGER IF NOT EXISTS rhombus_password_reset_autodelete
+    BEFORE INSERT ON rhombus_password_reset
+BEGIN
+    DELETE FROM rhombus_password_reset
+    WHERE expires < strftime('%s', 'now');
+END;
+
 CREATE TABLE IF NOT EXISTS rhombus_writeup (
     user_id INTEGER NOT NULL,
     challenge_id TEXT NOT NULL,
@@ -161,6 +239,55 @@ CREATE TABLE IF NOT EXISTS rhombus_team_historical_names (
     FOREIGN KEY (team_id) REFERENCES rhombus_team(id)
 );
 
+CREATE TABLE IF NOT EXISTS rhombus_team_division_history (
+    team_id INTEGER NOT NULL,
+    old_division_id TEXT NOT NULL,
+    new_division_id TEXT NOT NULL,
+    admin_user_id INTEGER, -- set if an admin moved the team regardless of eligibility
+    at INTEGER NOT NULL DEFAULT(strftime('%s', 'now')),
+    FOREIGN KEY (team_id) REFERENCES rhombus_team(id) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS rhombus_team_division_history_team_id ON rhombus_team_division_history(team_id);
+
+-- Invitations from a team owner to a specific user or email address, accepted through the code
+CREATE TABLE IF NOT EXISTS rhombus_team_invite (
+    code TEXT PRIMARY KEY NOT NULL,
+    team_id INTEGER NOT NULL,
+    user_id INTEGER,
+    email TEXT,
+    created_at INTEGER NOT NULL DEFAULT(strftime('%s', 'now')),
+    FOREIGN KEY (team_id) REFERENCES rhombus_team(id) ON DELETE CASCADE,
+    FOREIGN KEY (user_id) REFERENCES rhombus_user(id) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS rhombus_team_invite_team_id ON rhombus_team_invite(team_id);
+CREATE INDEX IF NOT EXISTS rhombus_team_invite_user_id ON rhombus_team_invite(user_id);
+
+-- Requests from a user to join a team, approved or denied by the team owner
+CREATE TABLE IF NOT EXISTS rhombus_team_join_request (
+    team_id INTEGER NOT NULL,
+    user_id INTEGER NOT NULL,
+    created_at INTEGER NOT NULL DEFAULT(strftime('%s', 'now')),
+    PRIMARY KEY (team_id, user_id),
+    FOREIGN KEY (team_id) REFERENCES rhombus_team(id) ON DELETE CASCADE,
+    FOREIGN KEY (user_id) REFERENCES rhombus_user(id) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS rhombus_team_join_request_user_id ON rhombus_team_join_request(user_id);
+
+-- Requests from a user to be admitted to a division which requires manual approval
+CREATE TABLE IF NOT EXISTS rhombus_division_approval (
+    division_id TEXT NOT NULL,
+    user_id INTEGER NOT NULL,
+    approved INTEGER, -- NULL while pending, else 0 or 1
+    requested_at INTEGER NOT NULL DEFAULT(strftime('%s', 'now')),
+    PRIMARY KEY (division_id, user_id),
+    FOREIGN KEY (user_id) REFERENCES rhombus_user(id) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS rhombus_division_approval_pending ON rhombus_division_approval(approved, requested_at);
+
 CREATE TABLE IF NOT EXISTS rhombus_ticket (
     ticket_number INTEGER NOT NULL UNIQUE,
     user_id INTEGER NOT NULL,
@@ -0,0 +1,3 @@
+ALTER TABLE rhombus_division ADD COLUMN max_players TEXT; -- only for divisions created by an admin
+ALTER TABLE rhombus_division ADD COLUMN archived INTEGER NOT NULL DEFAULT FALSE; -- 0 or 1
+ALTER TABLE rhombus_division ADD COLUMN admin_managed INTEGER NOT NULL DEFAULT FALSE; -- 1 if created by an admin instead of the config
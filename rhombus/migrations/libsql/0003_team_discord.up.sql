@@ -0,0 +1,3 @@
+ALTER TABLE rhombus_team ADD COLUMN discord_role_id INTEGER;
+ALTER TABLE rhombus_team ADD COLUMN discord_text_channel_id INTEGER;
+ALTER TABLE rhombus_team ADD COLUMN discord_voice_channel_id INTEGER;
@@ -0,0 +1,41 @@
+-- Tickets no longer need a Discord thread, so rebuild the table with nullable Discord ids
+CREATE TABLE rhombus_ticket_new (
+    ticket_number INTEGER NOT NULL UNIQUE,
+    user_id INTEGER NOT NULL,
+    challenge_id TEXT NOT NULL,
+    opened_at INTEGER NOT NULL DEFAULT(strftime('%s', 'now')),
+    closed_at INTEGER,
+    discord_channel_id INTEGER UNIQUE,
+    discord_panel_message_id INTEGER UNIQUE,
+    discord_last_message_id INTEGER,
+    PRIMARY KEY (ticket_number),
+    FOREIGN KEY (user_id) REFERENCES rhombus_user(id),
+    FOREIGN KEY (challenge_id) REFERENCES rhombus_challenge(id)
+);
+
+INSERT INTO rhombus_ticket_new (ticket_number, user_id, challenge_id, opened_at, closed_at, discord_channel_id, discord_panel_message_id, discord_last_message_id)
+SELECT ticket_number, user_id, challenge_id, opened_at, closed_at, discord_channel_id, discord_panel_message_id, discord_last_message_id
+FROM rhombus_ticket;
+
+DROP TABLE rhombus_ticket;
+ALTER TABLE rhombus_ticket_new RENAME TO rhombus_ticket;
+
+CREATE INDEX challenge_idx ON rhombus_ticket (challenge_id, user_id, closed_at);
+CREATE INDEX user_idx ON rhombus_ticket (user_id, opened_at DESC);
+CREATE INDEX rhombus_ticket_open ON rhombus_ticket (closed_at, opened_at DESC);
+
+CREATE TABLE rhombus_ticket_message (
+    id INTEGER PRIMARY KEY NOT NULL,
+    ticket_number INTEGER NOT NULL,
+    user_id INTEGER,
+    author_name TEXT NOT NULL,
+    author_avatar TEXT NOT NULL,
+    content TEXT NOT NULL,
+    discord_message_id INTEGER UNIQUE,
+    sent_at INTEGER NOT NULL DEFAULT(strftime('%s', 'now')),
+    edited_at INTEGER,
+    FOREIGN KEY (ticket_number) REFERENCES rhombus_ticket(ticket_number),
+    FOREIGN KEY (user_id) REFERENCES rhombus_user(id)
+);
+
+CREATE INDEX rhombus_ticket_message_ticket ON rhombus_ticket_message (ticket_number, sent_at);
@@ -0,0 +1,10 @@
+ALTER TABLE rhombus_ticket ADD COLUMN status TEXT NOT NULL DEFAULT 'open'; -- open, waiting or escalated while closed_at is NULL
+ALTER TABLE rhombus_ticket ADD COLUMN assignee_discord_id INTEGER;
+ALTER TABLE rhombus_ticket ADD COLUMN first_response_at INTEGER;
+
+CREATE TABLE rhombus_ticket_tag (
+    ticket_number INTEGER NOT NULL,
+    tag TEXT NOT NULL,
+    PRIMARY KEY (ticket_number, tag),
+    FOREIGN KEY (ticket_number) REFERENCES rhombus_ticket(ticket_number)
+);
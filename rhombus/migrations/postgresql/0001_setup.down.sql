@@ -1,4 +1,5 @@
 DROP TABLE IF EXISTS Challenge;
+DROP TABLE IF EXISTS UserPasskey;
 DROP TABLE IF EXISTS "User";
 DROP TABLE IF EXISTS Track;
 DROP TABLE IF EXISTS TrackConnection;
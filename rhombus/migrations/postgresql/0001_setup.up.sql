@@ -14,6 +14,18 @@ CREATE TABLE IF NOT EXISTS "User" (
     updated_at TIMESTAMP WITH TIME ZONE DEFAULT NOW()
 );
 
+CREATE TABLE IF NOT EXISTS UserPasskey (
+    id TEXT PRIMARY KEY,
+    user_id BIGINT NOT NULL,
+    name TEXT NOT NULL,
+    public_key BYTEA NOT NULL,
+    algorithm INT NOT NULL,
+    sign_count BIGINT NOT NULL DEFAULT(0),
+    created_at TIMESTAMP WITH TIME ZONE DEFAULT NOW(),
+    last_used_at TIMESTAMP WITH TIME ZONE,
+    FOREIGN KEY (user_id) REFERENCES "User"(id) ON DELETE CASCADE
+);
+
 CREATE TABLE IF NOT EXISTS Track (
     ip VARCHAR(39), -- 39 is the max length of an IPv6 address
     user_agent TEXT,
@@ -5,7 +5,6 @@ use std::{
     fmt::Debug,
     hash::{BuildHasher, BuildHasherDefault, Hasher},
     net::IpAddr,
-    num::NonZeroU32,
     rc::Rc,
     sync::Arc,
     time::Duration,
@@ -38,6 +37,11 @@ use crate::{
             route_signin, route_signin_credentials, route_signin_ctftime,
             route_signin_ctftime_callback, route_signin_discord, route_signin_discord_callback,
             route_signin_email, route_signin_email_callback, route_signin_email_confirm_callback,
+            route_signin_github, route_signin_github_callback, route_signin_google,
+            route_signin_google_callback, route_signin_oidc, route_signin_oidc_callback,
+            route_signin_passkey, route_signin_passkey_options, route_signin_register,
+            route_signin_register_confirm, route_signin_reset, route_signin_reset_confirm,
+            route_signin_reset_confirm_submit, route_signin_reset_request, route_signin_two_factor,
             route_signout, MaybeUser,
         },
         command_palette::route_command_palette_items,
@@ -47,8 +51,11 @@ use crate::{
         },
         discord::Bot,
         division::{
-            Division, DivisionEligibilityProvider, EmailDivisionEligibilityProvider,
-            MaxDivisionPlayers, OpenDivisionEligibilityProvider,
+            AllDivisionEligibilityProvider, CTFtimeDivisionEligibilityProvider,
+            DiscordRoleDivisionEligibilityProvider, Division, DivisionBot,
+            DivisionEligibilityProvider, EmailDivisionEligibilityProvider,
+            ManualDivisionEligibilityProvider, MaxDivisionPlayers, OpenDivisionEligibilityProvider,
+            UserListDivisionEligibilityProvider,
         },
         email::{mailgun::MailgunProvider, outbound_mailer::OutboundMailer},
         errors::{error_handler_middleware, handle_panic, route_not_found, timeout_inner},
@@ -65,12 +72,25 @@ use crate::{
             open_graph_cache_evictor, route_default_og_image, route_team_og_image,
             route_user_og_image,
         },
+        proof_of_work::route_proof_of_work_challenge,
         router::{route_reload, RouterState, RouterStateInner},
         routes::{
             account::{
                 discord_cache_evictor, route_account, route_account_add_email,
-                route_account_delete_email, route_account_email_verify_callback,
-                route_account_email_verify_confirm, route_account_roll_key, route_account_set_name,
+                route_account_add_passkey, route_account_delete_email,
+                route_account_delete_passkey, route_account_email_verify_callback,
+                route_account_email_verify_confirm, route_account_passkey_options,
+                route_account_revoke_all_sessions, route_account_revoke_session,
+                route_account_roll_key, route_account_set_name, route_account_set_password,
+                route_account_two_factor_disable, route_account_two_factor_enable,
+                route_account_two_factor_recovery_codes, route_account_two_factor_setup,
+                route_account_unlink_sign_in_method,
+            },
+            admin::{
+                route_admin_backup, route_admin_division, route_admin_division_archive,
+                route_admin_division_create, route_admin_division_update, route_admin_divisions,
+                route_admin_team_division, route_admin_ticket_update, route_admin_tickets,
+                route_admin_tickets_export,
             },
             challenges::{
                 route_challenge_submit, route_challenge_view, route_challenges,
@@ -84,18 +104,27 @@ use crate::{
                 route_scoreboard, route_scoreboard_division, route_scoreboard_division_ctftime,
             },
             team::{
-                route_team, route_team_roll_token, route_team_set_division, route_team_set_name,
-                route_user_kick,
+                route_team, route_team_disband, route_team_invite_accept, route_team_invite_delete,
+                route_team_invite_email, route_team_invite_user, route_team_join_request,
+                route_team_join_request_approve, route_team_join_request_deny,
+                route_team_roll_token, route_team_set_division, route_team_set_name,
+                route_team_transfer_ownership, route_user_kick,
             },
             terms::route_terms,
+            tickets::{
+                route_ticket, route_ticket_close, route_ticket_reopen, route_ticket_reply,
+                route_ticket_transcript, route_tickets,
+            },
         },
         settings::{DbConfig, IpPreset, Settings},
         static_serve::route_static_serve,
         templates::Templates,
+        upload_provider::enforce_signed_upload_middleware,
     },
+    llm_provider::OpenAiCompatibleProvider,
     plugin::{DatabaseProviderContext, GrpcBuilder, RunContext, UploadProviderContext},
     upload_provider::ErasedUploadProvider,
-    LocalUploadProvider, Plugin, Result, UploadProvider,
+    LlmProvider, LocalUploadProvider, Plugin, Result, UploadProvider,
 };
 
 #[cfg(feature = "imap")]
@@ -147,6 +176,7 @@ pub struct Builder {
     pub plugins: Vec<Box<dyn Plugin + Send + Sync>>,
     pub database: Option<DbConfig>,
     pub upload_provider: RefCell<Option<Box<dyn ErasedUploadProvider>>>,
+    pub llm_provider: Option<Arc<dyn LlmProvider>>,
     pub config_builder: config::ConfigBuilder<config::builder::DefaultState>,
     pub ip_extractor: Option<IpExtractorFn>,
 }
@@ -247,6 +277,16 @@ impl Builder {
             database: self.database,
             ip_extractor: self.ip_extractor,
             upload_provider: RefCell::new(Some(Box::new(upload_provider))),
+            llm_provider: self.llm_provider,
+        }
+    }
+
+    /// Use a custom language model for the `/ai` ticket assistant instead of the one configured
+    /// under `ai`
+    pub fn llm_provider(self, llm_provider: impl LlmProvider + 'static) -> Self {
+        Self {
+            llm_provider: Some(Arc::new(llm_provider)),
+            ..self
         }
     }
 
@@ -262,6 +302,7 @@ impl Builder {
             database: self.database,
             ip_extractor: self.ip_extractor,
             upload_provider: self.upload_provider,
+            llm_provider: self.llm_provider,
         }
     }
 
@@ -466,46 +507,60 @@ impl Builder {
                 self_rc.build_database(&settings).await?
             };
 
+            let division_bot: DivisionBot = Default::default();
+
             let mut divisions = if let Some(divisions) = &settings.divisions {
                 divisions
                     .iter()
                     .enumerate()
                     .map(|(i, division)| {
+                        let id = division.stable_id.as_ref().unwrap_or(&division.name);
+
+                        let mut providers: Vec<DivisionEligibilityProvider> = vec![];
+                        if let Some(email_regex) = &division.email_regex {
+                            providers.push(Arc::new(EmailDivisionEligibilityProvider::new(
+                                db.clone(),
+                                email_regex,
+                                division.requirement.clone(),
+                            )));
+                        }
+                        if let Some(role_id) = division.required_discord_role_id {
+                            providers.push(Arc::new(DiscordRoleDivisionEligibilityProvider::new(
+                                db.clone(),
+                                division_bot.clone(),
+                                role_id,
+                                division.requirement.clone(),
+                            )));
+                        }
+                        if let Some(ctftime) = &division.ctftime {
+                            providers.push(Arc::new(CTFtimeDivisionEligibilityProvider::new(
+                                ctftime.countries.clone(),
+                                division.requirement.clone(),
+                            )));
+                        }
+                        if let Some(user_ids) = &division.user_ids {
+                            providers.push(Arc::new(UserListDivisionEligibilityProvider::new(
+                                user_ids,
+                                division.requirement.clone(),
+                            )));
+                        }
+                        if division.manual_approval == Some(true) {
+                            providers.push(Arc::new(ManualDivisionEligibilityProvider::new(
+                                db.clone(),
+                                id,
+                                division.requirement.clone(),
+                            )));
+                        }
+
                         let division_eligibility: DivisionEligibilityProvider =
-                            if let Some(email_regex) = &division.email_regex {
-                                Arc::new(EmailDivisionEligibilityProvider::new(
-                                    db.clone(),
-                                    email_regex,
-                                    division.requirement.clone(),
-                                ))
-                            } else {
-                                Arc::new(OpenDivisionEligibilityProvider {})
+                            match providers.len() {
+                                0 => Arc::new(OpenDivisionEligibilityProvider {}),
+                                1 => providers.pop().unwrap(),
+                                _ => Arc::new(AllDivisionEligibilityProvider { providers }),
                             };
 
-                        let id = division.stable_id.as_ref().unwrap_or(&division.name);
-
-                        let max_players = if let Some(max_players) = &division.max_players {
-                            match max_players.as_str() {
-                                "unlimited" => MaxDivisionPlayers::Unlimited,
-                                "infinity" => MaxDivisionPlayers::Unlimited,
-                                "infinite" => MaxDivisionPlayers::Unlimited,
-                                "any" => MaxDivisionPlayers::Unlimited,
-                                _ => {
-                                    if let Ok(max_players) = max_players.parse::<NonZeroU32>() {
-                                        MaxDivisionPlayers::Limited(max_players)
-                                    } else {
-                                        tracing::error!(
-                                            max_players,
-                                            division = division.name,
-                                            "Invalid max players value. Defaulting to unlimited."
-                                        );
-                                        MaxDivisionPlayers::Unlimited
-                                    }
-                                }
-                            }
-                        } else {
-                            MaxDivisionPlayers::Unlimited
-                        };
+                        let max_players =
+                            parse_max_players(division.max_players.as_deref(), &division.name);
 
                         Division {
                             id: id.to_owned(),
@@ -515,6 +570,8 @@ impl Builder {
                             division_eligibility,
                             discord_role_id: division.discord_role_id,
                             is_default: i == 0,
+                            archived: false,
+                            admin_managed: false,
                         }
                     })
                     .collect()
@@ -529,9 +586,36 @@ impl Builder {
                     division_eligibility: Arc::new(OpenDivisionEligibilityProvider {}),
                     is_default: true,
                     discord_role_id: None,
+                    archived: false,
+                    admin_managed: false,
                 }]
             };
 
+            // divisions created and archived by admins at runtime
+            for stored_division in db.get_divisions().await? {
+                if stored_division.admin_managed {
+                    divisions.push(Division {
+                        max_players: parse_max_players(
+                            stored_division.max_players.as_deref(),
+                            &stored_division.name,
+                        ),
+                        id: stored_division.id,
+                        name: stored_division.name,
+                        description: stored_division.description,
+                        division_eligibility: Arc::new(OpenDivisionEligibilityProvider {}),
+                        is_default: false,
+                        discord_role_id: None,
+                        archived: stored_division.archived,
+                        admin_managed: true,
+                    });
+                } else if let Some(division) = divisions
+                    .iter_mut()
+                    .find(|division| division.id == stored_division.id)
+                {
+                    division.archived = stored_division.archived;
+                }
+            }
+
             tokio::task::spawn(async move {
                 let duration = Duration::from_secs(3 * 60);
                 loop {
@@ -703,9 +787,9 @@ impl Builder {
                 );
             }
 
-            let upload_provider: Box<dyn ErasedUploadProvider> = 'u: {
+            let upload_provider: Arc<dyn ErasedUploadProvider> = 'u: {
                 if let Some(builder_upload_provider) = self_rc.upload_provider.borrow_mut().take() {
-                    break 'u builder_upload_provider;
+                    break 'u builder_upload_provider.into();
                 }
 
                 let plugin_upload_provider_builder = UploadProviderContext {
@@ -717,7 +801,7 @@ impl Builder {
                         .upload_provider(&plugin_upload_provider_builder)
                         .await
                     {
-                        break 'u plugin_upload_provider;
+                        break 'u plugin_upload_provider.into();
                     }
                 }
 
@@ -726,7 +810,7 @@ impl Builder {
                     {
                         let s3_upload_provider =
                             crate::s3_upload_provider::S3UploadProvider::new(s3).await?;
-                        break 'u Box::new(s3_upload_provider);
+                        break 'u Arc::new(s3_upload_provider);
                     }
 
                     #[cfg(not(feature = "s3"))]
@@ -742,7 +826,7 @@ impl Builder {
                     .unwrap_or(false)
                 {
                     let database_upload_provider = DatabaseUploadProvider::new(db).await;
-                    break 'u Box::new(database_upload_provider);
+                    break 'u Arc::new(database_upload_provider);
                 }
 
                 let base_path = if let Some(local_upload_provider_options) =
@@ -763,7 +847,7 @@ impl Builder {
                 };
 
                 let local_upload_provider = LocalUploadProvider::new(base_path);
-                break 'u Box::new(local_upload_provider);
+                break 'u Arc::new(local_upload_provider);
             };
 
             let upload_router = upload_provider.routes()?;
@@ -775,7 +859,7 @@ impl Builder {
             };
 
             let mut plugin_builder = RunContext {
-                upload_provider,
+                upload_provider: upload_provider.clone(),
                 templates: &mut templates,
                 localizations: &mut localizer,
                 settings: settings.clone(),
@@ -895,6 +979,12 @@ impl Builder {
                     (None, axum::Router::new())
                 };
 
+            let llm_provider = match &self_rc.llm_provider {
+                Some(llm_provider) => Some(llm_provider.clone()),
+                None => OpenAiCompatibleProvider::from_settings(&*settings.read().await)?
+                    .map(|llm_provider| Arc::new(llm_provider) as Arc<dyn LlmProvider>),
+            };
+
             let bot = if settings.clone().read().await.discord.is_some() {
                 let bot = Arc::new(
                     Bot::new(
@@ -903,17 +993,47 @@ impl Builder {
                         outbound_mailer.clone(),
                         jinja.clone(),
                         divisions.clone(),
+                        llm_provider,
                     )
                     .await,
                 );
 
                 discord_cache_evictor();
 
+                _ = division_bot.set(Arc::downgrade(&bot));
+
                 let b = bot.clone();
                 tokio::task::spawn(async move {
                     b.sync_top10_discord_role().await;
                 });
 
+                let (team_roles, end_time) = {
+                    let settings = settings.read().await;
+                    (
+                        settings
+                            .discord
+                            .as_ref()
+                            .is_some_and(|d| d.team_roles.unwrap_or(false)),
+                        settings.end_time,
+                    )
+                };
+                if let (true, Some(end_time)) = (team_roles, end_time) {
+                    let b = Arc::downgrade(&bot);
+                    tokio::task::spawn(async move {
+                        if let Ok(until_end) = (end_time - chrono::Utc::now()).to_std() {
+                            tokio::time::sleep(until_end).await;
+                        }
+                        if let Some(bot) = b.upgrade() {
+                            if let Err(error) = bot.delete_team_discords().await {
+                                tracing::error!(
+                                    ?error,
+                                    "Failed to delete team Discord roles and channels"
+                                );
+                            }
+                        }
+                    });
+                }
+
                 Some(bot)
             } else {
                 None
@@ -921,16 +1041,15 @@ impl Builder {
 
             {
                 let locked_settings = settings.read().await;
-                if bot.is_some()
-                    && locked_settings
-                        .email
-                        .as_ref()
-                        .is_some_and(|e| e.imap.is_some())
+                if locked_settings
+                    .email
+                    .as_ref()
+                    .is_some_and(|e| e.imap.is_some())
                 {
                     #[cfg(feature = "imap")]
                     crate::internal::email::imap::ImapEmailReciever::new(
                         Arc::downgrade(&settings),
-                        Arc::downgrade(bot.as_ref().unwrap()),
+                        bot.as_ref().map(Arc::downgrade),
                         Arc::downgrade(&cached_db),
                     )
                     .receive_emails()
@@ -949,6 +1068,31 @@ impl Builder {
             }
             healthcheck_runner(Arc::downgrade(&cached_db));
 
+            let ldap_router: axum::Router<RouterState> = {
+                #[cfg(feature = "ldap")]
+                {
+                    axum::Router::new().route(
+                        "/signin/ldap",
+                        post(crate::internal::auth::route_signin_ldap),
+                    )
+                }
+
+                #[cfg(not(feature = "ldap"))]
+                {
+                    if settings
+                        .read()
+                        .await
+                        .auth
+                        .contains(&crate::internal::settings::AuthProvider::Ldap)
+                    {
+                        tracing::error!(
+                            "LDAP is configured but cargo feature `ldap` is not enabled"
+                        );
+                    }
+                    axum::Router::new()
+                }
+            };
+
             let global_page_meta = Arc::new(GlobalPageMeta {
                 title: settings.read().await.title.clone(),
                 description: settings.read().await.description.clone().unwrap_or(
@@ -970,6 +1114,7 @@ impl Builder {
                     .unwrap_or(Arc::new(default_ip_extractor)),
                 outbound_mailer,
                 divisions,
+                upload_provider,
                 router: rr.clone(),
                 global_page_meta,
                 score_type_map,
@@ -980,6 +1125,26 @@ impl Builder {
                 .fallback(route_not_found)
                 .route("/admin", get(|| async { (StatusCode::OK, Html("Admin")) }))
                 .route("/reload", get(route_reload))
+                .route("/admin/backup", get(route_admin_backup))
+                .route(
+                    "/admin/divisions",
+                    get(route_admin_divisions).post(route_admin_division_create),
+                )
+                .route(
+                    "/admin/divisions/:id",
+                    get(route_admin_division).post(route_admin_division_update),
+                )
+                .route(
+                    "/admin/divisions/:id/archive",
+                    post(route_admin_division_archive),
+                )
+                .route("/admin/team/:id/division", post(route_admin_team_division))
+                .route("/admin/tickets", get(route_admin_tickets))
+                .route("/admin/tickets/export", get(route_admin_tickets_export))
+                .route(
+                    "/admin/tickets/:ticket_number",
+                    post(route_admin_ticket_update),
+                )
                 .route_layer(middleware::from_fn(enforce_admin_middleware))
                 .route(
                     "/account/verify/confirm",
@@ -990,11 +1155,59 @@ impl Builder {
                     "/account/email",
                     post(route_account_add_email).delete(route_account_delete_email),
                 )
+                .route(
+                    "/account/sign-in-method",
+                    delete(route_account_unlink_sign_in_method),
+                )
+                .route("/account/password", post(route_account_set_password))
+                .route("/account/session", delete(route_account_revoke_session))
+                .route(
+                    "/account/sessions/revoke",
+                    post(route_account_revoke_all_sessions),
+                )
+                .route(
+                    "/account/passkey/options",
+                    post(route_account_passkey_options),
+                )
+                .route(
+                    "/account/passkey",
+                    post(route_account_add_passkey).delete(route_account_delete_passkey),
+                )
+                .route("/account/2fa/setup", post(route_account_two_factor_setup))
+                .route("/account/2fa/enable", post(route_account_two_factor_enable))
+                .route(
+                    "/account/2fa/recovery-codes",
+                    post(route_account_two_factor_recovery_codes),
+                )
+                .route(
+                    "/account/2fa/disable",
+                    post(route_account_two_factor_disable),
+                )
                 .route("/account/roll-key", post(route_account_roll_key))
                 .route("/account/name", post(route_account_set_name))
                 .route("/account", get(route_account))
                 .route("/team/division/:id", post(route_team_set_division))
                 .route("/team/user/:id", delete(route_user_kick))
+                .route(
+                    "/team/user/:id/transfer",
+                    post(route_team_transfer_ownership),
+                )
+                .route("/team/disband", post(route_team_disband))
+                .route("/team/invite", post(route_team_invite_email))
+                .route("/team/invite/user/:id", post(route_team_invite_user))
+                .route(
+                    "/team/invite/:code",
+                    get(route_team_invite_accept).delete(route_team_invite_delete),
+                )
+                .route("/team/:id/join-request", post(route_team_join_request))
+                .route(
+                    "/team/join-request/:id/approve",
+                    post(route_team_join_request_approve),
+                )
+                .route(
+                    "/team/join-request/:id",
+                    delete(route_team_join_request_deny),
+                )
                 .route("/team/roll-token", post(route_team_roll_token))
                 .route("/team/name", post(route_team_set_name))
                 .route("/team", get(route_team))
@@ -1012,14 +1225,45 @@ impl Builder {
                     "/challenges/:id",
                     get(route_challenge_view).post(route_challenge_submit),
                 )
+                .route("/tickets", get(route_tickets))
+                .route(
+                    "/tickets/:ticket_number",
+                    get(route_ticket).post(route_ticket_reply),
+                )
+                .route("/tickets/:ticket_number/close", post(route_ticket_close))
+                .route("/tickets/:ticket_number/reopen", post(route_ticket_reopen))
+                .route(
+                    "/tickets/:ticket_number/transcript",
+                    get(route_ticket_transcript),
+                )
                 .route_layer(middleware::from_fn(enforce_auth_middleware))
                 .nest_service("/static", get(route_static_serve))
                 .route("/command-palette", get(route_command_palette_items))
                 .route("/terms", get(route_terms))
                 .route("/", get(route_home))
                 .merge(mailgun_router)
+                .merge(ldap_router)
                 .route("/signout", get(route_signout))
+                .route("/proof-of-work", get(route_proof_of_work_challenge))
                 .route("/signin/credentials", post(route_signin_credentials))
+                .route(
+                    "/signin/register/confirm",
+                    get(route_signin_register_confirm),
+                )
+                .route("/signin/register", post(route_signin_register))
+                .route("/signin/2fa", post(route_signin_two_factor))
+                .route(
+                    "/signin/passkey",
+                    get(route_signin_passkey_options).post(route_signin_passkey),
+                )
+                .route(
+                    "/signin/reset/confirm",
+                    get(route_signin_reset_confirm).post(route_signin_reset_confirm_submit),
+                )
+                .route(
+                    "/signin/reset",
+                    get(route_signin_reset).post(route_signin_reset_request),
+                )
                 .route(
                     "/signin/email/confirm",
                     get(route_signin_email_confirm_callback),
@@ -1033,6 +1277,12 @@ impl Builder {
                     get(route_signin_ctftime_callback),
                 )
                 .route("/signin/ctftime", get(route_signin_ctftime))
+                .route("/signin/oidc/callback", get(route_signin_oidc_callback))
+                .route("/signin/oidc", get(route_signin_oidc))
+                .route("/signin/github/callback", get(route_signin_github_callback))
+                .route("/signin/github", get(route_signin_github))
+                .route("/signin/google/callback", get(route_signin_google_callback))
+                .route("/signin/google", get(route_signin_google))
                 .route(
                     "/signin/discord/callback",
                     get(route_signin_discord_callback),
@@ -1053,10 +1303,17 @@ impl Builder {
                 .route("/og-image.png", get(route_default_og_image))
                 .route("/robots.txt", get(route_robots_txt))
                 .with_state(router_state.clone())
-                .merge(upload_router.layer(middleware::from_fn_with_state(
-                    router_state.clone(),
-                    auth_injector_middleware,
-                )));
+                .merge(
+                    upload_router
+                        .layer(middleware::from_fn_with_state(
+                            router_state.clone(),
+                            enforce_signed_upload_middleware,
+                        ))
+                        .layer(middleware::from_fn_with_state(
+                            router_state.clone(),
+                            auth_injector_middleware,
+                        )),
+                );
 
             let router = if !self_rc.plugins.is_empty() {
                 axum::Router::new()
@@ -1184,6 +1441,21 @@ impl Builder {
     }
 }
 
+fn parse_max_players(max_players: Option<&str>, division_name: &str) -> MaxDivisionPlayers {
+    let Some(max_players) = max_players else {
+        return MaxDivisionPlayers::Unlimited;
+    };
+
+    MaxDivisionPlayers::parse(max_players).unwrap_or_else(|| {
+        tracing::error!(
+            max_players,
+            division = division_name,
+            "Invalid max players value. Defaulting to unlimited."
+        );
+        MaxDivisionPlayers::Unlimited
+    })
+}
+
 fn not_htmx_predicate<T>(req: &axum::http::Request<T>) -> bool {
     !req.headers().contains_key("hx-request")
 }
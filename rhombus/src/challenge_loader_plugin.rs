@@ -19,7 +19,7 @@ use tokio_util::{
 use crate::{
     internal::{
         database::libsql::LibSQLConnection, local_upload_provider::slice_to_hex_string,
-        router::RouterState,
+        router::RouterState, upload_provider::ATTACHMENT_LOCK,
     },
     plugin::{PluginMeta, RunContext},
     Plugin, Result, UploadProvider,
@@ -69,13 +69,31 @@ impl Plugin for ChallengeLoaderPlugin {
                     .unwrap()
                     .try_deserialize::<Challenge>()
                     .unwrap();
-                let metadata = Config::builder()
+                let mut metadata = Config::builder()
                     .add_source(config::File::from(path.as_path()))
                     .build()
                     .unwrap()
                     .try_deserialize::<serde_json::Value>()
                     .unwrap();
                 let root = path.parent().unwrap().to_path_buf();
+
+                // files only the `/ai` ticket assistant reads, never shown to players
+                let read_private = |file: &Option<String>| {
+                    file.as_ref().map(|file| {
+                        fs::read_to_string(root.join(file)).unwrap_or_else(|error| {
+                            panic!("Failed to read {} for {}: {}", file, path.display(), error)
+                        })
+                    })
+                };
+                let author_notes = read_private(&challenge.author_notes);
+                let solve_script = read_private(&challenge.solve_script);
+                if author_notes.is_some() || solve_script.is_some() {
+                    metadata["private_context"] = serde_json::json!({
+                        "author_notes": author_notes,
+                        "solve_script": solve_script,
+                    });
+                }
+
                 ChallengeIntermediate {
                     stable_id: challenge.stable_id,
                     name: challenge.name,
@@ -136,6 +154,7 @@ impl Plugin for ChallengeLoaderPlugin {
 
             #[cfg(feature = "libsql")]
             crate::builder::RawDb::LibSQL(db) => {
+                let _guard = ATTACHMENT_LOCK.read().await;
                 let tx = db.transaction().await?;
 
                 let new_challenge_ids = challenges
@@ -419,6 +438,10 @@ pub struct Challenge {
     pub score_type: Option<String>,
     pub files: Vec<Attachment>,
     pub healthscript: Option<String>,
+    /// Private notes for the `/ai` ticket assistant, relative to the challenge directory
+    pub author_notes: Option<String>,
+    /// Solve script for the `/ai` ticket assistant, relative to the challenge directory
+    pub solve_script: Option<String>,
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
@@ -3,24 +3,28 @@ use std::{io, sync::Arc};
 use axum::{
     body::{Body, Bytes},
     extract::State,
-    http::Response,
+    http::{header, HeaderMap, Response},
     response::IntoResponse,
     routing::{get, post},
     Extension, Router,
 };
-use futures::{Stream, TryStreamExt};
+use futures::{stream, Stream, TryStreamExt};
 use reqwest::StatusCode;
 use tokio::io::AsyncReadExt;
 use tokio_util::{bytes::BytesMut, io::StreamReader};
 
 use crate::{
+    errors::RhombusError,
     internal::{
         auth::MaybeUser,
         database::provider::Connection,
         local_upload_provider::slice_to_hex_string,
-        upload_provider::{route_upload_file, validate_simple_filename},
+        upload_provider::{
+            if_none_match, parse_range, parse_upload_url, route_upload_file, upload_etag,
+            validate_simple_filename,
+        },
     },
-    upload_provider::UploadProvider,
+    upload_provider::{ByteStream, UploadProvider},
     Result,
 };
 
@@ -74,12 +78,42 @@ impl UploadProvider for DatabaseUploadProvider {
 
         Ok(url)
     }
+
+    async fn download(&self, url: &str) -> Result<ByteStream> {
+        let (hash, filename) = parse_upload_url(url).ok_or(RhombusError::Unknown)?;
+
+        let (db_filename, size) = self.db.get_file_info(&hash).await?;
+        if filename != db_filename {
+            return Err(RhombusError::DatabaseReturnedNoRows);
+        }
+
+        Ok(stream_file(self.db.clone(), hash, 0, size))
+    }
+
+    async fn list(&self) -> Result<Vec<String>> {
+        let urls = self
+            .db
+            .list_files()
+            .await?
+            .into_iter()
+            .map(|(hash, filename)| format!("/uploads/{}-{}", hash, filename))
+            .collect();
+
+        Ok(urls)
+    }
+
+    async fn delete(&self, url: &str) -> Result<()> {
+        let (hash, _) = parse_upload_url(url).ok_or(RhombusError::Unknown)?;
+
+        self.db.delete_file(&hash).await
+    }
 }
 
 pub async fn route_database_download(
     state: State<Arc<DatabaseUploadProvider>>,
     Extension(maybe_user): Extension<MaybeUser>,
     axum::extract::Path(path): axum::extract::Path<String>,
+    headers: HeaderMap,
 ) -> impl IntoResponse {
     if !validate_simple_filename(&path) {
         return (StatusCode::BAD_REQUEST, "Invalid path".to_owned()).into_response();
@@ -91,21 +125,54 @@ pub async fn route_database_download(
         return (StatusCode::BAD_REQUEST, "Invalid path".to_owned()).into_response();
     };
 
-    let (contents, db_filename) = state.db.download_file(hash).await.unwrap();
+    let Ok((db_filename, size)) = state.db.get_file_info(hash).await else {
+        return (StatusCode::NOT_FOUND, "Not Found").into_response();
+    };
 
     if filename != db_filename {
         return (StatusCode::NOT_FOUND, "Not Found").into_response();
     }
 
+    let etag = upload_etag(hash);
+    if if_none_match(&headers, &etag) {
+        return (StatusCode::NOT_MODIFIED, [(header::ETAG, etag)]).into_response();
+    }
+
+    let (status, start, end) = match parse_range(&headers, size) {
+        None => (StatusCode::OK, 0, size),
+        Some(Ok((start, end))) => (StatusCode::PARTIAL_CONTENT, start, end + 1),
+        Some(Err(())) => {
+            return (
+                StatusCode::RANGE_NOT_SATISFIABLE,
+                [(header::CONTENT_RANGE, format!("bytes */{}", size))],
+            )
+                .into_response();
+        }
+    };
+
     tracing::info!(
         path = filename,
         user_id = maybe_user.map(|u| u.id),
         "Downloading from database"
     );
 
-    let body = Body::from(contents);
+    let body = Body::from_stream(stream_file(state.db.clone(), hash.to_owned(), start, end));
     let mut response = Response::new(body);
-    response.headers_mut().insert(
+    *response.status_mut() = status;
+
+    let response_headers = response.headers_mut();
+    response_headers.insert(header::ETAG, etag.parse().unwrap());
+    response_headers.insert(header::ACCEPT_RANGES, "bytes".parse().unwrap());
+    response_headers.insert(header::CONTENT_LENGTH, (end - start).into());
+    if status == StatusCode::PARTIAL_CONTENT {
+        response_headers.insert(
+            header::CONTENT_RANGE,
+            format!("bytes {}-{}/{}", start, end - 1, size)
+                .parse()
+                .unwrap(),
+        );
+    }
+    response_headers.insert(
         "Content-Disposition",
         format!("attachment; filename={}", &filename)
             .parse()
@@ -113,3 +180,68 @@ pub async fn route_database_download(
     );
     response.into_response()
 }
+
+/// Files are read out of the database in chunks of this many bytes, so that large
+/// files are never loaded into memory all at once
+const CHUNK_SIZE: u64 = 1024 * 1024;
+
+/// Stream the bytes `start..end` of a file stored in the database
+fn stream_file(db: Connection, hash: String, start: u64, end: u64) -> ByteStream {
+    Box::pin(stream::try_unfold(start, move |offset| {
+        let db = db.clone();
+        let hash = hash.clone();
+        async move {
+            if offset >= end {
+                return Ok(None);
+            }
+
+            let length = CHUNK_SIZE.min(end - offset);
+            let chunk = db.download_file_chunk(&hash, offset, length).await?;
+
+            Ok(Some((chunk, offset + length)))
+        }
+    }))
+}
+
+#[cfg(all(test, feature = "libsql"))]
+mod test {
+    use futures::TryStreamExt;
+
+    use super::*;
+    use crate::internal::database::{libsql::InMemoryLibSQL, provider::Database};
+
+    #[tokio::test]
+    async fn stream_file_in_chunks() {
+        let database = InMemoryLibSQL::new().await.unwrap();
+        database.migrate().await.unwrap();
+        let db: Connection = Arc::new(database);
+
+        let contents = (0..CHUNK_SIZE * 5 / 2)
+            .map(|i| (i % 251) as u8)
+            .collect::<Vec<_>>();
+        db.upload_file("abc", "large.bin", &contents).await.unwrap();
+
+        // every chunk of the stream is a separate read out of the database
+        let chunks = stream_file(db.clone(), "abc".to_owned(), 0, contents.len() as u64)
+            .try_collect::<Vec<_>>()
+            .await
+            .unwrap();
+        assert_eq!(
+            chunks
+                .iter()
+                .map(|chunk| chunk.len() as u64)
+                .collect::<Vec<_>>(),
+            vec![CHUNK_SIZE, CHUNK_SIZE, CHUNK_SIZE / 2]
+        );
+        assert_eq!(chunks.concat(), contents);
+
+        let start = CHUNK_SIZE / 2;
+        let end = CHUNK_SIZE * 2;
+        let chunks = stream_file(db, "abc".to_owned(), start, end)
+            .try_collect::<Vec<_>>()
+            .await
+            .unwrap();
+        assert_eq!(chunks.len(), 2);
+        assert_eq!(chunks.concat(), contents[start as usize..end as usize]);
+    }
+}
@@ -29,6 +29,9 @@ pub enum RhombusError {
     #[error("Database error")]
     UnknownDatabase,
 
+    #[error("Not supported by this database: {0}")]
+    UnsupportedDatabaseOperation(&'static str),
+
     #[error("Unknown")]
     Unknown,
 
@@ -53,12 +56,25 @@ pub enum RhombusError {
     #[error("Email: {0}")]
     Email3(#[from] lettre::error::Error),
 
+    #[cfg(feature = "ldap")]
+    #[error("LDAP error: {0}")]
+    Ldap(#[from] ldap3::LdapError),
+
     #[error("Password hash error: {0}")]
     PasswordHash(#[from] argon2::password_hash::Error),
 
     #[error("Reqwest error: {0}")]
     Reqwest(#[from] reqwest::Error),
 
+    #[error("LLM error: {0}")]
+    Llm(String),
+
+    #[error("Backup error: {0}")]
+    Backup(String),
+
+    #[error("JSON error: {0}")]
+    Json(#[from] serde_json::Error),
+
     #[cfg(feature = "s3")]
     #[error("S3 Error")]
     S3(#[from] s3::error::S3Error),
@@ -1,4 +1,10 @@
-use std::{fmt::Write, net::IpAddr, num::NonZeroU64, sync::Arc};
+use std::{
+    collections::HashMap,
+    fmt::Write,
+    net::IpAddr,
+    num::NonZeroU64,
+    sync::{Arc, LazyLock},
+};
 
 use axum::{
     body::Body,
@@ -15,6 +21,9 @@ use axum_extra::extract::{
     cookie::{Cookie, SameSite},
     CookieJar,
 };
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use dashmap::DashMap;
+use fancy_regex::Regex;
 use jsonwebtoken::{decode, encode, DecodingKey, EncodingKey, Header, Validation};
 use minijinja::context;
 use rand::{
@@ -30,12 +39,18 @@ use crate::{
     error_page_code,
     errors::RhombusError,
     internal::{
-        division::MaxDivisionPlayers,
+        database::provider::{IdentityUpsertError, RegisterError},
         errors::{error_page, IntoErrorResponse},
         locales::Languages,
+        proof_of_work::{verify_proof_of_work, ProofOfWorkRoute, ProofOfWorkSolution},
         router::RouterState,
-        routes::{meta::PageMeta, team::create_team_invite_token},
+        routes::{
+            meta::PageMeta,
+            team::{check_join_team, create_team_invite_token, join_team, join_team_error_page},
+        },
+        settings::AuthProvider,
         templates::{toast_header, ToastKind},
+        totp, webauthn,
     },
 };
 
@@ -67,6 +82,8 @@ pub type User = Arc<UserInner>;
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct TokenClaims {
     pub sub: i64,
+    /// The session in the database, which must not be revoked for the token to be accepted
+    pub sid: String,
     pub iat: i64,
     pub exp: i64,
 }
@@ -137,11 +154,17 @@ pub async fn auth_injector_middleware(
             &Validation::default(),
         ) {
             let sub = token_data.claims.sub;
-            req.extensions_mut().insert(Some(token_data.claims.clone()));
-            req.extensions_mut().insert(token_data.claims);
-            if let Ok(user) = state.db.get_user_from_id(sub).await {
-                req.extensions_mut().insert(Some(user.clone()));
-                req.extensions_mut().insert(user);
+            let session_active = matches!(
+                state.db.get_session_user_id(&token_data.claims.sid).await,
+                Ok(Some(user_id)) if user_id == sub
+            );
+            if session_active {
+                req.extensions_mut().insert(Some(token_data.claims.clone()));
+                req.extensions_mut().insert(token_data.claims);
+                if let Ok(user) = state.db.get_user_from_id(sub).await {
+                    req.extensions_mut().insert(Some(user.clone()));
+                    req.extensions_mut().insert(user);
+                }
             }
         }
     }
@@ -159,8 +182,15 @@ pub async fn route_signin(
     Extension(user): Extension<MaybeUser>,
     Extension(page): Extension<PageMeta>,
     Query(params): Query<SignInParams>,
+    cookie_jar: CookieJar,
     extensions: Extensions,
 ) -> std::result::Result<impl IntoResponse, Response<Body>> {
+    if user.is_none() {
+        if let Some(pending) = pending_two_factor_user(&state, &cookie_jar).await {
+            return render_two_factor_signin(&state, &page, pending.sub, &extensions).await;
+        }
+    }
+
     let mut invite_token_cookie = Cookie::build(("rhombus-invite-token", ""))
         .path("/")
         .removal()
@@ -186,127 +216,27 @@ pub async fn route_signin(
             // need an error page, it should just count as an invalid invite token and not register.
             if !new_team.users.is_empty() {
                 if let Some(user) = &user {
-                    // you cannot join a team if your current team has more than just you on it
                     let old_team = state
                         .db
                         .get_team_from_id(user.team_id)
                         .await
                         .map_err_page(&extensions, "Failed to get current team id")?;
-                    if old_team.users.len() > 1 {
-                        let html = state
-                            .jinja
-                            .get_template("team/join-error-existing-team.html")
-                            .unwrap()
-                            .render(context! {
-                                global => state.global_page_meta,
-                                page,
-                                title => format!("Team Join Error | {}", state.global_page_meta.title),
-                                user,
-                                team => old_team,
-                            })
-                            .unwrap();
-                        return Err(Html(html).into_response());
-                    }
 
-                    // you cannot join a team if the new team if, as a result of you joining it would
-                    // lead to the team having more players than the minimum required by any division
-                    let max_players = &state
-                        .divisions
-                        .iter()
-                        .find(|division| division.id == new_team.division_id)
-                        .unwrap()
-                        .max_players;
-
-                    match max_players {
-                        MaxDivisionPlayers::Unlimited => {}
-                        MaxDivisionPlayers::Limited(max_players) => {
-                            if new_team.users.len() >= max_players.get() as usize {
-                                let html = state
-                                    .jinja
-                                    .get_template("team/join-error-max.html")
-                                    .unwrap()
-                                    .render(context! {
-                                        global => state.global_page_meta,
-                                        page,
-                                        title => format!("Team Join Error | {}", state.global_page_meta.title),
-                                        user,
-                                        team => new_team,
-                                        max_players,
-                                    })
-                                    .unwrap();
-                                return Err(Html(html).into_response());
-                            }
-                        }
+                    // you cannot join a team if your current team has more than just you on it, if
+                    // it would leave the team with more players than its division allows, or if
+                    // you are not eligible for its division
+                    if let Err(error) =
+                        check_join_team(&state.divisions, user.id, &old_team, &new_team).await
+                    {
+                        return Err(join_team_error_page(
+                            &state, &page, user, error, old_team, new_team,
+                        ));
                     }
 
-                    state
-                        .db
-                        .add_user_to_team(user.id, team_meta.id, Some(old_team.id))
+                    join_team(&state, user, &old_team, team_meta.id)
                         .await
                         .map_err_page(&extensions, "Failed to add user to team")?;
 
-                    if let (Some(bot), Some(user_discord_id)) =
-                        (state.bot.as_ref(), user.discord_id)
-                    {
-                        let old_division = state
-                            .divisions
-                            .iter()
-                            .find(|d| d.id == old_team.division_id)
-                            .unwrap();
-
-                        let new_division = state
-                            .divisions
-                            .iter()
-                            .find(|d| d.id == new_team.division_id)
-                            .unwrap();
-
-                        if old_division.discord_role_id != new_division.discord_role_id {
-                            if let Some(discord_role_id) = old_division.discord_role_id {
-                                bot.remove_role_from_users(&[user_discord_id], discord_role_id)
-                                    .await;
-                            }
-
-                            if let Some(discord_role_id) = new_division.discord_role_id {
-                                bot.give_role_to_users(&[user_discord_id], discord_role_id)
-                                    .await;
-                            }
-                        }
-
-                        if let Some(top10_role_id) = state
-                            .settings
-                            .read()
-                            .await
-                            .discord
-                            .as_ref()
-                            .and_then(|discord| discord.top10_role_id)
-                        {
-                            let old_team_standing = state.db.get_team_standing(old_team.id);
-                            let new_team_standing = state.db.get_team_standing(new_team.id);
-
-                            let (old_team_standing, new_team_standing) =
-                                tokio::try_join!(old_team_standing, new_team_standing)
-                                    .map_err_page(&extensions, "Failed to get team standings")?;
-
-                            let old_team_top_10 = old_team_standing
-                                .map(|standing| standing.rank <= 10)
-                                .unwrap_or(false);
-
-                            let new_team_top_10 = new_team_standing
-                                .map(|standing| standing.rank <= 10)
-                                .unwrap_or(false);
-
-                            if old_team_top_10 != new_team_top_10 {
-                                if new_team_top_10 {
-                                    bot.give_role_to_users(&[user_discord_id], top10_role_id)
-                                        .await;
-                                } else {
-                                    bot.remove_role_from_users(&[user_discord_id], top10_role_id)
-                                        .await;
-                                }
-                            }
-                        }
-                    }
-
                     return Ok(Redirect::to("/team").into_response());
                 }
 
@@ -319,7 +249,28 @@ pub async fn route_signin(
         }
     };
 
-    let auth_options = state.settings.read().await.auth.clone();
+    let (auth_options, oidc_display_name, ldap_display_name, registration, proof_of_work) = {
+        let settings = state.settings.read().await;
+        (
+            settings.auth.clone(),
+            settings
+                .oidc
+                .as_ref()
+                .and_then(|oidc| oidc.display_name.clone()),
+            settings
+                .ldap
+                .as_ref()
+                .and_then(|ldap| ldap.display_name.clone()),
+            settings
+                .credentials
+                .as_ref()
+                .and_then(|credentials| credentials.registration)
+                .unwrap_or(true)
+                || team_name.is_some(),
+            settings.proof_of_work.clone().unwrap_or_default(),
+        )
+    };
+    let require_email = credentials_require_email(&state).await;
 
     let html = state
         .jinja
@@ -331,7 +282,13 @@ pub async fn route_signin(
             title => format!("Sign In | {}", state.global_page_meta.title),
             user,
             auth_options,
+            oidc_display_name,
+            ldap_display_name,
+            password_reset => state.outbound_mailer.is_some(),
+            registration,
+            require_email,
             team_name,
+            proof_of_work,
         })
         .unwrap();
 
@@ -342,6 +299,187 @@ pub async fn route_signin(
         .into_response())
 }
 
+async fn render_two_factor_signin(
+    state: &RouterState,
+    page: &PageMeta,
+    user_id: i64,
+    extensions: &Extensions,
+) -> std::result::Result<Response<Body>, Response<Body>> {
+    let pending_user = state
+        .db
+        .get_user_from_id(user_id)
+        .await
+        .map_err_page(extensions, "Failed to get user")?;
+
+    let totp = state
+        .db
+        .get_user_totp(user_id)
+        .await
+        .map_err_page(extensions, "Failed to get two-factor authentication")?;
+
+    // users required to use two-factor authentication without having set it up enroll here
+    let enrollment = match totp {
+        Some(totp) if totp.enabled => None,
+        Some(totp) => Some(totp.secret),
+        None => {
+            let secret = totp::generate_secret();
+            state
+                .db
+                .set_user_totp_pending(user_id, &secret)
+                .await
+                .map_err_page(extensions, "Failed to set up two-factor authentication")?;
+            Some(secret)
+        }
+    }
+    .map(|secret| {
+        let qr_code = totp::qr_code_data_url(&totp::otpauth_url(
+            &state.global_page_meta.title,
+            &pending_user.name,
+            &secret,
+        ));
+        context! { secret, qr_code }
+    });
+
+    let html = state
+        .jinja
+        .get_template("account/two-factor-signin.html")
+        .unwrap()
+        .render(context! {
+            global => state.global_page_meta,
+            page,
+            title => format!("Sign In | {}", state.global_page_meta.title),
+            enrollment,
+        })
+        .unwrap();
+
+    Ok(Html(html).into_response())
+}
+
+#[derive(Deserialize)]
+pub struct TwoFactorSubmit {
+    code: String,
+}
+
+pub async fn route_signin_two_factor(
+    State(state): State<RouterState>,
+    Extension(lang): Extension<Languages>,
+    Extension(page): Extension<PageMeta>,
+    cookie_jar: CookieJar,
+    Form(form): Form<TwoFactorSubmit>,
+) -> impl IntoResponse {
+    let Some(pending) = pending_two_factor_user(&state, &cookie_jar).await else {
+        return ([("HX-Redirect", "/signin")],).into_response();
+    };
+    let user_id = pending.sub;
+
+    let totp = match state.db.get_user_totp(user_id).await {
+        Ok(Some(totp)) => totp,
+        Ok(None) => return ([("HX-Redirect", "/signin")],).into_response(),
+        Err(e) => {
+            tracing::error!(error = ?e, user_id, "Failed to get two-factor authentication");
+            return ([(
+                "HX-Trigger",
+                toast_header(ToastKind::Error, "Failed to get two-factor authentication"),
+            )],)
+                .into_response();
+        }
+    };
+
+    match totp::verify_user_code(&state.db, user_id, &totp, &form.code, true).await {
+        Ok(true) => {
+            TWO_FACTOR_FAILURES.remove(&user_id);
+        }
+        Ok(false) => {
+            if record_two_factor_failure(user_id, chrono::Utc::now().timestamp()) {
+                return error_toast(&state, &lang, "two-factor-error-invalid-code");
+            }
+
+            tracing::warn!(user_id, "Too many failed two-factor attempts");
+            let mut response = error_toast(&state, &lang, "two-factor-error-too-many-attempts");
+            response.headers_mut().append(
+                header::SET_COOKIE,
+                two_factor_removal_cookie().to_string().parse().unwrap(),
+            );
+            return response;
+        }
+        Err(e) => {
+            tracing::error!(error = ?e, user_id, "Failed to verify two-factor code");
+            return ([(
+                "HX-Trigger",
+                toast_header(ToastKind::Error, "Failed to verify two-factor code"),
+            )],)
+                .into_response();
+        }
+    }
+
+    let recovery_codes = if totp.enabled {
+        None
+    } else {
+        let recovery_codes = totp::generate_recovery_codes();
+        let hashes = recovery_codes
+            .iter()
+            .map(|code| totp::hash_recovery_code(code))
+            .collect::<Vec<_>>();
+        if let Err(e) = state.db.enable_user_totp(user_id, &hashes).await {
+            tracing::error!(error = ?e, user_id, "Failed to enable two-factor authentication");
+            return ([(
+                "HX-Trigger",
+                toast_header(
+                    ToastKind::Error,
+                    "Failed to enable two-factor authentication",
+                ),
+            )],)
+                .into_response();
+        }
+        Some(recovery_codes)
+    };
+
+    let cookie = match issue_sign_in_cookie(&state, user_id, &cookie_jar).await {
+        Ok(cookie) => cookie,
+        Err(e) => {
+            tracing::error!(error = ?e, user_id, "Failed to add user to team");
+            return ([(
+                "HX-Trigger",
+                toast_header(ToastKind::Error, "Failed to add user to team"),
+            )],)
+                .into_response();
+        }
+    };
+
+    let mut response = match recovery_codes {
+        Some(recovery_codes) => {
+            let html = state
+                .jinja
+                .get_template("account/two-factor-recovery-codes.html")
+                .unwrap()
+                .render(context! {
+                    global => state.global_page_meta,
+                    page,
+                    recovery_codes,
+                    continue_url => "/team",
+                })
+                .unwrap();
+            (
+                [
+                    ("HX-Retarget", "#two-factor-signin"),
+                    ("HX-Reswap", "outerHTML"),
+                ],
+                Html(html),
+            )
+                .into_response()
+        }
+        None => ([("HX-Redirect", "/team")],).into_response(),
+    };
+
+    let headers = response.headers_mut();
+    headers.append(header::SET_COOKIE, cookie.to_string().parse().unwrap());
+    headers.append(
+        header::SET_COOKIE,
+        two_factor_removal_cookie().to_string().parse().unwrap(),
+    );
+    response
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct DiscordOAuthStateClaims {
     state: String,
@@ -660,6 +798,22 @@ pub async fn route_signin_discord_callback(
         ));
     }
 
+    if let Some(bot) = state.bot.as_ref() {
+        if bot.team_roles_enabled().await {
+            let result = match state.db.get_team_from_id(team_id).await {
+                Ok(team) => bot.sync_team_discord(&team).await,
+                Err(error) => Err(error),
+            };
+            if let Err(error) = result {
+                tracing::error!(
+                    ?error,
+                    team_id,
+                    "Failed to sync team Discord role and channels"
+                );
+            }
+        }
+    }
+
     let unset_oauth_state_cookie = Cookie::build(("rhombus-oauth-discord", ""))
         .path("/")
         .removal()
@@ -716,18 +870,11 @@ struct CTFtimeTeamData {
 pub async fn route_signin_ctftime_callback(
     State(state): State<RouterState>,
     Extension(user): Extension<MaybeUser>,
+    Extension(page): Extension<PageMeta>,
     Query(params): Query<CTFtimeCallback>,
     cookie_jar: CookieJar,
     extensions: Extensions,
 ) -> std::result::Result<impl IntoResponse, Response<Body>> {
-    if user.is_some() {
-        return Err(error_page_code!(
-            &extensions,
-            StatusCode::BAD_REQUEST,
-            "You are already signed in, so CTFtime cannot be linked. Please sign out, and sign back in with CTFtime as a new account.",
-        ));
-    }
-
     let ctftime_oauth_cookie = cookie_jar
         .get("rhombus-oauth-ctftime")
         .map(|cookie| cookie.value())
@@ -826,6 +973,28 @@ pub async fn route_signin_ctftime_callback(
         .await
         .map_err_page(&extensions, "Failed to parse CTFtime user data")?;
 
+    // linking from the account page only attaches the CTFtime account, without
+    // changing teams
+    if let Some(signed_in) = &user {
+        let result = state
+            .db
+            .link_user_ctftime(signed_in.id, user_data.id)
+            .await
+            .map_err_page(&extensions, "Failed to link CTFtime account")?
+            .map(|_| (signed_in.id, signed_in.team_id));
+        return finish_identity_signin(
+            &state,
+            &user,
+            &page,
+            &cookie_jar,
+            &extensions,
+            "rhombus-oauth-ctftime",
+            "CTFtime",
+            result,
+        )
+        .await;
+    }
+
     let location_url = state.settings.read().await.location_url.clone();
     let (user_id, _team_id, invite_token) = state
         .db
@@ -929,119 +1098,1387 @@ pub async fn route_signin_ctftime(
     Ok(response)
 }
 
-#[derive(Deserialize)]
-pub struct EmailSubmit {
-    email: String,
+#[derive(Debug, Deserialize)]
+struct OidcDiscovery {
+    authorization_endpoint: String,
+    token_endpoint: String,
+    userinfo_endpoint: Option<String>,
 }
 
-pub async fn route_signin_email(
-    State(state): State<RouterState>,
-    Extension(user): Extension<MaybeUser>,
-    Extension(lang): Extension<Languages>,
-    Extension(ip): Extension<Option<IpAddr>>,
-    Form(form): Form<EmailSubmit>,
-) -> impl IntoResponse {
-    if form.email.is_empty() || form.email.len() > 255 {
-        return ([(
-            "HX-Trigger",
-            toast_header(
-                ToastKind::Error,
-                &state
-                    .localizer
-                    .localize(&lang, "account-error-email-length", None)
-                    .unwrap(),
-            ),
-        )],)
-            .into_response();
-    }
+async fn get_oidc_discovery(client: &Client, issuer: &str) -> crate::Result<OidcDiscovery> {
+    let discovery = client
+        .get(format!(
+            "{}/.well-known/openid-configuration",
+            issuer.trim_end_matches('/')
+        ))
+        .send()
+        .await?
+        .error_for_status()?
+        .json::<OidcDiscovery>()
+        .await?;
 
-    let outbound_mailer = state.outbound_mailer.as_ref().unwrap();
+    Ok(discovery)
+}
 
-    let code = match state
-        .db
-        .create_email_signin_callback_code(&form.email)
-        .await
-    {
-        Ok(code) => code,
-        Err(e) => {
-            let user_id = user.as_ref().map(|u| u.id);
-            tracing::error!(error = ?e, user_id, "Failed to create email signin callback code");
-            return ([(
-                "HX-Trigger",
-                toast_header(
-                    ToastKind::Error,
-                    &state
-                        .localizer
-                        .localize(&lang, "account-error-signin-email", None)
-                        .unwrap(),
-                ),
-            )],)
-                .into_response();
+#[derive(Debug, Serialize, Deserialize)]
+pub struct OidcOAuthStateClaims {
+    state: String,
+    /// PKCE code verifier, kept in the signed cookie so it never appears in a url
+    verifier: String,
+    iat: i64,
+    exp: i64,
+}
+
+pub async fn route_signin_oidc(
+    State(state): State<RouterState>,
+    extensions: Extensions,
+) -> std::result::Result<impl IntoResponse, Response<Body>> {
+    let (jwt_secret, location_url, oidc) = {
+        let settings = state.settings.read().await;
+
+        if let Some(ref oidc) = settings.oidc {
+            (
+                settings.jwt_secret.clone(),
+                settings.location_url.clone(),
+                oidc.clone(),
+            )
+        } else {
+            return Err(error_page_code!(
+                &extensions,
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "OpenID Connect is not configured",
+            ));
         }
     };
 
-    if let Err(e) = outbound_mailer
-        .send_email_signin(ip.map(|ip| ip.to_string()).as_deref(), &form.email, &code)
+    let discovery = get_oidc_discovery(&Client::new(), &oidc.issuer)
         .await
-    {
-        let user_id = user.as_ref().map(|u| u.id);
-        tracing::error!(error = ?e, user_id, "Failed to create email signin callback code");
-        return ([(
-            "HX-Trigger",
-            toast_header(
-                ToastKind::Error,
-                &state
-                    .localizer
-                    .localize(&lang, "account-error-signin-email", None)
-                    .unwrap(),
+        .map_err_page(
+            &extensions,
+            "Failed to get OpenID Connect discovery document",
+        )?;
+
+    let oauth_state = create_team_invite_token();
+    let verifier = Alphanumeric.sample_string(&mut thread_rng(), 64);
+    let challenge = URL_SAFE_NO_PAD.encode(ring::digest::digest(
+        &ring::digest::SHA256,
+        verifier.as_bytes(),
+    ));
+
+    let now = chrono::Utc::now();
+    let iat = now.timestamp();
+    let exp = (now + chrono::Duration::try_hours(1).unwrap()).timestamp();
+
+    let signed_oauth_state = encode(
+        &Header::default(),
+        &OidcOAuthStateClaims {
+            state: oauth_state.clone(),
+            verifier,
+            iat,
+            exp,
+        },
+        &EncodingKey::from_secret(jwt_secret.as_ref()),
+    )
+    .unwrap();
+
+    let scopes = oidc
+        .scopes
+        .map(|scopes| scopes.join(" "))
+        .unwrap_or_else(|| "openid profile email".to_owned());
+
+    let signin_url = reqwest::Url::parse_with_params(
+        &discovery.authorization_endpoint,
+        &[
+            ("response_type", "code"),
+            ("client_id", oidc.client_id.as_str()),
+            (
+                "redirect_uri",
+                format!("{}/signin/oidc/callback", location_url).as_str(),
             ),
-        )],)
-            .into_response();
-    }
+            ("scope", scopes.as_str()),
+            ("state", oauth_state.as_str()),
+            ("code_challenge", challenge.as_str()),
+            ("code_challenge_method", "S256"),
+        ],
+    )
+    .map_err_page(&extensions, "Invalid OpenID Connect authorization endpoint")?;
 
-    tracing::info!(email = form.email, "Sent email signin");
+    let cookie = Cookie::build(("rhombus-oauth-oidc", signed_oauth_state))
+        .path("/")
+        .same_site(SameSite::Lax)
+        .http_only(true)
+        .build();
 
-    ([(
-        "HX-Trigger",
-        toast_header(
-            ToastKind::Success,
-            &state
-                .localizer
-                .localize(&lang, "account-check-email", None)
-                .unwrap(),
-        ),
-    )],)
-        .into_response()
+    let mut response = Redirect::temporary(signin_url.as_str()).into_response();
+    let headers = response.headers_mut();
+    headers.insert(header::SET_COOKIE, cookie.to_string().parse().unwrap());
+    Ok(response)
 }
 
-#[derive(Deserialize)]
-pub struct CredentialsSubmit {
+#[derive(Debug, Deserialize)]
+pub struct OAuthCallback {
+    code: Option<String>,
+    state: Option<String>,
+    error: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct OidcOAuthToken {
+    access_token: String,
+}
+
+/// Look up a string claim from the userinfo response
+fn oidc_claim<'a>(claims: &'a serde_json::Value, claim: &str) -> Option<&'a str> {
+    claims
+        .get(claim)
+        .and_then(|value| value.as_str())
+        .filter(|value| !value.is_empty())
+}
+
+pub async fn route_signin_oidc_callback(
+    State(state): State<RouterState>,
+    Extension(user): Extension<MaybeUser>,
+    Extension(page): Extension<PageMeta>,
+    Query(params): Query<OAuthCallback>,
+    cookie_jar: CookieJar,
+    extensions: Extensions,
+) -> std::result::Result<impl IntoResponse, Response<Body>> {
+    let oidc_oauth_cookie = cookie_jar
+        .get("rhombus-oauth-oidc")
+        .map(|cookie| cookie.value())
+        .map_err_page_code(&extensions, StatusCode::BAD_REQUEST, "State not set")?;
+
+    if let Some(error) = &params.error {
+        return Err(error_page_code!(
+            &extensions,
+            StatusCode::BAD_REQUEST,
+            "Identity provider returned an error in a callback",
+            error,
+        ));
+    }
+
+    let code = params.code.map_err_page_code(
+        &extensions,
+        StatusCode::BAD_REQUEST,
+        "Identity provider did not return a code",
+    )?;
+
+    let (oidc, location_url, jwt_secret) = {
+        let settings = state.settings.read().await;
+        (
+            settings.oidc.clone(),
+            settings.location_url.clone(),
+            settings.jwt_secret.clone(),
+        )
+    };
+
+    let claims = decode::<OidcOAuthStateClaims>(
+        oidc_oauth_cookie,
+        &DecodingKey::from_secret(jwt_secret.as_ref()),
+        &Validation::default(),
+    )
+    .map_err_page_code(&extensions, StatusCode::BAD_REQUEST, "Invalid state")?
+    .claims;
+
+    if params.state.as_ref() != Some(&claims.state) {
+        return Err(error_page_code!(
+            &extensions,
+            StatusCode::BAD_REQUEST,
+            "OAuth state mismatch",
+        ));
+    }
+
+    let oidc = oidc.map_err_page(&extensions, "OpenID Connect is not configured")?;
+
+    let client = Client::new();
+    let discovery = get_oidc_discovery(&client, &oidc.issuer)
+        .await
+        .map_err_page(
+            &extensions,
+            "Failed to get OpenID Connect discovery document",
+        )?;
+
+    let redirect_uri = format!("{}/signin/oidc/callback", location_url);
+    let mut request = client.post(&discovery.token_endpoint).form(&[
+        ("grant_type", "authorization_code"),
+        ("code", code.as_str()),
+        ("redirect_uri", redirect_uri.as_str()),
+        ("client_id", oidc.client_id.as_str()),
+        ("code_verifier", claims.verifier.as_str()),
+    ]);
+    if let Some(client_secret) = &oidc.client_secret {
+        request = request.basic_auth(&oidc.client_id, Some(client_secret));
+    }
+
+    let res = request
+        .send()
+        .await
+        .map_err_page(&extensions, "Failed to get OpenID Connect token")?;
+
+    if !res.status().is_success() {
+        let res = format!("{:?}", res.text().await);
+        return Err(error_page_code!(
+            &extensions,
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Identity provider returned an error getting token",
+            res,
+        ));
+    }
+
+    let oauth_token = res
+        .json::<OidcOAuthToken>()
+        .await
+        .map_err_page(&extensions, "Failed to parse OpenID Connect token")?;
+
+    let userinfo_endpoint = discovery.userinfo_endpoint.map_err_page(
+        &extensions,
+        "Identity provider does not have a userinfo endpoint",
+    )?;
+
+    let res = client
+        .get(&userinfo_endpoint)
+        .bearer_auth(&oauth_token.access_token)
+        .send()
+        .await
+        .map_err_page(&extensions, "Failed to get OpenID Connect user info")?;
+
+    if !res.status().is_success() {
+        let res = format!("{:?}", res.text().await);
+        return Err(error_page_code!(
+            &extensions,
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Identity provider returned an error getting user info",
+            res,
+        ));
+    }
+
+    let user_info = res
+        .json::<serde_json::Value>()
+        .await
+        .map_err_page(&extensions, "Failed to parse OpenID Connect user info")?;
+
+    let subject = oidc_claim(&user_info, "sub")
+        .map_err_page(&extensions, "Identity provider did not return a subject")?;
+
+    // only emails the identity provider has verified are used to link accounts
+    let email = oidc_claim(&user_info, oidc.email_claim.as_deref().unwrap_or("email"))
+        .filter(|_| user_info.get("email_verified").and_then(|v| v.as_bool()) == Some(true));
+
+    let name = match &oidc.name_claim {
+        Some(name_claim) => oidc_claim(&user_info, name_claim),
+        None => {
+            oidc_claim(&user_info, "preferred_username").or_else(|| oidc_claim(&user_info, "name"))
+        }
+    }
+    .or_else(|| email.and_then(|email| email.split('@').next()))
+    .unwrap_or(subject);
+
+    let avatar = oidc_claim(
+        &user_info,
+        oidc.avatar_claim.as_deref().unwrap_or("picture"),
+    )
+    .map(|avatar| avatar.to_owned())
+    .unwrap_or_else(|| avatar_from_email(email.unwrap_or(subject)));
+
+    let upsert_result = state
+        .db
+        .upsert_user_by_oidc(
+            name,
+            email,
+            &avatar,
+            subject,
+            user.as_ref().map(|u| u.id),
+            &location_url,
+        )
+        .await
+        .map_err_page(&extensions, "Failed to upsert user by OpenID Connect")?;
+
+    finish_identity_signin(
+        &state,
+        &user,
+        &page,
+        &cookie_jar,
+        &extensions,
+        "rhombus-oauth-oidc",
+        oidc.display_name.as_deref().unwrap_or("Single Sign-On"),
+        upsert_result,
+    )
+    .await
+}
+
+/// Sign in (or finish linking, if `user` is already signed in) after an identity provider
+/// callback, and clear the OAuth state cookie
+#[allow(clippy::too_many_arguments)]
+async fn finish_identity_signin(
+    state: &RouterState,
+    user: &MaybeUser,
+    page: &PageMeta,
+    cookie_jar: &CookieJar,
+    extensions: &Extensions,
+    oauth_cookie_name: &'static str,
+    provider_name: &str,
+    upsert_result: std::result::Result<(i64, i64), IdentityUpsertError>,
+) -> std::result::Result<Response<Body>, Response<Body>> {
+    let (user_id, _team_id) = match upsert_result {
+        Ok(r) => r,
+        Err(IdentityUpsertError::AlreadyInUse) => {
+            let html = state
+                .jinja
+                .get_template("account/identity-taken-error.html")
+                .unwrap()
+                .render(context! {
+                    global => state.global_page_meta,
+                    page,
+                    title => format!("Sign In Error | {}", state.global_page_meta.title),
+                    user,
+                    provider_name,
+                })
+                .unwrap();
+            return Err(Html(html).into_response());
+        }
+    };
+
+    let unset_oauth_state_cookie = Cookie::build((oauth_cookie_name, ""))
+        .path("/")
+        .removal()
+        .same_site(SameSite::Lax)
+        .http_only(true);
+
+    // linking from the account page keeps the current session
+    if user.is_some() {
+        let mut response = Redirect::temporary("/account").into_response();
+        response.headers_mut().insert(
+            header::SET_COOKIE,
+            unset_oauth_state_cookie.to_string().parse().unwrap(),
+        );
+        return Ok(response);
+    }
+
+    let cookie = sign_in_cookie(state, user_id, cookie_jar)
+        .await
+        .map_err_page(extensions, "Failed to add user to team")?;
+    let mut response = Redirect::temporary("/team").into_response();
+    let headers = response.headers_mut();
+    headers.insert(header::SET_COOKIE, cookie.to_string().parse().unwrap());
+    headers.append(
+        header::SET_COOKIE,
+        unset_oauth_state_cookie.to_string().parse().unwrap(),
+    );
+    Ok(response)
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct OAuthStateClaims {
+    state: String,
+    iat: i64,
+    exp: i64,
+}
+
+/// Redirect to the authorization page built by `authorize_url` from a signed state,
+/// which is also remembered in the `oauth_cookie_name` cookie
+fn oauth_authorize_redirect<E: std::error::Error + 'static>(
+    jwt_secret: &str,
+    oauth_cookie_name: &'static str,
+    authorize_url: impl FnOnce(&str) -> std::result::Result<reqwest::Url, E>,
+    extensions: &Extensions,
+) -> std::result::Result<Response<Body>, Response<Body>> {
+    let now = chrono::Utc::now();
+    let iat = now.timestamp();
+    let exp = (now + chrono::Duration::try_hours(1).unwrap()).timestamp();
+
+    let signed_oauth_state = encode(
+        &Header::default(),
+        &OAuthStateClaims {
+            state: create_team_invite_token(),
+            iat,
+            exp,
+        },
+        &EncodingKey::from_secret(jwt_secret.as_ref()),
+    )
+    .unwrap();
+
+    let signin_url = authorize_url(&signed_oauth_state)
+        .map_err_page(extensions, "Invalid OAuth authorization url")?;
+
+    let cookie = Cookie::build((oauth_cookie_name, signed_oauth_state))
+        .path("/")
+        .same_site(SameSite::Lax)
+        .http_only(true)
+        .build();
+
+    let mut response = Redirect::temporary(signin_url.as_str()).into_response();
+    let headers = response.headers_mut();
+    headers.insert(header::SET_COOKIE, cookie.to_string().parse().unwrap());
+    Ok(response)
+}
+
+/// Check the state returned to an OAuth callback against the signed state cookie, and
+/// return the authorization code
+fn verify_oauth_callback(
+    params: OAuthCallback,
+    cookie_jar: &CookieJar,
+    oauth_cookie_name: &str,
+    jwt_secret: &str,
+    extensions: &Extensions,
+) -> std::result::Result<String, Response<Body>> {
+    let oauth_cookie = cookie_jar
+        .get(oauth_cookie_name)
+        .map(|cookie| cookie.value())
+        .map_err_page_code(extensions, StatusCode::BAD_REQUEST, "State not set")?;
+
+    if let Some(error) = &params.error {
+        return Err(error_page_code!(
+            extensions,
+            StatusCode::BAD_REQUEST,
+            "OAuth provider returned an error in a callback",
+            error,
+        ));
+    }
+
+    if params.state.as_deref() != Some(oauth_cookie) {
+        return Err(error_page_code!(
+            extensions,
+            StatusCode::BAD_REQUEST,
+            "OAuth state mismatch",
+        ));
+    }
+
+    decode::<OAuthStateClaims>(
+        oauth_cookie,
+        &DecodingKey::from_secret(jwt_secret.as_ref()),
+        &Validation::default(),
+    )
+    .map_err_page_code(extensions, StatusCode::BAD_REQUEST, "Invalid state")?;
+
+    params.code.map_err_page_code(
+        extensions,
+        StatusCode::BAD_REQUEST,
+        "OAuth provider did not return a code",
+    )
+}
+
+pub async fn route_signin_github(
+    State(state): State<RouterState>,
+    extensions: Extensions,
+) -> std::result::Result<impl IntoResponse, Response<Body>> {
+    let (jwt_secret, location_url, client_id) = {
+        let settings = state.settings.read().await;
+
+        if let Some(ref github) = settings.github {
+            (
+                settings.jwt_secret.clone(),
+                settings.location_url.clone(),
+                github.client_id.clone(),
+            )
+        } else {
+            return Err(error_page_code!(
+                &extensions,
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "GitHub is not configured",
+            ));
+        }
+    };
+
+    oauth_authorize_redirect(
+        &jwt_secret,
+        "rhombus-oauth-github",
+        |oauth_state| {
+            reqwest::Url::parse_with_params(
+                "https://github.com/login/oauth/authorize",
+                &[
+                    ("client_id", client_id.as_str()),
+                    (
+                        "redirect_uri",
+                        format!("{}/signin/github/callback", location_url).as_str(),
+                    ),
+                    ("scope", "read:user user:email"),
+                    ("state", oauth_state),
+                ],
+            )
+        },
+        &extensions,
+    )
+}
+
+#[derive(Debug, Deserialize)]
+struct GitHubOAuthToken {
+    access_token: Option<String>,
+    error_description: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct GitHubProfile {
+    id: i64,
+    login: String,
+    name: Option<String>,
+    avatar_url: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct GitHubEmail {
+    email: String,
+    primary: bool,
+    verified: bool,
+}
+
+pub async fn route_signin_github_callback(
+    State(state): State<RouterState>,
+    Extension(user): Extension<MaybeUser>,
+    Extension(page): Extension<PageMeta>,
+    Query(params): Query<OAuthCallback>,
+    cookie_jar: CookieJar,
+    extensions: Extensions,
+) -> std::result::Result<impl IntoResponse, Response<Body>> {
+    let (github, location_url, jwt_secret) = {
+        let settings = state.settings.read().await;
+        (
+            settings.github.clone(),
+            settings.location_url.clone(),
+            settings.jwt_secret.clone(),
+        )
+    };
+
+    let code = verify_oauth_callback(
+        params,
+        &cookie_jar,
+        "rhombus-oauth-github",
+        &jwt_secret,
+        &extensions,
+    )?;
+
+    let github = github.map_err_page(&extensions, "GitHub is not configured")?;
+    let client_secret = github
+        .client_secret
+        .map_err_page(&extensions, "GitHub client secret is not configured")?;
+
+    let client = Client::new();
+    let oauth_token = client
+        .post("https://github.com/login/oauth/access_token")
+        .header(reqwest::header::ACCEPT, "application/json")
+        .form(&[
+            ("client_id", github.client_id.as_str()),
+            ("client_secret", client_secret.as_str()),
+            ("code", code.as_str()),
+            (
+                "redirect_uri",
+                format!("{}/signin/github/callback", location_url).as_str(),
+            ),
+        ])
+        .send()
+        .await
+        .map_err_page(&extensions, "Failed to get GitHub oauth token")?
+        .json::<GitHubOAuthToken>()
+        .await
+        .map_err_page(&extensions, "Failed to parse GitHub oauth token")?;
+
+    // GitHub responds with a successful status code even when the code is invalid
+    let Some(access_token) = oauth_token.access_token else {
+        let error = oauth_token.error_description.unwrap_or_default();
+        return Err(error_page_code!(
+            &extensions,
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "GitHub returned an error getting oauth token",
+            error,
+        ));
+    };
+
+    // the GitHub api rejects requests without a user agent
+    let profile = client
+        .get("https://api.github.com/user")
+        .bearer_auth(&access_token)
+        .header(reqwest::header::USER_AGENT, "rhombus")
+        .send()
+        .await
+        .and_then(|res| res.error_for_status())
+        .map_err_page(&extensions, "Failed to get GitHub profile")?
+        .json::<GitHubProfile>()
+        .await
+        .map_err_page(&extensions, "Failed to parse GitHub profile")?;
+
+    let emails = client
+        .get("https://api.github.com/user/emails")
+        .bearer_auth(&access_token)
+        .header(reqwest::header::USER_AGENT, "rhombus")
+        .send()
+        .await
+        .and_then(|res| res.error_for_status())
+        .map_err_page(&extensions, "Failed to get GitHub emails")?
+        .json::<Vec<GitHubEmail>>()
+        .await
+        .map_err_page(&extensions, "Failed to parse GitHub emails")?;
+
+    let email = emails
+        .iter()
+        .find(|email| email.primary && email.verified)
+        .map(|email| email.email.as_str());
+
+    let upsert_result = state
+        .db
+        .upsert_user_by_github(
+            profile.name.as_deref().unwrap_or(&profile.login),
+            email,
+            &profile.avatar_url,
+            profile.id,
+            user.as_ref().map(|u| u.id),
+            &location_url,
+        )
+        .await
+        .map_err_page(&extensions, "Failed to upsert user by GitHub")?;
+
+    finish_identity_signin(
+        &state,
+        &user,
+        &page,
+        &cookie_jar,
+        &extensions,
+        "rhombus-oauth-github",
+        "GitHub",
+        upsert_result,
+    )
+    .await
+}
+
+pub async fn route_signin_google(
+    State(state): State<RouterState>,
+    extensions: Extensions,
+) -> std::result::Result<impl IntoResponse, Response<Body>> {
+    let (jwt_secret, location_url, client_id) = {
+        let settings = state.settings.read().await;
+
+        if let Some(ref google) = settings.google {
+            (
+                settings.jwt_secret.clone(),
+                settings.location_url.clone(),
+                google.client_id.clone(),
+            )
+        } else {
+            return Err(error_page_code!(
+                &extensions,
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Google is not configured",
+            ));
+        }
+    };
+
+    oauth_authorize_redirect(
+        &jwt_secret,
+        "rhombus-oauth-google",
+        |oauth_state| {
+            reqwest::Url::parse_with_params(
+                "https://accounts.google.com/o/oauth2/v2/auth",
+                &[
+                    ("response_type", "code"),
+                    ("client_id", client_id.as_str()),
+                    (
+                        "redirect_uri",
+                        format!("{}/signin/google/callback", location_url).as_str(),
+                    ),
+                    ("scope", "openid email profile"),
+                    ("state", oauth_state),
+                ],
+            )
+        },
+        &extensions,
+    )
+}
+
+#[derive(Debug, Deserialize)]
+struct GoogleOAuthToken {
+    access_token: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct GoogleProfile {
+    sub: String,
+    name: Option<String>,
+    email: Option<String>,
+    email_verified: Option<bool>,
+    picture: Option<String>,
+}
+
+pub async fn route_signin_google_callback(
+    State(state): State<RouterState>,
+    Extension(user): Extension<MaybeUser>,
+    Extension(page): Extension<PageMeta>,
+    Query(params): Query<OAuthCallback>,
+    cookie_jar: CookieJar,
+    extensions: Extensions,
+) -> std::result::Result<impl IntoResponse, Response<Body>> {
+    let (google, location_url, jwt_secret) = {
+        let settings = state.settings.read().await;
+        (
+            settings.google.clone(),
+            settings.location_url.clone(),
+            settings.jwt_secret.clone(),
+        )
+    };
+
+    let code = verify_oauth_callback(
+        params,
+        &cookie_jar,
+        "rhombus-oauth-google",
+        &jwt_secret,
+        &extensions,
+    )?;
+
+    let google = google.map_err_page(&extensions, "Google is not configured")?;
+    let client_secret = google
+        .client_secret
+        .map_err_page(&extensions, "Google client secret is not configured")?;
+
+    let client = Client::new();
+    let res = client
+        .post("https://oauth2.googleapis.com/token")
+        .form(&[
+            ("grant_type", "authorization_code"),
+            ("code", code.as_str()),
+            ("client_id", google.client_id.as_str()),
+            ("client_secret", client_secret.as_str()),
+            (
+                "redirect_uri",
+                format!("{}/signin/google/callback", location_url).as_str(),
+            ),
+        ])
+        .send()
+        .await
+        .map_err_page(&extensions, "Failed to get Google oauth token")?;
+
+    if !res.status().is_success() {
+        let res = format!("{:?}", res.text().await);
+        return Err(error_page_code!(
+            &extensions,
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Google returned an error getting oauth token",
+            res,
+        ));
+    }
+
+    let oauth_token = res
+        .json::<GoogleOAuthToken>()
+        .await
+        .map_err_page(&extensions, "Failed to parse Google oauth token")?;
+
+    let profile = client
+        .get("https://openidconnect.googleapis.com/v1/userinfo")
+        .bearer_auth(&oauth_token.access_token)
+        .send()
+        .await
+        .and_then(|res| res.error_for_status())
+        .map_err_page(&extensions, "Failed to get Google profile")?
+        .json::<GoogleProfile>()
+        .await
+        .map_err_page(&extensions, "Failed to parse Google profile")?;
+
+    let email = profile
+        .email
+        .as_deref()
+        .filter(|_| profile.email_verified.unwrap_or(false));
+
+    let name = profile
+        .name
+        .as_deref()
+        .or_else(|| email.and_then(|email| email.split('@').next()))
+        .unwrap_or("Google user");
+
+    let avatar = profile
+        .picture
+        .clone()
+        .unwrap_or_else(|| avatar_from_email(email.unwrap_or(&profile.sub)));
+
+    let upsert_result = state
+        .db
+        .upsert_user_by_google(
+            name,
+            email,
+            &avatar,
+            &profile.sub,
+            user.as_ref().map(|u| u.id),
+            &location_url,
+        )
+        .await
+        .map_err_page(&extensions, "Failed to upsert user by Google")?;
+
+    finish_identity_signin(
+        &state,
+        &user,
+        &page,
+        &cookie_jar,
+        &extensions,
+        "rhombus-oauth-google",
+        "Google",
+        upsert_result,
+    )
+    .await
+}
+
+#[derive(Deserialize)]
+pub struct EmailSubmit {
+    email: String,
+    #[serde(flatten)]
+    proof_of_work: ProofOfWorkSolution,
+}
+
+pub async fn route_signin_email(
+    State(state): State<RouterState>,
+    Extension(user): Extension<MaybeUser>,
+    Extension(lang): Extension<Languages>,
+    Extension(ip): Extension<Option<IpAddr>>,
+    Form(form): Form<EmailSubmit>,
+) -> impl IntoResponse {
+    if !verify_proof_of_work(&state, ProofOfWorkRoute::Email, &form.proof_of_work).await {
+        return error_toast(&state, &lang, "proof-of-work-error");
+    }
+
+    if form.email.is_empty() || form.email.len() > 255 {
+        return ([(
+            "HX-Trigger",
+            toast_header(
+                ToastKind::Error,
+                &state
+                    .localizer
+                    .localize(&lang, "account-error-email-length", None)
+                    .unwrap(),
+            ),
+        )],)
+            .into_response();
+    }
+
+    let outbound_mailer = state.outbound_mailer.as_ref().unwrap();
+
+    let code = match state
+        .db
+        .create_email_signin_callback_code(&form.email)
+        .await
+    {
+        Ok(code) => code,
+        Err(e) => {
+            let user_id = user.as_ref().map(|u| u.id);
+            tracing::error!(error = ?e, user_id, "Failed to create email signin callback code");
+            return ([(
+                "HX-Trigger",
+                toast_header(
+                    ToastKind::Error,
+                    &state
+                        .localizer
+                        .localize(&lang, "account-error-signin-email", None)
+                        .unwrap(),
+                ),
+            )],)
+                .into_response();
+        }
+    };
+
+    if let Err(e) = outbound_mailer
+        .send_email_signin(ip.map(|ip| ip.to_string()).as_deref(), &form.email, &code)
+        .await
+    {
+        let user_id = user.as_ref().map(|u| u.id);
+        tracing::error!(error = ?e, user_id, "Failed to create email signin callback code");
+        return ([(
+            "HX-Trigger",
+            toast_header(
+                ToastKind::Error,
+                &state
+                    .localizer
+                    .localize(&lang, "account-error-signin-email", None)
+                    .unwrap(),
+            ),
+        )],)
+            .into_response();
+    }
+
+    tracing::info!(email = form.email, "Sent email signin");
+
+    ([(
+        "HX-Trigger",
+        toast_header(
+            ToastKind::Success,
+            &state
+                .localizer
+                .localize(&lang, "account-check-email", None)
+                .unwrap(),
+        ),
+    )],)
+        .into_response()
+}
+
+fn error_toast(state: &RouterState, lang: &Languages, key: &str) -> Response<Body> {
+    ([(
+        "HX-Trigger",
+        toast_header(
+            ToastKind::Error,
+            &state.localizer.localize(lang, key, None).unwrap(),
+        ),
+    )],)
+        .into_response()
+}
+
+/// Whether credentials accounts must confirm an email before signing in
+async fn credentials_require_email(state: &RouterState) -> bool {
+    state.outbound_mailer.is_some()
+        && state
+            .settings
+            .read()
+            .await
+            .credentials
+            .as_ref()
+            .and_then(|credentials| credentials.require_email)
+            .unwrap_or(false)
+}
+
+#[derive(Deserialize)]
+pub struct CredentialsSubmit {
+    username: String,
+    password: String,
+    #[serde(flatten)]
+    proof_of_work: ProofOfWorkSolution,
+}
+
+pub async fn route_signin_credentials(
+    State(state): State<RouterState>,
+    Extension(user): Extension<MaybeUser>,
+    Extension(lang): Extension<Languages>,
+    cookie_jar: CookieJar,
+    Form(form): Form<CredentialsSubmit>,
+) -> impl IntoResponse {
+    if !verify_proof_of_work(&state, ProofOfWorkRoute::Credentials, &form.proof_of_work).await {
+        return error_toast(&state, &lang, "proof-of-work-error");
+    }
+
+    if form.username.len() > 256 || form.password.len() > 256 {
+        return error_toast(&state, &lang, "account-error-invalid-credentials");
+    }
+
+    let maybe_user = match state
+        .db
+        .login_user_by_credentials(&form.username, &form.password)
+        .await
+    {
+        Ok(user) => user,
+        Err(e) => {
+            let user_id = user.as_ref().map(|u| u.id);
+            tracing::error!(error = ?e, user_id, "Failed to sign in user by credentials");
+            return ([(
+                "HX-Trigger",
+                toast_header(ToastKind::Error, "Failed to sign in user by credentials"),
+            )],)
+                .into_response();
+        }
+    };
+
+    let Some((user_id, _team_id)) = maybe_user else {
+        return error_toast(&state, &lang, "account-error-invalid-credentials");
+    };
+
+    if credentials_require_email(&state).await {
+        let confirmed = match state.db.get_emails_for_user_id(user_id).await {
+            Ok(emails) => emails.iter().any(|email| email.verified),
+            Err(e) => {
+                tracing::error!(error = ?e, user_id, "Failed to get emails for user");
+                false
+            }
+        };
+        if !confirmed {
+            return error_toast(&state, &lang, "account-error-email-unconfirmed");
+        }
+    }
+
+    let cookie = match sign_in_cookie(&state, user_id, &cookie_jar).await {
+        Ok(cookie) => cookie,
+        Err(e) => {
+            let user_id = user.as_ref().map(|u| u.id);
+            tracing::error!(error = ?e, user_id, "Failed to add user to team");
+            return ([(
+                "HX-Trigger",
+                toast_header(ToastKind::Error, "Failed to add user to team"),
+            )],)
+                .into_response();
+        }
+    };
+
+    ([
+        ("HX-Redirect", "/team"),
+        ("Set-Cookie", cookie.to_string().as_str()),
+    ],)
+        .into_response()
+}
+
+#[derive(Deserialize)]
+pub struct RegisterSubmit {
     username: String,
     password: String,
+    email: Option<String>,
+    #[serde(flatten)]
+    proof_of_work: ProofOfWorkSolution,
 }
 
-pub async fn route_signin_credentials(
+pub async fn route_signin_register(
     State(state): State<RouterState>,
-    Extension(user): Extension<MaybeUser>,
     Extension(lang): Extension<Languages>,
+    Extension(ip): Extension<Option<IpAddr>>,
     cookie_jar: CookieJar,
-    Form(form): Form<CredentialsSubmit>,
+    Form(form): Form<RegisterSubmit>,
 ) -> impl IntoResponse {
+    let (credentials_settings, location_url) = {
+        let settings = state.settings.read().await;
+        if !settings.auth.contains(&AuthProvider::Credentials) {
+            return StatusCode::NOT_FOUND.into_response();
+        }
+        (
+            settings.credentials.clone().unwrap_or_default(),
+            settings.location_url.clone(),
+        )
+    };
+
+    // invite-only events still let players register through a team invite link
+    if !credentials_settings.registration.unwrap_or(true) {
+        let invited = match cookie_jar.get("rhombus-invite-token") {
+            Some(invite_token) => matches!(
+                state
+                    .db
+                    .get_team_meta_from_invite_token(invite_token.value())
+                    .await,
+                Ok(Some(_))
+            ),
+            None => false,
+        };
+        if !invited {
+            return error_toast(&state, &lang, "account-error-registration-closed");
+        }
+    }
+
+    if !verify_proof_of_work(&state, ProofOfWorkRoute::Register, &form.proof_of_work).await {
+        return error_toast(&state, &lang, "proof-of-work-error");
+    }
+
+    let min_length = credentials_settings.username_min_length.unwrap_or(3);
+    let max_length = credentials_settings.username_max_length.unwrap_or(30);
     let username_graphemes = form.username.graphemes(true).count();
-    if !(3..=30).contains(&username_graphemes) || !(0..=256).contains(&form.username.len()) {
+    if !(min_length..=max_length).contains(&username_graphemes) || form.username.len() > 256 {
+        let mut args = HashMap::new();
+        args.insert("min", min_length.into());
+        args.insert("max", max_length.into());
+        return ([(
+            "HX-Trigger",
+            toast_header(
+                ToastKind::Error,
+                &state
+                    .localizer
+                    .localize(&lang, "account-error-username-length", Some(&args))
+                    .unwrap(),
+            ),
+        )],)
+            .into_response();
+    }
+
+    if let Some(username_regex) = &credentials_settings.username_regex {
+        let matches = match Regex::new(username_regex) {
+            Ok(regex) => regex.is_match(&form.username).unwrap_or(false),
+            Err(e) => {
+                tracing::error!(error = ?e, username_regex, "Invalid username regex");
+                false
+            }
+        };
+        if !matches {
+            return error_toast(&state, &lang, "account-error-username-invalid");
+        }
+    }
+
+    let password_graphemes = form.password.graphemes(true).count();
+    if !(8..=256).contains(&password_graphemes) || !(0..=256).contains(&form.password.len()) {
+        return error_toast(&state, &lang, "account-error-password-length");
+    }
+
+    let email = if credentials_require_email(&state).await {
+        match form.email.as_deref() {
+            Some(email) if !email.is_empty() && email.len() <= 255 => Some(email),
+            _ => return error_toast(&state, &lang, "account-error-email-length"),
+        }
+    } else {
+        None
+    };
+
+    let avatar = avatar_from_email(&form.username.trim().to_lowercase());
+
+    let (user_id, _team_id, code) = match state
+        .db
+        .register_user_by_credentials(
+            &form.username,
+            &avatar,
+            &form.password,
+            email,
+            &location_url,
+        )
+        .await
+    {
+        Ok(Ok(result)) => result,
+        Ok(Err(RegisterError::NameTaken)) => {
+            return error_toast(&state, &lang, "account-error-name-taken");
+        }
+        Ok(Err(RegisterError::EmailInUse)) => {
+            return error_toast(&state, &lang, "account-error-email-in-use");
+        }
+        Err(e) => {
+            tracing::error!(error = ?e, "Failed to register user by credentials");
+            return ([(
+                "HX-Trigger",
+                toast_header(ToastKind::Error, "Failed to register user"),
+            )],)
+                .into_response();
+        }
+    };
+
+    if let (Some(email), Some(code)) = (email, code) {
+        if let Err(e) = state
+            .outbound_mailer
+            .as_ref()
+            .unwrap()
+            .send_registration_confirmation(
+                &form.username,
+                ip.map(|ip| ip.to_string()).as_deref(),
+                email,
+                &code,
+            )
+            .await
+        {
+            tracing::error!(error = ?e, user_id, "Failed to send registration confirmation email");
+            return error_toast(&state, &lang, "account-error-verification-email");
+        }
+
+        return ([(
+            "HX-Trigger",
+            toast_header(
+                ToastKind::Success,
+                &state
+                    .localizer
+                    .localize(&lang, "account-check-email-registration", None)
+                    .unwrap(),
+            ),
+        )],)
+            .into_response();
+    }
+
+    let cookie = match sign_in_cookie(&state, user_id, &cookie_jar).await {
+        Ok(cookie) => cookie,
+        Err(e) => {
+            tracing::error!(error = ?e, user_id, "Failed to add user to team");
+            return ([(
+                "HX-Trigger",
+                toast_header(ToastKind::Error, "Failed to add user to team"),
+            )],)
+                .into_response();
+        }
+    };
+
+    ([
+        ("HX-Redirect", "/team"),
+        ("Set-Cookie", cookie.to_string().as_str()),
+    ],)
+        .into_response()
+}
+
+#[derive(Deserialize)]
+pub struct RegisterConfirmParams {
+    code: String,
+}
+
+pub async fn route_signin_register_confirm(
+    State(state): State<RouterState>,
+    Extension(user): Extension<MaybeUser>,
+    Extension(page): Extension<PageMeta>,
+    Query(params): Query<RegisterConfirmParams>,
+    cookie_jar: CookieJar,
+    extensions: Extensions,
+) -> std::result::Result<impl IntoResponse, Response<Body>> {
+    let user_id = match state
+        .db
+        .verify_email_verification_callback_code(&params.code)
+        .await
+    {
+        Err(RhombusError::DatabaseReturnedNoRows) => {
+            return Err(error_page(
+                StatusCode::NOT_FOUND,
+                "Invalid email confirmation code",
+                &state,
+                &user,
+                &page,
+            ));
+        }
+        result => result.map_err_page(&extensions, "Failed to confirm email")?,
+    };
+
+    let cookie = sign_in_cookie(&state, user_id, &cookie_jar)
+        .await
+        .map_err_page(&extensions, "Failed to add user to team")?;
+    let mut response = Redirect::temporary("/team").into_response();
+    response
+        .headers_mut()
+        .insert(header::SET_COOKIE, cookie.to_string().parse().unwrap());
+    Ok(response)
+}
+
+/// Password reset is offered when credentials are enabled and emails can be sent
+async fn password_reset_enabled(state: &RouterState) -> bool {
+    state.outbound_mailer.is_some()
+        && state
+            .settings
+            .read()
+            .await
+            .auth
+            .contains(&AuthProvider::Credentials)
+}
+
+pub async fn route_signin_reset(
+    State(state): State<RouterState>,
+    Extension(user): Extension<MaybeUser>,
+    Extension(page): Extension<PageMeta>,
+) -> std::result::Result<impl IntoResponse, Response<Body>> {
+    if !password_reset_enabled(&state).await {
+        return Err(error_page(
+            StatusCode::NOT_FOUND,
+            "Password reset is not enabled",
+            &state,
+            &user,
+            &page,
+        ));
+    }
+
+    Ok(Html(
+        state
+            .jinja
+            .get_template("account/password-reset-request.html")
+            .unwrap()
+            .render(context! {
+                global => state.global_page_meta,
+                page,
+                title => format!("Reset Password | {}", state.global_page_meta.title),
+                user,
+            })
+            .unwrap(),
+    ))
+}
+
+pub async fn route_signin_reset_request(
+    State(state): State<RouterState>,
+    Extension(lang): Extension<Languages>,
+    Extension(ip): Extension<Option<IpAddr>>,
+    Form(form): Form<EmailSubmit>,
+) -> impl IntoResponse {
+    if !password_reset_enabled(&state).await {
+        return StatusCode::NOT_FOUND.into_response();
+    }
+
+    if form.email.is_empty() || form.email.len() > 255 {
         return ([(
             "HX-Trigger",
             toast_header(
                 ToastKind::Error,
                 &state
                     .localizer
-                    .localize(&lang, "account-error-name-length", None)
+                    .localize(&lang, "account-error-email-length", None)
                     .unwrap(),
             ),
         )],)
             .into_response();
     }
 
+    // the response is the same whether or not an account exists, so emails cannot be enumerated
+    match state.db.create_password_reset_code(&form.email).await {
+        Ok(Some(reset)) => {
+            if let Err(e) = state
+                .outbound_mailer
+                .as_ref()
+                .unwrap()
+                .send_password_reset(
+                    &reset.user_name,
+                    ip.map(|ip| ip.to_string()).as_deref(),
+                    &form.email,
+                    &reset.code,
+                )
+                .await
+            {
+                tracing::error!(error = ?e, "Failed to send password reset email");
+            } else {
+                tracing::info!(email = form.email, "Sent password reset email");
+            }
+        }
+        Ok(None) => {}
+        Err(e) => {
+            tracing::error!(error = ?e, "Failed to create password reset code");
+        }
+    }
+
+    ([(
+        "HX-Trigger",
+        toast_header(
+            ToastKind::Success,
+            &state
+                .localizer
+                .localize(&lang, "account-check-email-password-reset", None)
+                .unwrap(),
+        ),
+    )],)
+        .into_response()
+}
+
+#[derive(Deserialize)]
+pub struct PasswordResetParams {
+    code: String,
+}
+
+pub async fn route_signin_reset_confirm(
+    State(state): State<RouterState>,
+    Extension(user): Extension<MaybeUser>,
+    Extension(page): Extension<PageMeta>,
+    Query(params): Query<PasswordResetParams>,
+    extensions: Extensions,
+) -> std::result::Result<impl IntoResponse, Response<Body>> {
+    let name = match state
+        .db
+        .get_password_reset_by_callback_code(&params.code)
+        .await
+    {
+        Err(RhombusError::DatabaseReturnedNoRows) => {
+            return Err(error_page(
+                StatusCode::NOT_FOUND,
+                "Invalid or expired password reset code",
+                &state,
+                &user,
+                &page,
+            ));
+        }
+        result => result.map_err_page(&extensions, "Failed to get password reset")?,
+    };
+
+    Ok(Html(
+        state
+            .jinja
+            .get_template("account/password-reset.html")
+            .unwrap()
+            .render(context! {
+                global => state.global_page_meta,
+                page,
+                title => format!("Reset Password | {}", state.global_page_meta.title),
+                user,
+                name,
+                code => params.code,
+            })
+            .unwrap(),
+    ))
+}
+
+#[derive(Deserialize)]
+pub struct PasswordResetSubmit {
+    code: String,
+    password: String,
+}
+
+pub async fn route_signin_reset_confirm_submit(
+    State(state): State<RouterState>,
+    Extension(lang): Extension<Languages>,
+    cookie_jar: CookieJar,
+    Form(form): Form<PasswordResetSubmit>,
+) -> impl IntoResponse {
     let password_graphemes = form.password.graphemes(true).count();
     if !(8..=256).contains(&password_graphemes) || !(0..=256).contains(&form.password.len()) {
         return ([(
@@ -1057,44 +2494,137 @@ pub async fn route_signin_credentials(
             .into_response();
     }
 
-    let avatar = avatar_from_email(&form.username.trim().to_lowercase());
-
-    let location_url = state.settings.read().await.location_url.clone();
-    let maybe_user = match state
+    let user_id = match state
         .db
-        .upsert_user_by_credentials(&form.username, &avatar, &form.password, &location_url)
+        .reset_password_by_callback_code(&form.code, &form.password)
         .await
     {
-        Ok(user) => user,
+        Ok(user_id) => user_id,
+        Err(RhombusError::DatabaseReturnedNoRows) => {
+            return ([(
+                "HX-Trigger",
+                toast_header(
+                    ToastKind::Error,
+                    &state
+                        .localizer
+                        .localize(&lang, "account-error-password-reset-expired", None)
+                        .unwrap(),
+                ),
+            )],)
+                .into_response();
+        }
         Err(e) => {
-            let user_id = user.as_ref().map(|u| u.id);
-            tracing::error!(error = ?e, user_id, "Failed to upsert user by credentials");
+            tracing::error!(error = ?e, "Failed to reset password");
             return ([(
                 "HX-Trigger",
-                toast_header(ToastKind::Error, "Failed to upsert user by credentials"),
+                toast_header(ToastKind::Error, "Failed to reset password"),
             )],)
                 .into_response();
         }
     };
 
-    let Some((user_id, _team_id)) = maybe_user else {
-        return ([(
-            "HX-Trigger",
-            toast_header(
-                ToastKind::Error,
-                &state
-                    .localizer
-                    .localize(&lang, "account-error-invalid-credentials", None)
-                    .unwrap(),
-            ),
-        )],)
-            .into_response();
+    // whoever knew the old password should not stay signed in
+    if let Err(e) = state.db.revoke_user_sessions(user_id, None).await {
+        tracing::error!(error = ?e, user_id, "Failed to revoke sessions");
+    }
+
+    let cookie = match sign_in_cookie(&state, user_id, &cookie_jar).await {
+        Ok(cookie) => cookie,
+        Err(e) => {
+            tracing::error!(error = ?e, user_id, "Failed to add user to team");
+            return ([(
+                "HX-Trigger",
+                toast_header(ToastKind::Error, "Failed to add user to team"),
+            )],)
+                .into_response();
+        }
+    };
+
+    ([
+        ("HX-Redirect", "/team"),
+        ("Set-Cookie", cookie.to_string().as_str()),
+    ],)
+        .into_response()
+}
+
+#[cfg(feature = "ldap")]
+#[derive(Deserialize)]
+pub struct LdapSubmit {
+    username: String,
+    password: String,
+    #[serde(flatten)]
+    proof_of_work: ProofOfWorkSolution,
+}
+
+#[cfg(feature = "ldap")]
+pub async fn route_signin_ldap(
+    State(state): State<RouterState>,
+    Extension(lang): Extension<Languages>,
+    cookie_jar: CookieJar,
+    Form(form): Form<LdapSubmit>,
+) -> impl IntoResponse {
+    let (ldap_settings, location_url) = {
+        let settings = state.settings.read().await;
+        let Some(ldap_settings) = settings
+            .ldap
+            .clone()
+            .filter(|_| settings.auth.contains(&AuthProvider::Ldap))
+        else {
+            return StatusCode::NOT_FOUND.into_response();
+        };
+        (ldap_settings, settings.location_url.clone())
+    };
+
+    if !verify_proof_of_work(&state, ProofOfWorkRoute::Credentials, &form.proof_of_work).await {
+        return error_toast(&state, &lang, "proof-of-work-error");
+    }
+
+    if form.username.len() > 256 || form.password.len() > 256 {
+        return error_toast(&state, &lang, "account-error-invalid-credentials");
+    }
+
+    let ldap_user = match crate::internal::ldap::authenticate(
+        &ldap_settings,
+        &form.username,
+        &form.password,
+    )
+    .await
+    {
+        Ok(Some(ldap_user)) => ldap_user,
+        Ok(None) => return error_toast(&state, &lang, "account-error-invalid-credentials"),
+        Err(e) => {
+            tracing::error!(error = ?e, username = form.username, "Failed to sign in with LDAP");
+            return error_toast(&state, &lang, "account-error-ldap");
+        }
+    };
+
+    let name = ldap_user.name.as_deref().unwrap_or(&form.username);
+    let avatar = avatar_from_email(ldap_user.email.as_deref().unwrap_or(&ldap_user.dn));
+    let (user_id, team_id) = match state
+        .db
+        .upsert_user_by_ldap(
+            name,
+            ldap_user.email.as_deref(),
+            &avatar,
+            &ldap_user.dn.to_lowercase(),
+            &location_url,
+        )
+        .await
+    {
+        Ok(Ok(ids)) => ids,
+        Ok(Err(IdentityUpsertError::AlreadyInUse)) | Err(_) => {
+            tracing::error!(dn = ldap_user.dn, "Failed to upsert user by LDAP");
+            return error_toast(&state, &lang, "account-error-ldap");
+        }
     };
 
+    if let Err(e) = apply_ldap_groups(&state, &ldap_settings, &ldap_user, user_id, team_id).await {
+        tracing::error!(error = ?e, user_id, "Failed to apply LDAP groups");
+    }
+
     let cookie = match sign_in_cookie(&state, user_id, &cookie_jar).await {
         Ok(cookie) => cookie,
         Err(e) => {
-            let user_id = user.as_ref().map(|u| u.id);
             tracing::error!(error = ?e, user_id, "Failed to add user to team");
             return ([(
                 "HX-Trigger",
@@ -1111,6 +2641,197 @@ pub async fn route_signin_credentials(
         .into_response()
 }
 
+/// Syncs admin status with the admin group, and moves teams owned by the user into the division
+/// mapped to their groups
+#[cfg(feature = "ldap")]
+async fn apply_ldap_groups(
+    state: &RouterState,
+    ldap_settings: &crate::internal::settings::LdapSettings,
+    ldap_user: &crate::internal::ldap::LdapUser,
+    user_id: i64,
+    team_id: i64,
+) -> crate::Result<()> {
+    let user = state.db.get_user_from_id(user_id).await?;
+
+    if let Some(admin_group) = &ldap_settings.admin_group {
+        let is_admin = ldap_user.is_member(admin_group);
+        if user.is_admin != is_admin {
+            state.db.set_user_admin(user_id, is_admin).await?;
+            tracing::info!(user_id, is_admin, "Set admin status from LDAP group");
+        }
+    }
+
+    let Some(division_id) = crate::internal::ldap::mapped_division(ldap_settings, ldap_user) else {
+        return Ok(());
+    };
+    if !user.is_team_owner || !state.divisions.iter().any(|d| d.id == division_id) {
+        return Ok(());
+    }
+
+    let team = state.db.get_team_from_id(team_id).await?;
+    if team.division_id != division_id {
+        crate::internal::routes::team::move_team_division(
+            state,
+            &team,
+            division_id,
+            chrono::Utc::now(),
+            None,
+        )
+        .await?;
+    }
+
+    Ok(())
+}
+
+async fn passkeys_enabled(state: &RouterState) -> bool {
+    state
+        .settings
+        .read()
+        .await
+        .auth
+        .contains(&AuthProvider::Passkey)
+}
+
+pub async fn route_signin_passkey_options(State(state): State<RouterState>) -> impl IntoResponse {
+    if !passkeys_enabled(&state).await {
+        return StatusCode::NOT_FOUND.into_response();
+    }
+
+    let Some(relying_party) =
+        webauthn::RelyingParty::from_location_url(&state.global_page_meta.location_url)
+    else {
+        tracing::error!(
+            location_url = state.global_page_meta.location_url,
+            "Location url is not a valid passkey origin"
+        );
+        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    };
+
+    let jwt_secret = state.settings.read().await.jwt_secret.clone();
+    let (challenge, token) = webauthn::create_challenge(&jwt_secret, None);
+
+    Json(json!({
+        "token": token,
+        "challenge": challenge,
+        "rp_id": relying_party.id,
+    }))
+    .into_response()
+}
+
+#[derive(Deserialize)]
+pub struct PasskeySignInSubmit {
+    token: String,
+    id: String,
+    client_data_json: String,
+    authenticator_data: String,
+    signature: String,
+}
+
+pub async fn route_signin_passkey(
+    State(state): State<RouterState>,
+    Extension(lang): Extension<Languages>,
+    cookie_jar: CookieJar,
+    Form(form): Form<PasskeySignInSubmit>,
+) -> impl IntoResponse {
+    if !passkeys_enabled(&state).await {
+        return StatusCode::NOT_FOUND.into_response();
+    }
+
+    let jwt_secret = state.settings.read().await.jwt_secret.clone();
+    let Some(claims) =
+        webauthn::decode_challenge(&jwt_secret, &form.token).filter(|claims| claims.sub.is_none())
+    else {
+        return error_toast(&state, &lang, "passkey-error");
+    };
+
+    let Some(relying_party) =
+        webauthn::RelyingParty::from_location_url(&state.global_page_meta.location_url)
+    else {
+        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    };
+
+    let (Ok(client_data_json), Ok(authenticator_data), Ok(signature)) = (
+        URL_SAFE_NO_PAD.decode(&form.client_data_json),
+        URL_SAFE_NO_PAD.decode(&form.authenticator_data),
+        URL_SAFE_NO_PAD.decode(&form.signature),
+    ) else {
+        return StatusCode::BAD_REQUEST.into_response();
+    };
+
+    let credential = match state.db.get_passkey_credential(&form.id).await {
+        Ok(Some(credential)) => credential,
+        Ok(None) => return error_toast(&state, &lang, "passkey-error-unknown"),
+        Err(e) => {
+            tracing::error!(error = ?e, "Failed to get passkey");
+            return ([(
+                "HX-Trigger",
+                toast_header(ToastKind::Error, "Failed to get passkey"),
+            )],)
+                .into_response();
+        }
+    };
+
+    let verified = webauthn::verify_client_data(
+        &client_data_json,
+        "webauthn.get",
+        &claims.challenge,
+        &relying_party,
+    ) && webauthn::verify_signature(
+        credential.algorithm,
+        &credential.public_key,
+        &authenticator_data,
+        &client_data_json,
+        &signature,
+    );
+    let sign_count = webauthn::verify_authenticator_data(&authenticator_data, &relying_party);
+    let (true, Some(sign_count)) = (verified, sign_count) else {
+        return error_toast(&state, &lang, "passkey-error");
+    };
+
+    if !webauthn::redeem_challenge(&claims) {
+        return error_toast(&state, &lang, "passkey-error");
+    }
+
+    match state.db.use_passkey(&form.id, sign_count).await {
+        Ok(true) => {}
+        Ok(false) => {
+            tracing::warn!(
+                user_id = credential.user_id,
+                sign_count,
+                stored_sign_count = credential.sign_count,
+                "Passkey signature counter went backwards"
+            );
+            return error_toast(&state, &lang, "passkey-error");
+        }
+        Err(e) => {
+            tracing::error!(error = ?e, user_id = credential.user_id, "Failed to use passkey");
+            return ([(
+                "HX-Trigger",
+                toast_header(ToastKind::Error, "Failed to use passkey"),
+            )],)
+                .into_response();
+        }
+    }
+
+    let cookie = match sign_in_cookie(&state, credential.user_id, &cookie_jar).await {
+        Ok(cookie) => cookie,
+        Err(e) => {
+            tracing::error!(error = ?e, user_id = credential.user_id, "Failed to add user to team");
+            return ([(
+                "HX-Trigger",
+                toast_header(ToastKind::Error, "Failed to add user to team"),
+            )],)
+                .into_response();
+        }
+    };
+
+    ([
+        ("HX-Redirect", "/team"),
+        ("Set-Cookie", cookie.to_string().as_str()),
+    ],)
+        .into_response()
+}
+
 #[derive(Deserialize)]
 pub struct EmailSignInParams {
     code: String,
@@ -1219,10 +2940,134 @@ pub async fn route_signin_email_confirm_callback(
     Ok(response)
 }
 
+/// Claims of the short lived cookie held between the first sign in step and the two-factor code
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TwoFactorClaims {
+    pub sub: i64,
+    pub iat: i64,
+    pub exp: i64,
+}
+
+/// Failed codes a user may submit within [`TWO_FACTOR_WINDOW`] before they are locked out
+const MAX_TWO_FACTOR_ATTEMPTS: u32 = 5;
+
+/// Seconds from the first failed code until a user's failed attempts are forgotten
+const TWO_FACTOR_WINDOW: i64 = 15 * 60;
+
+/// Failed attempts by user id, mapped to the count and when the window ends. Keyed by
+/// user rather than pending sign in, so signing in again with the password does not
+/// reset the count.
+static TWO_FACTOR_FAILURES: LazyLock<DashMap<i64, (u32, i64)>> = LazyLock::new(DashMap::new);
+
+/// Counts a failed code against the user, returning false once they may not try again
+fn record_two_factor_failure(user_id: i64, now: i64) -> bool {
+    TWO_FACTOR_FAILURES.retain(|_, (_, until)| *until > now);
+    let mut failures = TWO_FACTOR_FAILURES
+        .entry(user_id)
+        .or_insert((0, now + TWO_FACTOR_WINDOW));
+    failures.0 += 1;
+    failures.0 < MAX_TWO_FACTOR_ATTEMPTS
+}
+
+/// Whether the user has run out of two-factor attempts in the current window
+fn two_factor_locked(user_id: i64, now: i64) -> bool {
+    TWO_FACTOR_FAILURES
+        .get(&user_id)
+        .is_some_and(|failures| failures.1 > now && failures.0 >= MAX_TWO_FACTOR_ATTEMPTS)
+}
+
+/// Pending two-factor tokens are signed with a derived key, so they are never accepted as a `rhombus-token`
+fn two_factor_key(jwt_secret: &str) -> Vec<u8> {
+    format!("{}:two-factor", jwt_secret).into_bytes()
+}
+
+async fn two_factor_required(state: &RouterState, user_id: i64) -> crate::Result<bool> {
+    if state
+        .db
+        .get_user_totp(user_id)
+        .await?
+        .is_some_and(|totp| totp.enabled)
+    {
+        return Ok(true);
+    }
+
+    let require_for_admins = state
+        .settings
+        .read()
+        .await
+        .two_factor
+        .as_ref()
+        .and_then(|two_factor| two_factor.require_for_admins)
+        .unwrap_or(false);
+
+    Ok(require_for_admins && state.db.get_user_from_id(user_id).await?.is_admin)
+}
+
+/// Returns the pending sign in of the user waiting to complete two-factor authentication, if any
+/// and it has not run out of attempts
+async fn pending_two_factor_user(
+    state: &RouterState,
+    cookie_jar: &CookieJar,
+) -> Option<TwoFactorClaims> {
+    let token = cookie_jar.get("rhombus-two-factor")?.value();
+    let jwt_secret = state.settings.read().await.jwt_secret.clone();
+    decode::<TwoFactorClaims>(
+        token,
+        &DecodingKey::from_secret(&two_factor_key(&jwt_secret)),
+        &Validation::default(),
+    )
+    .ok()
+    .map(|data| data.claims)
+    .filter(|claims| !two_factor_locked(claims.sub, chrono::Utc::now().timestamp()))
+}
+
+fn two_factor_removal_cookie() -> Cookie<'static> {
+    Cookie::build(("rhombus-two-factor", ""))
+        .path("/")
+        .removal()
+        .same_site(SameSite::Lax)
+        .http_only(true)
+        .build()
+}
+
+/// Creates the cookie to set after a successful first sign in step. Users who need to pass two-factor
+/// authentication get a pending cookie instead, and finish signing in on `/signin`.
 async fn sign_in_cookie<'a>(
     state: &RouterState,
     user_id: i64,
     cookie_jar: &CookieJar,
+) -> crate::Result<Cookie<'static>> {
+    if !two_factor_required(state, user_id).await? {
+        return issue_sign_in_cookie(state, user_id, cookie_jar).await;
+    }
+
+    let jwt_secret = state.settings.read().await.jwt_secret.clone();
+
+    let now = chrono::Utc::now();
+    let claims = TwoFactorClaims {
+        sub: user_id,
+        iat: now.timestamp(),
+        exp: (now + chrono::Duration::minutes(10)).timestamp(),
+    };
+
+    let token = encode(
+        &Header::default(),
+        &claims,
+        &EncodingKey::from_secret(&two_factor_key(&jwt_secret)),
+    )
+    .unwrap();
+
+    Ok(Cookie::build(("rhombus-two-factor", token))
+        .path("/")
+        .same_site(SameSite::Lax)
+        .http_only(true)
+        .build())
+}
+
+async fn issue_sign_in_cookie(
+    state: &RouterState,
+    user_id: i64,
+    cookie_jar: &CookieJar,
 ) -> crate::Result<Cookie<'static>> {
     let jwt_secret = {
         let settings = state.settings.read().await;
@@ -1232,8 +3077,11 @@ async fn sign_in_cookie<'a>(
     let now = chrono::Utc::now();
     let iat = now.timestamp();
     let exp = (now + chrono::Duration::hours(72)).timestamp();
+    let sid = Alphanumeric.sample_string(&mut thread_rng(), 32);
+    state.db.create_session(&sid, user_id, exp).await?;
     let claims = TokenClaims {
         sub: user_id,
+        sid,
         exp,
         iat,
     };
@@ -1244,7 +3092,16 @@ async fn sign_in_cookie<'a>(
             .get_team_meta_from_invite_token(cookie_invite_token)
             .await?
         {
-            state.db.add_user_to_team(user_id, team.id, None).await?;
+            let user = state.db.get_user_from_id(user_id).await?;
+            let old_team = state.db.get_team_from_id(user.team_id);
+            let new_team = state.db.get_team_from_id(team.id);
+            let (old_team, new_team) = tokio::try_join!(old_team, new_team)?;
+            if check_join_team(&state.divisions, user_id, &old_team, &new_team)
+                .await
+                .is_ok()
+            {
+                state.db.add_user_to_team(user_id, team.id, None).await?;
+            }
         };
     }
 
@@ -1262,7 +3119,20 @@ async fn sign_in_cookie<'a>(
         .build())
 }
 
-pub async fn route_signout() -> impl IntoResponse {
+pub async fn route_signout(
+    State(state): State<RouterState>,
+    Extension(token_claims): Extension<MaybeTokenClaims>,
+) -> impl IntoResponse {
+    if let Some(token_claims) = token_claims {
+        if let Err(e) = state
+            .db
+            .revoke_session(token_claims.sub, &token_claims.sid)
+            .await
+        {
+            tracing::error!(error = ?e, user_id = token_claims.sub, "Failed to revoke session");
+        }
+    }
+
     let cookie = Cookie::build(("rhombus-token", ""))
         .path("/")
         .removal()
@@ -1270,8 +3140,36 @@ pub async fn route_signout() -> impl IntoResponse {
         .http_only(true);
 
     let mut response = Redirect::to("/signin").into_response();
+    let headers = response.headers_mut();
+    headers.insert(header::SET_COOKIE, cookie.to_string().parse().unwrap());
+    headers.append(
+        header::SET_COOKIE,
+        two_factor_removal_cookie().to_string().parse().unwrap(),
+    );
     response
-        .headers_mut()
-        .insert(header::SET_COOKIE, cookie.to_string().parse().unwrap());
-    response
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        record_two_factor_failure, two_factor_locked, MAX_TWO_FACTOR_ATTEMPTS, TWO_FACTOR_WINDOW,
+    };
+
+    #[test]
+    fn two_factor_failures_by_user() {
+        let (user_id, other_user_id, now) = (-1, -2, 1_000_000);
+
+        for _ in 1..MAX_TWO_FACTOR_ATTEMPTS {
+            assert!(record_two_factor_failure(user_id, now));
+        }
+        assert!(!two_factor_locked(user_id, now));
+        assert!(!record_two_factor_failure(user_id, now + 1));
+
+        // a new pending sign in for the same user is still locked out
+        assert!(two_factor_locked(user_id, now + 2));
+        assert!(!two_factor_locked(other_user_id, now + 2));
+
+        assert!(!two_factor_locked(user_id, now + TWO_FACTOR_WINDOW));
+        assert!(record_two_factor_failure(user_id, now + TWO_FACTOR_WINDOW));
+    }
 }
@@ -0,0 +1,551 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    sync::Arc,
+};
+
+use axum::body::Bytes;
+use chrono::{DateTime, Utc};
+use futures::{Stream, StreamExt, TryStreamExt};
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio_tar::{Archive, Builder, Header};
+use tokio_util::io::ReaderStream;
+
+use crate::{
+    errors::RhombusError,
+    internal::{database::provider::Connection, upload_provider::parse_upload_url},
+    upload_provider::ErasedUploadProvider,
+    Result, UploadProvider,
+};
+
+/// Version of the archive format. Bump this whenever the layout of the archive changes
+/// in a way which older versions of Rhombus cannot read.
+pub const BACKUP_VERSION: u32 = 2;
+
+/// Size of the chunks a backup archive is streamed in
+pub const BACKUP_CHUNK_SIZE: usize = 1024 * 1024;
+
+const MANIFEST_PATH: &str = "manifest.json";
+
+/// First entry of a backup archive. It is followed by one `tables/<name>.json` entry per
+/// table, and then one `files/<n>` entry with the raw contents of every uploaded file.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BackupManifest {
+    pub version: u32,
+    pub rhombus_version: String,
+    pub created_at: DateTime<Utc>,
+    pub tables: Vec<String>,
+    pub files: Vec<BackupFile>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct BackupTable {
+    pub columns: Vec<String>,
+    pub rows: Vec<Vec<BackupValue>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BackupValue {
+    Null,
+    Integer(i64),
+    Real(f64),
+    Text(String),
+    /// Base64 encoded bytes
+    Blob(String),
+}
+
+/// An uploaded file, keyed by the url it was referenced by in `rhombus_file_attachment`
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BackupFile {
+    pub url: String,
+    pub filename: String,
+    /// Path of the entry holding the contents of the file
+    pub path: String,
+}
+
+/// Urls of attachments which were uploaded through an upload provider. Attachments
+/// without a hash point to an external url and are not part of the backup.
+fn uploaded_attachment_urls(tables: &BTreeMap<String, BackupTable>) -> BTreeSet<String> {
+    let Some(attachments) = tables.get("rhombus_file_attachment") else {
+        return BTreeSet::new();
+    };
+
+    let url_index = attachments.columns.iter().position(|c| c == "url");
+    let hash_index = attachments.columns.iter().position(|c| c == "hash");
+    let (Some(url_index), Some(hash_index)) = (url_index, hash_index) else {
+        return BTreeSet::new();
+    };
+
+    attachments
+        .rows
+        .iter()
+        .filter(|row| row[hash_index] != BackupValue::Null)
+        .filter_map(|row| match &row[url_index] {
+            BackupValue::Text(url) => Some(url.clone()),
+            _ => None,
+        })
+        .collect()
+}
+
+fn table_path(table: &str) -> String {
+    format!("tables/{}.json", table)
+}
+
+async fn append_entry<W: AsyncWrite + Unpin + Send>(
+    builder: &mut Builder<W>,
+    path: &str,
+    contents: &[u8],
+) -> Result<()> {
+    let mut header = Header::new_gnu();
+    header.set_size(contents.len() as u64);
+    header.set_mode(0o644);
+    header.set_mtime(Utc::now().timestamp() as u64);
+    builder.append_data(&mut header, path, contents).await?;
+    Ok(())
+}
+
+/// Write a tar archive of the entire instance to `writer`, giving the writer back once the
+/// archive is finished
+pub async fn write_backup<W: AsyncWrite + Unpin + Send + 'static>(
+    db: &Connection,
+    upload_provider: &Arc<dyn ErasedUploadProvider>,
+    writer: W,
+) -> Result<W> {
+    let tables = db.export_backup_tables().await?;
+    write_archive(tables, upload_provider, writer).await
+}
+
+/// Only one uploaded file is held in memory at a time, since a tar header needs the size
+/// of its entry up front
+async fn write_archive<W: AsyncWrite + Unpin + Send + 'static>(
+    tables: BTreeMap<String, BackupTable>,
+    upload_provider: &Arc<dyn ErasedUploadProvider>,
+    writer: W,
+) -> Result<W> {
+    let files = uploaded_attachment_urls(&tables)
+        .into_iter()
+        .filter_map(|url| match parse_upload_url(&url) {
+            Some((_, filename)) => Some((url, filename)),
+            None => {
+                tracing::warn!(url, "Skipping attachment with unrecognized url");
+                None
+            }
+        })
+        .enumerate()
+        .map(|(i, (url, filename))| BackupFile {
+            url,
+            filename,
+            path: format!("files/{}", i),
+        })
+        .collect::<Vec<_>>();
+
+    let manifest = BackupManifest {
+        version: BACKUP_VERSION,
+        rhombus_version: env!("CARGO_PKG_VERSION").to_owned(),
+        created_at: Utc::now(),
+        tables: tables.keys().cloned().collect(),
+        files,
+    };
+
+    let mut builder = Builder::new(writer);
+    append_entry(&mut builder, MANIFEST_PATH, &serde_json::to_vec(&manifest)?).await?;
+
+    for (name, table) in tables.iter() {
+        append_entry(&mut builder, &table_path(name), &serde_json::to_vec(table)?).await?;
+    }
+
+    for file in manifest.files.iter() {
+        let contents = upload_provider
+            .download(&file.url)
+            .await?
+            .map_err(std::io::Error::other)
+            .try_fold(vec![], |mut contents, chunk| async move {
+                contents.extend_from_slice(&chunk);
+                Ok(contents)
+            })
+            .await?;
+        append_entry(&mut builder, &file.path, &contents).await?;
+    }
+
+    let mut writer = builder.into_inner().await?;
+    writer.shutdown().await?;
+
+    tracing::info!(
+        tables = manifest.tables.len(),
+        files = manifest.files.len(),
+        "Created backup"
+    );
+
+    Ok(writer)
+}
+
+/// Stream a tar archive of the entire instance. The tables are exported up front, so a
+/// database which does not support backups fails before anything is sent. The rest of the
+/// archive is written by a background task; if it fails, the error is yielded as the last
+/// item of the stream.
+pub async fn backup_stream(
+    db: &Connection,
+    upload_provider: Arc<dyn ErasedUploadProvider>,
+) -> Result<impl Stream<Item = Result<Bytes>> + Send + 'static> {
+    let tables = db.export_backup_tables().await?;
+
+    let (reader, writer) = tokio::io::duplex(BACKUP_CHUNK_SIZE);
+    let task = tokio::spawn(async move { write_archive(tables, &upload_provider, writer).await });
+
+    let finished = futures::stream::once(async move {
+        match task.await {
+            Ok(Ok(_)) => None,
+            Ok(Err(e)) => Some(Err(e)),
+            Err(e) => Some(Err(RhombusError::Backup(format!(
+                "backup task failed: {}",
+                e
+            )))),
+        }
+    })
+    .filter_map(futures::future::ready);
+
+    Ok(ReaderStream::with_capacity(reader, BACKUP_CHUNK_SIZE)
+        .map_err(RhombusError::from)
+        .chain(finished))
+}
+
+fn read_manifest(contents: &[u8]) -> Result<BackupManifest> {
+    #[derive(Deserialize)]
+    struct VersionOnly {
+        version: u32,
+    }
+
+    let version = serde_json::from_slice::<VersionOnly>(contents)?.version;
+    if version != BACKUP_VERSION {
+        return Err(RhombusError::Backup(format!(
+            "unsupported archive version {} (expected {})",
+            version, BACKUP_VERSION
+        )));
+    }
+
+    Ok(serde_json::from_slice(contents)?)
+}
+
+/// Restore a backup archive into a fresh database. Files are re-uploaded to the active
+/// upload provider and attachment urls are rewritten to point at their new location.
+/// If the restore fails, every file uploaded so far is deleted again.
+pub async fn restore_backup<R: AsyncRead + Unpin + Send>(
+    db: &Connection,
+    upload_provider: &Arc<dyn ErasedUploadProvider>,
+    reader: R,
+) -> Result<()> {
+    let mut new_urls = BTreeMap::new();
+    let mut existing_urls = None;
+    let result = restore_archive(
+        db,
+        upload_provider,
+        reader,
+        &mut new_urls,
+        &mut existing_urls,
+    )
+    .await;
+
+    if result.is_err() {
+        // files with the same contents may have been uploaded before the restore, since
+        // some upload providers store files by their hash
+        let existing_urls = existing_urls.unwrap_or_default();
+        for url in new_urls.values() {
+            if existing_urls.contains(url) {
+                continue;
+            }
+            if let Err(error) = upload_provider.delete(url).await {
+                tracing::warn!(url, ?error, "Failed to delete file of failed restore");
+            }
+        }
+    }
+
+    result
+}
+
+async fn restore_archive<R: AsyncRead + Unpin + Send>(
+    db: &Connection,
+    upload_provider: &Arc<dyn ErasedUploadProvider>,
+    reader: R,
+    new_urls: &mut BTreeMap<String, String>,
+    existing_urls: &mut Option<BTreeSet<String>>,
+) -> Result<()> {
+    // fail before reading the archive if the database cannot be restored into at all
+    db.check_backup_tables(&BTreeMap::new()).await?;
+
+    let mut archive = Archive::new(reader);
+    let mut entries = archive.entries()?;
+
+    let manifest = match entries.try_next().await? {
+        Some(mut entry) if entry.path()?.to_str() == Some(MANIFEST_PATH) => {
+            let mut contents = vec![];
+            entry.read_to_end(&mut contents).await?;
+            read_manifest(&contents)?
+        }
+        _ => {
+            return Err(RhombusError::Backup(format!(
+                "archive does not start with {}",
+                MANIFEST_PATH
+            )))
+        }
+    };
+
+    let mut tables = BTreeMap::new();
+    while let Some(mut entry) = entries.try_next().await? {
+        let path = entry.path()?.to_string_lossy().into_owned();
+
+        if let Some(table) = manifest
+            .tables
+            .iter()
+            .find(|table| table_path(table) == path)
+        {
+            if existing_urls.is_some() {
+                return Err(RhombusError::Backup(format!(
+                    "{} comes after the uploaded files",
+                    path
+                )));
+            }
+
+            let mut contents = vec![];
+            entry.read_to_end(&mut contents).await?;
+            tables.insert(
+                table.clone(),
+                serde_json::from_slice::<BackupTable>(&contents)?,
+            );
+        } else if let Some(file) = manifest.files.iter().find(|file| file.path == path) {
+            // tables come before files, so they can be checked before anything is uploaded
+            if existing_urls.is_none() {
+                check_tables(db, &manifest, &tables).await?;
+                *existing_urls = Some(upload_provider.list().await?.into_iter().collect());
+            }
+
+            let new_url = upload_provider
+                .upload(&file.filename, ReaderStream::new(entry))
+                .await?;
+            new_urls.insert(file.url.clone(), new_url);
+        } else {
+            tracing::warn!(path, "Skipping unknown archive entry");
+        }
+    }
+
+    if existing_urls.is_none() {
+        check_tables(db, &manifest, &tables).await?;
+    }
+
+    if let Some(file) = manifest
+        .files
+        .iter()
+        .find(|file| !new_urls.contains_key(&file.url))
+    {
+        return Err(RhombusError::Backup(format!(
+            "archive is missing {}",
+            file.path
+        )));
+    }
+
+    if let Some(attachments) = tables.get_mut("rhombus_file_attachment") {
+        if let Some(url_index) = attachments.columns.iter().position(|c| c == "url") {
+            for row in attachments.rows.iter_mut() {
+                if let BackupValue::Text(url) = &row[url_index] {
+                    if let Some(new_url) = new_urls.get(url) {
+                        row[url_index] = BackupValue::Text(new_url.clone());
+                    }
+                }
+            }
+        }
+    }
+
+    db.import_backup_tables(&tables).await?;
+
+    tracing::info!(
+        tables = tables.len(),
+        files = new_urls.len(),
+        "Restored backup"
+    );
+
+    Ok(())
+}
+
+/// Make sure every table listed in the manifest is in the archive and can be imported
+async fn check_tables(
+    db: &Connection,
+    manifest: &BackupManifest,
+    tables: &BTreeMap<String, BackupTable>,
+) -> Result<()> {
+    if let Some(table) = manifest
+        .tables
+        .iter()
+        .find(|table| !tables.contains_key(*table))
+    {
+        return Err(RhombusError::Backup(format!(
+            "archive is missing {}",
+            table_path(table)
+        )));
+    }
+
+    db.check_backup_tables(tables).await
+}
+
+#[cfg(all(test, feature = "libsql"))]
+mod test {
+    use std::io::Cursor;
+
+    use libsql::params;
+
+    use super::*;
+    use crate::{
+        database_upload_provider::DatabaseUploadProvider,
+        internal::database::libsql::test::{insert_test_challenge, test_db},
+    };
+
+    async fn test_instance() -> (Connection, Arc<dyn ErasedUploadProvider>) {
+        let db: Connection = Arc::new(test_db().await);
+        let upload_provider = Arc::new(DatabaseUploadProvider::new(db.clone()).await);
+        (db, upload_provider)
+    }
+
+    async fn upload(
+        upload_provider: &Arc<dyn ErasedUploadProvider>,
+        contents: &'static [u8],
+    ) -> String {
+        upload_provider
+            .upload(
+                "handout.txt",
+                futures::stream::once(async move {
+                    Ok::<_, std::io::Error>(Bytes::from_static(contents))
+                }),
+            )
+            .await
+            .unwrap()
+    }
+
+    async fn read_upload(upload_provider: &Arc<dyn ErasedUploadProvider>, url: &str) -> Vec<u8> {
+        upload_provider
+            .download(url)
+            .await
+            .unwrap()
+            .try_collect::<Vec<_>>()
+            .await
+            .unwrap()
+            .concat()
+    }
+
+    /// Archive of an instance with a user and a challenge with an uploaded attachment,
+    /// along with the id of the user
+    async fn test_archive() -> (i64, Vec<u8>) {
+        let database = test_db().await;
+        insert_test_challenge(&database).await;
+        let db: Connection = Arc::new(database.clone());
+        let upload_provider: Arc<dyn ErasedUploadProvider> =
+            Arc::new(DatabaseUploadProvider::new(db.clone()).await);
+
+        let (user_id, _) = db
+            .upsert_user_by_credentials("alice", "", "password", "http://localhost:3000")
+            .await
+            .unwrap()
+            .unwrap();
+
+        let url = upload(&upload_provider, b"handout").await;
+        let (hash, _) = parse_upload_url(&url).unwrap();
+        database
+            .conn
+            .execute(
+                "INSERT INTO rhombus_file_attachment (challenge_id, name, url, hash) VALUES ('web-1', 'handout.txt', ?1, ?2)",
+                params!(url, hash),
+            )
+            .await
+            .unwrap();
+
+        let archive = write_backup(&db, &upload_provider, Cursor::new(vec![]))
+            .await
+            .unwrap();
+        (user_id, archive.into_inner())
+    }
+
+    #[tokio::test]
+    async fn archive_roundtrip() {
+        let (user_id, archive) = test_archive().await;
+
+        let (restored, upload_provider) = test_instance().await;
+        restore_backup(&restored, &upload_provider, Cursor::new(archive))
+            .await
+            .unwrap();
+
+        let user = restored.get_user_from_id(user_id).await.unwrap();
+        assert_eq!("alice", user.name);
+
+        let urls = restored.get_uploaded_attachment_urls().await.unwrap();
+        assert_eq!(urls.len(), 1);
+        assert_eq!(read_upload(&upload_provider, &urls[0]).await, b"handout");
+    }
+
+    #[tokio::test]
+    async fn reject_invalid_archive() {
+        let (db, upload_provider) = test_instance().await;
+
+        let mut builder = Builder::new(Cursor::new(vec![]));
+        append_entry(&mut builder, "tables/rhombus_user.json", b"{}")
+            .await
+            .unwrap();
+        let archive = builder.into_inner().await.unwrap();
+
+        let result = restore_backup(&db, &upload_provider, Cursor::new(archive.into_inner())).await;
+        assert!(matches!(result, Err(RhombusError::Backup(_))));
+    }
+
+    /// Files must not be uploaded when the tables cannot be imported
+    #[tokio::test]
+    async fn reject_restore_over_existing_users() {
+        let (_, archive) = test_archive().await;
+
+        let (db, upload_provider) = test_instance().await;
+        db.upsert_user_by_credentials("bob", "", "password", "http://localhost:3000")
+            .await
+            .unwrap()
+            .unwrap();
+
+        let result = restore_backup(&db, &upload_provider, Cursor::new(archive)).await;
+        assert!(matches!(result, Err(RhombusError::Backup(_))));
+        assert!(upload_provider.list().await.unwrap().is_empty());
+    }
+
+    /// Files uploaded before a restore fails are deleted again, but files which
+    /// were already uploaded before the restore are kept
+    #[tokio::test]
+    async fn delete_uploads_of_failed_restore() {
+        let (db, upload_provider) = test_instance().await;
+        let existing_url = upload(&upload_provider, b"existing").await;
+
+        let manifest = BackupManifest {
+            version: BACKUP_VERSION,
+            rhombus_version: env!("CARGO_PKG_VERSION").to_owned(),
+            created_at: Utc::now(),
+            tables: vec![],
+            files: (0..3)
+                .map(|i| BackupFile {
+                    url: format!("/uploads/{}-handout.txt", i),
+                    filename: "handout.txt".to_owned(),
+                    path: format!("files/{}", i),
+                })
+                .collect(),
+        };
+        let mut builder = Builder::new(Cursor::new(vec![]));
+        append_entry(
+            &mut builder,
+            MANIFEST_PATH,
+            &serde_json::to_vec(&manifest).unwrap(),
+        )
+        .await
+        .unwrap();
+        append_entry(&mut builder, "files/0", b"new").await.unwrap();
+        append_entry(&mut builder, "files/1", b"existing")
+            .await
+            .unwrap();
+        let archive = builder.into_inner().await.unwrap();
+
+        // files/2 is missing from the archive
+        let result = restore_backup(&db, &upload_provider, Cursor::new(archive.into_inner())).await;
+        assert!(matches!(result, Err(RhombusError::Backup(_))));
+        assert_eq!(upload_provider.list().await.unwrap(), vec![existing_url]);
+    }
+}
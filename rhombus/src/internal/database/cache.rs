@@ -15,10 +15,16 @@ use tokio_util::bytes::Bytes;
 use crate::{
     internal::{
         auth::User,
+        backup::BackupTable,
         database::provider::{
-            Challenge, ChallengeData, Challenges, Connection, Database, DiscordUpsertError, Email,
-            Leaderboard, Scoreboard, SetAccountNameError, SetTeamNameError, SiteStatistics, Team,
-            TeamInner, TeamMeta, TeamStanding, Ticket, ToBeClosedTicket, UserTrack, Writeup,
+            Challenge, ChallengeData, Challenges, Connection, Database, DiscordUpsertError,
+            DivisionApproval, Email, IdentityUpsertError, Leaderboard, PasskeyCredential,
+            PasswordReset, RegisterError, Scoreboard, SetAccountNameError, SetPasswordError,
+            SetTeamNameError, SignInMethod, SiteStatistics, StoredDivision, Team, TeamDiscord,
+            TeamDivisionChange, TeamInner, TeamInvite, TeamJoinRequest, TeamMeta, TeamStanding,
+            Ticket, TicketMessage, TicketMessageAuthor, TicketStats, TicketStatus,
+            ToBeClosedTicket, UserIdentity, UserPasskey, UserSession, UserSignInMethods, UserTotp,
+            UserTrack, Writeup,
         },
         division::Division,
         settings::Settings,
@@ -107,6 +113,36 @@ impl Database for DbCache {
         result
     }
 
+    async fn login_user_by_credentials(
+        &self,
+        username: &str,
+        password: &str,
+    ) -> Result<Option<(i64, i64)>> {
+        self.inner
+            .login_user_by_credentials(username, password)
+            .await
+    }
+
+    async fn register_user_by_credentials(
+        &self,
+        username: &str,
+        avatar: &str,
+        password: &str,
+        email: Option<&str>,
+        location_url: &str,
+    ) -> Result<std::result::Result<(i64, i64, Option<String>), RegisterError>> {
+        let result = self
+            .inner
+            .register_user_by_credentials(username, avatar, password, email, location_url)
+            .await;
+
+        if let Ok(Ok(result)) = &result {
+            USER_CACHE.remove(&result.0);
+            TEAM_CACHE.remove(&result.1);
+        }
+        result
+    }
+
     async fn upsert_user_by_ctftime(
         &self,
         name: &str,
@@ -136,6 +172,225 @@ impl Database for DbCache {
         result
     }
 
+    async fn upsert_user_by_oidc(
+        &self,
+        name: &str,
+        email: Option<&str>,
+        avatar: &str,
+        subject: &str,
+        user_id: Option<i64>,
+        location_url: &str,
+    ) -> Result<std::result::Result<(i64, i64), IdentityUpsertError>> {
+        let result = self
+            .inner
+            .upsert_user_by_oidc(name, email, avatar, subject, user_id, location_url)
+            .await;
+        if let Ok(Ok(result)) = result {
+            USER_CACHE.remove(&result.0);
+            TEAM_CACHE.remove(&result.1);
+        }
+        result
+    }
+
+    async fn upsert_user_by_github(
+        &self,
+        name: &str,
+        email: Option<&str>,
+        avatar: &str,
+        github_id: i64,
+        user_id: Option<i64>,
+        location_url: &str,
+    ) -> Result<std::result::Result<(i64, i64), IdentityUpsertError>> {
+        let result = self
+            .inner
+            .upsert_user_by_github(name, email, avatar, github_id, user_id, location_url)
+            .await;
+        if let Ok(Ok(result)) = result {
+            USER_CACHE.remove(&result.0);
+            TEAM_CACHE.remove(&result.1);
+        }
+        result
+    }
+
+    async fn upsert_user_by_google(
+        &self,
+        name: &str,
+        email: Option<&str>,
+        avatar: &str,
+        google_id: &str,
+        user_id: Option<i64>,
+        location_url: &str,
+    ) -> Result<std::result::Result<(i64, i64), IdentityUpsertError>> {
+        let result = self
+            .inner
+            .upsert_user_by_google(name, email, avatar, google_id, user_id, location_url)
+            .await;
+        if let Ok(Ok(result)) = result {
+            USER_CACHE.remove(&result.0);
+            TEAM_CACHE.remove(&result.1);
+        }
+        result
+    }
+
+    async fn upsert_user_by_ldap(
+        &self,
+        name: &str,
+        email: Option<&str>,
+        avatar: &str,
+        dn: &str,
+        location_url: &str,
+    ) -> Result<std::result::Result<(i64, i64), IdentityUpsertError>> {
+        let result = self
+            .inner
+            .upsert_user_by_ldap(name, email, avatar, dn, location_url)
+            .await;
+        if let Ok(Ok(result)) = result {
+            USER_CACHE.remove(&result.0);
+            TEAM_CACHE.remove(&result.1);
+        }
+        result
+    }
+
+    async fn get_user_identities(&self, user_id: i64) -> Result<Vec<UserIdentity>> {
+        self.inner.get_user_identities(user_id).await
+    }
+
+    async fn get_user_sign_in_methods(&self, user_id: i64) -> Result<UserSignInMethods> {
+        self.inner.get_user_sign_in_methods(user_id).await
+    }
+
+    async fn link_user_ctftime(
+        &self,
+        user_id: i64,
+        ctftime_user_id: i64,
+    ) -> Result<std::result::Result<(), IdentityUpsertError>> {
+        self.inner.link_user_ctftime(user_id, ctftime_user_id).await
+    }
+
+    async fn set_user_password(
+        &self,
+        user_id: i64,
+        current_password: Option<&str>,
+        password: &str,
+    ) -> Result<std::result::Result<(), SetPasswordError>> {
+        self.inner
+            .set_user_password(user_id, current_password, password)
+            .await
+    }
+
+    async fn create_password_reset_code(&self, email: &str) -> Result<Option<PasswordReset>> {
+        self.inner.create_password_reset_code(email).await
+    }
+
+    async fn get_password_reset_by_callback_code(&self, code: &str) -> Result<String> {
+        self.inner.get_password_reset_by_callback_code(code).await
+    }
+
+    async fn reset_password_by_callback_code(&self, code: &str, password: &str) -> Result<i64> {
+        self.inner
+            .reset_password_by_callback_code(code, password)
+            .await
+    }
+
+    async fn unlink_sign_in_method(&self, user_id: i64, method: SignInMethod<'_>) -> Result<()> {
+        self.inner.unlink_sign_in_method(user_id, method).await?;
+        USER_CACHE.remove(&user_id);
+        Ok(())
+    }
+
+    async fn get_user_totp(&self, user_id: i64) -> Result<Option<UserTotp>> {
+        self.inner.get_user_totp(user_id).await
+    }
+
+    async fn set_user_totp_pending(&self, user_id: i64, secret: &str) -> Result<()> {
+        self.inner.set_user_totp_pending(user_id, secret).await
+    }
+
+    async fn enable_user_totp(&self, user_id: i64, recovery_code_hashes: &[String]) -> Result<()> {
+        self.inner
+            .enable_user_totp(user_id, recovery_code_hashes)
+            .await
+    }
+
+    async fn disable_user_totp(&self, user_id: i64) -> Result<()> {
+        self.inner.disable_user_totp(user_id).await
+    }
+
+    async fn use_user_totp_step(&self, user_id: i64, step: i64) -> Result<bool> {
+        self.inner.use_user_totp_step(user_id, step).await
+    }
+
+    async fn use_user_recovery_code(&self, user_id: i64, code_hash: &str) -> Result<bool> {
+        self.inner.use_user_recovery_code(user_id, code_hash).await
+    }
+
+    async fn add_user_passkey(
+        &self,
+        user_id: i64,
+        id: &str,
+        name: &str,
+        public_key: &[u8],
+        algorithm: i64,
+        sign_count: i64,
+    ) -> Result<()> {
+        self.inner
+            .add_user_passkey(user_id, id, name, public_key, algorithm, sign_count)
+            .await
+    }
+
+    async fn get_user_passkeys(&self, user_id: i64) -> Result<Vec<UserPasskey>> {
+        self.inner.get_user_passkeys(user_id).await
+    }
+
+    async fn get_passkey_credential(&self, id: &str) -> Result<Option<PasskeyCredential>> {
+        self.inner.get_passkey_credential(id).await
+    }
+
+    async fn use_passkey(&self, id: &str, sign_count: i64) -> Result<bool> {
+        self.inner.use_passkey(id, sign_count).await
+    }
+
+    async fn delete_user_passkey(&self, user_id: i64, id: &str) -> Result<()> {
+        self.inner.delete_user_passkey(user_id, id).await
+    }
+
+    async fn create_session(&self, id: &str, user_id: i64, expires: i64) -> Result<()> {
+        self.inner.create_session(id, user_id, expires).await
+    }
+
+    async fn get_session_user_id(&self, id: &str) -> Result<Option<i64>> {
+        get_session_user_id(&self.inner, id).await
+    }
+
+    async fn get_user_sessions(&self, user_id: i64) -> Result<Vec<UserSession>> {
+        self.inner.get_user_sessions(user_id).await
+    }
+
+    async fn touch_session(
+        &self,
+        id: &str,
+        ip: Option<IpAddr>,
+        user_agent: Option<&str>,
+        last_seen_at: i64,
+    ) -> Result<()> {
+        self.inner
+            .touch_session(id, ip, user_agent, last_seen_at)
+            .await
+    }
+
+    async fn revoke_session(&self, user_id: i64, id: &str) -> Result<()> {
+        self.inner.revoke_session(user_id, id).await?;
+        SESSION_CACHE.remove(id);
+        Ok(())
+    }
+
+    async fn revoke_user_sessions(&self, user_id: i64, except: Option<&str>) -> Result<()> {
+        self.inner.revoke_user_sessions(user_id, except).await?;
+        SESSION_CACHE
+            .retain(|id, session| session.value != Some(user_id) || Some(id.as_str()) == except);
+        Ok(())
+    }
+
     async fn insert_track(
         &self,
         ip: IpAddr,
@@ -219,6 +474,12 @@ impl Database for DbCache {
         get_user_from_id(&self.inner, user_id).await
     }
 
+    async fn set_user_admin(&self, user_id: i64, is_admin: bool) -> Result<()> {
+        self.inner.set_user_admin(user_id, is_admin).await?;
+        USER_CACHE.remove(&user_id);
+        Ok(())
+    }
+
     async fn get_user_from_discord_id(&self, discord_id: NonZeroU64) -> Result<User> {
         self.inner.get_user_from_discord_id(discord_id).await
     }
@@ -237,6 +498,49 @@ impl Database for DbCache {
         result
     }
 
+    async fn get_team_discord(&self, team_id: i64) -> Result<TeamDiscord> {
+        self.inner.get_team_discord(team_id).await
+    }
+
+    async fn set_team_discord(&self, team_id: i64, team_discord: &TeamDiscord) -> Result<()> {
+        self.inner.set_team_discord(team_id, team_discord).await
+    }
+
+    async fn get_team_discords(&self) -> Result<Vec<(i64, TeamDiscord)>> {
+        self.inner.get_team_discords().await
+    }
+
+    async fn transfer_team_ownership(
+        &self,
+        team_id: i64,
+        old_owner_user_id: i64,
+        new_owner_user_id: i64,
+    ) -> Result<i64> {
+        let result = self
+            .inner
+            .transfer_team_ownership(team_id, old_owner_user_id, new_owner_user_id)
+            .await;
+        if let Ok(old_owner_team_id) = result {
+            USER_CACHE.remove(&old_owner_user_id);
+            USER_CACHE.remove(&new_owner_user_id);
+            TEAM_CACHE.remove(&team_id);
+            TEAM_CACHE.remove(&old_owner_team_id);
+        }
+        result
+    }
+
+    async fn disband_team(&self, team_id: i64) -> Result<Vec<(i64, i64)>> {
+        let result = self.inner.disband_team(team_id).await;
+        if let Ok(moved) = &result {
+            TEAM_CACHE.remove(&team_id);
+            for (user_id, new_team_id) in moved {
+                USER_CACHE.remove(user_id);
+                TEAM_CACHE.remove(new_team_id);
+            }
+        }
+        result
+    }
+
     async fn roll_invite_token(&self, team_id: i64) -> Result<String> {
         let new_invite_token = self.inner.roll_invite_token(team_id).await;
         if new_invite_token.is_ok() {
@@ -245,6 +549,43 @@ impl Database for DbCache {
         new_invite_token
     }
 
+    async fn create_team_invite(
+        &self,
+        team_id: i64,
+        user_id: Option<i64>,
+        email: Option<&str>,
+    ) -> Result<String> {
+        self.inner.create_team_invite(team_id, user_id, email).await
+    }
+
+    async fn get_team_invite(&self, code: &str) -> Result<Option<TeamInvite>> {
+        self.inner.get_team_invite(code).await
+    }
+
+    async fn get_team_invites(&self, team_id: i64) -> Result<Vec<TeamInvite>> {
+        self.inner.get_team_invites(team_id).await
+    }
+
+    async fn get_user_team_invites(&self, user_id: i64) -> Result<Vec<TeamInvite>> {
+        self.inner.get_user_team_invites(user_id).await
+    }
+
+    async fn delete_team_invite(&self, code: &str) -> Result<()> {
+        self.inner.delete_team_invite(code).await
+    }
+
+    async fn create_team_join_request(&self, team_id: i64, user_id: i64) -> Result<()> {
+        self.inner.create_team_join_request(team_id, user_id).await
+    }
+
+    async fn get_team_join_requests(&self, team_id: i64) -> Result<Vec<TeamJoinRequest>> {
+        self.inner.get_team_join_requests(team_id).await
+    }
+
+    async fn delete_team_join_request(&self, team_id: i64, user_id: i64) -> Result<bool> {
+        self.inner.delete_team_join_request(team_id, user_id).await
+    }
+
     async fn roll_api_key(&self, user_id: i64, location_url: &str) -> Result<String> {
         let user = self.get_user_from_id(user_id).await?;
         USER_ID_CACHE_BY_API_KEY.remove(&user.api_key);
@@ -384,20 +725,95 @@ impl Database for DbCache {
         ticket_number: u64,
         user_id: i64,
         challenge_id: &str,
-        discord_channel_id: NonZeroU64,
-        panel_discord_message_id: NonZeroU64,
+        author: &TicketMessageAuthor<'_>,
+        content: &str,
+        assignee_discord_id: Option<NonZeroU64>,
     ) -> Result<()> {
         self.inner
             .create_ticket(
                 ticket_number,
                 user_id,
                 challenge_id,
-                discord_channel_id,
-                panel_discord_message_id,
+                author,
+                content,
+                assignee_discord_id,
             )
             .await
     }
 
+    async fn set_ticket_discord(
+        &self,
+        ticket_number: u64,
+        discord_channel_id: NonZeroU64,
+        discord_panel_message_id: NonZeroU64,
+    ) -> Result<()> {
+        self.inner
+            .set_ticket_discord(ticket_number, discord_channel_id, discord_panel_message_id)
+            .await
+    }
+
+    async fn add_ticket_message(
+        &self,
+        ticket_number: u64,
+        author: &TicketMessageAuthor<'_>,
+        content: &str,
+        discord_message_id: Option<NonZeroU64>,
+        is_staff: bool,
+    ) -> Result<()> {
+        self.inner
+            .add_ticket_message(ticket_number, author, content, discord_message_id, is_staff)
+            .await
+    }
+
+    async fn edit_ticket_message_by_discord_id(
+        &self,
+        discord_message_id: NonZeroU64,
+        content: &str,
+        edited_at: DateTime<Utc>,
+    ) -> Result<Option<u64>> {
+        self.inner
+            .edit_ticket_message_by_discord_id(discord_message_id, content, edited_at)
+            .await
+    }
+
+    async fn get_ticket_messages(&self, ticket_number: u64) -> Result<Vec<TicketMessage>> {
+        self.inner.get_ticket_messages(ticket_number).await
+    }
+
+    async fn get_user_tickets(&self, user_id: i64) -> Result<Vec<Ticket>> {
+        self.inner.get_user_tickets(user_id).await
+    }
+
+    async fn get_tickets(&self, include_closed: bool) -> Result<Vec<Ticket>> {
+        self.inner.get_tickets(include_closed).await
+    }
+
+    async fn get_challenge_tickets(&self, challenge_id: &str) -> Result<Vec<Ticket>> {
+        self.inner.get_challenge_tickets(challenge_id).await
+    }
+
+    async fn set_ticket_status(&self, ticket_number: u64, status: TicketStatus) -> Result<()> {
+        self.inner.set_ticket_status(ticket_number, status).await
+    }
+
+    async fn set_ticket_assignee(
+        &self,
+        ticket_number: u64,
+        assignee_discord_id: Option<NonZeroU64>,
+    ) -> Result<()> {
+        self.inner
+            .set_ticket_assignee(ticket_number, assignee_discord_id)
+            .await
+    }
+
+    async fn set_ticket_tags(&self, ticket_number: u64, tags: &[String]) -> Result<()> {
+        self.inner.set_ticket_tags(ticket_number, tags).await
+    }
+
+    async fn get_ticket_stats(&self) -> Result<TicketStats> {
+        self.inner.get_ticket_stats().await
+    }
+
     async fn get_ticket_by_ticket_number(&self, ticket_number: u64) -> Result<Ticket> {
         self.inner.get_ticket_by_ticket_number(ticket_number).await
     }
@@ -497,7 +913,7 @@ impl Database for DbCache {
         result
     }
 
-    async fn verify_email_verification_callback_code(&self, code: &str) -> Result<()> {
+    async fn verify_email_verification_callback_code(&self, code: &str) -> Result<i64> {
         let result = self
             .inner
             .verify_email_verification_callback_code(code)
@@ -540,10 +956,17 @@ impl Database for DbCache {
         old_division_id: &str,
         new_division_id: &str,
         now: DateTime<Utc>,
+        admin_user_id: Option<i64>,
     ) -> Result<()> {
         let result = self
             .inner
-            .set_team_division(team_id, old_division_id, new_division_id, now)
+            .set_team_division(
+                team_id,
+                old_division_id,
+                new_division_id,
+                now,
+                admin_user_id,
+            )
             .await;
         if result.is_ok() {
             TEAM_CACHE.alter(&team_id, |_, v| TimedCache {
@@ -557,6 +980,7 @@ impl Database for DbCache {
                     solves: v.value.solves.clone(),
                     writeups: v.value.writeups.clone(),
                     owner_user_id: v.value.owner_user_id,
+                    ctftime_id: v.value.ctftime_id,
                 }),
                 insert_timestamp: v.insert_timestamp,
             });
@@ -597,6 +1021,73 @@ impl Database for DbCache {
         result
     }
 
+    async fn get_divisions(&self) -> Result<Vec<StoredDivision>> {
+        self.inner.get_divisions().await
+    }
+
+    async fn create_division(
+        &self,
+        division_id: &str,
+        name: &str,
+        description: &str,
+        max_players: Option<&str>,
+    ) -> Result<bool> {
+        self.inner
+            .create_division(division_id, name, description, max_players)
+            .await
+    }
+
+    async fn update_division(
+        &self,
+        division_id: &str,
+        name: &str,
+        description: &str,
+        max_players: Option<&str>,
+    ) -> Result<()> {
+        self.inner
+            .update_division(division_id, name, description, max_players)
+            .await
+    }
+
+    async fn set_division_archived(&self, division_id: &str, archived: bool) -> Result<()> {
+        self.inner
+            .set_division_archived(division_id, archived)
+            .await
+    }
+
+    async fn get_division_teams(&self, division_id: &str) -> Result<Vec<TeamMeta>> {
+        self.inner.get_division_teams(division_id).await
+    }
+
+    async fn get_division_history(&self, division_id: &str) -> Result<Vec<TeamDivisionChange>> {
+        self.inner.get_division_history(division_id).await
+    }
+
+    async fn request_division_approval(&self, division_id: &str, user_id: i64) -> Result<()> {
+        self.inner
+            .request_division_approval(division_id, user_id)
+            .await
+    }
+
+    async fn get_division_approval(&self, division_id: &str, user_id: i64) -> Result<Option<bool>> {
+        self.inner.get_division_approval(division_id, user_id).await
+    }
+
+    async fn get_division_approvals(&self, include_decided: bool) -> Result<Vec<DivisionApproval>> {
+        self.inner.get_division_approvals(include_decided).await
+    }
+
+    async fn set_division_approval(
+        &self,
+        division_id: &str,
+        user_id: i64,
+        approved: bool,
+    ) -> Result<()> {
+        self.inner
+            .set_division_approval(division_id, user_id, approved)
+            .await
+    }
+
     async fn get_team_standing(&self, team_id: i64) -> Result<Option<TeamStanding>> {
         get_team_standing(&self.inner, team_id).await
     }
@@ -605,8 +1096,20 @@ impl Database for DbCache {
         self.inner.upload_file(hash, filename, bytes).await
     }
 
-    async fn download_file(&self, hash: &str) -> Result<(Bytes, String)> {
-        self.inner.download_file(hash).await
+    async fn get_file_info(&self, hash: &str) -> Result<(String, u64)> {
+        self.inner.get_file_info(hash).await
+    }
+
+    async fn download_file_chunk(&self, hash: &str, offset: u64, length: u64) -> Result<Bytes> {
+        self.inner.download_file_chunk(hash, offset, length).await
+    }
+
+    async fn list_files(&self) -> Result<Vec<(String, String)>> {
+        self.inner.list_files().await
+    }
+
+    async fn delete_file(&self, hash: &str) -> Result<()> {
+        self.inner.delete_file(hash).await
     }
 
     async fn get_site_statistics(&self) -> Result<SiteStatistics> {
@@ -616,6 +1119,34 @@ impl Database for DbCache {
     async fn get_last_created_ticket_time(&self, user_id: i64) -> Result<Option<DateTime<Utc>>> {
         self.inner.get_last_created_ticket_time(user_id).await
     }
+
+    async fn get_uploaded_attachment_urls(&self) -> Result<Vec<String>> {
+        self.inner.get_uploaded_attachment_urls().await
+    }
+
+    async fn rewrite_attachment_urls(&self, urls: &BTreeMap<String, String>) -> Result<()> {
+        let result = self.inner.rewrite_attachment_urls(urls).await;
+        if result.is_ok() {
+            *CHALLENGES_CACHE.write().await = None;
+        }
+        result
+    }
+
+    async fn export_backup_tables(&self) -> Result<BTreeMap<String, BackupTable>> {
+        self.inner.export_backup_tables().await
+    }
+
+    async fn check_backup_tables(&self, tables: &BTreeMap<String, BackupTable>) -> Result<()> {
+        self.inner.check_backup_tables(tables).await
+    }
+
+    async fn import_backup_tables(&self, tables: &BTreeMap<String, BackupTable>) -> Result<()> {
+        let result = self.inner.import_backup_tables(tables).await;
+        if result.is_ok() {
+            clear_all_caches().await;
+        }
+        result
+    }
 }
 
 pub static CHALLENGES_CACHE: LazyLock<RwLock<Option<Challenges>>> = LazyLock::new(RwLock::default);
@@ -699,6 +1230,24 @@ pub async fn get_user_from_api_key(db: &Connection, api_key: &str) -> Result<Use
     user
 }
 
+/// Caches which user a session belongs to, including revoked sessions as `None`
+pub static SESSION_CACHE: LazyLock<DashMap<String, TimedCache<Option<i64>>>> =
+    LazyLock::new(DashMap::new);
+
+pub async fn get_session_user_id(db: &Connection, id: &str) -> Result<Option<i64>> {
+    if let Some(user_id) = SESSION_CACHE.get(id) {
+        return Ok(user_id.value);
+    }
+    tracing::trace!("cache miss: get_session_user_id");
+
+    let user_id = db.get_session_user_id(id).await;
+
+    if let Ok(user_id) = &user_id {
+        SESSION_CACHE.insert(id.to_owned(), TimedCache::new(*user_id));
+    }
+    user_id
+}
+
 pub type Writeups = Arc<BTreeMap<i64, Writeup>>;
 
 pub static USER_WRITEUP_CACHE: LazyLock<DashMap<i64, TimedCache<Writeups>>> =
@@ -853,6 +1402,20 @@ pub fn database_cache_evictor(seconds: u64, db: Connection) {
                 tracing::trace!(count, "Evicted leaderboard cache");
             }
 
+            // Session cache
+            let mut count: i64 = 0;
+            SESSION_CACHE.retain(|_, v| {
+                if v.insert_timestamp > evict_threshold {
+                    true
+                } else {
+                    count += 1;
+                    false
+                }
+            });
+            if count > 0 {
+                tracing::trace!(count, "Evicted session cache");
+            }
+
             // User emails cache
             let mut count: i64 = 0;
             USER_EMAILS_CACHE.retain(|_, v| {
@@ -880,7 +1443,6 @@ pub fn database_cache_evictor(seconds: u64, db: Connection) {
     });
 }
 
-#[allow(unused)]
 pub async fn clear_all_caches() {
     USER_CACHE.clear();
     TEAM_CACHE.clear();
@@ -888,6 +1450,7 @@ pub async fn clear_all_caches() {
     SCOREBOARD_CACHE.clear();
     LEADERBOARD_CACHE.clear();
     USER_EMAILS_CACHE.clear();
+    SESSION_CACHE.clear();
     TEAM_STANDINGS.clear();
     *CHALLENGES_CACHE.write().await = None;
 }
@@ -1,7 +1,7 @@
 use std::{
     collections::{BTreeMap, BTreeSet},
     future::Future,
-    net::IpAddr,
+    net::{IpAddr, Ipv6Addr},
     num::NonZeroU64,
     path::Path,
     pin::Pin,
@@ -11,6 +11,7 @@ use std::{
 
 use argon2::{password_hash::SaltString, Argon2, PasswordHash, PasswordHasher, PasswordVerifier};
 use async_trait::async_trait;
+use base64::{engine::general_purpose::STANDARD, Engine};
 use chrono::{DateTime, TimeZone, Utc};
 use futures::stream::StreamExt;
 use libsql::{de, params, Builder, Transaction};
@@ -23,15 +24,20 @@ use crate::{
     errors::RhombusError,
     internal::{
         auth::{create_user_api_key, User, UserInner},
+        backup::{BackupTable, BackupValue},
         database::{
             cache::Writeups,
             provider::{
                 Author, Category, Challenge, ChallengeAttachment, ChallengeData, ChallengeDivision,
-                ChallengeSolve, Challenges, Database, DiscordUpsertError, Email, Leaderboard,
-                LeaderboardEntry, Scoreboard, ScoreboardInner, ScoreboardSeriesPoint,
-                ScoreboardTeam, SetAccountNameError, SetTeamNameError, SiteStatistics,
-                StatisticsCategory, Team, TeamInner, TeamMeta, TeamMetaInner, TeamStanding,
-                TeamUser, Ticket, ToBeClosedTicket, UserTrack, Writeup,
+                ChallengeSolve, Challenges, Database, DiscordUpsertError, DivisionApproval, Email,
+                IdentityUpsertError, Leaderboard, LeaderboardEntry, PasskeyCredential,
+                PasswordReset, RegisterError, Scoreboard, ScoreboardInner, ScoreboardSeriesPoint,
+                ScoreboardTeam, SetAccountNameError, SetPasswordError, SetTeamNameError,
+                SignInMethod, SiteStatistics, StatisticsCategory, StoredDivision, Team,
+                TeamDiscord, TeamDivisionChange, TeamInner, TeamInvite, TeamJoinRequest, TeamMeta,
+                TeamMetaInner, TeamStanding, TeamUser, Ticket, TicketMessage, TicketMessageAuthor,
+                TicketStats, TicketStatus, ToBeClosedTicket, UserIdentity, UserPasskey,
+                UserSession, UserSignInMethods, UserTotp, UserTrack, Writeup,
             },
         },
         division::Division,
@@ -205,16 +211,306 @@ impl From<RemoteLibSQL> for LibSQL {
 #[folder = "migrations/libsql"]
 struct Migrations;
 
+/// Changes to the schema after `0001_setup.up.sql`, which runs on every startup and so can only
+/// create what does not exist yet. Each runs once, in order, and is recorded in `user_version`.
+const SCHEMA_MIGRATIONS: &[(i64, &str)] = &[
+    (2, "0002_division_management.up.sql"),
+    (3, "0003_team_discord.up.sql"),
+    (4, "0004_ticket_storage.up.sql"),
+    (5, "0005_ticket_workflow.up.sql"),
+];
+
+fn migration_sql(name: &str) -> String {
+    String::from_utf8(Migrations::get(name).unwrap().data.into_owned()).unwrap()
+}
+
+const TEAM_INVITE_SELECT: &str = "
+    SELECT rhombus_team_invite.code, rhombus_team_invite.team_id, rhombus_team.name AS team_name,
+        rhombus_team_invite.user_id, rhombus_user.name AS user_name, rhombus_team_invite.email,
+        rhombus_team_invite.created_at
+    FROM rhombus_team_invite
+    JOIN rhombus_team ON rhombus_team.id = rhombus_team_invite.team_id
+    LEFT JOIN rhombus_user ON rhombus_user.id = rhombus_team_invite.user_id
+";
+
+#[derive(Debug, Deserialize)]
+struct QueryTeamInvite {
+    code: String,
+    team_id: i64,
+    team_name: String,
+    user_id: Option<i64>,
+    user_name: Option<String>,
+    email: Option<String>,
+    created_at: i64,
+}
+
+impl From<QueryTeamInvite> for TeamInvite {
+    fn from(invite: QueryTeamInvite) -> Self {
+        TeamInvite {
+            code: invite.code,
+            team_id: invite.team_id,
+            team_name: invite.team_name,
+            user_id: invite.user_id,
+            user_name: invite.user_name,
+            email: invite.email,
+            created_at: DateTime::<Utc>::from_timestamp(invite.created_at, 0).unwrap(),
+        }
+    }
+}
+
+const TICKET_SELECT: &str = "
+    SELECT ticket_number, user_id, challenge_id, opened_at, closed_at, status, assignee_discord_id,
+        first_response_at, discord_channel_id, discord_panel_message_id,
+        (
+            SELECT GROUP_CONCAT(tag, ',')
+            FROM rhombus_ticket_tag
+            WHERE rhombus_ticket_tag.ticket_number = rhombus_ticket.ticket_number
+        ) AS tags
+    FROM rhombus_ticket
+";
+
+#[derive(Debug, Deserialize)]
+struct DbTicket {
+    ticket_number: u64,
+    user_id: i64,
+    challenge_id: String,
+    opened_at: i64,
+    closed_at: Option<i64>,
+    status: String,
+    assignee_discord_id: Option<NonZeroU64>,
+    first_response_at: Option<i64>,
+    discord_channel_id: Option<NonZeroU64>,
+    discord_panel_message_id: Option<NonZeroU64>,
+    tags: Option<String>,
+}
+
+impl DbTicket {
+    fn into_ticket(self, email_references: Vec<(String, bool)>) -> Ticket {
+        Ticket {
+            ticket_number: self.ticket_number,
+            user_id: self.user_id,
+            challenge_id: self.challenge_id,
+            opened_at: DateTime::<Utc>::from_timestamp(self.opened_at, 0).unwrap(),
+            closed_at: self
+                .closed_at
+                .map(|ts| DateTime::<Utc>::from_timestamp(ts, 0).unwrap()),
+            status: if self.closed_at.is_some() {
+                TicketStatus::Closed
+            } else {
+                TicketStatus::parse(&self.status).unwrap_or(TicketStatus::Open)
+            },
+            assignee_discord_id: self.assignee_discord_id,
+            first_response_at: self
+                .first_response_at
+                .map(|ts| DateTime::<Utc>::from_timestamp(ts, 0).unwrap()),
+            tags: self
+                .tags
+                .map(|tags| tags.split(',').map(str::to_owned).collect())
+                .unwrap_or_default(),
+            discord_channel_id: self.discord_channel_id,
+            discord_panel_message_id: self.discord_panel_message_id,
+            email_in_reply_to: email_references
+                .iter()
+                .find(|(_, user_sent)| !user_sent)
+                .map(|(message_id, _)| message_id.clone()),
+            email_references: email_references
+                .into_iter()
+                .map(|(message_id, _)| message_id)
+                .collect(),
+        }
+    }
+}
+
+/// Email message ids sent for a ticket, and whether the user sent them
+async fn get_ticket_email_references(
+    tx: &Transaction,
+    ticket_number: u64,
+) -> Result<Vec<(String, bool)>> {
+    #[derive(Debug, Deserialize)]
+    struct DbTicketEmailMessageIdReference {
+        pub message_id: String,
+        pub user_sent: bool,
+    }
+
+    let email_references = tx
+        .query(
+            "
+            SELECT message_id, user_sent
+            FROM rhombus_ticket_email_message_id_reference
+            WHERE ticket_number = ?1
+        ",
+            [ticket_number],
+        )
+        .await?
+        .into_stream()
+        .map(|row| de::from_row::<DbTicketEmailMessageIdReference>(&row.unwrap()).unwrap())
+        .map(|r| (r.message_id, r.user_sent))
+        .collect::<Vec<_>>()
+        .await;
+
+    Ok(email_references)
+}
+
+/// Tables included in a backup, in an order which satisfies foreign key constraints.
+/// Uploaded file contents are backed up through the upload provider instead of `rhombus_file`.
+const BACKUP_TABLES: &[&str] = &[
+    "rhombus_division",
+    "rhombus_author",
+    "rhombus_category",
+    "rhombus_challenge",
+    "rhombus_file_attachment",
+    "rhombus_team",
+    "rhombus_team_historical_names",
+    "rhombus_team_division_history",
+    "rhombus_user",
+    "rhombus_user_historical_names",
+    "rhombus_team_invite",
+    "rhombus_team_join_request",
+    "rhombus_division_approval",
+    "rhombus_email",
+    "rhombus_user_identity",
+    "rhombus_user_totp",
+    "rhombus_user_recovery_code",
+    "rhombus_user_passkey",
+    "rhombus_points_snapshot",
+    "rhombus_solve",
+    "rhombus_challenge_division_solves",
+    "rhombus_writeup",
+    "rhombus_ticket",
+    "rhombus_ticket_message",
+    "rhombus_ticket_tag",
+    "rhombus_ticket_email_message_id_reference",
+    "rhombus_ticket_number_counter",
+    "rhombus_config",
+    "rhombus_track",
+    "rhombus_track_ip",
+];
+
+/// Make sure `tables` can be imported into this database: it must not have any users yet,
+/// and every table and column must exist in the current schema
+async fn check_backup_tables(
+    conn: &libsql::Connection,
+    tables: &BTreeMap<String, BackupTable>,
+) -> Result<()> {
+    let existing_users = conn
+        .query("SELECT COUNT(*) FROM rhombus_user", ())
+        .await?
+        .next()
+        .await?
+        .ok_or(RhombusError::DatabaseReturnedNoRows)?
+        .get::<i64>(0)?;
+    if existing_users > 0 {
+        return Err(RhombusError::Backup(format!(
+            "refusing to restore into a database which already has {} users",
+            existing_users
+        )));
+    }
+
+    for (table, backup_table) in tables {
+        if !BACKUP_TABLES.contains(&table.as_str()) {
+            return Err(RhombusError::Backup(format!("unknown table {}", table)));
+        }
+
+        let columns = conn
+            .query("SELECT name FROM pragma_table_info(?1)", [table.as_str()])
+            .await?
+            .into_stream()
+            .map(|row| row.and_then(|row| row.get::<String>(0)))
+            .collect::<Vec<_>>()
+            .await
+            .into_iter()
+            .collect::<std::result::Result<BTreeSet<_>, _>>()?;
+
+        let valid_columns = !backup_table.columns.is_empty()
+            && backup_table
+                .columns
+                .iter()
+                .all(|column| columns.contains(column));
+        if !valid_columns {
+            return Err(RhombusError::Backup(format!(
+                "invalid columns in table {}",
+                table
+            )));
+        }
+
+        if backup_table
+            .rows
+            .iter()
+            .any(|row| row.len() != backup_table.columns.len())
+        {
+            return Err(RhombusError::Backup(format!(
+                "rows do not match the columns of table {}",
+                table
+            )));
+        }
+    }
+
+    Ok(())
+}
+
+fn to_backup_value(value: libsql::Value) -> BackupValue {
+    match value {
+        libsql::Value::Null => BackupValue::Null,
+        libsql::Value::Integer(i) => BackupValue::Integer(i),
+        libsql::Value::Real(r) => BackupValue::Real(r),
+        libsql::Value::Text(t) => BackupValue::Text(t),
+        libsql::Value::Blob(b) => BackupValue::Blob(STANDARD.encode(b)),
+    }
+}
+
+fn from_backup_value(value: &BackupValue) -> Result<libsql::Value> {
+    Ok(match value {
+        BackupValue::Null => libsql::Value::Null,
+        BackupValue::Integer(i) => libsql::Value::Integer(*i),
+        BackupValue::Real(r) => libsql::Value::Real(*r),
+        BackupValue::Text(t) => libsql::Value::Text(t.clone()),
+        BackupValue::Blob(b) => libsql::Value::Blob(
+            STANDARD
+                .decode(b)
+                .map_err(|e| RhombusError::Backup(format!("invalid blob: {}", e)))?,
+        ),
+    })
+}
+
 #[async_trait]
 impl<T: ?Sized + LibSQLConnection + Send + Sync> Database for T {
     async fn migrate(&self) -> Result<()> {
-        self.connect()
+        let conn = self.connect().await?;
+        conn.execute_batch(&migration_sql("0001_setup.up.sql"))
+            .await?;
+
+        let schema_version = conn
+            .query("PRAGMA user_version", ())
             .await?
-            .execute_batch(
-                std::str::from_utf8(Migrations::get("0001_setup.up.sql").unwrap().data.as_ref())
-                    .unwrap(),
-            )
+            .next()
+            .await?
+            .map(|row| row.get::<i64>(0))
+            .transpose()?
+            .unwrap_or(0);
+
+        for (version, name) in SCHEMA_MIGRATIONS {
+            if *version <= schema_version {
+                continue;
+            }
+
+            tracing::info!(version, name, "Migrating database");
+
+            // tables are rebuilt by dropping the old one, which must not touch rows referencing it
+            conn.execute_batch(&format!(
+                "
+                PRAGMA foreign_keys = FALSE;
+                BEGIN;
+                {}
+                PRAGMA user_version = {};
+                COMMIT;
+                PRAGMA foreign_keys = TRUE;
+            ",
+                migration_sql(name),
+                version
+            ))
             .await?;
+        }
+
         Ok(())
     }
 
@@ -428,6 +724,117 @@ impl<T: ?Sized + LibSQLConnection + Send + Sync> Database for T {
         }
     }
 
+    async fn login_user_by_credentials(
+        &self,
+        username: &str,
+        password: &str,
+    ) -> Result<Option<(i64, i64)>> {
+        #[derive(Debug, Deserialize)]
+        struct QueryUser {
+            id: i64,
+            team_id: i64,
+            password: String,
+        }
+
+        let existing_user = self
+            .connect()
+            .await?
+            .query(
+                "
+                SELECT id, team_id, password
+                FROM rhombus_user
+                WHERE name = ?1 AND password IS NOT NULL
+            ",
+                [username],
+            )
+            .await?
+            .next()
+            .await?
+            .map(|row| de::from_row::<QueryUser>(&row).unwrap());
+
+        let Some(existing_user) = existing_user else {
+            return Ok(None);
+        };
+
+        let parsed_hash = PasswordHash::new(&existing_user.password)?;
+        if Argon2::default()
+            .verify_password(password.as_bytes(), &parsed_hash)
+            .is_ok()
+        {
+            Ok(Some((existing_user.id, existing_user.team_id)))
+        } else {
+            Ok(None)
+        }
+    }
+
+    async fn register_user_by_credentials(
+        &self,
+        username: &str,
+        avatar: &str,
+        password: &str,
+        email: Option<&str>,
+        location_url: &str,
+    ) -> Result<std::result::Result<(i64, i64, Option<String>), RegisterError>> {
+        let tx = self.transaction().await?;
+
+        let name_taken = tx
+            .query("SELECT 1 FROM rhombus_user WHERE name = ?1", [username])
+            .await?
+            .next()
+            .await?
+            .is_some();
+        if name_taken {
+            return Ok(Err(RegisterError::NameTaken));
+        }
+
+        if let Some(email) = email {
+            let email_in_use = tx
+                .query("SELECT 1 FROM rhombus_email WHERE email = ?1", [email])
+                .await?
+                .next()
+                .await?
+                .is_some();
+            if email_in_use {
+                return Ok(Err(RegisterError::EmailInUse));
+            }
+        }
+
+        let team_id = create_team(&tx).await?;
+
+        let salt = SaltString::generate(&mut OsRng);
+        let hashed_password = Argon2::default()
+            .hash_password(password.as_bytes(), &salt)?
+            .to_string();
+
+        let api_key = create_user_api_key(location_url);
+
+        let user_id = tx
+            .query(
+                "INSERT INTO rhombus_user (name, password, avatar, team_id, owner_team_id, api_key) VALUES (?1, ?2, ?3, ?4, ?4, ?5) RETURNING id",
+                params!(username, hashed_password, avatar, team_id, api_key.as_str()),
+            )
+            .await?
+            .next()
+            .await?
+            .unwrap()
+            .get::<i64>(0)?;
+
+        let code = if let Some(email) = email {
+            let code = generate_email_callback_code();
+            tx.execute(
+                "INSERT INTO rhombus_email (email, user_id, code) VALUES (?1, ?2, ?3)",
+                params!(email, user_id, code.as_str()),
+            )
+            .await?;
+            Some(code)
+        } else {
+            None
+        };
+
+        tx.commit().await?;
+        Ok(Ok((user_id, team_id, code)))
+    }
+
     async fn upsert_user_by_ctftime(
         &self,
         name: &str,
@@ -444,7 +851,7 @@ impl<T: ?Sized + LibSQLConnection + Send + Sync> Database for T {
         struct QueryUser {
             user_id: i64,
             team_id: i64,
-            team_ctftime_id: i64,
+            team_ctftime_id: Option<i64>,
         }
 
         let existing_user = tx
@@ -462,7 +869,7 @@ impl<T: ?Sized + LibSQLConnection + Send + Sync> Database for T {
             .map(|row| de::from_row::<QueryUser>(&row).unwrap());
 
         if let Some(ref existing_user) = existing_user {
-            if existing_user.team_ctftime_id == ctftime_team_id {
+            if existing_user.team_ctftime_id == Some(ctftime_team_id) {
                 // if the user exists and is on the team, just auth in
                 tx.commit().await?;
                 return Ok((existing_user.user_id, existing_user.team_id, None));
@@ -525,6 +932,13 @@ impl<T: ?Sized + LibSQLConnection + Send + Sync> Database for T {
             return Ok((user_id, scratch_team_id, Some(existing_team.invite_token)));
         }
 
+        if let Some(existing_user) = existing_user {
+            // the user has since moved to a team which is not on CTFtime, or linked
+            // CTFtime to an existing account, so keep them where they are
+            tx.commit().await?;
+            return Ok((existing_user.user_id, existing_user.team_id, None));
+        }
+
         // if the team does not exist, create the team and add the user to the team
 
         let team_invite_token = create_team_invite_token();
@@ -563,1953 +977,5341 @@ impl<T: ?Sized + LibSQLConnection + Send + Sync> Database for T {
         Ok((user_id, team_id, None))
     }
 
-    async fn insert_track(
+    async fn upsert_user_by_oidc(
         &self,
-        ip: IpAddr,
-        user_agent: Option<&str>,
+        name: &str,
+        email: Option<&str>,
+        avatar: &str,
+        subject: &str,
         user_id: Option<i64>,
-        requests: u64,
-    ) -> Result<()> {
-        let ip = match ip {
-            IpAddr::V4(ip) => ip.to_ipv6_mapped(),
-            IpAddr::V6(ip) => ip,
+        location_url: &str,
+    ) -> Result<std::result::Result<(i64, i64), IdentityUpsertError>> {
+        let tx = self.transaction().await?;
+        let result = upsert_user_by_identity(
+            &tx,
+            "oidc",
+            name,
+            email,
+            avatar,
+            subject,
+            user_id,
+            location_url,
+        )
+        .await?;
+        tx.commit().await?;
+        Ok(result)
+    }
+
+    async fn upsert_user_by_github(
+        &self,
+        name: &str,
+        email: Option<&str>,
+        avatar: &str,
+        github_id: i64,
+        user_id: Option<i64>,
+        location_url: &str,
+    ) -> Result<std::result::Result<(i64, i64), IdentityUpsertError>> {
+        let tx = self.transaction().await?;
+        let result = upsert_user_by_identity(
+            &tx,
+            "github",
+            name,
+            email,
+            avatar,
+            &github_id.to_string(),
+            user_id,
+            location_url,
+        )
+        .await?;
+        tx.commit().await?;
+        Ok(result)
+    }
+
+    async fn upsert_user_by_google(
+        &self,
+        name: &str,
+        email: Option<&str>,
+        avatar: &str,
+        google_id: &str,
+        user_id: Option<i64>,
+        location_url: &str,
+    ) -> Result<std::result::Result<(i64, i64), IdentityUpsertError>> {
+        let tx = self.transaction().await?;
+        let result = upsert_user_by_identity(
+            &tx,
+            "google",
+            name,
+            email,
+            avatar,
+            google_id,
+            user_id,
+            location_url,
+        )
+        .await?;
+        tx.commit().await?;
+        Ok(result)
+    }
+
+    async fn upsert_user_by_ldap(
+        &self,
+        name: &str,
+        email: Option<&str>,
+        avatar: &str,
+        dn: &str,
+        location_url: &str,
+    ) -> Result<std::result::Result<(i64, i64), IdentityUpsertError>> {
+        let tx = self.transaction().await?;
+        let result =
+            upsert_user_by_identity(&tx, "ldap", name, email, avatar, dn, None, location_url)
+                .await?;
+        tx.commit().await?;
+        Ok(result)
+    }
+
+    async fn get_user_identities(&self, user_id: i64) -> Result<Vec<UserIdentity>> {
+        #[derive(Debug, Deserialize)]
+        struct QueryIdentity {
+            provider: String,
+            subject: String,
         }
-        .octets();
 
-        let track_id = self
+        let identities = self
             .connect()
             .await?
             .query(
                 "
-                INSERT INTO rhombus_track (ip, user_agent, requests) VALUES (?1, ?2, ?3)
-                ON CONFLICT (ip, user_agent) DO
-                    UPDATE SET
-                        last_seen_at = strftime('%s', 'now'),
-                        requests = rhombus_track.requests + ?3
-                RETURNING id
+                SELECT provider, subject
+                FROM rhombus_user_identity
+                WHERE user_id = ?1
+                ORDER BY provider, subject
             ",
-                params!(ip, user_agent.unwrap_or(""), requests),
+                [user_id],
             )
             .await?
-            .next()
-            .await?
-            .unwrap()
-            .get::<i64>(0)
-            .unwrap();
-
-        if let Some(user_id) = user_id {
-            self.connect()
-                .await?
-                .execute(
-                    "
-                    INSERT INTO rhombus_track_ip (user_id, track_id) VALUES (?1, ?2)
-                    ON CONFLICT (user_id, track_id) DO NOTHING
-                ",
-                    [user_id, track_id],
-                )
-                .await
-                .unwrap();
-        }
+            .into_stream()
+            .map(|row| {
+                let identity = de::from_row::<QueryIdentity>(&row.unwrap()).unwrap();
+                UserIdentity {
+                    provider: identity.provider,
+                    subject: identity.subject,
+                }
+            })
+            .collect::<Vec<_>>()
+            .await;
 
-        Ok(())
+        Ok(identities)
     }
 
-    async fn get_challenges(&self) -> Result<Challenges> {
-        let tx = self.transaction().await?;
-
-        let mut division_rows = tx
-            .query("SELECT id, name FROM rhombus_division", ())
-            .await?;
+    async fn get_user_sign_in_methods(&self, user_id: i64) -> Result<UserSignInMethods> {
         #[derive(Debug, Deserialize)]
-        struct DbDivision {
-            id: String,
-            name: String,
+        struct QueryUser {
+            discord: bool,
+            ctftime: bool,
+            password: bool,
+            passkey: bool,
+        }
+
+        let row = self
+            .connect()
+            .await?
+            .query(
+                "
+                SELECT discord_id IS NOT NULL AS discord, ctftime_id IS NOT NULL AS ctftime, password IS NOT NULL AS password,
+                    EXISTS(SELECT 1 FROM rhombus_user_passkey WHERE user_id = rhombus_user.id) AS passkey
+                FROM rhombus_user
+                WHERE id = ?1
+            ",
+                [user_id],
+            )
+            .await?
+            .next()
+            .await?
+            .ok_or(RhombusError::DatabaseReturnedNoRows)?;
+        let user = de::from_row::<QueryUser>(&row).unwrap();
+
+        Ok(UserSignInMethods {
+            discord: user.discord,
+            ctftime: user.ctftime,
+            password: user.password,
+            passkey: user.passkey,
+        })
+    }
+
+    async fn link_user_ctftime(
+        &self,
+        user_id: i64,
+        ctftime_user_id: i64,
+    ) -> Result<std::result::Result<(), IdentityUpsertError>> {
+        let tx = self.transaction().await?;
+
+        let existing_user_id = tx
+            .query(
+                "SELECT id FROM rhombus_user WHERE ctftime_id = ?1",
+                [ctftime_user_id],
+            )
+            .await?
+            .next()
+            .await?
+            .map(|row| row.get::<i64>(0).unwrap());
+
+        match existing_user_id {
+            Some(existing_user_id) if existing_user_id != user_id => {
+                return Ok(Err(IdentityUpsertError::AlreadyInUse));
+            }
+            Some(_) => {}
+            None => {
+                tx.execute(
+                    "UPDATE rhombus_user SET ctftime_id = ?1 WHERE id = ?2",
+                    params!(ctftime_user_id, user_id),
+                )
+                .await?;
+            }
+        }
+
+        tx.commit().await?;
+        Ok(Ok(()))
+    }
+
+    async fn set_user_password(
+        &self,
+        user_id: i64,
+        current_password: Option<&str>,
+        password: &str,
+    ) -> Result<std::result::Result<(), SetPasswordError>> {
+        let tx = self.transaction().await?;
+
+        let existing_password = tx
+            .query("SELECT password FROM rhombus_user WHERE id = ?1", [user_id])
+            .await?
+            .next()
+            .await?
+            .ok_or(RhombusError::DatabaseReturnedNoRows)?
+            .get::<Option<String>>(0)?;
+        if let Some(existing_password) = existing_password {
+            let parsed_hash = PasswordHash::new(&existing_password)?;
+            let matches = current_password.is_some_and(|current_password| {
+                Argon2::default()
+                    .verify_password(current_password.as_bytes(), &parsed_hash)
+                    .is_ok()
+            });
+            if !matches {
+                return Ok(Err(SetPasswordError::IncorrectPassword));
+            }
+        }
+
+        // credentials sign in looks users up by name, so it must stay unambiguous
+        let name_in_use = tx
+            .query(
+                "
+                SELECT 1
+                FROM rhombus_user
+                WHERE name = (SELECT name FROM rhombus_user WHERE id = ?1) AND id != ?1 AND password IS NOT NULL
+            ",
+                [user_id],
+            )
+            .await?
+            .next()
+            .await?
+            .is_some();
+        if name_in_use {
+            return Ok(Err(SetPasswordError::NameInUse));
+        }
+
+        let salt = SaltString::generate(&mut OsRng);
+        let hashed_password = Argon2::default()
+            .hash_password(password.as_bytes(), &salt)?
+            .to_string();
+
+        tx.execute(
+            "UPDATE rhombus_user SET password = ?1 WHERE id = ?2",
+            params!(hashed_password, user_id),
+        )
+        .await?;
+
+        tx.commit().await?;
+        Ok(Ok(()))
+    }
+
+    async fn create_password_reset_code(&self, email: &str) -> Result<Option<PasswordReset>> {
+        let tx = self.transaction().await?;
+
+        #[derive(Debug, Deserialize)]
+        struct QueryUser {
+            id: i64,
+            name: String,
+        }
+
+        // only users who already sign in with a password can reset it
+        let user = tx
+            .query(
+                "
+                SELECT rhombus_user.id, rhombus_user.name
+                FROM rhombus_email JOIN rhombus_user ON rhombus_email.user_id = rhombus_user.id
+                WHERE rhombus_email.email = ?1 AND rhombus_email.code IS NULL AND rhombus_user.password IS NOT NULL
+            ",
+                [email],
+            )
+            .await?
+            .next()
+            .await?
+            .map(|row| de::from_row::<QueryUser>(&row).unwrap());
+
+        let Some(user) = user else {
+            return Ok(None);
+        };
+
+        let code = generate_email_callback_code();
+        tx.execute(
+            "INSERT OR REPLACE INTO rhombus_password_reset (user_id, code) VALUES (?1, ?2)",
+            params!(user.id, code.as_str()),
+        )
+        .await?;
+
+        tx.commit().await?;
+        Ok(Some(PasswordReset {
+            user_name: user.name,
+            code,
+        }))
+    }
+
+    async fn get_password_reset_by_callback_code(&self, code: &str) -> Result<String> {
+        let name = self
+            .connect()
+            .await?
+            .query(
+                "
+                SELECT rhombus_user.name
+                FROM rhombus_password_reset JOIN rhombus_user ON rhombus_password_reset.user_id = rhombus_user.id
+                WHERE rhombus_password_reset.code = ?1 AND rhombus_password_reset.expires >= strftime('%s', 'now')
+            ",
+                [code],
+            )
+            .await?
+            .next()
+            .await?
+            .ok_or(RhombusError::DatabaseReturnedNoRows)?
+            .get::<String>(0)
+            .unwrap();
+
+        Ok(name)
+    }
+
+    async fn reset_password_by_callback_code(&self, code: &str, password: &str) -> Result<i64> {
+        let tx = self.transaction().await?;
+
+        let user_id = tx
+            .query(
+                "
+                DELETE FROM rhombus_password_reset
+                WHERE code = ?1 AND expires >= strftime('%s', 'now')
+                RETURNING user_id
+            ",
+                [code],
+            )
+            .await?
+            .next()
+            .await?
+            .ok_or(RhombusError::DatabaseReturnedNoRows)?
+            .get::<i64>(0)
+            .unwrap();
+
+        let salt = SaltString::generate(&mut OsRng);
+        let hashed_password = Argon2::default()
+            .hash_password(password.as_bytes(), &salt)?
+            .to_string();
+
+        tx.execute(
+            "UPDATE rhombus_user SET password = ?1 WHERE id = ?2",
+            params!(hashed_password, user_id),
+        )
+        .await?;
+
+        tx.commit().await?;
+        Ok(user_id)
+    }
+
+    async fn unlink_sign_in_method(&self, user_id: i64, method: SignInMethod<'_>) -> Result<()> {
+        let conn = self.connect().await?;
+
+        match method {
+            SignInMethod::Discord => {
+                conn.execute(
+                    "UPDATE rhombus_user SET discord_id = NULL WHERE id = ?1",
+                    [user_id],
+                )
+                .await?;
+            }
+            SignInMethod::CTFtime => {
+                conn.execute(
+                    "UPDATE rhombus_user SET ctftime_id = NULL WHERE id = ?1",
+                    [user_id],
+                )
+                .await?;
+            }
+            SignInMethod::Password => {
+                conn.execute(
+                    "UPDATE rhombus_user SET password = NULL WHERE id = ?1",
+                    [user_id],
+                )
+                .await?;
+            }
+            SignInMethod::Identity { provider } => {
+                conn.execute(
+                    "DELETE FROM rhombus_user_identity WHERE user_id = ?1 AND provider = ?2",
+                    params!(user_id, provider),
+                )
+                .await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn get_user_totp(&self, user_id: i64) -> Result<Option<UserTotp>> {
+        #[derive(Debug, Deserialize)]
+        struct QueryTotp {
+            secret: String,
+            enabled: bool,
+            recovery_codes: i64,
+        }
+
+        let totp = self
+            .connect()
+            .await?
+            .query(
+                "
+                SELECT secret, enabled, (SELECT COUNT(*) FROM rhombus_user_recovery_code WHERE user_id = ?1) AS recovery_codes
+                FROM rhombus_user_totp
+                WHERE user_id = ?1
+            ",
+                [user_id],
+            )
+            .await?
+            .next()
+            .await?
+            .map(|row| de::from_row::<QueryTotp>(&row).unwrap())
+            .map(|totp| UserTotp {
+                secret: totp.secret,
+                enabled: totp.enabled,
+                recovery_codes: totp.recovery_codes,
+            });
+
+        Ok(totp)
+    }
+
+    async fn set_user_totp_pending(&self, user_id: i64, secret: &str) -> Result<()> {
+        self.connect()
+            .await?
+            .execute(
+                "
+                INSERT INTO rhombus_user_totp (user_id, secret) VALUES (?1, ?2)
+                ON CONFLICT (user_id) DO UPDATE SET secret = ?2, last_used_step = NULL WHERE enabled = FALSE
+            ",
+                params!(user_id, secret),
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn enable_user_totp(&self, user_id: i64, recovery_code_hashes: &[String]) -> Result<()> {
+        let tx = self.transaction().await?;
+
+        tx.execute(
+            "UPDATE rhombus_user_totp SET enabled = TRUE WHERE user_id = ?1",
+            [user_id],
+        )
+        .await?;
+
+        tx.execute(
+            "DELETE FROM rhombus_user_recovery_code WHERE user_id = ?1",
+            [user_id],
+        )
+        .await?;
+
+        for code_hash in recovery_code_hashes {
+            tx.execute(
+                "INSERT INTO rhombus_user_recovery_code (user_id, code_hash) VALUES (?1, ?2)",
+                params!(user_id, code_hash.as_str()),
+            )
+            .await?;
+        }
+
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn disable_user_totp(&self, user_id: i64) -> Result<()> {
+        let tx = self.transaction().await?;
+
+        tx.execute(
+            "DELETE FROM rhombus_user_totp WHERE user_id = ?1",
+            [user_id],
+        )
+        .await?;
+
+        tx.execute(
+            "DELETE FROM rhombus_user_recovery_code WHERE user_id = ?1",
+            [user_id],
+        )
+        .await?;
+
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn use_user_totp_step(&self, user_id: i64, step: i64) -> Result<bool> {
+        let updated = self
+            .connect()
+            .await?
+            .execute(
+                "
+                UPDATE rhombus_user_totp
+                SET last_used_step = ?2
+                WHERE user_id = ?1 AND (last_used_step IS NULL OR last_used_step < ?2)
+            ",
+                params!(user_id, step),
+            )
+            .await?;
+
+        Ok(updated > 0)
+    }
+
+    async fn use_user_recovery_code(&self, user_id: i64, code_hash: &str) -> Result<bool> {
+        let deleted = self
+            .connect()
+            .await?
+            .execute(
+                "DELETE FROM rhombus_user_recovery_code WHERE user_id = ?1 AND code_hash = ?2",
+                params!(user_id, code_hash),
+            )
+            .await?;
+
+        Ok(deleted > 0)
+    }
+
+    async fn add_user_passkey(
+        &self,
+        user_id: i64,
+        id: &str,
+        name: &str,
+        public_key: &[u8],
+        algorithm: i64,
+        sign_count: i64,
+    ) -> Result<()> {
+        self.connect()
+            .await?
+            .execute(
+                "
+                INSERT INTO rhombus_user_passkey (id, user_id, name, public_key, algorithm, sign_count)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+            ",
+                params!(id, user_id, name, public_key, algorithm, sign_count),
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn get_user_passkeys(&self, user_id: i64) -> Result<Vec<UserPasskey>> {
+        #[derive(Debug, Deserialize)]
+        struct QueryPasskey {
+            id: String,
+            name: String,
+            created_at: i64,
+            last_used_at: Option<i64>,
+        }
+
+        let mut rows = self
+            .connect()
+            .await?
+            .query(
+                "
+                SELECT id, name, created_at, last_used_at
+                FROM rhombus_user_passkey
+                WHERE user_id = ?1
+                ORDER BY created_at
+            ",
+                [user_id],
+            )
+            .await?;
+
+        let mut passkeys = vec![];
+        while let Some(row) = rows.next().await? {
+            let passkey = de::from_row::<QueryPasskey>(&row).unwrap();
+            passkeys.push(UserPasskey {
+                id: passkey.id,
+                name: passkey.name,
+                created_at: DateTime::<Utc>::from_timestamp(passkey.created_at, 0).unwrap(),
+                last_used_at: passkey
+                    .last_used_at
+                    .map(|t| DateTime::<Utc>::from_timestamp(t, 0).unwrap()),
+            });
+        }
+
+        Ok(passkeys)
+    }
+
+    async fn get_passkey_credential(&self, id: &str) -> Result<Option<PasskeyCredential>> {
+        let row = self
+            .connect()
+            .await?
+            .query(
+                "
+                SELECT user_id, public_key, algorithm, sign_count
+                FROM rhombus_user_passkey
+                WHERE id = ?1
+            ",
+                [id],
+            )
+            .await?
+            .next()
+            .await?;
+
+        Ok(match row {
+            Some(row) => Some(PasskeyCredential {
+                user_id: row.get(0)?,
+                public_key: row.get(1)?,
+                algorithm: row.get(2)?,
+                sign_count: row.get(3)?,
+            }),
+            None => None,
+        })
+    }
+
+    async fn use_passkey(&self, id: &str, sign_count: i64) -> Result<bool> {
+        // authenticators without a counter always report 0
+        let updated = self
+            .connect()
+            .await?
+            .execute(
+                "
+                UPDATE rhombus_user_passkey
+                SET sign_count = ?2, last_used_at = strftime('%s', 'now')
+                WHERE id = ?1 AND ((?2 = 0 AND sign_count = 0) OR ?2 > sign_count)
+            ",
+                params!(id, sign_count),
+            )
+            .await?;
+
+        Ok(updated > 0)
+    }
+
+    async fn delete_user_passkey(&self, user_id: i64, id: &str) -> Result<()> {
+        self.connect()
+            .await?
+            .execute(
+                "DELETE FROM rhombus_user_passkey WHERE user_id = ?1 AND id = ?2",
+                params!(user_id, id),
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn create_session(&self, id: &str, user_id: i64, expires: i64) -> Result<()> {
+        self.connect()
+            .await?
+            .execute(
+                "INSERT INTO rhombus_session (id, user_id, expires) VALUES (?1, ?2, ?3)",
+                params!(id, user_id, expires),
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn get_session_user_id(&self, id: &str) -> Result<Option<i64>> {
+        let row = self
+            .connect()
+            .await?
+            .query(
+                "SELECT user_id FROM rhombus_session WHERE id = ?1 AND expires >= strftime('%s', 'now')",
+                [id],
+            )
+            .await?
+            .next()
+            .await?;
+
+        Ok(match row {
+            Some(row) => Some(row.get(0)?),
+            None => None,
+        })
+    }
+
+    async fn get_user_sessions(&self, user_id: i64) -> Result<Vec<UserSession>> {
+        #[derive(Debug, Deserialize)]
+        struct QuerySession {
+            id: String,
+            ip: Option<Vec<u8>>,
+            user_agent: Option<String>,
+            created_at: i64,
+            last_seen_at: i64,
+        }
+
+        let mut rows = self
+            .connect()
+            .await?
+            .query(
+                "
+                SELECT id, ip, user_agent, created_at, last_seen_at
+                FROM rhombus_session
+                WHERE user_id = ?1 AND expires >= strftime('%s', 'now')
+                ORDER BY last_seen_at DESC
+            ",
+                [user_id],
+            )
+            .await?;
+
+        let mut sessions = vec![];
+        while let Some(row) = rows.next().await? {
+            let session = de::from_row::<QuerySession>(&row).unwrap();
+            sessions.push(UserSession {
+                id: session.id,
+                ip: session
+                    .ip
+                    .and_then(|ip| <[u8; 16]>::try_from(ip).ok())
+                    .map(|ip| Ipv6Addr::from(ip).to_canonical()),
+                user_agent: session.user_agent,
+                created_at: DateTime::<Utc>::from_timestamp(session.created_at, 0).unwrap(),
+                last_seen_at: DateTime::<Utc>::from_timestamp(session.last_seen_at, 0).unwrap(),
+            });
+        }
+
+        Ok(sessions)
+    }
+
+    async fn touch_session(
+        &self,
+        id: &str,
+        ip: Option<IpAddr>,
+        user_agent: Option<&str>,
+        last_seen_at: i64,
+    ) -> Result<()> {
+        let ip = ip.map(|ip| {
+            match ip {
+                IpAddr::V4(ip) => ip.to_ipv6_mapped(),
+                IpAddr::V6(ip) => ip,
+            }
+            .octets()
+            .to_vec()
+        });
+
+        self.connect()
+            .await?
+            .execute(
+                "
+                UPDATE rhombus_session
+                SET ip = COALESCE(?2, ip), user_agent = COALESCE(?3, user_agent), last_seen_at = MAX(last_seen_at, ?4)
+                WHERE id = ?1
+            ",
+                params!(id, ip, user_agent, last_seen_at),
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn revoke_session(&self, user_id: i64, id: &str) -> Result<()> {
+        self.connect()
+            .await?
+            .execute(
+                "DELETE FROM rhombus_session WHERE user_id = ?1 AND id = ?2",
+                params!(user_id, id),
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn revoke_user_sessions(&self, user_id: i64, except: Option<&str>) -> Result<()> {
+        self.connect()
+            .await?
+            .execute(
+                "DELETE FROM rhombus_session WHERE user_id = ?1 AND id IS NOT ?2",
+                params!(user_id, except),
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn insert_track(
+        &self,
+        ip: IpAddr,
+        user_agent: Option<&str>,
+        user_id: Option<i64>,
+        requests: u64,
+    ) -> Result<()> {
+        let ip = match ip {
+            IpAddr::V4(ip) => ip.to_ipv6_mapped(),
+            IpAddr::V6(ip) => ip,
+        }
+        .octets();
+
+        let track_id = self
+            .connect()
+            .await?
+            .query(
+                "
+                INSERT INTO rhombus_track (ip, user_agent, requests) VALUES (?1, ?2, ?3)
+                ON CONFLICT (ip, user_agent) DO
+                    UPDATE SET
+                        last_seen_at = strftime('%s', 'now'),
+                        requests = rhombus_track.requests + ?3
+                RETURNING id
+            ",
+                params!(ip, user_agent.unwrap_or(""), requests),
+            )
+            .await?
+            .next()
+            .await?
+            .unwrap()
+            .get::<i64>(0)
+            .unwrap();
+
+        if let Some(user_id) = user_id {
+            self.connect()
+                .await?
+                .execute(
+                    "
+                    INSERT INTO rhombus_track_ip (user_id, track_id) VALUES (?1, ?2)
+                    ON CONFLICT (user_id, track_id) DO NOTHING
+                ",
+                    [user_id, track_id],
+                )
+                .await
+                .unwrap();
+        }
+
+        Ok(())
+    }
+
+    async fn get_challenges(&self) -> Result<Challenges> {
+        let tx = self.transaction().await?;
+
+        let mut division_rows = tx
+            .query("SELECT id, name FROM rhombus_division", ())
+            .await?;
+        #[derive(Debug, Deserialize)]
+        struct DbDivision {
+            id: String,
+            name: String,
+        }
+        let mut divisions: BTreeMap<String, ChallengeDivision> = Default::default();
+        while let Some(row) = division_rows.next().await? {
+            let query_division = de::from_row::<DbDivision>(&row).unwrap();
+            divisions.insert(
+                query_division.id.clone(),
+                ChallengeDivision {
+                    id: query_division.id,
+                    name: query_division.name,
+                },
+            );
+        }
+
+        let challenge_division_solves_rows = tx
+            .query("SELECT * FROM rhombus_challenge_division_solves", ())
+            .await?
+            .into_stream()
+            .map(|row| de::from_row::<DbChallengeDivisionSolves>(&row.unwrap()).unwrap())
+            .collect::<Vec<_>>()
+            .await;
+        #[derive(Debug, Deserialize)]
+        struct DbChallengeDivisionSolves {
+            challenge_id: String,
+            division_id: String,
+            solves: i64,
+        }
+        let mut challenge_division_solves = BTreeMap::new();
+        for row in challenge_division_solves_rows.into_iter() {
+            match challenge_division_solves.get_mut(&row.challenge_id) {
+                None => {
+                    let mut map = BTreeMap::new();
+                    map.insert(row.division_id, row.solves as u64);
+                    _ = challenge_division_solves.insert(row.challenge_id, map);
+                }
+                Some(division_solves) => {
+                    _ = division_solves.insert(row.division_id, row.solves as u64)
+                }
+            }
+        }
+
+        let mut query_challenges = tx
+            .query("SELECT * FROM rhombus_file_attachment", ())
+            .await?;
+        #[derive(Debug, Deserialize)]
+        struct QueryChallengeFileAttachment {
+            challenge_id: String,
+            name: String,
+            url: String,
+        }
+        let mut challenge_attachments = BTreeMap::new();
+        while let Some(row) = query_challenges.next().await? {
+            let query_attachment = de::from_row::<QueryChallengeFileAttachment>(&row).unwrap();
+            let attachment = ChallengeAttachment {
+                name: query_attachment.name,
+                url: query_attachment.url,
+            };
+            match challenge_attachments.get_mut(&query_attachment.challenge_id) {
+                None => {
+                    _ = challenge_attachments
+                        .insert(query_attachment.challenge_id, vec![attachment])
+                }
+                Some(attachments) => attachments.push(attachment),
+            };
+        }
+
+        let challenge_rows = tx.query("SELECT * FROM rhombus_challenge ", ()).await?;
+
+        #[derive(Debug, Deserialize)]
+        struct DbChallenge {
+            id: String,
+            name: String,
+            description: String,
+            flag: String,
+            category_id: String,
+            author_id: String,
+            ticket_template: Option<String>,
+            healthscript: Option<String>,
+            healthy: Option<bool>,
+            last_healthcheck: Option<i64>,
+            metadata: String,
+            points: i64,
+            score_type: String,
+        }
+        let challenges = challenge_rows
+            .into_stream()
+            .map(|row| de::from_row::<DbChallenge>(&row.unwrap()).unwrap())
+            .map(|challenge| {
+                (
+                    challenge.id.clone(),
+                    Challenge {
+                        id: challenge.id.clone(),
+                        name: challenge.name,
+                        description: challenge.description,
+                        flag: challenge.flag,
+                        category_id: challenge.category_id,
+                        author_id: challenge.author_id,
+                        ticket_template: challenge.ticket_template,
+                        healthscript: challenge.healthscript,
+                        healthy: challenge.healthy,
+                        last_healthcheck: challenge
+                            .last_healthcheck
+                            .map(|t| Utc.timestamp_opt(t, 0).unwrap()),
+                        score_type: challenge.score_type,
+                        metadata: serde_json::from_str(&challenge.metadata).unwrap(),
+                        points: challenge.points,
+                        attachments: challenge_attachments
+                            .get(&challenge.id)
+                            .unwrap_or(&vec![])
+                            .to_vec(),
+                        division_solves: {
+                            let mut division_solves = challenge_division_solves
+                                .get(&challenge.id)
+                                .unwrap_or(&BTreeMap::new())
+                                .clone();
+                            for (division_id, _) in divisions.iter() {
+                                if !division_solves.contains_key(division_id) {
+                                    division_solves.insert(division_id.clone(), 0);
+                                }
+                            }
+                            division_solves
+                        },
+                    },
+                )
+            })
+            .collect::<BTreeMap<_, _>>()
+            .await;
+
+        let category_rows = tx
+            .query("SELECT * FROM rhombus_category ORDER BY sequence", ())
+            .await?;
+        #[derive(Debug, Deserialize)]
+        struct DbCategory {
+            id: String,
+            name: String,
+            color: String,
+        }
+        let categories = category_rows
+            .into_stream()
+            .map(|row| de::from_row::<DbCategory>(&row.unwrap()).unwrap())
+            .map(|category| {
+                (
+                    category.id.clone(),
+                    Category {
+                        id: category.id,
+                        name: category.name,
+                        color: category.color,
+                    },
+                )
+            })
+            .collect::<BTreeMap<String, Category>>()
+            .await;
+
+        let mut author_rows = tx.query("SELECT * FROM rhombus_author", ()).await?;
+        #[derive(Debug, Deserialize)]
+        struct DbAuthor {
+            id: String,
+            name: String,
+            avatar: String,
+            discord_id: NonZeroU64,
+        }
+        let mut authors = BTreeMap::new();
+        while let Some(row) = author_rows.next().await? {
+            let query_author = de::from_row::<DbAuthor>(&row).unwrap();
+            authors.insert(
+                query_author.id.clone(),
+                Author {
+                    id: query_author.id,
+                    name: query_author.name,
+                    avatar_url: query_author.avatar,
+                    discord_id: query_author.discord_id,
+                },
+            );
+        }
+
+        tx.commit().await?;
+
+        Ok(Arc::new(ChallengeData {
+            challenges,
+            categories,
+            authors,
+            divisions,
+        }))
+    }
+
+    async fn set_challenge_health(
+        &self,
+        challenge_id: &str,
+        healthy: Option<bool>,
+        checked_at: DateTime<Utc>,
+    ) -> Result<()> {
+        self.connect()
+            .await?
+            .execute(
+                "UPDATE rhombus_challenge SET healthy = ?2, last_healthcheck = ?3 WHERE id = ?1",
+                params!(challenge_id, healthy, checked_at.timestamp()),
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn get_team_meta_from_invite_token(
+        &self,
+        invite_token: &str,
+    ) -> Result<Option<TeamMeta>> {
+        #[derive(Debug, Deserialize)]
+        struct DbTeam {
+            id: i64,
+            name: String,
+        }
+        let team = self
+            .connect()
+            .await?
+            .query(
+                "SELECT id, name FROM rhombus_team WHERE invite_token = ?",
+                [invite_token],
+            )
+            .await?
+            .next()
+            .await?;
+
+        if let Some(row) = &team {
+            let team = de::from_row::<DbTeam>(row).unwrap();
+            Ok(Some(Arc::new(TeamMetaInner {
+                id: team.id,
+                name: team.name,
+            })))
+        } else {
+            Ok(None)
+        }
+    }
+
+    async fn get_team_from_id(&self, team_id: i64) -> Result<Team> {
+        let tx = self.transaction().await?;
+
+        #[derive(Debug, Deserialize)]
+        struct QueryTeam {
+            name: String,
+            invite_token: String,
+            division_id: String,
+            last_division_change: Option<i64>,
+            ctftime_id: Option<i64>,
+        }
+        let query_team_row = tx
+            .query(
+                "SELECT name, invite_token, division_id, last_division_change, ctftime_id FROM rhombus_team WHERE id = ?1",
+                [team_id],
+            )
+            .await?
+            .next()
+            .await?
+            .ok_or(RhombusError::DatabaseReturnedNoRows)?;
+        let query_team = de::from_row::<QueryTeam>(&query_team_row).unwrap();
+
+        #[derive(Debug, Deserialize)]
+        struct QueryTeamUser {
+            id: i64,
+            name: String,
+            avatar: String,
+            discord_id: Option<NonZeroU64>,
+            owner_team_id: i64,
+        }
+        let mut query_user_rows = tx
+            .query(
+                "SELECT id, name, avatar, discord_id, owner_team_id FROM rhombus_user WHERE team_id = ?1",
+                [team_id],
+            )
+            .await?;
+        let mut owner_user_id = 0;
+        let mut users: BTreeMap<i64, TeamUser> = Default::default();
+        while let Some(row) = query_user_rows.next().await? {
+            let query_user = de::from_row::<QueryTeamUser>(&row).unwrap();
+            if query_user.owner_team_id == team_id {
+                owner_user_id = query_user.id;
+            }
+            users.insert(
+                query_user.id,
+                TeamUser {
+                    name: query_user.name,
+                    avatar_url: query_user.avatar,
+                    discord_id: query_user.discord_id,
+                    is_team_owner: query_user.owner_team_id == team_id,
+                },
+            );
+        }
+
+        #[derive(Debug, Deserialize)]
+        struct QuerySolve {
+            pub challenge_id: String,
+            pub user_id: i64,
+            pub solved_at: i64,
+            pub points: Option<i64>,
+        }
+        let mut query_solves = tx
+            .query(
+                "
+                SELECT challenge_id, user_id, solved_at, points
+                FROM rhombus_solve
+                WHERE team_id = ?1
+            ",
+                [team_id],
+            )
+            .await?;
+        let mut solves: BTreeMap<String, ChallengeSolve> = Default::default();
+        while let Some(row) = query_solves.next().await? {
+            let query_solve = de::from_row::<QuerySolve>(&row).unwrap();
+            let solve = ChallengeSolve {
+                user_id: query_solve.user_id,
+                solved_at: DateTime::<Utc>::from_timestamp(query_solve.solved_at, 0).unwrap(),
+                points: query_solve.points,
+            };
+
+            // favor the earliest solve
+            if match solves.get(&query_solve.challenge_id) {
+                Some(old) => solve < *old,
+                None => true,
+            } {
+                solves.insert(query_solve.challenge_id, solve);
+            }
+        }
+
+        #[derive(Debug, Deserialize)]
+        struct QueryWriteup {
+            pub user_id: i64,
+            pub challenge_id: String,
+            pub url: String,
+        }
+        let mut query_writeups = tx
+            .query(
+                "
+                SELECT user_id, challenge_id, url
+                FROM rhombus_writeup
+                WHERE user_id IN (
+                    SELECT id
+                    FROM rhombus_user
+                    WHERE team_id = ?1
+                )
+            ",
+                [team_id],
+            )
+            .await?;
+        let mut writeups = BTreeMap::new();
+        while let Some(row) = query_writeups.next().await? {
+            let query_writeup = de::from_row::<QueryWriteup>(&row).unwrap();
+            let writeup = Writeup {
+                url: query_writeup.url,
+                user_id: query_writeup.user_id,
+            };
+            match writeups.get_mut(&query_writeup.challenge_id) {
+                None => _ = writeups.insert(query_writeup.challenge_id, vec![writeup]),
+                Some(ws) => ws.push(writeup),
+            };
+        }
+
+        tx.commit().await?;
+
+        Ok(Arc::new(TeamInner {
+            id: team_id,
+            name: query_team.name,
+            invite_token: query_team.invite_token,
+            division_id: query_team.division_id,
+            last_division_change: query_team
+                .last_division_change
+                .map(|t| DateTime::<Utc>::from_timestamp(t, 0).unwrap()),
+            ctftime_id: query_team.ctftime_id,
+            owner_user_id,
+            users,
+            solves,
+            writeups,
+        }))
+    }
+
+    async fn add_user_to_team(
+        &self,
+        user_id: i64,
+        team_id: i64,
+        _old_team_id: Option<i64>,
+    ) -> Result<()> {
+        let tx = self.transaction().await?;
+
+        tx.execute(
+            "
+            UPDATE rhombus_user
+            SET team_id = ?2
+            WHERE id = ?1
+        ",
+            [user_id, team_id],
+        )
+        .await?;
+
+        tx.execute(
+            "
+            UPDATE rhombus_solve
+            SET team_id = ?2
+            WHERE user_id = ?1
+        ",
+            [user_id, team_id],
+        )
+        .await?;
+
+        // requests to join other teams and invites to this one are settled by joining
+        tx.execute(
+            "DELETE FROM rhombus_team_join_request WHERE user_id = ?1",
+            [user_id],
+        )
+        .await?;
+
+        tx.execute(
+            "DELETE FROM rhombus_team_invite WHERE user_id = ?1 AND team_id = ?2",
+            [user_id, team_id],
+        )
+        .await?;
+
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn get_user_from_id(&self, user_id: i64) -> Result<User> {
+        #[derive(Debug, Deserialize)]
+        struct DbUser {
+            id: i64,
+            name: String,
+            avatar: String,
+            discord_id: Option<NonZeroU64>,
+            team_id: i64,
+            owner_team_id: i64,
+            disabled: bool,
+            is_admin: bool,
+            api_key: String,
+        }
+
+        let row = self
+            .connect()
+            .await?
+            .query("SELECT * FROM rhombus_user WHERE id = ?1", [user_id])
+            .await?
+            .next()
+            .await?
+            .ok_or(RhombusError::DatabaseReturnedNoRows)?;
+
+        let user = de::from_row::<DbUser>(&row).unwrap();
+        Ok(Arc::new(UserInner {
+            id: user.id,
+            name: user.name,
+            avatar: user.avatar,
+            discord_id: user.discord_id,
+            disabled: user.disabled,
+            is_admin: user.is_admin,
+            team_id: user.team_id,
+            is_team_owner: user.team_id == user.owner_team_id,
+            api_key: user.api_key,
+        }))
+    }
+
+    async fn set_user_admin(&self, user_id: i64, is_admin: bool) -> Result<()> {
+        self.connect()
+            .await?
+            .execute(
+                "UPDATE rhombus_user SET is_admin = ?2 WHERE id = ?1",
+                params!(user_id, is_admin),
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn get_user_from_discord_id(&self, discord_id: NonZeroU64) -> Result<User> {
+        #[derive(Debug, Deserialize)]
+        struct DbUser {
+            id: i64,
+            name: String,
+            avatar: String,
+            discord_id: Option<NonZeroU64>,
+            team_id: i64,
+            owner_team_id: i64,
+            disabled: bool,
+            is_admin: bool,
+            api_key: String,
+        }
+
+        let row = self
+            .connect()
+            .await?
+            .query(
+                "SELECT * FROM rhombus_user WHERE discord_id = ?1",
+                [discord_id.get()],
+            )
+            .await?
+            .next()
+            .await?
+            .ok_or(libsql::Error::QueryReturnedNoRows)?;
+
+        let user = de::from_row::<DbUser>(&row).unwrap();
+        Ok(Arc::new(UserInner {
+            id: user.id,
+            name: user.name,
+            avatar: user.avatar,
+            discord_id: user.discord_id,
+            disabled: user.disabled,
+            is_admin: user.is_admin,
+            team_id: user.team_id,
+            is_team_owner: user.team_id == user.owner_team_id,
+            api_key: user.api_key,
+        }))
+    }
+
+    async fn get_user_from_api_key(&self, api_key: &str) -> Result<User> {
+        #[derive(Debug, Deserialize)]
+        struct DbUser {
+            id: i64,
+            name: String,
+            avatar: String,
+            discord_id: Option<NonZeroU64>,
+            team_id: i64,
+            owner_team_id: i64,
+            disabled: bool,
+            is_admin: bool,
+            api_key: String,
+        }
+
+        let row = self
+            .connect()
+            .await?
+            .query("SELECT * FROM rhombus_user WHERE api_key = ?1", [api_key])
+            .await?
+            .next()
+            .await?
+            .ok_or(libsql::Error::QueryReturnedNoRows)?;
+
+        let user = de::from_row::<DbUser>(&row).unwrap();
+        Ok(Arc::new(UserInner {
+            id: user.id,
+            name: user.name,
+            avatar: user.avatar,
+            discord_id: user.discord_id,
+            disabled: user.disabled,
+            is_admin: user.is_admin,
+            team_id: user.team_id,
+            is_team_owner: user.team_id == user.owner_team_id,
+            api_key: user.api_key,
+        }))
+    }
+
+    async fn kick_user(&self, user_id: i64, _team_id: i64) -> Result<i64> {
+        let tx = self.transaction().await?;
+
+        let new_team_id = tx
+            .query(
+                "
+                UPDATE rhombus_user
+                SET team_id = owner_team_id
+                WHERE id = ?1
+                RETURNING owner_team_id
+            ",
+                [user_id],
+            )
+            .await?
+            .next()
+            .await?
+            .unwrap()
+            .get::<i64>(0)
+            .unwrap();
+
+        tx.execute(
+            "
+            UPDATE rhombus_solve
+            SET team_id = ?2
+            WHERE user_id = ?1
+        ",
+            [user_id, new_team_id],
+        )
+        .await?;
+
+        tx.commit().await?;
+        Ok(new_team_id)
+    }
+
+    async fn get_team_discord(&self, team_id: i64) -> Result<TeamDiscord> {
+        #[derive(Debug, Deserialize)]
+        struct QueryTeamDiscord {
+            discord_role_id: Option<NonZeroU64>,
+            discord_text_channel_id: Option<NonZeroU64>,
+            discord_voice_channel_id: Option<NonZeroU64>,
+        }
+
+        let row = self
+            .connect()
+            .await?
+            .query(
+                "SELECT discord_role_id, discord_text_channel_id, discord_voice_channel_id FROM rhombus_team WHERE id = ?1",
+                [team_id],
+            )
+            .await?
+            .next()
+            .await?
+            .ok_or(RhombusError::DatabaseReturnedNoRows)?;
+        let team_discord = de::from_row::<QueryTeamDiscord>(&row).unwrap();
+
+        Ok(TeamDiscord {
+            role_id: team_discord.discord_role_id,
+            text_channel_id: team_discord.discord_text_channel_id,
+            voice_channel_id: team_discord.discord_voice_channel_id,
+        })
+    }
+
+    async fn set_team_discord(&self, team_id: i64, team_discord: &TeamDiscord) -> Result<()> {
+        self.connect()
+            .await?
+            .execute(
+                "UPDATE rhombus_team SET discord_role_id = ?2, discord_text_channel_id = ?3, discord_voice_channel_id = ?4 WHERE id = ?1",
+                params!(
+                    team_id,
+                    team_discord.role_id.map(|id| id.get() as i64),
+                    team_discord.text_channel_id.map(|id| id.get() as i64),
+                    team_discord.voice_channel_id.map(|id| id.get() as i64)
+                ),
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn get_team_discords(&self) -> Result<Vec<(i64, TeamDiscord)>> {
+        #[derive(Debug, Deserialize)]
+        struct QueryTeamDiscord {
+            id: i64,
+            discord_role_id: Option<NonZeroU64>,
+            discord_text_channel_id: Option<NonZeroU64>,
+            discord_voice_channel_id: Option<NonZeroU64>,
+        }
+
+        let mut rows = self
+            .connect()
+            .await?
+            .query(
+                "
+                SELECT id, discord_role_id, discord_text_channel_id, discord_voice_channel_id
+                FROM rhombus_team
+                WHERE discord_role_id IS NOT NULL OR discord_text_channel_id IS NOT NULL OR discord_voice_channel_id IS NOT NULL
+            ",
+                (),
+            )
+            .await?;
+
+        let mut team_discords = vec![];
+        while let Some(row) = rows.next().await? {
+            let team_discord = de::from_row::<QueryTeamDiscord>(&row).unwrap();
+            team_discords.push((
+                team_discord.id,
+                TeamDiscord {
+                    role_id: team_discord.discord_role_id,
+                    text_channel_id: team_discord.discord_text_channel_id,
+                    voice_channel_id: team_discord.discord_voice_channel_id,
+                },
+            ));
+        }
+
+        Ok(team_discords)
+    }
+
+    async fn transfer_team_ownership(
+        &self,
+        team_id: i64,
+        old_owner_user_id: i64,
+        new_owner_user_id: i64,
+    ) -> Result<i64> {
+        let tx = self.transaction().await?;
+
+        let previous_team_id = tx
+            .query(
+                "SELECT owner_team_id FROM rhombus_user WHERE id = ?1 AND team_id = ?2",
+                [new_owner_user_id, team_id],
+            )
+            .await?
+            .next()
+            .await?
+            .ok_or(RhombusError::DatabaseReturnedNoRows)?
+            .get::<i64>(0)?;
+
+        tx.execute(
+            "UPDATE rhombus_user SET owner_team_id = ?2 WHERE id = ?1",
+            [new_owner_user_id, team_id],
+        )
+        .await?;
+
+        // the new owner's personal team is left without an owner, so the old owner
+        // takes it over as long as nobody is playing in it
+        let previous_team_empty = tx
+            .query(
+                "SELECT NOT EXISTS (SELECT 1 FROM rhombus_user WHERE team_id = ?1)",
+                [previous_team_id],
+            )
+            .await?
+            .next()
+            .await?
+            .ok_or(RhombusError::DatabaseReturnedNoRows)?
+            .get::<bool>(0)?;
+        let personal_team_id = if previous_team_empty {
+            tx.execute(
+                "UPDATE rhombus_team SET invite_token = ?2 WHERE id = ?1",
+                params!(previous_team_id, create_team_invite_token()),
+            )
+            .await?;
+            previous_team_id
+        } else {
+            create_team(&tx).await?
+        };
+
+        tx.execute(
+            "UPDATE rhombus_user SET owner_team_id = ?3 WHERE id = ?1 AND owner_team_id = ?2",
+            [old_owner_user_id, team_id, personal_team_id],
+        )
+        .await?;
+
+        tx.commit().await?;
+        Ok(personal_team_id)
+    }
+
+    async fn disband_team(&self, team_id: i64) -> Result<Vec<(i64, i64)>> {
+        let tx = self.transaction().await?;
+
+        let mut rows = tx
+            .query(
+                "
+                UPDATE rhombus_user
+                SET team_id = owner_team_id
+                WHERE team_id = ?1 AND owner_team_id != ?1
+                RETURNING id, owner_team_id
+            ",
+                [team_id],
+            )
+            .await?;
+        let mut moved = vec![];
+        while let Some(row) = rows.next().await? {
+            moved.push((row.get::<i64>(0)?, row.get::<i64>(1)?));
+        }
+
+        tx.execute(
+            "
+            UPDATE rhombus_solve
+            SET team_id = (SELECT owner_team_id FROM rhombus_user WHERE rhombus_user.id = rhombus_solve.user_id)
+            WHERE team_id = ?1
+        ",
+            [team_id],
+        )
+        .await?;
+
+        tx.execute(
+            "UPDATE rhombus_team SET invite_token = ?2 WHERE id = ?1",
+            params!(team_id, create_team_invite_token()),
+        )
+        .await?;
+
+        tx.commit().await?;
+        Ok(moved)
+    }
+
+    async fn roll_invite_token(&self, team_id: i64) -> Result<String> {
+        let new_invite_token = create_team_invite_token();
+
+        self.connect()
+            .await?
+            .execute(
+                "UPDATE rhombus_team SET invite_token = ?2 WHERE id = ?1",
+                params!(team_id, new_invite_token.as_str()),
+            )
+            .await?;
+
+        Ok(new_invite_token)
+    }
+
+    async fn create_team_invite(
+        &self,
+        team_id: i64,
+        user_id: Option<i64>,
+        email: Option<&str>,
+    ) -> Result<String> {
+        let code = generate_email_callback_code();
+
+        self.connect()
+            .await?
+            .execute(
+                "INSERT INTO rhombus_team_invite (code, team_id, user_id, email) VALUES (?1, ?2, ?3, ?4)",
+                params!(code.as_str(), team_id, user_id, email),
+            )
+            .await?;
+
+        Ok(code)
+    }
+
+    async fn get_team_invite(&self, code: &str) -> Result<Option<TeamInvite>> {
+        let row = self
+            .connect()
+            .await?
+            .query(
+                &format!("{} WHERE rhombus_team_invite.code = ?1", TEAM_INVITE_SELECT),
+                [code],
+            )
+            .await?
+            .next()
+            .await?;
+
+        Ok(row.map(|row| de::from_row::<QueryTeamInvite>(&row).unwrap().into()))
+    }
+
+    async fn get_team_invites(&self, team_id: i64) -> Result<Vec<TeamInvite>> {
+        let mut rows = self
+            .connect()
+            .await?
+            .query(
+                &format!(
+                    "{} WHERE rhombus_team_invite.team_id = ?1 ORDER BY rhombus_team_invite.created_at DESC",
+                    TEAM_INVITE_SELECT
+                ),
+                [team_id],
+            )
+            .await?;
+
+        let mut invites = vec![];
+        while let Some(row) = rows.next().await? {
+            invites.push(de::from_row::<QueryTeamInvite>(&row).unwrap().into());
+        }
+
+        Ok(invites)
+    }
+
+    async fn get_user_team_invites(&self, user_id: i64) -> Result<Vec<TeamInvite>> {
+        let mut rows = self
+            .connect()
+            .await?
+            .query(
+                &format!(
+                    "{} WHERE rhombus_team_invite.user_id = ?1 ORDER BY rhombus_team_invite.created_at DESC",
+                    TEAM_INVITE_SELECT
+                ),
+                [user_id],
+            )
+            .await?;
+
+        let mut invites = vec![];
+        while let Some(row) = rows.next().await? {
+            invites.push(de::from_row::<QueryTeamInvite>(&row).unwrap().into());
+        }
+
+        Ok(invites)
+    }
+
+    async fn delete_team_invite(&self, code: &str) -> Result<()> {
+        self.connect()
+            .await?
+            .execute("DELETE FROM rhombus_team_invite WHERE code = ?1", [code])
+            .await?;
+
+        Ok(())
+    }
+
+    async fn create_team_join_request(&self, team_id: i64, user_id: i64) -> Result<()> {
+        self.connect()
+            .await?
+            .execute(
+                "INSERT OR IGNORE INTO rhombus_team_join_request (team_id, user_id) VALUES (?1, ?2)",
+                [team_id, user_id],
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn get_team_join_requests(&self, team_id: i64) -> Result<Vec<TeamJoinRequest>> {
+        #[derive(Debug, Deserialize)]
+        struct QueryJoinRequest {
+            user_id: i64,
+            name: String,
+            avatar: String,
+            created_at: i64,
+        }
+
+        let mut rows = self
+            .connect()
+            .await?
+            .query(
+                "
+                SELECT rhombus_team_join_request.user_id, rhombus_user.name, rhombus_user.avatar, rhombus_team_join_request.created_at
+                FROM rhombus_team_join_request
+                JOIN rhombus_user ON rhombus_user.id = rhombus_team_join_request.user_id
+                WHERE rhombus_team_join_request.team_id = ?1
+                ORDER BY rhombus_team_join_request.created_at ASC
+            ",
+                [team_id],
+            )
+            .await?;
+
+        let mut requests = vec![];
+        while let Some(row) = rows.next().await? {
+            let request = de::from_row::<QueryJoinRequest>(&row).unwrap();
+            requests.push(TeamJoinRequest {
+                user_id: request.user_id,
+                user_name: request.name,
+                avatar_url: request.avatar,
+                created_at: DateTime::<Utc>::from_timestamp(request.created_at, 0).unwrap(),
+            });
+        }
+
+        Ok(requests)
+    }
+
+    async fn delete_team_join_request(&self, team_id: i64, user_id: i64) -> Result<bool> {
+        let deleted = self
+            .connect()
+            .await?
+            .execute(
+                "DELETE FROM rhombus_team_join_request WHERE team_id = ?1 AND user_id = ?2",
+                [team_id, user_id],
+            )
+            .await?;
+
+        Ok(deleted > 0)
+    }
+
+    async fn roll_api_key(&self, user_id: i64, location_url: &str) -> Result<String> {
+        let new_api_key = create_user_api_key(location_url);
+
+        self.connect()
+            .await?
+            .execute(
+                "UPDATE rhombus_user SET api_key = ?2 WHERE id = ?1",
+                params!(user_id, new_api_key.as_str()),
+            )
+            .await?;
+
+        Ok(new_api_key)
+    }
+
+    async fn set_team_name(
+        &self,
+        team_id: i64,
+        new_team_name: &str,
+        timeout_seconds: u64,
+    ) -> Result<std::result::Result<(), SetTeamNameError>> {
+        let tx = self.transaction().await?;
+
+        let last_change_timestamp = tx
+            .query(
+                "
+                SELECT at
+                FROM rhombus_team_historical_names
+                WHERE team_id = ?1
+                ORDER BY at DESC
+                LIMIT 1
+            ",
+                [team_id],
+            )
+            .await?
+            .next()
+            .await?
+            .map(|row| row.get::<i64>(0).unwrap());
+
+        let last_change_date =
+            DateTime::<Utc>::from_timestamp(last_change_timestamp.unwrap_or(0), 0).unwrap();
+
+        let next_allowed = last_change_date + Duration::from_secs(timeout_seconds);
+        if Utc::now() < next_allowed {
+            return Ok(Err(SetTeamNameError::Timeout(next_allowed)));
+        }
+
+        tx.execute(
+            "
+            INSERT INTO rhombus_team_historical_names (team_id, name)
+            SELECT ?1, name
+            FROM rhombus_team
+            WHERE id = ?1
+            ",
+            params!(team_id),
+        )
+        .await?;
+
+        if tx
+            .execute(
+                "UPDATE rhombus_team SET name = ?2 WHERE id = ?1",
+                params!(team_id, new_team_name),
+            )
+            .await
+            .is_err()
+        {
+            return Ok(Err(SetTeamNameError::Taken));
+        }
+
+        tx.commit().await?;
+        Ok(Ok(()))
+    }
+
+    async fn set_account_name(
+        &self,
+        user_id: i64,
+        _team_id: i64,
+        new_account_name: &str,
+        timeout_seconds: u64,
+    ) -> Result<std::result::Result<(), SetAccountNameError>> {
+        let tx = self.transaction().await?;
+
+        let last_change_timestamp = tx
+            .query(
+                "
+                SELECT at
+                FROM rhombus_user_historical_names
+                WHERE user_id = ?1
+                ORDER BY at DESC
+                LIMIT 1
+            ",
+                [user_id],
+            )
+            .await?
+            .next()
+            .await?
+            .map(|row| row.get::<i64>(0).unwrap());
+
+        let last_change_date =
+            DateTime::<Utc>::from_timestamp(last_change_timestamp.unwrap_or(0), 0).unwrap();
+
+        let next_allowed = last_change_date + Duration::from_secs(timeout_seconds);
+        if Utc::now() < next_allowed {
+            return Ok(Err(SetAccountNameError::Timeout(next_allowed)));
+        }
+
+        tx.execute(
+            "
+            INSERT INTO rhombus_user_historical_names (user_id, name)
+            SELECT ?1, name
+            FROM rhombus_user
+            WHERE id = ?1
+            ",
+            params!(user_id),
+        )
+        .await?;
+
+        if tx
+            .execute(
+                "UPDATE rhombus_user SET name = ?2 WHERE id = ?1",
+                params!(user_id, new_account_name),
+            )
+            .await
+            .is_err()
+        {
+            return Ok(Err(SetAccountNameError::Taken));
+        }
+
+        tx.commit().await?;
+        Ok(Ok(()))
+    }
+
+    async fn solve_challenge(
+        &self,
+        user_id: i64,
+        team_id: i64,
+        division_id: &str,
+        challenge: &Challenge,
+        next_points: i64,
+        now: DateTime<Utc>,
+    ) -> Result<()> {
+        let tx = self.transaction().await?;
+
+        let top_10 = tx
+            .query(
+                "
+                SELECT id
+                FROM rhombus_team
+                WHERE division_id = ?1 AND points > 0
+                ORDER BY points DESC, last_solved_at ASC
+                LIMIT 10
+            ",
+                [division_id],
+            )
+            .await?
+            .into_stream()
+            .map(|row| row.unwrap().get::<i64>(0).unwrap())
+            .collect::<Vec<_>>()
+            .await;
+
+        let point_difference = challenge.points - next_points;
+
+        if point_difference != 0 {
+            tx.execute(
+                "
+                UPDATE rhombus_team
+                SET points = points - ?1
+                WHERE id in (
+                    SELECT team_id
+                    FROM rhombus_solve
+                    WHERE challenge_id = ?2 and points IS NULL
+                )
+            ",
+                params!(point_difference, challenge.id.as_str()),
+            )
+            .await?;
+        }
+
+        tx.execute(
+            "INSERT INTO rhombus_solve (challenge_id, user_id, team_id, solved_at) VALUES (?1, ?2, ?3, ?4)",
+            params!(challenge.id.as_str(), user_id, team_id, now.timestamp()),
+        )
+        .await?;
+
+        tx.execute(
+            "
+            INSERT INTO rhombus_challenge_division_solves (challenge_id, division_id, solves) VALUES (?1, ?2, 1)
+            ON CONFLICT (challenge_id, division_id) DO UPDATE SET solves = solves + 1
+        ",
+            params!(challenge.id.as_str(), division_id),
+        )
+        .await?;
+
+        tx.execute(
+            "
+            UPDATE rhombus_team
+            SET points = points + ?1, last_solved_at = ?2
+            WHERE id = ?3
+        ",
+            params!(next_points, now.timestamp(), team_id),
+        )
+        .await?;
+
+        tx.execute(
+            "
+            UPDATE rhombus_challenge
+            SET points = ?2
+            WHERE id = ?1
+        ",
+            params!(challenge.id.as_str(), next_points),
+        )
+        .await?;
+
+        let new_top_10 = tx
+            .query(
+                "
+                SELECT id
+                FROM rhombus_team
+                WHERE division_id = ?1 AND points > 0
+                ORDER BY points DESC, last_solved_at ASC
+                LIMIT 10
+            ",
+                [division_id],
+            )
+            .await?
+            .into_stream()
+            .map(|row| row.unwrap().get::<i64>(0).unwrap())
+            .collect::<Vec<_>>()
+            .await;
+
+        if top_10 != new_top_10 {
+            tx.execute(
+                "
+                INSERT INTO rhombus_points_snapshot
+                SELECT id, ?2, points
+                FROM rhombus_team
+                WHERE division_id = ?1 AND points > 0
+                ORDER BY points DESC, last_solved_at ASC
+                LIMIT 20
+            ",
+                params!(division_id, now.timestamp()),
+            )
+            .await?;
+        }
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    async fn add_writeup(
+        &self,
+        user_id: i64,
+        _team_id: i64,
+        challenge_id: i64,
+        writeup_url: &str,
+    ) -> Result<()> {
+        self.connect()
+            .await?
+            .execute(
+                "
+                INSERT INTO rhombus_writeup (user_id, challenge_id, url) VALUES (?1, ?2, ?3)
+            ",
+                params!(user_id, challenge_id, writeup_url),
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn get_writeups_from_user_id(&self, user_id: i64) -> Result<Writeups> {
+        #[derive(Debug, Deserialize)]
+        struct QueryWriteup {
+            pub user_id: i64,
+            pub challenge_id: i64,
+            pub url: String,
+        }
+        let mut db_writeups = self
+            .connect()
+            .await?
+            .query(
+                "
+                SELECT user_id, challenge_id, url
+                FROM rhombus_writeup
+                WHERE user_id = ?1
+            ",
+                [user_id],
+            )
+            .await?;
+        let mut writeups = BTreeMap::new();
+        while let Some(writeup) = db_writeups.next().await? {
+            let writeup = de::from_row::<QueryWriteup>(&writeup).unwrap();
+            writeups.insert(
+                writeup.challenge_id,
+                Writeup {
+                    url: writeup.url,
+                    user_id: writeup.user_id,
+                },
+            );
+        }
+        Ok(Arc::new(writeups))
+    }
+
+    async fn delete_writeup(&self, challenge_id: i64, user_id: i64, _team_id: i64) -> Result<()> {
+        self.connect()
+            .await?
+            .execute(
+                "
+                DELETE FROM rhombus_writeup
+                WHERE challenge_id = ?1 AND user_id = ?2
+            ",
+                [challenge_id, user_id],
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn get_next_ticket_number(&self) -> Result<u64> {
+        let ticket_number = self
+            .connect()
+            .await?
+            .query(
+                "
+                UPDATE rhombus_ticket_number_counter
+                SET ticket_number = ticket_number + 1
+                WHERE rowid = 1
+                RETURNING ticket_number
+            ",
+                (),
+            )
+            .await?
+            .next()
+            .await?
+            .unwrap()
+            .get::<u64>(0)
+            .unwrap();
+
+        Ok(ticket_number)
+    }
+
+    async fn create_ticket(
+        &self,
+        ticket_number: u64,
+        user_id: i64,
+        challenge_id: &str,
+        author: &TicketMessageAuthor<'_>,
+        content: &str,
+        assignee_discord_id: Option<NonZeroU64>,
+    ) -> Result<()> {
+        let tx = self.transaction().await?;
+
+        tx.execute(
+            "
+            INSERT INTO rhombus_ticket (ticket_number, user_id, challenge_id, assignee_discord_id)
+            VALUES (?1, ?2, ?3, ?4)
+        ",
+            params!(
+                ticket_number,
+                user_id,
+                challenge_id,
+                assignee_discord_id.map(|id| id.get() as i64)
+            ),
+        )
+        .await?;
+
+        tx.execute(
+            "
+            INSERT INTO rhombus_ticket_message (ticket_number, user_id, author_name, author_avatar, content)
+            VALUES (?1, ?2, ?3, ?4, ?5)
+        ",
+            params!(ticket_number, author.user_id, author.name, author.avatar, content),
+        )
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    async fn set_ticket_discord(
+        &self,
+        ticket_number: u64,
+        discord_channel_id: NonZeroU64,
+        discord_panel_message_id: NonZeroU64,
+    ) -> Result<()> {
+        self.connect()
+            .await?
+            .execute(
+                "
+                UPDATE rhombus_ticket
+                SET discord_channel_id = ?2, discord_panel_message_id = ?3
+                WHERE ticket_number = ?1
+            ",
+                params!(
+                    ticket_number,
+                    discord_channel_id.get(),
+                    discord_panel_message_id.get()
+                ),
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn add_ticket_message(
+        &self,
+        ticket_number: u64,
+        author: &TicketMessageAuthor<'_>,
+        content: &str,
+        discord_message_id: Option<NonZeroU64>,
+        is_staff: bool,
+    ) -> Result<()> {
+        let tx = self.transaction().await?;
+
+        tx.execute(
+            "
+            INSERT INTO rhombus_ticket_message (ticket_number, user_id, author_name, author_avatar, content, discord_message_id)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+        ",
+            params!(
+                ticket_number,
+                author.user_id,
+                author.name,
+                author.avatar,
+                content,
+                discord_message_id.map(|id| id.get() as i64)
+            ),
+        )
+        .await?;
+
+        tx.execute(
+            "
+            UPDATE rhombus_ticket
+            SET
+                first_response_at = CASE
+                    WHEN ?2 THEN COALESCE(first_response_at, strftime('%s', 'now'))
+                    ELSE first_response_at
+                END,
+                status = CASE
+                    WHEN status = 'escalated' THEN status
+                    WHEN ?2 THEN 'waiting'
+                    ELSE 'open'
+                END
+            WHERE ticket_number = ?1
+        ",
+            params!(ticket_number, is_staff),
+        )
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    async fn edit_ticket_message_by_discord_id(
+        &self,
+        discord_message_id: NonZeroU64,
+        content: &str,
+        edited_at: DateTime<Utc>,
+    ) -> Result<Option<u64>> {
+        let ticket_number = self
+            .connect()
+            .await?
+            .query(
+                "
+                UPDATE rhombus_ticket_message
+                SET content = ?2, edited_at = ?3
+                WHERE discord_message_id = ?1
+                RETURNING ticket_number
+            ",
+                params!(discord_message_id.get(), content, edited_at.timestamp()),
+            )
+            .await?
+            .next()
+            .await?
+            .map(|row| row.get::<u64>(0).unwrap());
+
+        Ok(ticket_number)
+    }
+
+    async fn get_ticket_messages(&self, ticket_number: u64) -> Result<Vec<TicketMessage>> {
+        #[derive(Debug, Deserialize)]
+        struct DbTicketMessage {
+            id: i64,
+            user_id: Option<i64>,
+            author_name: String,
+            author_avatar: String,
+            content: String,
+            discord_message_id: Option<NonZeroU64>,
+            sent_at: i64,
+            edited_at: Option<i64>,
+        }
+
+        let messages = self
+            .connect()
+            .await?
+            .query(
+                "
+                SELECT id, user_id, author_name, author_avatar, content, discord_message_id, sent_at, edited_at
+                FROM rhombus_ticket_message
+                WHERE ticket_number = ?1
+                ORDER BY sent_at, id
+            ",
+                [ticket_number],
+            )
+            .await?
+            .into_stream()
+            .map(|row| de::from_row::<DbTicketMessage>(&row.unwrap()).unwrap())
+            .map(|message| TicketMessage {
+                id: message.id,
+                user_id: message.user_id,
+                author_name: message.author_name,
+                author_avatar: message.author_avatar,
+                content: message.content,
+                discord_message_id: message.discord_message_id,
+                sent_at: DateTime::<Utc>::from_timestamp(message.sent_at, 0).unwrap(),
+                edited_at: message
+                    .edited_at
+                    .map(|ts| DateTime::<Utc>::from_timestamp(ts, 0).unwrap()),
+            })
+            .collect::<Vec<_>>()
+            .await;
+
+        Ok(messages)
+    }
+
+    async fn get_user_tickets(&self, user_id: i64) -> Result<Vec<Ticket>> {
+        let tickets = self
+            .connect()
+            .await?
+            .query(
+                &format!(
+                    "{} WHERE user_id = ?1 ORDER BY opened_at DESC",
+                    TICKET_SELECT
+                ),
+                [user_id],
+            )
+            .await?
+            .into_stream()
+            .map(|row| {
+                de::from_row::<DbTicket>(&row.unwrap())
+                    .unwrap()
+                    .into_ticket(vec![])
+            })
+            .collect::<Vec<_>>()
+            .await;
+
+        Ok(tickets)
+    }
+
+    async fn get_tickets(&self, include_closed: bool) -> Result<Vec<Ticket>> {
+        let tickets = self
+            .connect()
+            .await?
+            .query(
+                &format!(
+                    "{} WHERE ?1 OR closed_at IS NULL ORDER BY opened_at DESC LIMIT 500",
+                    TICKET_SELECT
+                ),
+                [include_closed],
+            )
+            .await?
+            .into_stream()
+            .map(|row| {
+                de::from_row::<DbTicket>(&row.unwrap())
+                    .unwrap()
+                    .into_ticket(vec![])
+            })
+            .collect::<Vec<_>>()
+            .await;
+
+        Ok(tickets)
+    }
+
+    async fn get_challenge_tickets(&self, challenge_id: &str) -> Result<Vec<Ticket>> {
+        let tickets = self
+            .connect()
+            .await?
+            .query(
+                &format!(
+                    "{} WHERE challenge_id = ?1 ORDER BY opened_at",
+                    TICKET_SELECT
+                ),
+                [challenge_id],
+            )
+            .await?
+            .into_stream()
+            .map(|row| {
+                de::from_row::<DbTicket>(&row.unwrap())
+                    .unwrap()
+                    .into_ticket(vec![])
+            })
+            .collect::<Vec<_>>()
+            .await;
+
+        Ok(tickets)
+    }
+
+    async fn set_ticket_status(&self, ticket_number: u64, status: TicketStatus) -> Result<()> {
+        self.connect()
+            .await?
+            .execute(
+                "
+                UPDATE rhombus_ticket
+                SET status = ?2
+                WHERE ticket_number = ?1
+            ",
+                params!(ticket_number, status.as_str()),
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn set_ticket_assignee(
+        &self,
+        ticket_number: u64,
+        assignee_discord_id: Option<NonZeroU64>,
+    ) -> Result<()> {
+        self.connect()
+            .await?
+            .execute(
+                "
+                UPDATE rhombus_ticket
+                SET assignee_discord_id = ?2
+                WHERE ticket_number = ?1
+            ",
+                params!(ticket_number, assignee_discord_id.map(|id| id.get() as i64)),
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn set_ticket_tags(&self, ticket_number: u64, tags: &[String]) -> Result<()> {
+        let tx = self.transaction().await?;
+
+        tx.execute(
+            "DELETE FROM rhombus_ticket_tag WHERE ticket_number = ?1",
+            [ticket_number],
+        )
+        .await?;
+
+        for tag in tags {
+            tx.execute(
+                "INSERT OR IGNORE INTO rhombus_ticket_tag (ticket_number, tag) VALUES (?1, ?2)",
+                params!(ticket_number, tag.as_str()),
+            )
+            .await?;
         }
-        let mut divisions: BTreeMap<String, ChallengeDivision> = Default::default();
-        while let Some(row) = division_rows.next().await? {
-            let query_division = de::from_row::<DbDivision>(&row).unwrap();
-            divisions.insert(
-                query_division.id.clone(),
-                ChallengeDivision {
-                    id: query_division.id,
-                    name: query_division.name,
-                },
-            );
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    async fn get_ticket_stats(&self) -> Result<TicketStats> {
+        #[derive(Debug, Deserialize)]
+        struct DbTicketStats {
+            open: u64,
+            waiting: u64,
+            escalated: u64,
+            closed: u64,
+            average_first_response_seconds: Option<f64>,
+            average_resolution_seconds: Option<f64>,
+        }
+
+        let row = self
+            .connect()
+            .await?
+            .query(
+                "
+                SELECT
+                    COUNT(*) FILTER (WHERE closed_at IS NULL AND status = 'open') AS open,
+                    COUNT(*) FILTER (WHERE closed_at IS NULL AND status = 'waiting') AS waiting,
+                    COUNT(*) FILTER (WHERE closed_at IS NULL AND status = 'escalated') AS escalated,
+                    COUNT(*) FILTER (WHERE closed_at IS NOT NULL) AS closed,
+                    AVG(first_response_at - opened_at) AS average_first_response_seconds,
+                    AVG(closed_at - opened_at) AS average_resolution_seconds
+                FROM rhombus_ticket
+            ",
+                (),
+            )
+            .await?
+            .next()
+            .await?
+            .unwrap();
+
+        let stats = de::from_row::<DbTicketStats>(&row).unwrap();
+
+        Ok(TicketStats {
+            open: stats.open,
+            waiting: stats.waiting,
+            escalated: stats.escalated,
+            closed: stats.closed,
+            average_first_response_seconds: stats
+                .average_first_response_seconds
+                .map(|seconds| seconds as i64),
+            average_resolution_seconds: stats
+                .average_resolution_seconds
+                .map(|seconds| seconds as i64),
+        })
+    }
+
+    async fn get_ticket_by_ticket_number(&self, ticket_number: u64) -> Result<Ticket> {
+        let tx = self.transaction().await?;
+
+        let ticket_row = tx
+            .query(
+                &format!("{} WHERE ticket_number = ?1", TICKET_SELECT),
+                [ticket_number],
+            )
+            .await?
+            .next()
+            .await?
+            .ok_or(libsql::Error::QueryReturnedNoRows)?;
+
+        let db_ticket = de::from_row::<DbTicket>(&ticket_row).unwrap();
+
+        let email_references = get_ticket_email_references(&tx, ticket_number).await?;
+
+        tx.commit().await?;
+
+        Ok(db_ticket.into_ticket(email_references))
+    }
+
+    async fn get_ticket_by_discord_channel_id(
+        &self,
+        discord_channel_id: NonZeroU64,
+    ) -> Result<Ticket> {
+        let tx = self.transaction().await?;
+
+        let ticket_row = tx
+            .query(
+                &format!("{} WHERE discord_channel_id = ?1", TICKET_SELECT),
+                [discord_channel_id.get()],
+            )
+            .await?
+            .next()
+            .await?
+            .ok_or(libsql::Error::QueryReturnedNoRows)?;
+
+        let db_ticket = de::from_row::<DbTicket>(&ticket_row).unwrap();
+
+        let email_references = get_ticket_email_references(&tx, db_ticket.ticket_number).await?;
+
+        tx.commit().await?;
+
+        Ok(db_ticket.into_ticket(email_references))
+    }
+
+    async fn close_ticket(&self, ticket_number: u64, time: DateTime<Utc>) -> Result<()> {
+        self.connect()
+            .await?
+            .execute(
+                "
+                UPDATE rhombus_ticket
+                SET closed_at = ?1
+                WHERE ticket_number = ?2
+            ",
+                params!(time.timestamp(), ticket_number),
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn close_tickets_for_challenge(
+        &self,
+        user_id: i64,
+        challenge_id: &str,
+        time: DateTime<Utc>,
+    ) -> Result<Vec<ToBeClosedTicket>> {
+        let conn = self.connect().await?;
+
+        #[derive(Debug, Deserialize)]
+        struct DbTicket {
+            pub discord_channel_id: Option<NonZeroU64>,
+            pub discord_panel_message_id: Option<NonZeroU64>,
+            pub ticket_number: u64,
         }
 
-        let challenge_division_solves_rows = tx
-            .query("SELECT * FROM rhombus_challenge_division_solves", ())
+        let tickets = conn
+            .query(
+                "
+                UPDATE rhombus_ticket
+                SET closed_at = ?3
+                WHERE user_id = ?1 AND challenge_id = ?2 AND closed_at IS NULL
+                RETURNING ticket_number, discord_channel_id, discord_panel_message_id
+            ",
+                params!(user_id, challenge_id, time.timestamp()),
+            )
+            .await?
+            .into_stream()
+            .map(|row| de::from_row::<DbTicket>(&row.unwrap()).unwrap())
+            .map(|db_ticket| ToBeClosedTicket {
+                discord_channel_id: db_ticket.discord_channel_id,
+                discord_panel_message_id: db_ticket.discord_panel_message_id,
+                ticket_number: db_ticket.ticket_number,
+            })
+            .collect::<Vec<_>>()
+            .await;
+
+        Ok(tickets)
+    }
+
+    async fn get_discord_ticket_channel_ids_for_challenge(
+        &self,
+        challenge_id: &str,
+    ) -> Result<Vec<u64>> {
+        let conn = self.connect().await?;
+
+        let discord_channel_ids = conn
+            .query(
+                "
+                SELECT discord_channel_id
+                FROM rhombus_ticket
+                WHERE challenge_id = ?1 AND discord_channel_id IS NOT NULL
+            ",
+                [challenge_id],
+            )
+            .await?
+            .into_stream()
+            .map(|row| row.unwrap().get::<u64>(0).unwrap())
+            .collect::<Vec<_>>()
+            .await;
+
+        Ok(discord_channel_ids)
+    }
+
+    async fn reopen_ticket(&self, ticket_number: u64) -> Result<()> {
+        self.connect()
+            .await?
+            .execute(
+                "
+                UPDATE rhombus_ticket
+                SET closed_at = NULL, status = 'open'
+                WHERE ticket_number = ?1
+            ",
+                [ticket_number],
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn add_email_message_id_to_ticket(
+        &self,
+        ticket_number: u64,
+        message_id: &str,
+        user_sent: bool,
+    ) -> Result<()> {
+        self.connect().await?
+            .execute(
+                "
+                INSERT INTO rhombus_ticket_email_message_id_reference (ticket_number, message_id, user_sent) VALUES (?1, ?2, ?3)
+            ",
+                params!(ticket_number, message_id, user_sent),
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn get_ticket_number_by_message_id(&self, message_id: &str) -> Result<Option<u64>> {
+        let ticket_number = self
+            .connect()
             .await?
-            .into_stream()
-            .map(|row| de::from_row::<DbChallengeDivisionSolves>(&row.unwrap()).unwrap())
-            .collect::<Vec<_>>()
-            .await;
-        #[derive(Debug, Deserialize)]
-        struct DbChallengeDivisionSolves {
-            challenge_id: String,
-            division_id: String,
-            solves: i64,
-        }
-        let mut challenge_division_solves = BTreeMap::new();
-        for row in challenge_division_solves_rows.into_iter() {
-            match challenge_division_solves.get_mut(&row.challenge_id) {
-                None => {
-                    let mut map = BTreeMap::new();
-                    map.insert(row.division_id, row.solves as u64);
-                    _ = challenge_division_solves.insert(row.challenge_id, map);
-                }
-                Some(division_solves) => {
-                    _ = division_solves.insert(row.division_id, row.solves as u64)
-                }
-            }
+            .query(
+                "
+                SELECT ticket_number
+                FROM rhombus_ticket_email_message_id_reference
+                WHERE message_id = ?1
+            ",
+                [message_id],
+            )
+            .await?
+            .next()
+            .await?
+            .and_then(|row| row.get::<u64>(0).ok());
+
+        Ok(ticket_number)
+    }
+
+    async fn save_settings(&self, settings: &Settings) -> Result<()> {
+        if settings.immutable_config {
+            return Ok(());
         }
 
-        let mut query_challenges = tx
-            .query("SELECT * FROM rhombus_file_attachment", ())
+        let json = serde_json::to_string(settings).unwrap();
+
+        self.connect()
+            .await?
+            .execute(
+                "
+                INSERT OR REPLACE INTO rhombus_config (id, config) VALUES (1, ?1)
+            ",
+                [json],
+            )
             .await?;
-        #[derive(Debug, Deserialize)]
-        struct QueryChallengeFileAttachment {
-            challenge_id: String,
-            name: String,
-            url: String,
-        }
-        let mut challenge_attachments = BTreeMap::new();
-        while let Some(row) = query_challenges.next().await? {
-            let query_attachment = de::from_row::<QueryChallengeFileAttachment>(&row).unwrap();
-            let attachment = ChallengeAttachment {
-                name: query_attachment.name,
-                url: query_attachment.url,
-            };
-            match challenge_attachments.get_mut(&query_attachment.challenge_id) {
-                None => {
-                    _ = challenge_attachments
-                        .insert(query_attachment.challenge_id, vec![attachment])
-                }
-                Some(attachments) => attachments.push(attachment),
-            };
-        }
 
-        let challenge_rows = tx.query("SELECT * FROM rhombus_challenge ", ()).await?;
+        Ok(())
+    }
+
+    async fn load_settings(&self, _settings: &mut Settings) -> Result<()> {
+        // if settings.immutable_config {
+        //     return Ok(());
+        // }
+
+        // let db_settings: Option<Settings> = self
+        //     .db
+        //     .query("SELECT config FROM rhombus_config", ())
+        //     .await?
+        //     .next()
+        //     .await?
+        //     .map(|row| serde_json::from_str(&row.get::<String>(0).unwrap()).unwrap());
+
+        // if let Some(db_settings) = db_settings {
+        //     if settings.discord.first_blood_channel_id.is_none() {
+        //         settings.discord.first_blood_channel_id =
+        //             db_settings.discord.first_blood_channel_id;
+        //     }
+
+        //     if settings.discord.support_channel_id.is_none() {
+        //         settings.discord.support_channel_id = db_settings.discord.support_channel_id;
+        //     }
+
+        //     if settings.discord.author_role_id.is_none() {
+        //         settings.discord.author_role_id = db_settings.discord.author_role_id;
+        //     }
+        // }
+
+        Ok(())
+    }
+
+    async fn get_scoreboard(&self, division_id: &str) -> Result<Scoreboard> {
+        let tx = self.transaction().await?;
 
         #[derive(Debug, Deserialize)]
-        struct DbChallenge {
-            id: String,
+        struct DbTeam {
+            id: i64,
             name: String,
-            description: String,
-            flag: String,
-            category_id: String,
-            author_id: String,
-            ticket_template: Option<String>,
-            healthscript: Option<String>,
-            healthy: Option<bool>,
-            last_healthcheck: Option<i64>,
-            metadata: String,
             points: i64,
-            score_type: String,
         }
-        let challenges = challenge_rows
-            .into_stream()
-            .map(|row| de::from_row::<DbChallenge>(&row.unwrap()).unwrap())
-            .map(|challenge| {
-                (
-                    challenge.id.clone(),
-                    Challenge {
-                        id: challenge.id.clone(),
-                        name: challenge.name,
-                        description: challenge.description,
-                        flag: challenge.flag,
-                        category_id: challenge.category_id,
-                        author_id: challenge.author_id,
-                        ticket_template: challenge.ticket_template,
-                        healthscript: challenge.healthscript,
-                        healthy: challenge.healthy,
-                        last_healthcheck: challenge
-                            .last_healthcheck
-                            .map(|t| Utc.timestamp_opt(t, 0).unwrap()),
-                        score_type: challenge.score_type,
-                        metadata: serde_json::from_str(&challenge.metadata).unwrap(),
-                        points: challenge.points,
-                        attachments: challenge_attachments
-                            .get(&challenge.id)
-                            .unwrap_or(&vec![])
-                            .to_vec(),
-                        division_solves: {
-                            let mut division_solves = challenge_division_solves
-                                .get(&challenge.id)
-                                .unwrap_or(&BTreeMap::new())
-                                .clone();
-                            for (division_id, _) in divisions.iter() {
-                                if !division_solves.contains_key(division_id) {
-                                    division_solves.insert(division_id.clone(), 0);
-                                }
-                            }
-                            division_solves
-                        },
-                    },
-                )
-            })
-            .collect::<BTreeMap<_, _>>()
-            .await;
 
-        let category_rows = tx
-            .query("SELECT * FROM rhombus_category ORDER BY sequence", ())
+        let mut db_teams = tx
+            .query(
+                "
+                SELECT id, name, points
+                FROM rhombus_team
+                WHERE division_id = ?1 AND points > 0
+                ORDER BY points DESC, last_solved_at ASC
+                LIMIT 10
+            ",
+                params!(division_id),
+            )
             .await?;
-        #[derive(Debug, Deserialize)]
-        struct DbCategory {
-            id: String,
-            name: String,
-            color: String,
-        }
-        let categories = category_rows
-            .into_stream()
-            .map(|row| de::from_row::<DbCategory>(&row.unwrap()).unwrap())
-            .map(|category| {
+
+        let mut teams = BTreeMap::new();
+        while let Some(row) = db_teams.next().await? {
+            let team = de::from_row::<DbTeam>(&row).unwrap();
+            teams.insert(
+                team.id,
                 (
-                    category.id.clone(),
-                    Category {
-                        id: category.id,
-                        name: category.name,
-                        color: category.color,
+                    ScoreboardTeam {
+                        team_name: team.name,
+                        series: vec![],
                     },
-                )
-            })
-            .collect::<BTreeMap<String, Category>>()
-            .await;
+                    team.points,
+                ),
+            );
+        }
 
-        let mut author_rows = tx.query("SELECT * FROM rhombus_author", ()).await?;
         #[derive(Debug, Deserialize)]
-        struct DbAuthor {
-            id: String,
-            name: String,
-            avatar: String,
-            discord_id: NonZeroU64,
-        }
-        let mut authors = BTreeMap::new();
-        while let Some(row) = author_rows.next().await? {
-            let query_author = de::from_row::<DbAuthor>(&row).unwrap();
-            authors.insert(
-                query_author.id.clone(),
-                Author {
-                    id: query_author.id,
-                    name: query_author.name,
-                    avatar_url: query_author.avatar,
-                    discord_id: query_author.discord_id,
-                },
-            );
+        struct DbScoreboard {
+            at: i64,
+            points: i64,
         }
 
-        tx.commit().await?;
+        for (team_id, (team, _)) in teams.iter_mut() {
+            let mut db_scoreboard = tx
+                .query(
+                    "
+                    SELECT at, points
+                    FROM rhombus_points_snapshot
+                    WHERE team_id = ?
+                    ORDER BY at ASC
+                ",
+                    params!(team_id),
+                )
+                .await?;
 
-        Ok(Arc::new(ChallengeData {
-            challenges,
-            categories,
-            authors,
-            divisions,
-        }))
-    }
+            while let Some(row) = db_scoreboard.next().await? {
+                let scoreboard = de::from_row::<DbScoreboard>(&row).unwrap();
+                let series_point = ScoreboardSeriesPoint {
+                    timestamp: scoreboard.at,
+                    total_score: scoreboard.points,
+                };
+                team.series.push(series_point);
+            }
+        }
 
-    async fn set_challenge_health(
-        &self,
-        challenge_id: &str,
-        healthy: Option<bool>,
-        checked_at: DateTime<Utc>,
-    ) -> Result<()> {
-        self.connect()
-            .await?
-            .execute(
-                "UPDATE rhombus_challenge SET healthy = ?2, last_healthcheck = ?3 WHERE id = ?1",
-                params!(challenge_id, healthy, checked_at.timestamp()),
-            )
-            .await?;
+        tx.commit().await?;
 
-        Ok(())
+        let now = chrono::Utc::now().timestamp();
+        let teams = teams
+            .into_iter()
+            .map(|(team_id, (mut team, current_points))| {
+                team.series.push(ScoreboardSeriesPoint {
+                    timestamp: now,
+                    total_score: current_points,
+                });
+                (team_id, team)
+            })
+            .collect();
+
+        Ok(Arc::new(ScoreboardInner::new(teams)))
     }
 
-    async fn get_team_meta_from_invite_token(
-        &self,
-        invite_token: &str,
-    ) -> Result<Option<TeamMeta>> {
+    async fn get_leaderboard(&self, division_id: &str) -> Result<Leaderboard> {
         #[derive(Debug, Deserialize)]
-        struct DbTeam {
-            id: i64,
+        struct DbLeaderboard {
+            team_id: i64,
             name: String,
+            points: f64,
         }
-        let team = self
+
+        let mut rank = 0;
+
+        let leaderboard_entries = self
             .connect()
             .await?
             .query(
-                "SELECT id, name FROM rhombus_team WHERE invite_token = ?",
-                [invite_token],
+                "
+                SELECT id AS team_id, name, points
+                FROM rhombus_team
+                WHERE division_id = ?1
+                ORDER BY points DESC, last_solved_at ASC
+            ",
+                params!(division_id),
             )
             .await?
-            .next()
-            .await?;
+            .into_stream()
+            .map(|row| {
+                let db_leaderboard = de::from_row::<DbLeaderboard>(&row.unwrap()).unwrap();
+                rank += 1;
+                LeaderboardEntry {
+                    rank,
+                    team_id: db_leaderboard.team_id,
+                    team_name: db_leaderboard.name,
+                    score: db_leaderboard.points.round() as i64,
+                }
+            })
+            .collect::<Vec<_>>()
+            .await;
 
-        if let Some(row) = &team {
-            let team = de::from_row::<DbTeam>(row).unwrap();
-            Ok(Some(Arc::new(TeamMetaInner {
-                id: team.id,
-                name: team.name,
-            })))
-        } else {
-            Ok(None)
-        }
+        Ok(Arc::new(leaderboard_entries))
     }
 
-    async fn get_team_from_id(&self, team_id: i64) -> Result<Team> {
-        let tx = self.transaction().await?;
-
-        #[derive(Debug, Deserialize)]
-        struct QueryTeam {
-            name: String,
-            invite_token: String,
-            division_id: String,
-            last_division_change: Option<i64>,
-        }
-        let query_team_row = tx
+    async fn get_top10_discord_ids(&self) -> Result<BTreeSet<NonZeroU64>> {
+        let top10_discord_ids = self
+            .connect()
+            .await?
             .query(
-                "SELECT name, invite_token, division_id, last_division_change FROM rhombus_team WHERE id = ?1",
-                [team_id],
+                "
+                SELECT rhombus_user.discord_id
+                FROM rhombus_division
+                JOIN rhombus_user
+                WHERE rhombus_user.team_id IN (
+                    SELECT id
+                    FROM rhombus_team
+                    WHERE division_id = rhombus_division.id AND points > 0
+                    ORDER BY points DESC, last_solved_at ASC
+                    LIMIT 10
+                )
+                AND rhombus_user.discord_id IS NOT NULL
+            ",
+                (),
             )
             .await?
-            .next()
-            .await?
-            .ok_or(RhombusError::DatabaseReturnedNoRows)?;
-        let query_team = de::from_row::<QueryTeam>(&query_team_row).unwrap();
+            .into_stream()
+            .map(|row| NonZeroU64::new(row.unwrap().get::<u64>(0).unwrap()).unwrap())
+            .collect()
+            .await;
 
-        #[derive(Debug, Deserialize)]
-        struct QueryTeamUser {
-            id: i64,
-            name: String,
-            avatar: String,
-            discord_id: Option<NonZeroU64>,
-            owner_team_id: i64,
-        }
-        let mut query_user_rows = tx
-            .query(
-                "SELECT id, name, avatar, discord_id, owner_team_id FROM rhombus_user WHERE team_id = ?1",
-                [team_id],
-            )
-            .await?;
-        let mut owner_user_id = 0;
-        let mut users: BTreeMap<i64, TeamUser> = Default::default();
-        while let Some(row) = query_user_rows.next().await? {
-            let query_user = de::from_row::<QueryTeamUser>(&row).unwrap();
-            if query_user.owner_team_id == team_id {
-                owner_user_id = query_user.id;
-            }
-            users.insert(
-                query_user.id,
-                TeamUser {
-                    name: query_user.name,
-                    avatar_url: query_user.avatar,
-                    discord_id: query_user.discord_id,
-                    is_team_owner: query_user.owner_team_id == team_id,
-                },
-            );
-        }
+        Ok(top10_discord_ids)
+    }
 
+    async fn get_emails_for_user_id(&self, user_id: i64) -> Result<Vec<Email>> {
         #[derive(Debug, Deserialize)]
-        struct QuerySolve {
-            pub challenge_id: String,
-            pub user_id: i64,
-            pub solved_at: i64,
-            pub points: Option<i64>,
+        struct QueryEmail {
+            email: String,
+            code: Option<String>,
         }
-        let mut query_solves = tx
+
+        let emails = self
+            .connect()
+            .await?
             .query(
-                "
-                SELECT challenge_id, user_id, solved_at, points
-                FROM rhombus_solve
-                WHERE team_id = ?1
-            ",
-                [team_id],
+                "SELECT email, code FROM rhombus_email WHERE user_id = ?1",
+                [user_id],
             )
-            .await?;
-        let mut solves: BTreeMap<String, ChallengeSolve> = Default::default();
-        while let Some(row) = query_solves.next().await? {
-            let query_solve = de::from_row::<QuerySolve>(&row).unwrap();
-            let solve = ChallengeSolve {
-                user_id: query_solve.user_id,
-                solved_at: DateTime::<Utc>::from_timestamp(query_solve.solved_at, 0).unwrap(),
-                points: query_solve.points,
-            };
+            .await?
+            .into_stream()
+            .map(|row| {
+                let email = de::from_row::<QueryEmail>(&row.unwrap()).unwrap();
+                Email {
+                    address: email.email,
+                    verified: email.code.is_none(),
+                }
+            })
+            .collect::<Vec<_>>()
+            .await;
 
-            // favor the earliest solve
-            if match solves.get(&query_solve.challenge_id) {
-                Some(old) => solve < *old,
-                None => true,
-            } {
-                solves.insert(query_solve.challenge_id, solve);
-            }
-        }
+        Ok(emails)
+    }
 
+    async fn get_team_tracks(&self, team_id: i64) -> Result<BTreeMap<i64, UserTrack>> {
         #[derive(Debug, Deserialize)]
-        struct QueryWriteup {
-            pub user_id: i64,
-            pub challenge_id: String,
-            pub url: String,
+        struct DbTrack {
+            user_id: i64,
+            ip: [u8; 16],
+            last_seen_at: i64,
         }
-        let mut query_writeups = tx
+
+        let mut tracks = BTreeMap::new();
+
+        let mut query_locations = self
+            .connect()
+            .await?
             .query(
                 "
-                SELECT user_id, challenge_id, url
-                FROM rhombus_writeup
-                WHERE user_id IN (
-                    SELECT id
+                WITH RankedTracks AS (
+                    SELECT
+                        user_id,
+                        ip,
+                        last_seen_at,
+                        ROW_NUMBER() OVER (PARTITION BY rhombus_user.id ORDER BY last_seen_at DESC) as rn
                     FROM rhombus_user
-                    WHERE team_id = ?1
+                    JOIN rhombus_track_ip ON rhombus_user.id = rhombus_track_ip.user_id
+                    JOIN rhombus_track ON rhombus_track.id = rhombus_track_ip.track_id
+                    WHERE rhombus_user.team_id = 1
                 )
+                SELECT user_id, ip, last_seen_at
+                FROM RankedTracks
+                WHERE rn = 1
             ",
                 [team_id],
             )
             .await?;
-        let mut writeups = BTreeMap::new();
-        while let Some(row) = query_writeups.next().await? {
-            let query_writeup = de::from_row::<QueryWriteup>(&row).unwrap();
-            let writeup = Writeup {
-                url: query_writeup.url,
-                user_id: query_writeup.user_id,
-            };
-            match writeups.get_mut(&query_writeup.challenge_id) {
-                None => _ = writeups.insert(query_writeup.challenge_id, vec![writeup]),
-                Some(ws) => ws.push(writeup),
-            };
-        }
 
-        tx.commit().await?;
+        while let Some(row) = query_locations.next().await? {
+            let db_track = de::from_row::<DbTrack>(&row).unwrap();
+            let ip = IpAddr::from(db_track.ip);
+            let last_seen_at = DateTime::<Utc>::from_timestamp(db_track.last_seen_at, 0).unwrap();
+            let track = UserTrack { ip, last_seen_at };
+            tracks.insert(db_track.user_id, track);
+        }
 
-        Ok(Arc::new(TeamInner {
-            id: team_id,
-            name: query_team.name,
-            invite_token: query_team.invite_token,
-            division_id: query_team.division_id,
-            last_division_change: query_team
-                .last_division_change
-                .map(|t| DateTime::<Utc>::from_timestamp(t, 0).unwrap()),
-            owner_user_id,
-            users,
-            solves,
-            writeups,
-        }))
+        Ok(tracks)
     }
 
-    async fn add_user_to_team(
+    async fn create_email_verification_callback_code(
         &self,
         user_id: i64,
-        team_id: i64,
-        _old_team_id: Option<i64>,
-    ) -> Result<()> {
-        let tx = self.transaction().await?;
-
-        tx.execute(
-            "
-            UPDATE rhombus_user
-            SET team_id = ?2
-            WHERE id = ?1
-        ",
-            [user_id, team_id],
-        )
-        .await?;
+        email: &str,
+    ) -> Result<String> {
+        let code = generate_email_callback_code();
 
-        tx.execute(
-            "
-            UPDATE rhombus_solve
-            SET team_id = ?2
-            WHERE user_id = ?1
-        ",
-            [user_id, team_id],
-        )
-        .await?;
+        self.connect()
+            .await?
+            .execute(
+                "INSERT INTO rhombus_email (email, user_id, code) VALUES (?1, ?2, ?3)",
+                params!(email, user_id, code.as_str()),
+            )
+            .await?;
 
-        tx.commit().await?;
-        Ok(())
+        Ok(code)
     }
 
-    async fn get_user_from_id(&self, user_id: i64) -> Result<User> {
-        #[derive(Debug, Deserialize)]
-        struct DbUser {
-            id: i64,
-            name: String,
-            avatar: String,
-            discord_id: Option<NonZeroU64>,
-            team_id: i64,
-            owner_team_id: i64,
-            disabled: bool,
-            is_admin: bool,
-            api_key: String,
-        }
-
-        let row = self
+    async fn verify_email_verification_callback_code(&self, code: &str) -> Result<i64> {
+        let user_id = self
             .connect()
             .await?
-            .query("SELECT * FROM rhombus_user WHERE id = ?1", [user_id])
+            .query(
+                "
+                UPDATE rhombus_email
+                SET code = NULL
+                WHERE code = ?1
+                RETURNING user_id
+            ",
+                [code],
+            )
             .await?
             .next()
             .await?
-            .ok_or(RhombusError::DatabaseReturnedNoRows)?;
+            .ok_or(RhombusError::DatabaseReturnedNoRows)?
+            .get::<i64>(0)?;
 
-        let user = de::from_row::<DbUser>(&row).unwrap();
-        Ok(Arc::new(UserInner {
-            id: user.id,
-            name: user.name,
-            avatar: user.avatar,
-            discord_id: user.discord_id,
-            disabled: user.disabled,
-            is_admin: user.is_admin,
-            team_id: user.team_id,
-            is_team_owner: user.team_id == user.owner_team_id,
-            api_key: user.api_key,
-        }))
+        Ok(user_id)
     }
 
-    async fn get_user_from_discord_id(&self, discord_id: NonZeroU64) -> Result<User> {
-        #[derive(Debug, Deserialize)]
-        struct DbUser {
-            id: i64,
-            name: String,
-            avatar: String,
-            discord_id: Option<NonZeroU64>,
-            team_id: i64,
-            owner_team_id: i64,
-            disabled: bool,
-            is_admin: bool,
-            api_key: String,
-        }
-
-        let row = self
+    async fn get_email_verification_by_callback_code(&self, code: &str) -> Result<String> {
+        let email = self
             .connect()
             .await?
             .query(
-                "SELECT * FROM rhombus_user WHERE discord_id = ?1",
-                [discord_id.get()],
+                "
+                SELECT email
+                FROM rhombus_email
+                WHERE code = ?1
+            ",
+                [code],
             )
             .await?
             .next()
             .await?
-            .ok_or(libsql::Error::QueryReturnedNoRows)?;
+            .ok_or(RhombusError::DatabaseReturnedNoRows)?
+            .get::<String>(0)
+            .unwrap();
 
-        let user = de::from_row::<DbUser>(&row).unwrap();
-        Ok(Arc::new(UserInner {
-            id: user.id,
-            name: user.name,
-            avatar: user.avatar,
-            discord_id: user.discord_id,
-            disabled: user.disabled,
-            is_admin: user.is_admin,
-            team_id: user.team_id,
-            is_team_owner: user.team_id == user.owner_team_id,
-            api_key: user.api_key,
-        }))
+        Ok(email)
     }
 
-    async fn get_user_from_api_key(&self, api_key: &str) -> Result<User> {
-        #[derive(Debug, Deserialize)]
-        struct DbUser {
-            id: i64,
-            name: String,
-            avatar: String,
-            discord_id: Option<NonZeroU64>,
-            team_id: i64,
-            owner_team_id: i64,
-            disabled: bool,
-            is_admin: bool,
-            api_key: String,
-        }
+    async fn create_email_signin_callback_code(&self, email: &str) -> Result<String> {
+        let code = generate_email_callback_code();
 
-        let row = self
-            .connect()
-            .await?
-            .query("SELECT * FROM rhombus_user WHERE api_key = ?1", [api_key])
-            .await?
-            .next()
+        self.connect()
             .await?
-            .ok_or(libsql::Error::QueryReturnedNoRows)?;
+            .execute(
+                "INSERT OR REPLACE INTO rhombus_email_signin (email, code) VALUES (?1, ?2)",
+                params!(email, code.as_str()),
+            )
+            .await?;
 
-        let user = de::from_row::<DbUser>(&row).unwrap();
-        Ok(Arc::new(UserInner {
-            id: user.id,
-            name: user.name,
-            avatar: user.avatar,
-            discord_id: user.discord_id,
-            disabled: user.disabled,
-            is_admin: user.is_admin,
-            team_id: user.team_id,
-            is_team_owner: user.team_id == user.owner_team_id,
-            api_key: user.api_key,
-        }))
+        Ok(code)
     }
 
-    async fn kick_user(&self, user_id: i64, _team_id: i64) -> Result<i64> {
-        let tx = self.transaction().await?;
-
-        let new_team_id = tx
+    async fn verify_email_signin_callback_code(&self, code: &str) -> Result<String> {
+        let email = self
+            .connect()
+            .await?
             .query(
                 "
-                UPDATE rhombus_user
-                SET team_id = owner_team_id
-                WHERE id = ?1
-                RETURNING owner_team_id
+                DELETE FROM rhombus_email_signin
+                WHERE code = ?1
+                RETURNING email
             ",
-                [user_id],
+                [code],
             )
             .await?
             .next()
             .await?
-            .unwrap()
-            .get::<i64>(0)
+            .ok_or(libsql::Error::QueryReturnedNoRows)?
+            .get::<String>(0)
             .unwrap();
 
-        tx.execute(
-            "
-            UPDATE rhombus_solve
-            SET team_id = ?2
-            WHERE user_id = ?1
-        ",
-            [user_id, new_team_id],
-        )
-        .await?;
-
-        tx.commit().await?;
-        Ok(new_team_id)
+        Ok(email)
     }
 
-    async fn roll_invite_token(&self, team_id: i64) -> Result<String> {
-        let new_invite_token = create_team_invite_token();
-
-        self.connect()
+    async fn get_email_signin_by_callback_code(&self, code: &str) -> Result<String> {
+        let email = self
+            .connect()
             .await?
-            .execute(
-                "UPDATE rhombus_team SET invite_token = ?2 WHERE id = ?1",
-                params!(team_id, new_invite_token.as_str()),
+            .query(
+                "
+                SELECT email
+                FROM rhombus_email_signin
+                WHERE code = ?1
+            ",
+                [code],
             )
-            .await?;
+            .await?
+            .next()
+            .await?
+            .ok_or(libsql::Error::QueryReturnedNoRows)?
+            .get::<String>(0)
+            .unwrap();
 
-        Ok(new_invite_token)
+        Ok(email)
     }
 
-    async fn roll_api_key(&self, user_id: i64, location_url: &str) -> Result<String> {
-        let new_api_key = create_user_api_key(location_url);
-
+    async fn delete_email(&self, user_id: i64, email: &str) -> Result<()> {
         self.connect()
             .await?
             .execute(
-                "UPDATE rhombus_user SET api_key = ?2 WHERE id = ?1",
-                params!(user_id, new_api_key.as_str()),
+                "DELETE FROM rhombus_email WHERE user_id = ?1 AND email = ?2",
+                params!(user_id, email),
             )
             .await?;
 
-        Ok(new_api_key)
+        Ok(())
     }
 
-    async fn set_team_name(
+    async fn set_team_division(
         &self,
         team_id: i64,
-        new_team_name: &str,
-        timeout_seconds: u64,
-    ) -> Result<std::result::Result<(), SetTeamNameError>> {
+        old_division_id: &str,
+        new_division_id: &str,
+        now: DateTime<Utc>,
+        admin_user_id: Option<i64>,
+    ) -> Result<()> {
         let tx = self.transaction().await?;
 
-        let last_change_timestamp = tx
-            .query(
-                "
-                SELECT at
-                FROM rhombus_team_historical_names
-                WHERE team_id = ?1
-                ORDER BY at DESC
-                LIMIT 1
-            ",
-                [team_id],
-            )
-            .await?
-            .next()
-            .await?
-            .map(|row| row.get::<i64>(0).unwrap());
-
-        let last_change_date =
-            DateTime::<Utc>::from_timestamp(last_change_timestamp.unwrap_or(0), 0).unwrap();
-
-        let next_allowed = last_change_date + Duration::from_secs(timeout_seconds);
-        if Utc::now() < next_allowed {
-            return Ok(Err(SetTeamNameError::Timeout(next_allowed)));
-        }
+        tx.execute(
+            "UPDATE rhombus_team SET division_id = ?1, last_division_change = ?2 WHERE id = ?3",
+            params!(new_division_id, now.timestamp(), team_id),
+        )
+        .await?;
 
         tx.execute(
             "
-            INSERT INTO rhombus_team_historical_names (team_id, name)
-            SELECT ?1, name
-            FROM rhombus_team
-            WHERE id = ?1
-            ",
-            params!(team_id),
+            INSERT INTO rhombus_team_division_history (team_id, old_division_id, new_division_id, admin_user_id, at)
+            VALUES (?1, ?2, ?3, ?4, ?5)
+        ",
+            params!(
+                team_id,
+                old_division_id,
+                new_division_id,
+                admin_user_id,
+                now.timestamp()
+            ),
         )
         .await?;
 
-        if tx
-            .execute(
-                "UPDATE rhombus_team SET name = ?2 WHERE id = ?1",
-                params!(team_id, new_team_name),
-            )
-            .await
-            .is_err()
-        {
-            return Ok(Err(SetTeamNameError::Taken));
-        }
-
         tx.commit().await?;
-        Ok(Ok(()))
+
+        Ok(())
     }
 
-    async fn set_account_name(
-        &self,
-        user_id: i64,
-        _team_id: i64,
-        new_account_name: &str,
-        timeout_seconds: u64,
-    ) -> Result<std::result::Result<(), SetAccountNameError>> {
+    async fn insert_divisions(&self, divisions: &[Division]) -> Result<()> {
         let tx = self.transaction().await?;
 
-        let last_change_timestamp = tx
-            .query(
+        let current_division_ids = tx
+            .query("SELECT id FROM rhombus_division", ())
+            .await?
+            .into_stream()
+            .map(|row| row.unwrap().get::<String>(0).unwrap())
+            .collect::<Vec<_>>()
+            .await;
+
+        for division_id in current_division_ids {
+            if !divisions.iter().any(|d| d.id == division_id) {
+                _ = tx
+                    .execute("DELETE FROM rhombus_division WHERE id = ?1", [division_id])
+                    .await;
+            }
+        }
+
+        // admin managed divisions are inserted by the admin, so only their default status changes
+        for division in divisions {
+            tx.execute(
                 "
-                SELECT at
-                FROM rhombus_user_historical_names
-                WHERE user_id = ?1
-                ORDER BY at DESC
-                LIMIT 1
+                INSERT INTO rhombus_division (id, name, description, is_default) VALUES (?1, ?2, ?3, ?4)
+                ON CONFLICT (id) DO UPDATE SET
+                    name = CASE WHEN admin_managed THEN name ELSE excluded.name END,
+                    description = CASE WHEN admin_managed THEN description ELSE excluded.description END,
+                    is_default = excluded.is_default
             ",
-                [user_id],
+                params!(division.id.as_str(), division.name.as_str(), division.description.as_str(), division.is_default),
             )
-            .await?
-            .next()
-            .await?
-            .map(|row| row.get::<i64>(0).unwrap());
+            .await?;
+        }
 
-        let last_change_date =
-            DateTime::<Utc>::from_timestamp(last_change_timestamp.unwrap_or(0), 0).unwrap();
+        tx.commit().await?;
 
-        let next_allowed = last_change_date + Duration::from_secs(timeout_seconds);
-        if Utc::now() < next_allowed {
-            return Ok(Err(SetAccountNameError::Timeout(next_allowed)));
+        Ok(())
+    }
+
+    async fn get_divisions(&self) -> Result<Vec<StoredDivision>> {
+        #[derive(Debug, Deserialize)]
+        struct QueryDivision {
+            id: String,
+            name: String,
+            description: String,
+            max_players: Option<String>,
+            archived: bool,
+            admin_managed: bool,
         }
 
-        tx.execute(
-            "
-            INSERT INTO rhombus_user_historical_names (user_id, name)
-            SELECT ?1, name
-            FROM rhombus_user
-            WHERE id = ?1
-            ",
-            params!(user_id),
-        )
-        .await?;
+        let mut rows = self
+            .connect()
+            .await?
+            .query(
+                "SELECT id, name, description, max_players, archived, admin_managed FROM rhombus_division ORDER BY rowid",
+                (),
+            )
+            .await?;
 
-        if tx
+        let mut divisions = vec![];
+        while let Some(row) = rows.next().await? {
+            let division = de::from_row::<QueryDivision>(&row).unwrap();
+            divisions.push(StoredDivision {
+                id: division.id,
+                name: division.name,
+                description: division.description,
+                max_players: division.max_players,
+                archived: division.archived,
+                admin_managed: division.admin_managed,
+            });
+        }
+
+        Ok(divisions)
+    }
+
+    async fn create_division(
+        &self,
+        division_id: &str,
+        name: &str,
+        description: &str,
+        max_players: Option<&str>,
+    ) -> Result<bool> {
+        let created = self
+            .connect()
+            .await?
             .execute(
-                "UPDATE rhombus_user SET name = ?2 WHERE id = ?1",
-                params!(user_id, new_account_name),
+                "INSERT INTO rhombus_division (id, name, description, max_players, admin_managed) VALUES (?1, ?2, ?3, ?4, TRUE) ON CONFLICT DO NOTHING",
+                params!(division_id, name, description, max_players),
             )
-            .await
-            .is_err()
-        {
-            return Ok(Err(SetAccountNameError::Taken));
-        }
+            .await?;
 
-        tx.commit().await?;
-        Ok(Ok(()))
+        Ok(created != 0)
     }
 
-    async fn solve_challenge(
+    async fn update_division(
         &self,
-        user_id: i64,
-        team_id: i64,
         division_id: &str,
-        challenge: &Challenge,
-        next_points: i64,
-        now: DateTime<Utc>,
+        name: &str,
+        description: &str,
+        max_players: Option<&str>,
     ) -> Result<()> {
-        let tx = self.transaction().await?;
-
-        let top_10 = tx
-            .query(
-                "
-                SELECT id
-                FROM rhombus_team
-                WHERE division_id = ?1 AND points > 0
-                ORDER BY points DESC, last_solved_at ASC
-                LIMIT 10
-            ",
-                [division_id],
-            )
+        let updated = self
+            .connect()
             .await?
-            .into_stream()
-            .map(|row| row.unwrap().get::<i64>(0).unwrap())
-            .collect::<Vec<_>>()
-            .await;
+            .execute(
+                "UPDATE rhombus_division SET name = ?2, description = ?3, max_players = ?4 WHERE id = ?1 AND admin_managed",
+                params!(division_id, name, description, max_players),
+            )
+            .await?;
 
-        let point_difference = challenge.points - next_points;
+        if updated == 0 {
+            return Err(RhombusError::DatabaseReturnedNoRows);
+        }
 
-        if point_difference != 0 {
-            tx.execute(
-                "
-                UPDATE rhombus_team
-                SET points = points - ?1
-                WHERE id in (
-                    SELECT team_id
-                    FROM rhombus_solve
-                    WHERE challenge_id = ?2 and points IS NULL
-                )
-            ",
-                params!(point_difference, challenge.id.as_str()),
+        Ok(())
+    }
+
+    async fn set_division_archived(&self, division_id: &str, archived: bool) -> Result<()> {
+        self.connect()
+            .await?
+            .execute(
+                "UPDATE rhombus_division SET archived = ?2 WHERE id = ?1",
+                params!(division_id, archived),
             )
             .await?;
-        }
 
-        tx.execute(
-            "INSERT INTO rhombus_solve (challenge_id, user_id, team_id, solved_at) VALUES (?1, ?2, ?3, ?4)",
-            params!(challenge.id.as_str(), user_id, team_id, now.timestamp()),
-        )
-        .await?;
+        Ok(())
+    }
 
-        tx.execute(
-            "
-            INSERT INTO rhombus_challenge_division_solves (challenge_id, division_id, solves) VALUES (?1, ?2, 1)
-            ON CONFLICT (challenge_id, division_id) DO UPDATE SET solves = solves + 1
-        ",
-            params!(challenge.id.as_str(), division_id),
-        )
-        .await?;
+    async fn get_division_teams(&self, division_id: &str) -> Result<Vec<TeamMeta>> {
+        let mut rows = self
+            .connect()
+            .await?
+            .query(
+                "SELECT id, name FROM rhombus_team WHERE division_id = ?1 ORDER BY name",
+                [division_id],
+            )
+            .await?;
 
-        tx.execute(
-            "
-            UPDATE rhombus_team
-            SET points = points + ?1, last_solved_at = ?2
-            WHERE id = ?3
-        ",
-            params!(next_points, now.timestamp(), team_id),
-        )
-        .await?;
+        let mut teams = vec![];
+        while let Some(row) = rows.next().await? {
+            teams.push(Arc::new(TeamMetaInner {
+                id: row.get(0).unwrap(),
+                name: row.get(1).unwrap(),
+            }));
+        }
 
-        tx.execute(
-            "
-            UPDATE rhombus_challenge
-            SET points = ?2
-            WHERE id = ?1
-        ",
-            params!(challenge.id.as_str(), next_points),
-        )
-        .await?;
+        Ok(teams)
+    }
+
+    async fn get_division_history(&self, division_id: &str) -> Result<Vec<TeamDivisionChange>> {
+        #[derive(Debug, Deserialize)]
+        struct QueryDivisionChange {
+            team_id: i64,
+            team_name: String,
+            old_division_id: String,
+            new_division_id: String,
+            admin_user_id: Option<i64>,
+            admin_user_name: Option<String>,
+            at: i64,
+        }
 
-        let new_top_10 = tx
+        let mut rows = self
+            .connect()
+            .await?
             .query(
                 "
-                SELECT id
-                FROM rhombus_team
-                WHERE division_id = ?1 AND points > 0
-                ORDER BY points DESC, last_solved_at ASC
-                LIMIT 10
+                SELECT rhombus_team_division_history.team_id, rhombus_team.name AS team_name, old_division_id, new_division_id,
+                    admin_user_id, rhombus_user.name AS admin_user_name, at
+                FROM rhombus_team_division_history
+                JOIN rhombus_team ON rhombus_team.id = rhombus_team_division_history.team_id
+                LEFT JOIN rhombus_user ON rhombus_user.id = rhombus_team_division_history.admin_user_id
+                WHERE old_division_id = ?1 OR new_division_id = ?1
+                ORDER BY at DESC
+                LIMIT 100
             ",
                 [division_id],
             )
+            .await?;
+
+        let mut history = vec![];
+        while let Some(row) = rows.next().await? {
+            let change = de::from_row::<QueryDivisionChange>(&row).unwrap();
+            history.push(TeamDivisionChange {
+                team_id: change.team_id,
+                team_name: change.team_name,
+                old_division_id: change.old_division_id,
+                new_division_id: change.new_division_id,
+                admin_user_id: change.admin_user_id,
+                admin_user_name: change.admin_user_name,
+                at: DateTime::<Utc>::from_timestamp(change.at, 0).unwrap(),
+            });
+        }
+
+        Ok(history)
+    }
+
+    async fn request_division_approval(&self, division_id: &str, user_id: i64) -> Result<()> {
+        self.connect()
             .await?
-            .into_stream()
-            .map(|row| row.unwrap().get::<i64>(0).unwrap())
-            .collect::<Vec<_>>()
-            .await;
+            .execute(
+                "INSERT OR IGNORE INTO rhombus_division_approval (division_id, user_id) VALUES (?1, ?2)",
+                params!(division_id, user_id),
+            )
+            .await?;
 
-        if top_10 != new_top_10 {
-            tx.execute(
+        Ok(())
+    }
+
+    async fn get_division_approval(&self, division_id: &str, user_id: i64) -> Result<Option<bool>> {
+        let approved = self
+            .connect()
+            .await?
+            .query(
+                "SELECT approved FROM rhombus_division_approval WHERE division_id = ?1 AND user_id = ?2",
+                params!(division_id, user_id),
+            )
+            .await?
+            .next()
+            .await?
+            .and_then(|row| row.get::<Option<bool>>(0).unwrap());
+
+        Ok(approved)
+    }
+
+    async fn get_division_approvals(&self, include_decided: bool) -> Result<Vec<DivisionApproval>> {
+        #[derive(Debug, Deserialize)]
+        struct QueryDivisionApproval {
+            division_id: String,
+            user_id: i64,
+            name: String,
+            approved: Option<bool>,
+            requested_at: i64,
+        }
+
+        let mut rows = self
+            .connect()
+            .await?
+            .query(
                 "
-                INSERT INTO rhombus_points_snapshot
-                SELECT id, ?2, points
-                FROM rhombus_team
-                WHERE division_id = ?1 AND points > 0
-                ORDER BY points DESC, last_solved_at ASC
-                LIMIT 20
+                SELECT rhombus_division_approval.division_id, rhombus_division_approval.user_id, rhombus_user.name, rhombus_division_approval.approved, rhombus_division_approval.requested_at
+                FROM rhombus_division_approval
+                JOIN rhombus_user ON rhombus_user.id = rhombus_division_approval.user_id
+                WHERE ?1 OR rhombus_division_approval.approved IS NULL
+                ORDER BY rhombus_division_approval.requested_at ASC
             ",
-                params!(division_id, now.timestamp()),
+                [include_decided],
             )
             .await?;
-        }
 
-        tx.commit().await?;
+        let mut approvals = vec![];
+        while let Some(row) = rows.next().await? {
+            let approval = de::from_row::<QueryDivisionApproval>(&row).unwrap();
+            approvals.push(DivisionApproval {
+                division_id: approval.division_id,
+                user_id: approval.user_id,
+                user_name: approval.name,
+                approved: approval.approved,
+                requested_at: DateTime::<Utc>::from_timestamp(approval.requested_at, 0).unwrap(),
+            });
+        }
 
-        Ok(())
+        Ok(approvals)
     }
 
-    async fn add_writeup(
+    async fn set_division_approval(
         &self,
+        division_id: &str,
         user_id: i64,
-        _team_id: i64,
-        challenge_id: i64,
-        writeup_url: &str,
+        approved: bool,
     ) -> Result<()> {
         self.connect()
             .await?
             .execute(
                 "
-                INSERT INTO rhombus_writeup (user_id, challenge_id, url) VALUES (?1, ?2, ?3)
+                INSERT INTO rhombus_division_approval (division_id, user_id, approved) VALUES (?1, ?2, ?3)
+                ON CONFLICT (division_id, user_id) DO UPDATE SET approved = excluded.approved
             ",
-                params!(user_id, challenge_id, writeup_url),
+                params!(division_id, user_id, approved),
             )
             .await?;
 
         Ok(())
     }
 
-    async fn get_writeups_from_user_id(&self, user_id: i64) -> Result<Writeups> {
+    async fn get_team_standing(&self, team_id: i64) -> Result<Option<TeamStanding>> {
         #[derive(Debug, Deserialize)]
-        struct QueryWriteup {
-            pub user_id: i64,
-            pub challenge_id: i64,
-            pub url: String,
+        struct DbPointsRank {
+            points: f64,
+            rank: i64,
         }
-        let mut db_writeups = self
+
+        let standing = self
             .connect()
             .await?
             .query(
                 "
-                SELECT user_id, challenge_id, url
-                FROM rhombus_writeup
-                WHERE user_id = ?1
+                SELECT
+                    points,
+                    (SELECT COUNT(*) + 1 FROM rhombus_team t2 WHERE t2.division_id = t1.division_id
+                        AND (t2.points > t1.points OR (t2.points = t1.points AND t2.last_solved_at < t1.last_solved_at))
+                    ) as rank
+                FROM rhombus_team t1
+                WHERE t1.id = ?1
             ",
-                [user_id],
+                params!(team_id),
+            )
+            .await?
+            .next()
+            .await?
+            .map(|row| {
+                let points_rank = de::from_row::<DbPointsRank>(&row).unwrap();
+                TeamStanding {
+                    points: points_rank.points as i64,
+                    rank: points_rank.rank as u64,
+                }
+            });
+
+        Ok(standing)
+    }
+
+    async fn upload_file(&self, hash: &str, filename: &str, bytes: &[u8]) -> Result<()> {
+        self.connect()
+            .await?
+            .query(
+                "INSERT INTO rhombus_file (hash, filename, contents) VALUES (?1, ?2, ?3)",
+                params!(hash, filename, bytes),
             )
             .await?;
-        let mut writeups = BTreeMap::new();
-        while let Some(writeup) = db_writeups.next().await? {
-            let writeup = de::from_row::<QueryWriteup>(&writeup).unwrap();
-            writeups.insert(
-                writeup.challenge_id,
-                Writeup {
-                    url: writeup.url,
-                    user_id: writeup.user_id,
-                },
-            );
+
+        Ok(())
+    }
+
+    async fn get_file_info(&self, hash: &str) -> Result<(String, u64)> {
+        #[derive(Debug, Deserialize)]
+        struct DbFileInfo {
+            filename: String,
+            size: i64,
         }
-        Ok(Arc::new(writeups))
+
+        let row = self
+            .connect()
+            .await?
+            .query(
+                "SELECT filename, length(contents) AS size FROM rhombus_file WHERE hash = ?1",
+                [hash],
+            )
+            .await?
+            .next()
+            .await?
+            .ok_or(RhombusError::DatabaseReturnedNoRows)?;
+
+        let info = de::from_row::<DbFileInfo>(&row).unwrap();
+
+        Ok((info.filename, info.size as u64))
     }
 
-    async fn delete_writeup(&self, challenge_id: i64, user_id: i64, _team_id: i64) -> Result<()> {
+    async fn download_file_chunk(&self, hash: &str, offset: u64, length: u64) -> Result<Bytes> {
+        // substr is 1-indexed and operates on bytes for blobs
+        let contents = self
+            .connect()
+            .await?
+            .query(
+                "SELECT substr(contents, ?2, ?3) FROM rhombus_file WHERE hash = ?1",
+                params!(hash, offset as i64 + 1, length as i64),
+            )
+            .await?
+            .next()
+            .await?
+            .ok_or(RhombusError::DatabaseReturnedNoRows)?
+            .get::<Vec<u8>>(0)?;
+
+        Ok(Bytes::from(contents))
+    }
+
+    async fn list_files(&self) -> Result<Vec<(String, String)>> {
+        let files = self
+            .connect()
+            .await?
+            .query("SELECT hash, filename FROM rhombus_file", ())
+            .await?
+            .into_stream()
+            .map(|row| {
+                let row = row.unwrap();
+                (row.get::<String>(0).unwrap(), row.get::<String>(1).unwrap())
+            })
+            .collect::<Vec<_>>()
+            .await;
+
+        Ok(files)
+    }
+
+    async fn delete_file(&self, hash: &str) -> Result<()> {
         self.connect()
             .await?
-            .execute(
+            .execute("DELETE FROM rhombus_file WHERE hash = ?1", [hash])
+            .await?;
+
+        Ok(())
+    }
+
+    async fn get_site_statistics(&self) -> Result<SiteStatistics> {
+        let tx = self.transaction().await?;
+
+        let total_users = tx
+            .query("SELECT COUNT(*) FROM rhombus_user", ())
+            .await?
+            .next()
+            .await?
+            .unwrap()
+            .get::<i64>(0)
+            .unwrap();
+
+        let total_teams = tx
+            .query("SELECT COUNT(DISTINCT team_id) FROM rhombus_user", ())
+            .await?
+            .next()
+            .await?
+            .unwrap()
+            .get::<i64>(0)
+            .unwrap();
+
+        let total_challenges = tx
+            .query("SELECT COUNT(*) FROM rhombus_challenge", ())
+            .await?
+            .next()
+            .await?
+            .unwrap()
+            .get::<i64>(0)
+            .unwrap();
+
+        let total_solves = tx
+            .query("SELECT COUNT(*) FROM rhombus_solve", ())
+            .await?
+            .next()
+            .await?
+            .unwrap()
+            .get::<i64>(0)
+            .unwrap();
+
+        #[derive(Debug, Deserialize)]
+        struct QueryCategory {
+            name: String,
+            color: String,
+            challenge_count: i64,
+        }
+
+        let categories = tx
+            .query(
+                "
+            SELECT rhombus_category.name, rhombus_category.color, COUNT(*) AS challenge_count
+            FROM rhombus_category
+            JOIN rhombus_challenge ON rhombus_challenge.category_id = rhombus_category.id
+            GROUP BY rhombus_category.id
+            ORDER BY challenge_count DESC
+            ",
+                (),
+            )
+            .await?
+            .into_stream()
+            .map(|row| {
+                let category = de::from_row::<QueryCategory>(&row.unwrap()).unwrap();
+                StatisticsCategory {
+                    name: category.name,
+                    color: category.color,
+                    num: category.challenge_count as u64,
+                }
+            })
+            .collect::<Vec<_>>()
+            .await;
+
+        tx.commit().await?;
+        Ok(SiteStatistics {
+            total_users: total_users as u64,
+            total_teams: total_teams as u64,
+            total_challenges: total_challenges as u64,
+            total_solves: total_solves as u64,
+            categories,
+        })
+    }
+
+    async fn get_last_created_ticket_time(&self, user_id: i64) -> Result<Option<DateTime<Utc>>> {
+        let last_opened_at_timestamp = self
+            .connect()
+            .await?
+            .query(
                 "
-                DELETE FROM rhombus_writeup
-                WHERE challenge_id = ?1 AND user_id = ?2
+                SELECT opened_at
+                FROM rhombus_ticket
+                WHERE user_id = ?1
+                ORDER BY opened_at DESC
+                LIMIT 1
             ",
-                [challenge_id, user_id],
+                [user_id],
             )
-            .await?;
+            .await?
+            .next()
+            .await?
+            .map(|row| row.get::<i64>(0).unwrap());
 
-        Ok(())
+        let last_opened_at =
+            last_opened_at_timestamp.and_then(|t| DateTime::<Utc>::from_timestamp(t, 0));
+
+        Ok(last_opened_at)
     }
 
-    async fn get_next_ticket_number(&self) -> Result<u64> {
-        let ticket_number = self
+    async fn get_uploaded_attachment_urls(&self) -> Result<Vec<String>> {
+        let urls = self
             .connect()
             .await?
             .query(
-                "
-                UPDATE rhombus_ticket_number_counter
-                SET ticket_number = ticket_number + 1
-                WHERE rowid = 1
-                RETURNING ticket_number
-            ",
+                "SELECT DISTINCT url FROM rhombus_file_attachment WHERE hash IS NOT NULL",
                 (),
             )
             .await?
-            .next()
-            .await?
-            .unwrap()
-            .get::<u64>(0)
-            .unwrap();
+            .into_stream()
+            .map(|row| row.unwrap().get::<String>(0).unwrap())
+            .collect::<Vec<_>>()
+            .await;
 
-        Ok(ticket_number)
+        Ok(urls)
     }
 
-    async fn create_ticket(
-        &self,
-        ticket_number: u64,
-        user_id: i64,
-        challenge_id: &str,
-        discord_channel_id: NonZeroU64,
-        panel_discord_message_id: NonZeroU64,
-    ) -> Result<()> {
-        self
-            .connect().await?
-            .execute(
-                "
-                INSERT INTO rhombus_ticket (ticket_number, user_id, challenge_id, discord_channel_id, discord_panel_message_id) VALUES (?1, ?2, ?3, ?4, ?5)
-            ",
-                params!(ticket_number, user_id, challenge_id, discord_channel_id.get(), panel_discord_message_id.get()),
+    async fn rewrite_attachment_urls(&self, urls: &BTreeMap<String, String>) -> Result<()> {
+        let tx = self.transaction().await?;
+
+        // look up the rows by their old url before changing any of them, so a new url
+        // which is also the old url of another file is not rewritten twice
+        let mut rows = tx
+            .query("SELECT rowid, url FROM rhombus_file_attachment", ())
+            .await?;
+        let mut rewrites = vec![];
+        while let Some(row) = rows.next().await? {
+            let url = row.get::<String>(1)?;
+            if let Some(new_url) = urls.get(&url).filter(|new_url| **new_url != url) {
+                rewrites.push((row.get::<i64>(0)?, new_url));
+            }
+        }
+
+        // move the rows out of the way first, since urls are unique per challenge
+        for (rowid, _) in rewrites.iter() {
+            tx.execute(
+                "UPDATE rhombus_file_attachment SET url = 'rewriting:' || rowid WHERE rowid = ?1",
+                [*rowid],
+            )
+            .await?;
+        }
+        for (rowid, new_url) in rewrites.iter() {
+            tx.execute(
+                "UPDATE rhombus_file_attachment SET url = ?2 WHERE rowid = ?1",
+                params!(*rowid, new_url.as_str()),
             )
             .await?;
+        }
+
+        tx.commit().await?;
 
         Ok(())
     }
 
-    async fn get_ticket_by_ticket_number(&self, ticket_number: u64) -> Result<Ticket> {
-        #[derive(Debug, Deserialize)]
-        struct DbTicket {
-            pub user_id: i64,
-            pub challenge_id: String,
-            pub closed_at: Option<i64>,
-            pub discord_channel_id: NonZeroU64,
-            pub discord_panel_message_id: NonZeroU64,
+    async fn export_backup_tables(&self) -> Result<BTreeMap<String, BackupTable>> {
+        let tx = self.transaction().await?;
+
+        let mut tables = BTreeMap::new();
+        for table in BACKUP_TABLES {
+            let mut rows = tx.query(&format!("SELECT * FROM {}", table), ()).await?;
+
+            let columns = (0..rows.column_count())
+                .map(|i| rows.column_name(i).unwrap_or_default().to_owned())
+                .collect::<Vec<_>>();
+
+            let mut backup_rows = vec![];
+            while let Some(row) = rows.next().await? {
+                let values = (0..row.column_count())
+                    .map(|i| row.get_value(i).map(to_backup_value))
+                    .collect::<std::result::Result<Vec<_>, _>>()?;
+                backup_rows.push(values);
+            }
+
+            tables.insert(
+                table.to_string(),
+                BackupTable {
+                    columns,
+                    rows: backup_rows,
+                },
+            );
         }
 
+        tx.commit().await?;
+
+        Ok(tables)
+    }
+
+    async fn check_backup_tables(&self, tables: &BTreeMap<String, BackupTable>) -> Result<()> {
+        check_backup_tables(&self.connect().await?, tables).await
+    }
+
+    async fn import_backup_tables(&self, tables: &BTreeMap<String, BackupTable>) -> Result<()> {
         let tx = self.transaction().await?;
 
-        let ticket_row = tx
-            .query(
-                "
-                SELECT user_id, challenge_id, closed_at, discord_channel_id, discord_panel_message_id
-                FROM rhombus_ticket
-                WHERE ticket_number = ?1
-            ",
-                [ticket_number],
-            )
-            .await?
-            .next()
-            .await?;
+        check_backup_tables(&tx, tables).await?;
 
-        let db_ticket = de::from_row::<DbTicket>(&ticket_row.unwrap()).unwrap();
+        tx.execute("PRAGMA defer_foreign_keys = ON", ()).await?;
 
-        #[derive(Debug, Deserialize)]
-        struct DbTicketEmailMessageIdReference {
-            pub message_id: String,
-            pub user_sent: bool,
-        }
+        for table in BACKUP_TABLES {
+            let Some(backup_table) = tables.get(*table) else {
+                continue;
+            };
 
-        let email_references = tx
-            .query(
-                "
-                SELECT message_id, user_sent
-                FROM rhombus_ticket_email_message_id_reference
-                WHERE ticket_number = ?1
-            ",
-                [ticket_number],
-            )
-            .await?
-            .into_stream()
-            .map(|row| de::from_row::<DbTicketEmailMessageIdReference>(&row.unwrap()).unwrap())
-            .collect::<Vec<_>>()
-            .await;
+            tx.execute(&format!("DELETE FROM {}", table), ()).await?;
+
+            let sql = format!(
+                "INSERT INTO {} ({}) VALUES ({})",
+                table,
+                backup_table.columns.join(", "),
+                (1..=backup_table.columns.len())
+                    .map(|i| format!("?{}", i))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            );
+            for row in backup_table.rows.iter() {
+                let values = row
+                    .iter()
+                    .map(from_backup_value)
+                    .collect::<Result<Vec<_>>>()?;
+                tx.execute(&sql, values).await?;
+            }
+        }
 
         tx.commit().await?;
 
-        Ok(Ticket {
-            ticket_number,
-            user_id: db_ticket.user_id,
-            challenge_id: db_ticket.challenge_id,
-            closed_at: db_ticket
-                .closed_at
-                .map(|ts| DateTime::<Utc>::from_timestamp(ts, 0).unwrap()),
-            discord_channel_id: db_ticket.discord_channel_id,
-            discord_panel_message_id: db_ticket.discord_panel_message_id,
-            email_in_reply_to: email_references
-                .iter()
-                .find(|r| !r.user_sent)
-                .map(|r| r.message_id.clone()),
-            email_references: email_references.into_iter().map(|r| r.message_id).collect(),
-        })
+        Ok(())
     }
+}
 
-    async fn get_ticket_by_discord_channel_id(
-        &self,
-        discord_channel_id: NonZeroU64,
-    ) -> Result<Ticket> {
-        #[derive(Debug, Deserialize)]
-        struct DbTicket {
-            pub ticket_number: u64,
-            pub user_id: i64,
-            pub challenge_id: String,
-            pub closed_at: Option<i64>,
-            pub discord_channel_id: NonZeroU64,
-            pub discord_panel_message_id: NonZeroU64,
-        }
+/// Sign in as the user linked to an identity on an external provider, link it to `user_id`,
+/// or create a new user for it. A verified `email` links the identity to the user who already
+/// verified that email, just like signing in by email would.
+#[allow(clippy::too_many_arguments)]
+async fn upsert_user_by_identity(
+    tx: &Transaction,
+    provider: &str,
+    name: &str,
+    email: Option<&str>,
+    avatar: &str,
+    subject: &str,
+    user_id: Option<i64>,
+    location_url: &str,
+) -> Result<std::result::Result<(i64, i64), IdentityUpsertError>> {
+    let linked_user_id = tx
+        .query(
+            "SELECT user_id FROM rhombus_user_identity WHERE provider = ?1 AND subject = ?2",
+            params!(provider, subject),
+        )
+        .await?
+        .next()
+        .await?
+        .map(|row| row.get::<i64>(0).unwrap());
 
-        let tx = self.transaction().await?;
+    let existing_user = if let Some(user_id) = user_id {
+        match linked_user_id {
+            Some(linked_user_id) if linked_user_id != user_id => {
+                return Ok(Err(IdentityUpsertError::AlreadyInUse));
+            }
+            Some(_) => {}
+            None => {
+                tx.execute(
+                    "INSERT INTO rhombus_user_identity (provider, subject, user_id) VALUES (?1, ?2, ?3)",
+                    params!(provider, subject, user_id),
+                )
+                .await?;
+            }
+        }
 
-        let ticket_row = self
-            .connect()
+        let team_id = tx
+            .query("SELECT team_id FROM rhombus_user WHERE id = ?1", [user_id])
+            .await?
+            .next()
             .await?
+            .ok_or(RhombusError::DatabaseReturnedNoRows)?
+            .get::<i64>(0)?;
+        Some((user_id, team_id))
+    } else if let Some(linked_user_id) = linked_user_id {
+        tx.query(
+            "UPDATE rhombus_user SET avatar = ?1 WHERE id = ?2 RETURNING id, team_id",
+            params!(avatar, linked_user_id),
+        )
+        .await?
+        .next()
+        .await?
+        .map(|row| (row.get::<i64>(0).unwrap(), row.get::<i64>(1).unwrap()))
+    } else if let Some(email) = email {
+        let user_with_email = tx
             .query(
                 "
-                SELECT ticket_number, user_id, challenge_id, closed_at, discord_channel_id, discord_panel_message_id
-                FROM rhombus_ticket
-                WHERE discord_channel_id = ?1
+                SELECT user_id, team_id
+                FROM rhombus_email JOIN rhombus_user ON rhombus_user.id = rhombus_email.user_id
+                WHERE email = ?1 AND code IS NULL
             ",
-                [discord_channel_id.get()],
+                [email],
             )
             .await?
             .next()
             .await?
-            .ok_or(libsql::Error::QueryReturnedNoRows)?;
-
-        let db_ticket = de::from_row::<DbTicket>(&ticket_row).unwrap();
+            .map(|row| (row.get::<i64>(0).unwrap(), row.get::<i64>(1).unwrap()));
 
-        #[derive(Debug, Deserialize)]
-        struct DbTicketEmailMessageIdReference {
-            pub message_id: String,
-            pub user_sent: bool,
+        if let Some((user_id, _)) = user_with_email {
+            tx.execute(
+                "INSERT INTO rhombus_user_identity (provider, subject, user_id) VALUES (?1, ?2, ?3)",
+                params!(provider, subject, user_id),
+            )
+            .await?;
         }
 
-        let email_references = tx
+        user_with_email
+    } else {
+        None
+    };
+
+    let (user_id, team_id) = if let Some(existing_user) = existing_user {
+        existing_user
+    } else {
+        let team_id = create_team(tx).await?;
+        let api_key = create_user_api_key(location_url);
+        let user_id = tx
             .query(
-                "
-                SELECT message_id, user_sent
-                FROM rhombus_ticket_email_message_id_reference
-                WHERE ticket_number = ?1
-            ",
-                [db_ticket.ticket_number],
+                "INSERT INTO rhombus_user (name, avatar, team_id, owner_team_id, api_key) VALUES (?1, ?2, ?3, ?3, ?4) RETURNING id",
+                params!(name, avatar, team_id, api_key.as_str()),
             )
             .await?
-            .into_stream()
-            .map(|row| de::from_row::<DbTicketEmailMessageIdReference>(&row.unwrap()).unwrap())
-            .collect::<Vec<_>>()
-            .await;
+            .next()
+            .await?
+            .unwrap()
+            .get::<i64>(0)
+            .unwrap();
 
-        tx.commit().await?;
+        tx.execute(
+            "INSERT INTO rhombus_user_identity (provider, subject, user_id) VALUES (?1, ?2, ?3)",
+            params!(provider, subject, user_id),
+        )
+        .await?;
 
-        Ok(Ticket {
-            ticket_number: db_ticket.ticket_number,
-            user_id: db_ticket.user_id,
-            challenge_id: db_ticket.challenge_id,
-            closed_at: db_ticket
-                .closed_at
-                .map(|ts| DateTime::<Utc>::from_timestamp(ts, 0).unwrap()),
-            discord_channel_id: db_ticket.discord_channel_id,
-            discord_panel_message_id: db_ticket.discord_panel_message_id,
-            email_in_reply_to: email_references
-                .iter()
-                .find(|r| !r.user_sent)
-                .map(|r| r.message_id.clone()),
-            email_references: email_references.into_iter().map(|r| r.message_id).collect(),
-        })
+        (user_id, team_id)
+    };
+
+    if let Some(email) = email {
+        // the email may already be verified by another user, in which case it stays theirs
+        tx.execute(
+            "INSERT OR IGNORE INTO rhombus_email (email, user_id) VALUES (?1, ?2)",
+            params!(email, user_id),
+        )
+        .await?;
     }
 
-    async fn close_ticket(&self, ticket_number: u64, time: DateTime<Utc>) -> Result<()> {
-        self.connect()
-            .await?
-            .execute(
-                "
-                UPDATE rhombus_ticket
-                SET closed_at = ?1
-                WHERE ticket_number = ?2
-            ",
-                params!(time.timestamp(), ticket_number),
-            )
-            .await?;
+    Ok(Ok((user_id, team_id)))
+}
 
-        Ok(())
-    }
+pub async fn create_team(tx: &Transaction) -> Result<i64> {
+    let team_invite_token = create_team_invite_token();
 
-    async fn close_tickets_for_challenge(
-        &self,
-        user_id: i64,
-        challenge_id: &str,
-        time: DateTime<Utc>,
-    ) -> Result<Vec<ToBeClosedTicket>> {
-        let conn = self.connect().await?;
+    let team_id = tx
+        .query(
+            "INSERT INTO rhombus_team (name, invite_token, division_id) VALUES ('Team ' || (SELECT COALESCE(MAX(id) + 1, 1) FROM rhombus_team), ?1, (SELECT id FROM rhombus_division WHERE is_default = TRUE LIMIT 1)) RETURNING id",
+            params!(team_invite_token),
+        )
+        .await?
+        .next()
+        .await?
+        .ok_or(RhombusError::LibSQL(libsql::Error::QueryReturnedNoRows))?
+        .get::<i64>(0)?;
 
-        #[derive(Debug, Deserialize)]
-        struct DbTicket {
-            pub discord_channel_id: NonZeroU64,
-            pub discord_panel_message_id: NonZeroU64,
-            pub ticket_number: u64,
-        }
+    Ok(team_id)
+}
 
-        let tickets = conn
-            .query(
-                "
-                UPDATE rhombus_ticket
-                SET closed_at = ?3
-                WHERE user_id = ?1 AND challenge_id = ?2 AND closed_at IS NULL
-                RETURNING ticket_number, discord_channel_id, discord_panel_message_id
-            ",
-                params!(user_id, challenge_id, time.timestamp()),
-            )
-            .await?
-            .into_stream()
-            .map(|row| de::from_row::<DbTicket>(&row.unwrap()).unwrap())
-            .map(|db_ticket| ToBeClosedTicket {
-                discord_channel_id: db_ticket.discord_channel_id,
-                discord_panel_message_id: db_ticket.discord_panel_message_id,
-                ticket_number: db_ticket.ticket_number,
-            })
-            .collect::<Vec<_>>()
-            .await;
+#[cfg(test)]
+pub(crate) mod test {
+    use std::{
+        collections::BTreeMap,
+        net::IpAddr,
+        num::{NonZeroU32, NonZeroU64},
+        sync::Arc,
+    };
+
+    use axum::body::Bytes;
+    use chrono::Utc;
+    use futures::{StreamExt, TryStreamExt};
+    use libsql::params;
+    use serde_json::json;
+
+    use crate::{
+        database_upload_provider::DatabaseUploadProvider,
+        internal::{
+            database::{
+                libsql::InMemoryLibSQL,
+                provider::{
+                    Connection, Database, RegisterError, SignInMethod, TeamDiscord,
+                    TicketMessageAuthor, TicketStatus,
+                },
+            },
+            division::{Division, MaxDivisionPlayers, OpenDivisionEligibilityProvider},
+            routes::team::{check_join_team, has_verified_email, JoinTeamError},
+            settings::Settings,
+            tickets::{get_ticket_transcript, render_canned_responses},
+            upload_provider::{collect_garbage, migrate_uploads, parse_upload_url},
+        },
+        upload_provider::ErasedUploadProvider,
+        LocalUploadProvider, UploadProvider,
+    };
 
-        Ok(tickets)
+    /// A migrated database with the default open division, as the builder sets it up
+    pub(crate) async fn test_db() -> InMemoryLibSQL {
+        let database = InMemoryLibSQL::new().await.unwrap();
+        database.migrate().await.unwrap();
+        database
+            .insert_divisions(&[Division {
+                id: "open".to_owned(),
+                name: "Open".to_owned(),
+                description: "Open division for everyone".to_owned(),
+                max_players: MaxDivisionPlayers::Unlimited,
+                division_eligibility: Arc::new(OpenDivisionEligibilityProvider {}),
+                is_default: true,
+                discord_role_id: None,
+                archived: false,
+                admin_managed: false,
+            }])
+            .await
+            .unwrap();
+        database
     }
 
-    async fn get_discord_ticket_channel_ids_for_challenge(
-        &self,
-        challenge_id: &str,
-    ) -> Result<Vec<u64>> {
-        let conn = self.connect().await?;
-
-        let discord_channel_ids = conn
-            .query(
-                "
-                SELECT discord_channel_id
-                FROM rhombus_ticket
-                WHERE challenge_id = ?1
-            ",
-                [challenge_id],
+    /// Adds the challenge `web-1` by `alice`, whose Discord id is 1
+    pub(crate) async fn insert_test_challenge(database: &InMemoryLibSQL) {
+        database
+            .conn
+            .execute_batch(
+                r#"
+                INSERT INTO rhombus_category (id, name, color, sequence) VALUES ('web', 'Web', '#ffffff', 0);
+                INSERT INTO rhombus_author (id, name, avatar, discord_id) VALUES ('alice', 'alice', '', 1);
+                INSERT INTO rhombus_challenge (id, name, description, flag, category_id, author_id, score_type, metadata, points)
+                VALUES ('web-1', 'Web 1', '', 'flag{test}', 'web', 'alice', 'static', '{"canned_responses":{"hint":"Hi {{ participant.name }} of {{ team.name }}, look at {{ challenge.name }}"}}', 100);
+            "#,
             )
-            .await?
-            .into_stream()
-            .map(|row| row.unwrap().get::<u64>(0).unwrap())
-            .collect::<Vec<_>>()
-            .await;
-
-        Ok(discord_channel_ids)
+            .await
+            .unwrap();
     }
 
-    async fn reopen_ticket(&self, ticket_number: u64) -> Result<()> {
-        self.connect()
-            .await?
-            .execute(
-                "
-                UPDATE rhombus_ticket
-                SET closed_at = NULL
-                WHERE ticket_number = ?1
-            ",
-                [ticket_number],
-            )
-            .await?;
+    #[tokio::test]
+    async fn migrate_libsql() {
+        let database = InMemoryLibSQL::new().await.unwrap();
+        database.migrate().await.unwrap();
+    }
 
-        Ok(())
+    #[tokio::test]
+    async fn migrate_libsql_twice() {
+        let database = InMemoryLibSQL::new().await.unwrap();
+        database.migrate().await.unwrap();
+        database.migrate().await.unwrap();
     }
 
-    async fn add_email_message_id_to_ticket(
-        &self,
-        ticket_number: u64,
-        message_id: &str,
-        user_sent: bool,
-    ) -> Result<()> {
-        self.connect().await?
-            .execute(
+    /// Databases created before a column was added must get it on startup
+    #[tokio::test]
+    async fn migrate_existing_libsql() {
+        let database = InMemoryLibSQL::new().await.unwrap();
+        database
+            .conn
+            .execute_batch(
                 "
-                INSERT INTO rhombus_ticket_email_message_id_reference (ticket_number, message_id, user_sent) VALUES (?1, ?2, ?3)
+                CREATE TABLE rhombus_division (
+                    id TEXT PRIMARY KEY NOT NULL,
+                    name TEXT NOT NULL UNIQUE,
+                    description TEXT NOT NULL,
+                    is_default INTEGER NOT NULL DEFAULT(FALSE)
+                );
+                INSERT INTO rhombus_division (id, name, description, is_default) VALUES ('open', 'Open', 'Open division', TRUE);
             ",
-                params!(ticket_number, message_id, user_sent),
             )
-            .await?;
+            .await
+            .unwrap();
 
-        Ok(())
+        database.migrate().await.unwrap();
+
+        let divisions = database.get_divisions().await.unwrap();
+        assert_eq!(1, divisions.len());
+        assert!(!divisions[0].archived);
     }
 
-    async fn get_ticket_number_by_message_id(&self, message_id: &str) -> Result<Option<u64>> {
-        let ticket_number = self
-            .connect()
-            .await?
-            .query(
-                "
-                SELECT ticket_number
-                FROM rhombus_ticket_email_message_id_reference
-                WHERE message_id = ?1
-            ",
-                [message_id],
-            )
-            .await?
+    #[tokio::test]
+    async fn track_load() {
+        let database = InMemoryLibSQL::new().await.unwrap();
+        database.migrate().await.unwrap();
+
+        let ip: IpAddr = "1.2.3.4".parse().unwrap();
+        for i in 0..1000 {
+            let user_agent = i.to_string();
+            database
+                .insert_track(ip, Some(user_agent.as_str()), None, 1)
+                .await
+                .unwrap();
+        }
+
+        let num_tracks = database
+            .conn
+            .query("SELECT COUNT(*) FROM rhombus_track", ())
+            .await
+            .unwrap()
             .next()
-            .await?
-            .and_then(|row| row.get::<u64>(0).ok());
+            .await
+            .unwrap()
+            .unwrap()
+            .get::<i64>(0)
+            .unwrap();
 
-        Ok(ticket_number)
+        assert_eq!(32, num_tracks);
     }
 
-    async fn save_settings(&self, settings: &Settings) -> Result<()> {
-        if settings.immutable_config {
-            return Ok(());
-        }
+    #[tokio::test]
+    async fn identities_link_verified_email() {
+        let database = test_db().await;
 
-        let json = serde_json::to_string(settings).unwrap();
+        let location_url = "http://localhost:3000";
+        let (email_user_id, _) = database
+            .upsert_user_by_email("alice", "alice@example.com", "", location_url)
+            .await
+            .unwrap();
 
-        self.connect()
-            .await?
-            .execute(
-                "
-                INSERT OR REPLACE INTO rhombus_config (id, config) VALUES (1, ?1)
-            ",
-                [json],
+        let (user_id, _) = database
+            .upsert_user_by_oidc(
+                "alice",
+                Some("alice@example.com"),
+                "",
+                "sub-1",
+                None,
+                location_url,
             )
-            .await?;
+            .await
+            .unwrap()
+            .ok()
+            .unwrap();
+        assert_eq!(email_user_id, user_id);
 
-        Ok(())
+        let (user_id, _) = database
+            .upsert_user_by_oidc("alice", None, "", "sub-1", None, location_url)
+            .await
+            .unwrap()
+            .ok()
+            .unwrap();
+        assert_eq!(email_user_id, user_id);
+
+        let (other_user_id, _) = database
+            .upsert_user_by_oidc(
+                "bob",
+                Some("bob@example.com"),
+                "",
+                "sub-2",
+                None,
+                location_url,
+            )
+            .await
+            .unwrap()
+            .ok()
+            .unwrap();
+        assert_ne!(email_user_id, other_user_id);
+
+        // linking another user's identity is refused, linking a new one is not
+        assert!(database
+            .upsert_user_by_github("alice", None, "", 1, Some(email_user_id), location_url)
+            .await
+            .unwrap()
+            .is_ok());
+        assert!(database
+            .upsert_user_by_github("bob", None, "", 1, Some(other_user_id), location_url)
+            .await
+            .unwrap()
+            .is_err());
+        assert_eq!(
+            database
+                .get_user_identities(email_user_id)
+                .await
+                .unwrap()
+                .len(),
+            2
+        );
     }
 
-    async fn load_settings(&self, _settings: &mut Settings) -> Result<()> {
-        // if settings.immutable_config {
-        //     return Ok(());
-        // }
+    #[tokio::test]
+    async fn sign_in_methods_link_unlink() {
+        let database = test_db().await;
 
-        // let db_settings: Option<Settings> = self
-        //     .db
-        //     .query("SELECT config FROM rhombus_config", ())
-        //     .await?
-        //     .next()
-        //     .await?
-        //     .map(|row| serde_json::from_str(&row.get::<String>(0).unwrap()).unwrap());
+        let location_url = "http://localhost:3000";
+        let (alice, _) = database
+            .upsert_user_by_email("alice", "alice@example.com", "", location_url)
+            .await
+            .unwrap();
+        let (bob, _) = database
+            .upsert_user_by_credentials("bob", "", "password", location_url)
+            .await
+            .unwrap()
+            .unwrap();
 
-        // if let Some(db_settings) = db_settings {
-        //     if settings.discord.first_blood_channel_id.is_none() {
-        //         settings.discord.first_blood_channel_id =
-        //             db_settings.discord.first_blood_channel_id;
-        //     }
+        let methods = database.get_user_sign_in_methods(alice).await.unwrap();
+        assert!(!methods.ctftime && !methods.password && !methods.discord);
 
-        //     if settings.discord.support_channel_id.is_none() {
-        //         settings.discord.support_channel_id = db_settings.discord.support_channel_id;
-        //     }
+        assert!(database.link_user_ctftime(alice, 7).await.unwrap().is_ok());
+        assert!(database.link_user_ctftime(bob, 7).await.unwrap().is_err());
+        assert!(database
+            .set_user_password(alice, None, "hunter22")
+            .await
+            .unwrap()
+            .is_ok());
+
+        let methods = database.get_user_sign_in_methods(alice).await.unwrap();
+        assert!(methods.ctftime && methods.password);
+
+        // signing in with the linked CTFtime account keeps the user on their team
+        let (user_id, _, invite_token) = database
+            .upsert_user_by_ctftime(
+                "alice",
+                "alice@example.com",
+                "",
+                7,
+                1,
+                "ctf team",
+                location_url,
+            )
+            .await
+            .unwrap();
+        assert_eq!(user_id, alice);
+        assert!(invite_token.is_none());
 
-        //     if settings.discord.author_role_id.is_none() {
-        //         settings.discord.author_role_id = db_settings.discord.author_role_id;
-        //     }
-        // }
+        database
+            .unlink_sign_in_method(alice, SignInMethod::CTFtime)
+            .await
+            .unwrap();
+        database
+            .unlink_sign_in_method(alice, SignInMethod::Password)
+            .await
+            .unwrap();
+        let methods = database.get_user_sign_in_methods(alice).await.unwrap();
+        assert!(!methods.ctftime && !methods.password);
 
-        Ok(())
+        // bob already signs in with a password under this name
+        database
+            .conn
+            .execute(
+                "UPDATE rhombus_user SET name = 'bob' WHERE id = ?1",
+                [alice],
+            )
+            .await
+            .unwrap();
+        assert!(database
+            .set_user_password(alice, None, "hunter22")
+            .await
+            .unwrap()
+            .is_err());
     }
 
-    async fn get_scoreboard(&self, division_id: &str) -> Result<Scoreboard> {
-        let tx = self.transaction().await?;
+    #[tokio::test]
+    async fn credentials_register_and_login() {
+        let database = test_db().await;
 
-        #[derive(Debug, Deserialize)]
-        struct DbTeam {
-            id: i64,
-            name: String,
-            points: i64,
-        }
+        let location_url = "http://localhost:3000";
 
-        let mut db_teams = tx
-            .query(
-                "
-                SELECT id, name, points
-                FROM rhombus_team
-                WHERE division_id = ?1 AND points > 0
-                ORDER BY points DESC, last_solved_at ASC
-                LIMIT 10
-            ",
-                params!(division_id),
+        // unknown usernames do not create an account
+        assert!(database
+            .login_user_by_credentials("alice", "password")
+            .await
+            .unwrap()
+            .is_none());
+
+        let (alice, _, code) = database
+            .register_user_by_credentials(
+                "alice",
+                "",
+                "password",
+                Some("alice@example.com"),
+                location_url,
             )
-            .await?;
+            .await
+            .unwrap()
+            .ok()
+            .unwrap();
+        assert!(matches!(
+            database
+                .register_user_by_credentials("alice", "", "password", None, location_url)
+                .await
+                .unwrap(),
+            Err(RegisterError::NameTaken)
+        ));
+        assert!(matches!(
+            database
+                .register_user_by_credentials(
+                    "bob",
+                    "",
+                    "password",
+                    Some("alice@example.com"),
+                    location_url
+                )
+                .await
+                .unwrap(),
+            Err(RegisterError::EmailInUse)
+        ));
+
+        let emails = database.get_emails_for_user_id(alice).await.unwrap();
+        assert!(!emails[0].verified);
+        assert_eq!(
+            database
+                .verify_email_verification_callback_code(&code.unwrap())
+                .await
+                .unwrap(),
+            alice
+        );
+
+        assert!(database
+            .login_user_by_credentials("alice", "wrong password")
+            .await
+            .unwrap()
+            .is_none());
+        assert_eq!(
+            database
+                .login_user_by_credentials("alice", "password")
+                .await
+                .unwrap()
+                .unwrap()
+                .0,
+            alice
+        );
+    }
+
+    #[tokio::test]
+    async fn password_change_and_reset() {
+        let database = test_db().await;
+
+        let location_url = "http://localhost:3000";
+        let (bob, _) = database
+            .upsert_user_by_credentials("bob", "", "password", location_url)
+            .await
+            .unwrap()
+            .unwrap();
+        database
+            .conn
+            .execute(
+                "INSERT INTO rhombus_email (email, user_id) VALUES ('bob@example.com', ?1)",
+                [bob],
+            )
+            .await
+            .unwrap();
+
+        assert!(database
+            .set_user_password(bob, Some("wrong password"), "new password")
+            .await
+            .unwrap()
+            .is_err());
+        assert!(database
+            .set_user_password(bob, Some("password"), "new password")
+            .await
+            .unwrap()
+            .is_ok());
+
+        assert!(database
+            .create_password_reset_code("alice@example.com")
+            .await
+            .unwrap()
+            .is_none());
+        let reset = database
+            .create_password_reset_code("bob@example.com")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(reset.user_name, "bob");
+        assert_eq!(
+            database
+                .get_password_reset_by_callback_code(&reset.code)
+                .await
+                .unwrap(),
+            "bob"
+        );
+        assert_eq!(
+            database
+                .reset_password_by_callback_code(&reset.code, "reset password")
+                .await
+                .unwrap(),
+            bob
+        );
+        assert!(database
+            .reset_password_by_callback_code(&reset.code, "reset password")
+            .await
+            .is_err());
 
-        let mut teams = BTreeMap::new();
-        while let Some(row) = db_teams.next().await? {
-            let team = de::from_row::<DbTeam>(&row).unwrap();
-            teams.insert(
-                team.id,
-                (
-                    ScoreboardTeam {
-                        team_name: team.name,
-                        series: vec![],
-                    },
-                    team.points,
-                ),
-            );
-        }
+        assert!(database
+            .upsert_user_by_credentials("bob", "", "reset password", location_url)
+            .await
+            .unwrap()
+            .is_some());
+    }
 
-        #[derive(Debug, Deserialize)]
-        struct DbScoreboard {
-            at: i64,
-            points: i64,
-        }
+    #[tokio::test]
+    async fn backup_roundtrip() {
+        let database = test_db().await;
 
-        for (team_id, (team, _)) in teams.iter_mut() {
-            let mut db_scoreboard = tx
-                .query(
-                    "
-                    SELECT at, points
-                    FROM rhombus_points_snapshot
-                    WHERE team_id = ?
-                    ORDER BY at ASC
-                ",
-                    params!(team_id),
-                )
-                .await?;
+        let (user_id, _) = database
+            .upsert_user_by_credentials("alice", "", "password", "http://localhost:3000")
+            .await
+            .unwrap()
+            .unwrap();
 
-            while let Some(row) = db_scoreboard.next().await? {
-                let scoreboard = de::from_row::<DbScoreboard>(&row).unwrap();
-                let series_point = ScoreboardSeriesPoint {
-                    timestamp: scoreboard.at,
-                    total_score: scoreboard.points,
-                };
-                team.series.push(series_point);
-            }
-        }
+        let tables = database.export_backup_tables().await.unwrap();
 
-        tx.commit().await?;
+        let restored = InMemoryLibSQL::new().await.unwrap();
+        restored.migrate().await.unwrap();
+        restored.import_backup_tables(&tables).await.unwrap();
 
-        let now = chrono::Utc::now().timestamp();
-        let teams = teams
-            .into_iter()
-            .map(|(team_id, (mut team, current_points))| {
-                team.series.push(ScoreboardSeriesPoint {
-                    timestamp: now,
-                    total_score: current_points,
-                });
-                (team_id, team)
-            })
-            .collect();
+        let user = restored.get_user_from_id(user_id).await.unwrap();
+        assert_eq!("alice", user.name);
 
-        Ok(Arc::new(ScoreboardInner::new(teams)))
+        // restoring over an existing instance is refused
+        assert!(restored.import_backup_tables(&tables).await.is_err());
     }
 
-    async fn get_leaderboard(&self, division_id: &str) -> Result<Leaderboard> {
-        #[derive(Debug, Deserialize)]
-        struct DbLeaderboard {
-            team_id: i64,
-            name: String,
-            points: f64,
-        }
+    /// Upload `contents` and attach it to the test challenge
+    async fn insert_test_attachment(
+        database: &InMemoryLibSQL,
+        upload_provider: &Arc<dyn ErasedUploadProvider>,
+        name: &str,
+        contents: &'static [u8],
+    ) -> String {
+        let url = upload_provider
+            .upload(
+                name,
+                futures::stream::once(async move {
+                    Ok::<_, std::io::Error>(Bytes::from_static(contents))
+                }),
+            )
+            .await
+            .unwrap();
+        let (hash, _) = parse_upload_url(&url).unwrap();
+        database
+            .conn
+            .execute(
+                "INSERT INTO rhombus_file_attachment (challenge_id, name, url, hash) VALUES ('web-1', ?1, ?2, ?3)",
+                params!(name, url.as_str(), hash),
+            )
+            .await
+            .unwrap();
+        url
+    }
 
-        let mut rank = 0;
+    async fn read_upload(upload_provider: &Arc<dyn ErasedUploadProvider>, url: &str) -> Vec<u8> {
+        upload_provider
+            .download(url)
+            .await
+            .unwrap()
+            .try_collect::<Vec<_>>()
+            .await
+            .unwrap()
+            .concat()
+    }
 
-        let leaderboard_entries = self
-            .connect()
-            .await?
-            .query(
-                "
-                SELECT id AS team_id, name, points
-                FROM rhombus_team
-                WHERE division_id = ?1
-                ORDER BY points DESC, last_solved_at ASC
-            ",
-                params!(division_id),
-            )
-            .await?
-            .into_stream()
-            .map(|row| {
-                let db_leaderboard = de::from_row::<DbLeaderboard>(&row.unwrap()).unwrap();
-                rank += 1;
-                LeaderboardEntry {
-                    rank,
-                    team_id: db_leaderboard.team_id,
-                    team_name: db_leaderboard.name,
-                    score: db_leaderboard.points.round() as i64,
-                }
-            })
-            .collect::<Vec<_>>()
-            .await;
+    #[tokio::test]
+    async fn migrate_uploads_between_providers() {
+        let database = test_db().await;
+        insert_test_challenge(&database).await;
+        let db: Connection = Arc::new(database.clone());
+
+        let folder = tempfile::tempdir().unwrap();
+        let database_provider: Arc<dyn ErasedUploadProvider> =
+            Arc::new(DatabaseUploadProvider::new(db.clone()).await);
+        let local_provider: Arc<dyn ErasedUploadProvider> =
+            Arc::new(LocalUploadProvider::new(folder.path().to_owned()));
+
+        insert_test_attachment(&database, &database_provider, "a.txt", b"first").await;
+        insert_test_attachment(&database, &database_provider, "b.txt", b"second").await;
+
+        assert_eq!(
+            migrate_uploads(&db, &database_provider, &local_provider)
+                .await
+                .unwrap(),
+            2
+        );
+        // running the migration again leaves every attachment where it is
+        migrate_uploads(&db, &local_provider, &local_provider)
+            .await
+            .unwrap();
 
-        Ok(Arc::new(leaderboard_entries))
+        let urls = db.get_uploaded_attachment_urls().await.unwrap();
+        assert_eq!(urls.len(), 2);
+        for url in urls.iter() {
+            let contents = if url.ends_with("-a.txt") {
+                &b"first"[..]
+            } else {
+                &b"second"[..]
+            };
+            assert_eq!(read_upload(&local_provider, url).await, contents);
+        }
+        assert_eq!(local_provider.list().await.unwrap().len(), 2);
     }
 
-    async fn get_top10_discord_ids(&self) -> Result<BTreeSet<NonZeroU64>> {
-        let top10_discord_ids = self
-            .connect()
-            .await?
-            .query(
+    /// A new url which is the old url of another attachment is not rewritten again
+    #[tokio::test]
+    async fn rewrite_attachment_urls_once() {
+        let database = test_db().await;
+        insert_test_challenge(&database).await;
+        database
+            .conn
+            .execute_batch(
                 "
-                SELECT rhombus_user.discord_id
-                FROM rhombus_division
-                JOIN rhombus_user
-                WHERE rhombus_user.team_id IN (
-                    SELECT id
-                    FROM rhombus_team
-                    WHERE division_id = rhombus_division.id AND points > 0
-                    ORDER BY points DESC, last_solved_at ASC
-                    LIMIT 10
-                )
-                AND rhombus_user.discord_id IS NOT NULL
+                INSERT INTO rhombus_file_attachment (challenge_id, name, url, hash) VALUES ('web-1', 'a', '/a', 'a');
+                INSERT INTO rhombus_file_attachment (challenge_id, name, url, hash) VALUES ('web-1', 'b', '/b', 'b');
+                INSERT INTO rhombus_file_attachment (challenge_id, name, url, hash) VALUES ('web-1', 'c', '/c', 'c');
             ",
-                (),
             )
-            .await?
-            .into_stream()
-            .map(|row| NonZeroU64::new(row.unwrap().get::<u64>(0).unwrap()).unwrap())
-            .collect()
-            .await;
-
-        Ok(top10_discord_ids)
-    }
+            .await
+            .unwrap();
 
-    async fn get_emails_for_user_id(&self, user_id: i64) -> Result<Vec<Email>> {
-        #[derive(Debug, Deserialize)]
-        struct QueryEmail {
-            email: String,
-            code: Option<String>,
-        }
+        database
+            .rewrite_attachment_urls(&BTreeMap::from([
+                ("/a".to_owned(), "/b".to_owned()),
+                ("/b".to_owned(), "/c2".to_owned()),
+                ("/c".to_owned(), "/c".to_owned()),
+            ]))
+            .await
+            .unwrap();
 
-        let emails = self
-            .connect()
-            .await?
+        let urls = database
+            .conn
             .query(
-                "SELECT email, code FROM rhombus_email WHERE user_id = ?1",
-                [user_id],
+                "SELECT name, url FROM rhombus_file_attachment ORDER BY name",
+                (),
             )
-            .await?
+            .await
+            .unwrap()
             .into_stream()
             .map(|row| {
-                let email = de::from_row::<QueryEmail>(&row.unwrap()).unwrap();
-                Email {
-                    address: email.email,
-                    verified: email.code.is_none(),
-                }
+                let row = row.unwrap();
+                (row.get::<String>(0).unwrap(), row.get::<String>(1).unwrap())
             })
             .collect::<Vec<_>>()
             .await;
-
-        Ok(emails)
-    }
-
-    async fn get_team_tracks(&self, team_id: i64) -> Result<BTreeMap<i64, UserTrack>> {
-        #[derive(Debug, Deserialize)]
-        struct DbTrack {
-            user_id: i64,
-            ip: [u8; 16],
-            last_seen_at: i64,
-        }
-
-        let mut tracks = BTreeMap::new();
-
-        let mut query_locations = self
-            .connect()
-            .await?
-            .query(
-                "
-                WITH RankedTracks AS (
-                    SELECT
-                        user_id,
-                        ip,
-                        last_seen_at,
-                        ROW_NUMBER() OVER (PARTITION BY rhombus_user.id ORDER BY last_seen_at DESC) as rn
-                    FROM rhombus_user
-                    JOIN rhombus_track_ip ON rhombus_user.id = rhombus_track_ip.user_id
-                    JOIN rhombus_track ON rhombus_track.id = rhombus_track_ip.track_id
-                    WHERE rhombus_user.team_id = 1
-                )
-                SELECT user_id, ip, last_seen_at
-                FROM RankedTracks
-                WHERE rn = 1
-            ",
-                [team_id],
-            )
-            .await?;
-
-        while let Some(row) = query_locations.next().await? {
-            let db_track = de::from_row::<DbTrack>(&row).unwrap();
-            let ip = IpAddr::from(db_track.ip);
-            let last_seen_at = DateTime::<Utc>::from_timestamp(db_track.last_seen_at, 0).unwrap();
-            let track = UserTrack { ip, last_seen_at };
-            tracks.insert(db_track.user_id, track);
-        }
-
-        Ok(tracks)
+        assert_eq!(
+            urls,
+            vec![
+                ("a".to_owned(), "/b".to_owned()),
+                ("b".to_owned(), "/c2".to_owned()),
+                ("c".to_owned(), "/c".to_owned()),
+            ]
+        );
     }
 
-    async fn create_email_verification_callback_code(
-        &self,
-        user_id: i64,
-        email: &str,
-    ) -> Result<String> {
-        let code = generate_email_callback_code();
-
-        self.connect()
-            .await?
-            .execute(
-                "INSERT INTO rhombus_email (email, user_id, code) VALUES (?1, ?2, ?3)",
-                params!(email, user_id, code.as_str()),
+    #[tokio::test]
+    async fn collect_garbage_deletes_orphans() {
+        let database = test_db().await;
+        insert_test_challenge(&database).await;
+        let db: Connection = Arc::new(database.clone());
+
+        let folder = tempfile::tempdir().unwrap();
+        let providers: [Arc<dyn ErasedUploadProvider>; 2] = [
+            Arc::new(DatabaseUploadProvider::new(db.clone()).await),
+            Arc::new(LocalUploadProvider::new(folder.path().to_owned())),
+        ];
+
+        for (i, upload_provider) in providers.iter().enumerate() {
+            let referenced = insert_test_attachment(
+                &database,
+                upload_provider,
+                &format!("referenced-{}.txt", i),
+                b"referenced",
             )
-            .await?;
+            .await;
+            let orphan = upload_provider
+                .upload(
+                    "orphan.txt",
+                    futures::stream::once(async {
+                        Ok::<_, std::io::Error>(Bytes::from_static(b"orphan"))
+                    }),
+                )
+                .await
+                .unwrap();
 
-        Ok(code)
-    }
+            let orphaned = collect_garbage(&db, upload_provider, true).await.unwrap();
+            assert_eq!(orphaned, vec![orphan.clone()]);
+            assert_eq!(upload_provider.list().await.unwrap().len(), 2);
 
-    async fn verify_email_verification_callback_code(&self, code: &str) -> Result<()> {
-        self.connect()
-            .await?
-            .execute(
-                "
-                UPDATE rhombus_email
-                SET code = NULL
-                WHERE code = ?1
-            ",
-                [code],
-            )
-            .await?;
+            let orphaned = collect_garbage(&db, upload_provider, false).await.unwrap();
+            assert_eq!(orphaned, vec![orphan]);
+            assert_eq!(
+                upload_provider.list().await.unwrap(),
+                vec![referenced.clone()]
+            );
+            assert_eq!(
+                read_upload(upload_provider, &referenced).await,
+                b"referenced"
+            );
 
-        Ok(())
+            database
+                .conn
+                .execute("DELETE FROM rhombus_file_attachment", ())
+                .await
+                .unwrap();
+            collect_garbage(&db, upload_provider, false).await.unwrap();
+            assert!(upload_provider.list().await.unwrap().is_empty());
+        }
     }
 
-    async fn get_email_verification_by_callback_code(&self, code: &str) -> Result<String> {
-        let email = self
-            .connect()
-            .await?
-            .query(
-                "
-                SELECT email
-                FROM rhombus_email
-                WHERE code = ?1
-            ",
-                [code],
-            )
-            .await?
-            .next()
-            .await?
-            .ok_or(RhombusError::DatabaseReturnedNoRows)?
-            .get::<String>(0)
+    #[tokio::test]
+    async fn two_factor() {
+        let database = test_db().await;
+
+        let (bob, _) = database
+            .upsert_user_by_credentials("bob", "", "password", "http://localhost:3000")
+            .await
+            .unwrap()
             .unwrap();
+        assert!(database.get_user_totp(bob).await.unwrap().is_none());
 
-        Ok(email)
+        database.set_user_totp_pending(bob, "first").await.unwrap();
+        database.set_user_totp_pending(bob, "second").await.unwrap();
+        let totp = database.get_user_totp(bob).await.unwrap().unwrap();
+        assert_eq!(totp.secret, "second");
+        assert!(!totp.enabled);
+
+        database
+            .enable_user_totp(bob, &["a".to_owned(), "b".to_owned()])
+            .await
+            .unwrap();
+        // the secret cannot be replaced once enabled
+        database.set_user_totp_pending(bob, "third").await.unwrap();
+        let totp = database.get_user_totp(bob).await.unwrap().unwrap();
+        assert_eq!(totp.secret, "second");
+        assert!(totp.enabled);
+        assert_eq!(totp.recovery_codes, 2);
+
+        assert!(database.use_user_totp_step(bob, 10).await.unwrap());
+        assert!(!database.use_user_totp_step(bob, 10).await.unwrap());
+        assert!(!database.use_user_totp_step(bob, 9).await.unwrap());
+        assert!(database.use_user_totp_step(bob, 11).await.unwrap());
+
+        assert!(database.use_user_recovery_code(bob, "a").await.unwrap());
+        assert!(!database.use_user_recovery_code(bob, "a").await.unwrap());
+        assert_eq!(
+            database
+                .get_user_totp(bob)
+                .await
+                .unwrap()
+                .unwrap()
+                .recovery_codes,
+            1
+        );
+
+        database.disable_user_totp(bob).await.unwrap();
+        assert!(database.get_user_totp(bob).await.unwrap().is_none());
+        assert!(!database.use_user_recovery_code(bob, "b").await.unwrap());
     }
 
-    async fn create_email_signin_callback_code(&self, email: &str) -> Result<String> {
-        let code = generate_email_callback_code();
+    #[tokio::test]
+    async fn passkeys() {
+        let database = test_db().await;
 
-        self.connect()
-            .await?
-            .execute(
-                "INSERT OR REPLACE INTO rhombus_email_signin (email, code) VALUES (?1, ?2)",
-                params!(email, code.as_str()),
-            )
-            .await?;
+        let (bob, _) = database
+            .upsert_user_by_credentials("bob", "", "password", "http://localhost:3000")
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(
+            !database
+                .get_user_sign_in_methods(bob)
+                .await
+                .unwrap()
+                .passkey
+        );
 
-        Ok(code)
-    }
+        database
+            .add_user_passkey(bob, "credential", "Laptop", &[1, 2, 3], -7, 5)
+            .await
+            .unwrap();
+        assert!(
+            database
+                .get_user_sign_in_methods(bob)
+                .await
+                .unwrap()
+                .passkey
+        );
+        // credential ids are unique
+        assert!(database
+            .add_user_passkey(bob, "credential", "Phone", &[4], -7, 0)
+            .await
+            .is_err());
 
-    async fn verify_email_signin_callback_code(&self, code: &str) -> Result<String> {
-        let email = self
-            .connect()
-            .await?
-            .query(
-                "
-                DELETE FROM rhombus_email_signin
-                WHERE code = ?1
-                RETURNING email
-            ",
-                [code],
-            )
-            .await?
-            .next()
-            .await?
-            .ok_or(libsql::Error::QueryReturnedNoRows)?
-            .get::<String>(0)
+        let credential = database
+            .get_passkey_credential("credential")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(credential.user_id, bob);
+        assert_eq!(credential.public_key, vec![1, 2, 3]);
+        assert_eq!(credential.sign_count, 5);
+
+        // the signature counter must increase
+        assert!(!database.use_passkey("credential", 5).await.unwrap());
+        assert!(database.use_passkey("credential", 6).await.unwrap());
+        let passkeys = database.get_user_passkeys(bob).await.unwrap();
+        assert_eq!(passkeys.len(), 1);
+        assert_eq!(passkeys[0].name, "Laptop");
+        assert!(passkeys[0].last_used_at.is_some());
+
+        // authenticators without a counter always report 0
+        database
+            .add_user_passkey(bob, "counterless", "Key", &[4], -8, 0)
+            .await
             .unwrap();
+        assert!(database.use_passkey("counterless", 0).await.unwrap());
+        assert!(database.use_passkey("counterless", 0).await.unwrap());
 
-        Ok(email)
+        database
+            .delete_user_passkey(bob, "credential")
+            .await
+            .unwrap();
+        database
+            .delete_user_passkey(bob, "counterless")
+            .await
+            .unwrap();
+        assert!(database
+            .get_passkey_credential("credential")
+            .await
+            .unwrap()
+            .is_none());
+        assert!(
+            !database
+                .get_user_sign_in_methods(bob)
+                .await
+                .unwrap()
+                .passkey
+        );
     }
 
-    async fn get_email_signin_by_callback_code(&self, code: &str) -> Result<String> {
-        let email = self
-            .connect()
-            .await?
-            .query(
-                "
-                SELECT email
-                FROM rhombus_email_signin
-                WHERE code = ?1
-            ",
-                [code],
-            )
-            .await?
-            .next()
-            .await?
-            .ok_or(libsql::Error::QueryReturnedNoRows)?
-            .get::<String>(0)
+    #[tokio::test]
+    async fn sessions() {
+        let database = test_db().await;
+
+        let (bob, _) = database
+            .upsert_user_by_credentials("bob", "", "password", "http://localhost:3000")
+            .await
+            .unwrap()
+            .unwrap();
+        let expires = chrono::Utc::now().timestamp() + 60;
+        database
+            .create_session("laptop", bob, expires)
+            .await
+            .unwrap();
+        database
+            .create_session("phone", bob, expires)
+            .await
+            .unwrap();
+        database
+            .create_session("expired", bob, expires - 120)
+            .await
             .unwrap();
 
-        Ok(email)
-    }
+        assert_eq!(
+            database.get_session_user_id("laptop").await.unwrap(),
+            Some(bob)
+        );
+        assert_eq!(database.get_session_user_id("expired").await.unwrap(), None);
 
-    async fn delete_email(&self, user_id: i64, email: &str) -> Result<()> {
-        self.connect()
-            .await?
-            .execute(
-                "DELETE FROM rhombus_email WHERE user_id = ?1 AND email = ?2",
-                params!(user_id, email),
-            )
-            .await?;
+        let ip: IpAddr = "1.2.3.4".parse().unwrap();
+        database
+            .touch_session("laptop", Some(ip), Some("Firefox"), expires)
+            .await
+            .unwrap();
+        let sessions = database.get_user_sessions(bob).await.unwrap();
+        assert_eq!(sessions.len(), 2);
+        assert_eq!(sessions[0].id, "laptop");
+        assert_eq!(sessions[0].ip, Some(ip));
+        assert_eq!(sessions[0].user_agent.as_deref(), Some("Firefox"));
 
-        Ok(())
-    }
+        database.revoke_session(bob, "phone").await.unwrap();
+        assert_eq!(database.get_session_user_id("phone").await.unwrap(), None);
 
-    async fn set_team_division(
-        &self,
-        team_id: i64,
-        _old_division_id: &str,
-        new_division_id: &str,
-        now: DateTime<Utc>,
-    ) -> Result<()> {
-        let conn = self.connect().await?;
+        database
+            .create_session("tablet", bob, expires)
+            .await
+            .unwrap();
+        database
+            .revoke_user_sessions(bob, Some("laptop"))
+            .await
+            .unwrap();
+        assert_eq!(database.get_session_user_id("tablet").await.unwrap(), None);
+        assert_eq!(
+            database.get_session_user_id("laptop").await.unwrap(),
+            Some(bob)
+        );
+
+        database.revoke_user_sessions(bob, None).await.unwrap();
+        assert!(database.get_user_sessions(bob).await.unwrap().is_empty());
+    }
 
-        conn.execute(
-            "UPDATE rhombus_team SET division_id = ?1, last_division_change = ?2 WHERE id = ?3",
-            params!(new_division_id, now.timestamp(), team_id),
-        )
-        .await?;
+    #[tokio::test]
+    async fn ldap_identity() {
+        let database = test_db().await;
 
-        Ok(())
+        let dn = "cn=bob,ou=users,dc=example,dc=org";
+        let (bob, team) = database
+            .upsert_user_by_ldap("bob", None, "", dn, "http://localhost:3000")
+            .await
+            .unwrap()
+            .ok()
+            .unwrap();
+        assert_eq!(
+            database
+                .upsert_user_by_ldap("Bob", None, "", dn, "http://localhost:3000")
+                .await
+                .unwrap()
+                .ok(),
+            Some((bob, team))
+        );
+
+        let identities = database.get_user_identities(bob).await.unwrap();
+        assert_eq!(identities.len(), 1);
+        assert_eq!(identities[0].provider, "ldap");
+        assert_eq!(identities[0].subject, dn);
+
+        assert!(!database.get_user_from_id(bob).await.unwrap().is_admin);
+        database.set_user_admin(bob, true).await.unwrap();
+        assert!(database.get_user_from_id(bob).await.unwrap().is_admin);
+        database.set_user_admin(bob, false).await.unwrap();
+        assert!(!database.get_user_from_id(bob).await.unwrap().is_admin);
     }
 
-    async fn insert_divisions(&self, divisions: &[Division]) -> Result<()> {
-        let tx = self.transaction().await?;
+    #[tokio::test]
+    async fn ticket_messages() {
+        let database = test_db().await;
+        insert_test_challenge(&database).await;
 
-        let current_division_ids = tx
-            .query("SELECT id FROM rhombus_division", ())
-            .await?
-            .into_stream()
-            .map(|row| row.unwrap().get::<String>(0).unwrap())
-            .collect::<Vec<_>>()
-            .await;
+        let (bob, _) = database
+            .upsert_user_by_credentials("bob", "", "password", "http://localhost:3000")
+            .await
+            .unwrap()
+            .unwrap();
+        let participant = TicketMessageAuthor {
+            user_id: Some(bob),
+            name: "bob",
+            avatar: "",
+        };
+        let staff = TicketMessageAuthor {
+            user_id: None,
+            name: "alice",
+            avatar: "",
+        };
 
-        for division_id in current_division_ids {
-            if !divisions.iter().any(|d| d.id == division_id) {
-                _ = tx
-                    .execute("DELETE FROM rhombus_division WHERE id = ?1", [division_id])
-                    .await;
-            }
+        // tickets no longer need a Discord thread
+        for ticket_number in [1, 2] {
+            database
+                .create_ticket(ticket_number, bob, "web-1", &participant, "Help", None)
+                .await
+                .unwrap();
         }
-
-        for division in divisions {
-            tx.execute(
-                "INSERT OR REPLACE INTO rhombus_division (id, name, description, is_default) VALUES (?1, ?2, ?3, ?4)",
-                params!(division.id.as_str(), division.name.as_str(), division.description.as_str(), division.is_default),
+        let ticket = database.get_ticket_by_ticket_number(1).await.unwrap();
+        assert_eq!(ticket.discord_channel_id, None);
+        assert_eq!(ticket.discord_panel_message_id, None);
+        assert_eq!(ticket.status, TicketStatus::Open);
+        assert_eq!(ticket.first_response_at, None);
+
+        database
+            .set_ticket_discord(
+                1,
+                NonZeroU64::new(10).unwrap(),
+                NonZeroU64::new(11).unwrap(),
             )
-            .await?;
-        }
-
-        tx.commit().await?;
+            .await
+            .unwrap();
+        let ticket = database.get_ticket_by_ticket_number(1).await.unwrap();
+        assert_eq!(ticket.discord_channel_id, NonZeroU64::new(10));
 
-        Ok(())
-    }
+        // a participant replying to their own ticket is not a response
+        database
+            .add_ticket_message(1, &participant, "Still there?", None, false)
+            .await
+            .unwrap();
+        let ticket = database.get_ticket_by_ticket_number(1).await.unwrap();
+        assert_eq!(ticket.status, TicketStatus::Open);
+        assert_eq!(ticket.first_response_at, None);
 
-    async fn get_team_standing(&self, team_id: i64) -> Result<Option<TeamStanding>> {
-        #[derive(Debug, Deserialize)]
-        struct DbPointsRank {
-            points: f64,
-            rank: i64,
-        }
+        // staff without an account on the site still count as a response
+        database
+            .add_ticket_message(1, &staff, "Looking", NonZeroU64::new(12), true)
+            .await
+            .unwrap();
+        let ticket = database.get_ticket_by_ticket_number(1).await.unwrap();
+        assert_eq!(ticket.status, TicketStatus::Waiting);
+        let first_response_at = ticket.first_response_at.unwrap();
 
-        let standing = self
-            .connect()
-            .await?
-            .query(
-                "
-                SELECT
-                    points,
-                    (SELECT COUNT(*) + 1 FROM rhombus_team t2 WHERE t2.division_id = t1.division_id
-                        AND (t2.points > t1.points OR (t2.points = t1.points AND t2.last_solved_at < t1.last_solved_at))
-                    ) as rank
-                FROM rhombus_team t1
-                WHERE t1.id = ?1
-            ",
-                params!(team_id),
-            )
-            .await?
-            .next()
-            .await?
-            .map(|row| {
-                let points_rank = de::from_row::<DbPointsRank>(&row).unwrap();
-                TeamStanding {
-                    points: points_rank.points as i64,
-                    rank: points_rank.rank as u64,
-                }
-            });
+        database
+            .add_ticket_message(1, &participant, "Thanks", None, false)
+            .await
+            .unwrap();
+        let ticket = database.get_ticket_by_ticket_number(1).await.unwrap();
+        assert_eq!(ticket.status, TicketStatus::Open);
+        assert_eq!(ticket.first_response_at, Some(first_response_at));
 
-        Ok(standing)
+        database
+            .set_ticket_status(1, TicketStatus::Escalated)
+            .await
+            .unwrap();
+        database
+            .add_ticket_message(1, &staff, "Asking an admin", None, true)
+            .await
+            .unwrap();
+        database
+            .add_ticket_message(1, &participant, "Ok", None, false)
+            .await
+            .unwrap();
+        let ticket = database.get_ticket_by_ticket_number(1).await.unwrap();
+        assert_eq!(ticket.status, TicketStatus::Escalated);
+
+        let messages = database.get_ticket_messages(1).await.unwrap();
+        assert_eq!(messages.len(), 6);
+        assert_eq!(messages[2].user_id, None);
+        assert_eq!(messages[2].discord_message_id, NonZeroU64::new(12));
+        assert_eq!(database.get_ticket_messages(2).await.unwrap().len(), 1);
     }
 
-    async fn upload_file(&self, hash: &str, filename: &str, bytes: &[u8]) -> Result<()> {
-        self.connect()
-            .await?
-            .query(
-                "INSERT INTO rhombus_file (hash, filename, contents) VALUES (?1, ?2, ?3)",
-                params!(hash, filename, bytes),
+    #[tokio::test]
+    async fn ticket_canned_responses() {
+        let database = test_db().await;
+        insert_test_challenge(&database).await;
+
+        let (bob, team_id) = database
+            .upsert_user_by_credentials("bob", "", "password", "http://localhost:3000")
+            .await
+            .unwrap()
+            .unwrap();
+        database
+            .create_ticket(
+                1,
+                bob,
+                "web-1",
+                &TicketMessageAuthor {
+                    user_id: Some(bob),
+                    name: "bob",
+                    avatar: "",
+                },
+                "Help",
+                None,
             )
-            .await?;
+            .await
+            .unwrap();
+        let ticket = database.get_ticket_by_ticket_number(1).await.unwrap();
+        let team = database.get_team_from_id(team_id).await.unwrap();
+
+        let settings: Settings = serde_json::from_value(json!({
+            "title": "CTF",
+            "location_url": "http://localhost:3000",
+            "jwt_secret": "secret",
+            "live_reload": false,
+            "default_ticket_template": "",
+            "immutable_config": false,
+            "auth": [],
+            "in_memory_cache": "false",
+            "canned_responses": {
+                "ticket": "Ticket #{{ ticket_number }} at {{ location_url }}",
+                "broken": "{{ unclosed",
+            },
+        }))
+        .unwrap();
+
+        let db: Connection = Arc::new(database);
+        let challenge_data = db.get_challenges().await.unwrap();
+        let rendered = render_canned_responses(&db, &settings, &challenge_data, &ticket)
+            .await
+            .unwrap();
 
-        Ok(())
+        // responses which fail to render are left out
+        assert_eq!(rendered.len(), 2);
+        assert_eq!(rendered["ticket"], "Ticket #1 at http://localhost:3000");
+        assert_eq!(
+            rendered["hint"],
+            format!("Hi bob of {}, look at Web 1", team.name)
+        );
     }
 
-    async fn download_file(&self, hash: &str) -> Result<(Bytes, String)> {
-        #[derive(Debug, Deserialize)]
-        struct DbFile {
-            filename: String,
-            contents: Vec<u8>,
-        }
+    #[tokio::test]
+    async fn ticket_transcript() {
+        let database = test_db().await;
+        insert_test_challenge(&database).await;
 
-        let row = self
-            .connect()
-            .await?
-            .query(
-                "SELECT filename, contents FROM rhombus_file WHERE hash = ?1",
-                [hash],
+        let (bob, _) = database
+            .upsert_user_by_credentials("bob", "", "password", "http://localhost:3000")
+            .await
+            .unwrap()
+            .unwrap();
+        let participant = TicketMessageAuthor {
+            user_id: Some(bob),
+            name: "bob",
+            avatar: "",
+        };
+        database
+            .create_ticket(1, bob, "web-1", &participant, "The remote is down", None)
+            .await
+            .unwrap();
+        database
+            .add_ticket_message(
+                1,
+                &TicketMessageAuthor {
+                    user_id: None,
+                    name: "alice",
+                    avatar: "",
+                },
+                "Fixed it",
+                NonZeroU64::new(12),
+                true,
             )
-            .await?
-            .next()
-            .await?
-            .ok_or(libsql::Error::QueryReturnedNoRows)?;
+            .await
+            .unwrap();
+        let edited_at = Utc::now();
+        assert_eq!(
+            database
+                .edit_ticket_message_by_discord_id(
+                    NonZeroU64::new(12).unwrap(),
+                    "Fixed it, try again",
+                    edited_at,
+                )
+                .await
+                .unwrap(),
+            Some(1)
+        );
+        database
+            .set_ticket_tags(1, &["infra".to_owned(), "bug".to_owned()])
+            .await
+            .unwrap();
 
-        let file = de::from_row::<DbFile>(&row).unwrap();
+        let db: Connection = Arc::new(database);
+        let challenge_data = db.get_challenges().await.unwrap();
+        let ticket = db.get_ticket_by_ticket_number(1).await.unwrap();
+        let transcript = get_ticket_transcript(&db, &challenge_data, ticket)
+            .await
+            .unwrap();
 
-        Ok((Bytes::from(file.contents), file.filename))
+        let json: serde_json::Value =
+            serde_json::from_str(&serde_json::to_string(&transcript).unwrap()).unwrap();
+        assert_eq!(json["ticket_number"], 1);
+        assert_eq!(json["challenge_name"], "Web 1");
+        assert_eq!(json["creator_id"], bob);
+        assert_eq!(json["creator_name"], "bob");
+        assert_eq!(json["status"], "waiting");
+        assert_eq!(json["tags"], json!(["bug", "infra"]));
+        let messages = json["messages"].as_array().unwrap();
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[0]["content"], "The remote is down");
+        assert!(messages[0]["edited_at"].is_null());
+        assert_eq!(messages[1]["author_name"], "alice");
+        assert_eq!(messages[1]["content"], "Fixed it, try again");
+        assert_eq!(messages[1]["discord_message_id"], 12);
+        assert!(messages[1]["edited_at"].is_string());
+
+        let markdown = transcript.to_markdown();
+        assert!(markdown.starts_with("# Ticket #1: Web 1\n\n- Opened by: bob\n"));
+        assert!(markdown.contains("- Status: waiting\n- Tags: bug, infra\n"));
+        assert!(markdown.contains("\n\nThe remote is down\n"));
+        assert!(markdown.contains(&format!(
+            ", edited {}\n\nFixed it, try again\n",
+            transcript.messages[1].edited_at.unwrap().to_rfc3339()
+        )));
+        assert!(!markdown.contains("Fixed it\n"));
     }
 
-    async fn get_site_statistics(&self) -> Result<SiteStatistics> {
-        let tx = self.transaction().await?;
+    #[tokio::test]
+    async fn transfer_ownership_and_disband() {
+        let database = test_db().await;
 
-        let total_users = tx
-            .query("SELECT COUNT(*) FROM rhombus_user", ())
-            .await?
-            .next()
-            .await?
+        let location_url = "http://localhost:3000";
+        let (alice, alice_team) = database
+            .upsert_user_by_credentials("alice", "", "password", location_url)
+            .await
             .unwrap()
-            .get::<i64>(0)
             .unwrap();
-
-        let total_teams = tx
-            .query("SELECT COUNT(DISTINCT team_id) FROM rhombus_user", ())
-            .await?
-            .next()
-            .await?
+        let (bob, bob_team) = database
+            .upsert_user_by_credentials("bob", "", "password", location_url)
+            .await
             .unwrap()
-            .get::<i64>(0)
+            .unwrap();
+        database
+            .add_user_to_team(bob, alice_team, Some(bob_team))
+            .await
             .unwrap();
 
-        let total_challenges = tx
-            .query("SELECT COUNT(*) FROM rhombus_challenge", ())
-            .await?
+        let team_count = || async {
+            database
+                .conn
+                .query("SELECT COUNT(*) FROM rhombus_team", ())
+                .await
+                .unwrap()
+                .next()
+                .await
+                .unwrap()
+                .unwrap()
+                .get::<i64>(0)
+                .unwrap()
+        };
+        let teams = team_count().await;
+
+        // only a member can become the owner
+        assert!(database
+            .transfer_team_ownership(bob_team, bob, alice)
+            .await
+            .is_err());
+
+        // alice takes over the team bob left behind instead of a new one
+        assert_eq!(
+            database
+                .transfer_team_ownership(alice_team, alice, bob)
+                .await
+                .unwrap(),
+            bob_team
+        );
+        assert_eq!(team_count().await, teams);
+        assert_eq!(
+            database
+                .get_team_from_id(alice_team)
+                .await
+                .unwrap()
+                .owner_user_id,
+            bob
+        );
+        let owner_team_id = database
+            .conn
+            .query(
+                "SELECT owner_team_id FROM rhombus_user WHERE id = ?1",
+                [alice],
+            )
+            .await
+            .unwrap()
             .next()
-            .await?
+            .await
+            .unwrap()
             .unwrap()
             .get::<i64>(0)
             .unwrap();
+        assert_eq!(owner_team_id, bob_team);
+        let user = database.get_user_from_id(alice).await.unwrap();
+        assert_eq!(user.team_id, alice_team);
+        assert!(!user.is_team_owner);
+
+        assert_eq!(
+            database.disband_team(alice_team).await.unwrap(),
+            vec![(alice, bob_team)]
+        );
+        assert_eq!(
+            database.get_user_from_id(alice).await.unwrap().team_id,
+            bob_team
+        );
+        let user = database.get_user_from_id(bob).await.unwrap();
+        assert_eq!(user.team_id, alice_team);
+        assert!(user.is_team_owner);
+    }
 
-        let total_solves = tx
-            .query("SELECT COUNT(*) FROM rhombus_solve", ())
-            .await?
-            .next()
-            .await?
+    #[tokio::test]
+    async fn team_invites() {
+        let database = test_db().await;
+
+        let location_url = "http://localhost:3000";
+        let (alice, alice_team) = database
+            .upsert_user_by_email("alice", "alice@example.com", "", location_url)
+            .await
+            .unwrap();
+        let (bob, _) = database
+            .upsert_user_by_credentials("bob", "", "password", location_url)
+            .await
             .unwrap()
-            .get::<i64>(0)
             .unwrap();
 
-        #[derive(Debug, Deserialize)]
-        struct QueryCategory {
-            name: String,
-            color: String,
-            challenge_count: i64,
-        }
-
-        let categories = tx
-            .query(
-                "
-            SELECT rhombus_category.name, rhombus_category.color, COUNT(*) AS challenge_count
-            FROM rhombus_category
-            JOIN rhombus_challenge ON rhombus_challenge.category_id = rhombus_category.id
-            GROUP BY rhombus_category.id
-            ORDER BY challenge_count DESC
-            ",
-                (),
-            )
-            .await?
-            .into_stream()
-            .map(|row| {
-                let category = de::from_row::<QueryCategory>(&row.unwrap()).unwrap();
-                StatisticsCategory {
-                    name: category.name,
-                    color: category.color,
-                    num: category.challenge_count as u64,
-                }
-            })
-            .collect::<Vec<_>>()
-            .await;
+        let user_code = database
+            .create_team_invite(alice_team, Some(bob), None)
+            .await
+            .unwrap();
+        let email_code = database
+            .create_team_invite(alice_team, None, Some("Bob@Example.com"))
+            .await
+            .unwrap();
+        assert_eq!(
+            database.get_team_invites(alice_team).await.unwrap().len(),
+            2
+        );
+        assert_eq!(
+            database.get_user_team_invites(bob).await.unwrap()[0].code,
+            user_code
+        );
+
+        // the email invite needs bob to verify the address it was sent to
+        let invite = database
+            .get_team_invite(&email_code)
+            .await
+            .unwrap()
+            .unwrap();
+        let invite_email = invite.email.unwrap();
+        let verification_code = database
+            .create_email_verification_callback_code(bob, "bob@example.com")
+            .await
+            .unwrap();
+        let emails = database.get_emails_for_user_id(bob).await.unwrap();
+        assert!(!has_verified_email(&emails, &invite_email));
 
-        tx.commit().await?;
-        Ok(SiteStatistics {
-            total_users: total_users as u64,
-            total_teams: total_teams as u64,
-            total_challenges: total_challenges as u64,
-            total_solves: total_solves as u64,
-            categories,
-        })
+        database
+            .verify_email_verification_callback_code(&verification_code)
+            .await
+            .unwrap();
+        let emails = database.get_emails_for_user_id(bob).await.unwrap();
+        assert!(has_verified_email(&emails, &invite_email));
+        let emails = database.get_emails_for_user_id(alice).await.unwrap();
+        assert!(!has_verified_email(&emails, &invite_email));
+
+        // joining settles the invites to the user, but not those to an email
+        database
+            .add_user_to_team(bob, alice_team, None)
+            .await
+            .unwrap();
+        assert!(database
+            .get_team_invite(&user_code)
+            .await
+            .unwrap()
+            .is_none());
+        database.delete_team_invite(&email_code).await.unwrap();
+        assert!(database
+            .get_team_invites(alice_team)
+            .await
+            .unwrap()
+            .is_empty());
     }
 
-    async fn get_last_created_ticket_time(&self, user_id: i64) -> Result<Option<DateTime<Utc>>> {
-        let last_opened_at_timestamp = self
-            .connect()
-            .await?
-            .query(
-                "
-                SELECT opened_at
-                FROM rhombus_ticket
-                WHERE user_id = ?1
-                ORDER BY opened_at DESC
-                LIMIT 1
-            ",
-                [user_id],
-            )
-            .await?
-            .next()
-            .await?
-            .map(|row| row.get::<i64>(0).unwrap());
+    #[tokio::test]
+    async fn team_join_requests() {
+        let database = test_db().await;
+        let divisions = [Division {
+            id: "open".to_owned(),
+            name: "Open".to_owned(),
+            description: "Open division for everyone".to_owned(),
+            max_players: MaxDivisionPlayers::Limited(NonZeroU32::new(2).unwrap()),
+            division_eligibility: Arc::new(OpenDivisionEligibilityProvider {}),
+            is_default: true,
+            discord_role_id: None,
+            archived: false,
+            admin_managed: false,
+        }];
+
+        let location_url = "http://localhost:3000";
+        let mut users = vec![];
+        for name in ["alice", "bob", "carol"] {
+            users.push(
+                database
+                    .upsert_user_by_credentials(name, "", "password", location_url)
+                    .await
+                    .unwrap()
+                    .unwrap(),
+            );
+        }
+        let [(alice, alice_team), (bob, bob_team), (carol, carol_team)] = users[..] else {
+            unreachable!()
+        };
 
-        let last_opened_at =
-            last_opened_at_timestamp.and_then(|t| DateTime::<Utc>::from_timestamp(t, 0));
+        database
+            .create_team_join_request(alice_team, bob)
+            .await
+            .unwrap();
+        database
+            .create_team_join_request(alice_team, carol)
+            .await
+            .unwrap();
+        database
+            .create_team_join_request(carol_team, bob)
+            .await
+            .unwrap();
+        assert_eq!(
+            database
+                .get_team_join_requests(alice_team)
+                .await
+                .unwrap()
+                .iter()
+                .map(|request| request.user_id)
+                .collect::<Vec<_>>(),
+            vec![bob, carol]
+        );
+
+        // approving bob lets them join, and settles their other requests
+        let old_team = database.get_team_from_id(bob_team).await.unwrap();
+        let new_team = database.get_team_from_id(alice_team).await.unwrap();
+        assert!(check_join_team(&divisions, bob, &old_team, &new_team)
+            .await
+            .is_ok());
+        assert!(database
+            .delete_team_join_request(alice_team, bob)
+            .await
+            .unwrap());
+        database
+            .add_user_to_team(bob, alice_team, Some(bob_team))
+            .await
+            .unwrap();
+        assert!(database
+            .get_team_join_requests(carol_team)
+            .await
+            .unwrap()
+            .is_empty());
+
+        // the team is now full, so carol's request can only be denied
+        let old_team = database.get_team_from_id(carol_team).await.unwrap();
+        let new_team = database.get_team_from_id(alice_team).await.unwrap();
+        assert!(matches!(
+            check_join_team(&divisions, carol, &old_team, &new_team).await,
+            Err(JoinTeamError::Full(_))
+        ));
+        assert!(database
+            .delete_team_join_request(alice_team, carol)
+            .await
+            .unwrap());
+        assert!(!database
+            .delete_team_join_request(alice_team, carol)
+            .await
+            .unwrap());
 
-        Ok(last_opened_at)
+        // members of a team with other players cannot move to another team
+        assert!(matches!(
+            check_join_team(&divisions, alice, &new_team, &old_team).await,
+            Err(JoinTeamError::ExistingTeam)
+        ));
     }
-}
 
-pub async fn create_team(tx: &Transaction) -> Result<i64> {
-    let team_invite_token = create_team_invite_token();
+    #[tokio::test]
+    async fn admin_divisions() {
+        let database = test_db().await;
 
-    let team_id = tx
-        .query(
-            "INSERT INTO rhombus_team (name, invite_token, division_id) VALUES ('Team ' || (SELECT COALESCE(MAX(id) + 1, 1) FROM rhombus_team), ?1, (SELECT id FROM rhombus_division WHERE is_default = TRUE LIMIT 1)) RETURNING id",
-            params!(team_invite_token),
-        )
-        .await?
-        .next()
-        .await?
-        .ok_or(RhombusError::LibSQL(libsql::Error::QueryReturnedNoRows))?
-        .get::<i64>(0)?;
+        assert!(database
+            .create_division("students", "Students", "For students", Some("4"))
+            .await
+            .unwrap());
+        // neither the id nor the name of an existing division can be taken again
+        assert!(!database
+            .create_division("students", "Other", "Other", None)
+            .await
+            .unwrap());
+        assert!(!database
+            .create_division("other", "Students", "Other", None)
+            .await
+            .unwrap());
 
-    Ok(team_id)
-}
+        database
+            .set_division_archived("students", true)
+            .await
+            .unwrap();
+        let divisions = database.get_divisions().await.unwrap();
+        assert_eq!(divisions.len(), 2);
+        let students = divisions.iter().find(|d| d.id == "students").unwrap();
+        assert_eq!(students.description, "For students");
+        assert_eq!(students.max_players.as_deref(), Some("4"));
+        assert!(students.archived);
+        assert!(students.admin_managed);
+
+        let (alice, alice_team) = database
+            .upsert_user_by_credentials("alice", "", "password", "http://localhost:3000")
+            .await
+            .unwrap()
+            .unwrap();
+        let (_, bob_team) = database
+            .upsert_user_by_credentials("bob", "", "password", "http://localhost:3000")
+            .await
+            .unwrap()
+            .unwrap();
 
-#[cfg(test)]
-mod test {
-    use std::net::IpAddr;
+        let now = Utc::now();
+        database
+            .set_team_division(alice_team, "open", "students", now, Some(alice))
+            .await
+            .unwrap();
+        database
+            .set_team_division(bob_team, "open", "students", now, None)
+            .await
+            .unwrap();
+        database
+            .set_team_division(
+                bob_team,
+                "students",
+                "open",
+                now + chrono::Duration::seconds(1),
+                None,
+            )
+            .await
+            .unwrap();
 
-    use crate::internal::database::{libsql::InMemoryLibSQL, provider::Database};
+        let team = database.get_team_from_id(alice_team).await.unwrap();
+        assert_eq!(team.division_id, "students");
+        assert_eq!(
+            team.last_division_change.unwrap().timestamp(),
+            now.timestamp()
+        );
 
-    #[tokio::test]
-    async fn migrate_libsql() {
-        let database = InMemoryLibSQL::new().await.unwrap();
-        database.migrate().await.unwrap();
+        let history = database.get_division_history("students").await.unwrap();
+        assert_eq!(
+            history
+                .iter()
+                .map(|change| (
+                    change.team_id,
+                    change.new_division_id.as_str(),
+                    change.admin_user_name.as_deref()
+                ))
+                .collect::<Vec<_>>(),
+            vec![
+                (bob_team, "open", None),
+                (alice_team, "students", Some("alice")),
+                (bob_team, "students", None),
+            ]
+        );
+        assert_eq!(
+            database
+                .get_division_teams("students")
+                .await
+                .unwrap()
+                .iter()
+                .map(|team| team.id)
+                .collect::<Vec<_>>(),
+            vec![alice_team]
+        );
     }
 
     #[tokio::test]
-    async fn track_load() {
-        let database = InMemoryLibSQL::new().await.unwrap();
-        database.migrate().await.unwrap();
+    async fn team_discord() {
+        let database = test_db().await;
 
-        let ip: IpAddr = "1.2.3.4".parse().unwrap();
-        for i in 0..1000 {
-            let user_agent = i.to_string();
-            database
-                .insert_track(ip, Some(user_agent.as_str()), None, 1)
+        let mut teams = vec![];
+        for name in ["alice", "bob"] {
+            let (_, team_id) = database
+                .upsert_user_by_credentials(name, "", "password", "http://localhost:3000")
                 .await
+                .unwrap()
                 .unwrap();
+            teams.push(team_id);
         }
 
-        let num_tracks = database
-            .conn
-            .query("SELECT COUNT(*) FROM rhombus_track", ())
+        assert_eq!(
+            database.get_team_discord(teams[0]).await.unwrap(),
+            TeamDiscord::default()
+        );
+        assert!(database.get_team_discords().await.unwrap().is_empty());
+
+        let team_discord = TeamDiscord {
+            role_id: NonZeroU64::new(1234567890123456789),
+            text_channel_id: NonZeroU64::new(2),
+            voice_channel_id: None,
+        };
+        database
+            .set_team_discord(teams[0], &team_discord)
             .await
-            .unwrap()
-            .next()
+            .unwrap();
+        assert_eq!(
+            database.get_team_discord(teams[0]).await.unwrap(),
+            team_discord
+        );
+        assert_eq!(
+            database.get_team_discord(teams[1]).await.unwrap(),
+            TeamDiscord::default()
+        );
+        assert_eq!(
+            database.get_team_discords().await.unwrap(),
+            vec![(teams[0], team_discord)]
+        );
+
+        // clearing the role and channels forgets the team
+        database
+            .set_team_discord(teams[0], &TeamDiscord::default())
             .await
-            .unwrap()
-            .unwrap()
-            .get::<i64>(0)
             .unwrap();
-
-        assert_eq!(32, num_tracks);
+        assert!(database.get_team_discords().await.unwrap().is_empty());
+        assert!(database.get_team_discord(-1).await.is_err());
     }
 }
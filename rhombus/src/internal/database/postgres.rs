@@ -10,14 +10,20 @@ use sqlx::{FromRow, PgPool};
 use tokio_util::bytes::Bytes;
 
 use crate::{
+    errors::RhombusError,
     internal::{
         auth::User,
+        backup::BackupTable,
         database::{
             cache::Writeups,
             provider::{
-                Challenge, Challenges, Database, DiscordUpsertError, Email, Leaderboard,
-                Scoreboard, SetAccountNameError, SetTeamNameError, SiteStatistics, Team, TeamMeta,
-                TeamStanding, Ticket, ToBeClosedTicket, UserTrack,
+                Challenge, Challenges, Database, DiscordUpsertError, DivisionApproval, Email,
+                IdentityUpsertError, Leaderboard, PasskeyCredential, PasswordReset, RegisterError,
+                Scoreboard, SetAccountNameError, SetPasswordError, SetTeamNameError, SignInMethod,
+                SiteStatistics, StoredDivision, Team, TeamDiscord, TeamDivisionChange, TeamInvite,
+                TeamJoinRequest, TeamMeta, TeamStanding, Ticket, TicketMessage,
+                TicketMessageAuthor, TicketStats, TicketStatus, ToBeClosedTicket, UserIdentity,
+                UserPasskey, UserSession, UserSignInMethods, UserTotp, UserTrack,
             },
         },
         division::Division,
@@ -97,6 +103,29 @@ impl Database for Postgres {
         todo!()
     }
 
+    async fn login_user_by_credentials(
+        &self,
+        _username: &str,
+        _password: &str,
+    ) -> Result<Option<(i64, i64)>> {
+        Err(RhombusError::UnsupportedDatabaseOperation(
+            "login_user_by_credentials",
+        ))
+    }
+
+    async fn register_user_by_credentials(
+        &self,
+        _username: &str,
+        _avatar: &str,
+        _password: &str,
+        _email: Option<&str>,
+        _location_url: &str,
+    ) -> Result<std::result::Result<(i64, i64, Option<String>), RegisterError>> {
+        Err(RhombusError::UnsupportedDatabaseOperation(
+            "register_user_by_credentials",
+        ))
+    }
+
     async fn upsert_user_by_ctftime(
         &self,
         _name: &str,
@@ -110,6 +139,228 @@ impl Database for Postgres {
         todo!()
     }
 
+    async fn upsert_user_by_oidc(
+        &self,
+        _name: &str,
+        _email: Option<&str>,
+        _avatar: &str,
+        _subject: &str,
+        _user_id: Option<i64>,
+        _location_url: &str,
+    ) -> Result<std::result::Result<(i64, i64), IdentityUpsertError>> {
+        Err(RhombusError::UnsupportedDatabaseOperation(
+            "upsert_user_by_oidc",
+        ))
+    }
+
+    async fn upsert_user_by_github(
+        &self,
+        _name: &str,
+        _email: Option<&str>,
+        _avatar: &str,
+        _github_id: i64,
+        _user_id: Option<i64>,
+        _location_url: &str,
+    ) -> Result<std::result::Result<(i64, i64), IdentityUpsertError>> {
+        Err(RhombusError::UnsupportedDatabaseOperation(
+            "upsert_user_by_github",
+        ))
+    }
+
+    async fn upsert_user_by_google(
+        &self,
+        _name: &str,
+        _email: Option<&str>,
+        _avatar: &str,
+        _google_id: &str,
+        _user_id: Option<i64>,
+        _location_url: &str,
+    ) -> Result<std::result::Result<(i64, i64), IdentityUpsertError>> {
+        Err(RhombusError::UnsupportedDatabaseOperation(
+            "upsert_user_by_google",
+        ))
+    }
+
+    async fn upsert_user_by_ldap(
+        &self,
+        _name: &str,
+        _email: Option<&str>,
+        _avatar: &str,
+        _dn: &str,
+        _location_url: &str,
+    ) -> Result<std::result::Result<(i64, i64), IdentityUpsertError>> {
+        Err(RhombusError::UnsupportedDatabaseOperation(
+            "upsert_user_by_ldap",
+        ))
+    }
+
+    async fn get_user_identities(&self, _user_id: i64) -> Result<Vec<UserIdentity>> {
+        Err(RhombusError::UnsupportedDatabaseOperation(
+            "get_user_identities",
+        ))
+    }
+
+    async fn get_user_sign_in_methods(&self, _user_id: i64) -> Result<UserSignInMethods> {
+        Err(RhombusError::UnsupportedDatabaseOperation(
+            "get_user_sign_in_methods",
+        ))
+    }
+
+    async fn link_user_ctftime(
+        &self,
+        _user_id: i64,
+        _ctftime_user_id: i64,
+    ) -> Result<std::result::Result<(), IdentityUpsertError>> {
+        Err(RhombusError::UnsupportedDatabaseOperation(
+            "link_user_ctftime",
+        ))
+    }
+
+    async fn set_user_password(
+        &self,
+        _user_id: i64,
+        _current_password: Option<&str>,
+        _password: &str,
+    ) -> Result<std::result::Result<(), SetPasswordError>> {
+        Err(RhombusError::UnsupportedDatabaseOperation(
+            "set_user_password",
+        ))
+    }
+
+    async fn create_password_reset_code(&self, _email: &str) -> Result<Option<PasswordReset>> {
+        Err(RhombusError::UnsupportedDatabaseOperation(
+            "create_password_reset_code",
+        ))
+    }
+
+    async fn get_password_reset_by_callback_code(&self, _code: &str) -> Result<String> {
+        Err(RhombusError::UnsupportedDatabaseOperation(
+            "get_password_reset_by_callback_code",
+        ))
+    }
+
+    async fn reset_password_by_callback_code(&self, _code: &str, _password: &str) -> Result<i64> {
+        Err(RhombusError::UnsupportedDatabaseOperation(
+            "reset_password_by_callback_code",
+        ))
+    }
+
+    async fn unlink_sign_in_method(&self, _user_id: i64, _method: SignInMethod<'_>) -> Result<()> {
+        Err(RhombusError::UnsupportedDatabaseOperation(
+            "unlink_sign_in_method",
+        ))
+    }
+
+    async fn get_user_totp(&self, _user_id: i64) -> Result<Option<UserTotp>> {
+        Err(RhombusError::UnsupportedDatabaseOperation("get_user_totp"))
+    }
+
+    async fn set_user_totp_pending(&self, _user_id: i64, _secret: &str) -> Result<()> {
+        Err(RhombusError::UnsupportedDatabaseOperation(
+            "set_user_totp_pending",
+        ))
+    }
+
+    async fn enable_user_totp(
+        &self,
+        _user_id: i64,
+        _recovery_code_hashes: &[String],
+    ) -> Result<()> {
+        Err(RhombusError::UnsupportedDatabaseOperation(
+            "enable_user_totp",
+        ))
+    }
+
+    async fn disable_user_totp(&self, _user_id: i64) -> Result<()> {
+        Err(RhombusError::UnsupportedDatabaseOperation(
+            "disable_user_totp",
+        ))
+    }
+
+    async fn use_user_totp_step(&self, _user_id: i64, _step: i64) -> Result<bool> {
+        Err(RhombusError::UnsupportedDatabaseOperation(
+            "use_user_totp_step",
+        ))
+    }
+
+    async fn use_user_recovery_code(&self, _user_id: i64, _code_hash: &str) -> Result<bool> {
+        Err(RhombusError::UnsupportedDatabaseOperation(
+            "use_user_recovery_code",
+        ))
+    }
+
+    async fn add_user_passkey(
+        &self,
+        _user_id: i64,
+        _id: &str,
+        _name: &str,
+        _public_key: &[u8],
+        _algorithm: i64,
+        _sign_count: i64,
+    ) -> Result<()> {
+        Err(RhombusError::UnsupportedDatabaseOperation(
+            "add_user_passkey",
+        ))
+    }
+
+    async fn get_user_passkeys(&self, _user_id: i64) -> Result<Vec<UserPasskey>> {
+        Err(RhombusError::UnsupportedDatabaseOperation(
+            "get_user_passkeys",
+        ))
+    }
+
+    async fn get_passkey_credential(&self, _id: &str) -> Result<Option<PasskeyCredential>> {
+        Err(RhombusError::UnsupportedDatabaseOperation(
+            "get_passkey_credential",
+        ))
+    }
+
+    async fn use_passkey(&self, _id: &str, _sign_count: i64) -> Result<bool> {
+        Err(RhombusError::UnsupportedDatabaseOperation("use_passkey"))
+    }
+
+    async fn delete_user_passkey(&self, _user_id: i64, _id: &str) -> Result<()> {
+        Err(RhombusError::UnsupportedDatabaseOperation(
+            "delete_user_passkey",
+        ))
+    }
+
+    async fn create_session(&self, _id: &str, _user_id: i64, _expires: i64) -> Result<()> {
+        Err(RhombusError::UnsupportedDatabaseOperation("create_session"))
+    }
+
+    async fn get_session_user_id(&self, _id: &str) -> Result<Option<i64>> {
+        Err(RhombusError::UnsupportedDatabaseOperation(
+            "get_session_user_id",
+        ))
+    }
+
+    async fn get_user_sessions(&self, _user_id: i64) -> Result<Vec<UserSession>> {
+        Err(RhombusError::UnsupportedDatabaseOperation(
+            "get_user_sessions",
+        ))
+    }
+
+    async fn touch_session(
+        &self,
+        _id: &str,
+        _ip: Option<IpAddr>,
+        _user_agent: Option<&str>,
+        _last_seen_at: i64,
+    ) -> Result<()> {
+        Err(RhombusError::UnsupportedDatabaseOperation("touch_session"))
+    }
+
+    async fn revoke_session(&self, _user_id: i64, _id: &str) -> Result<()> {
+        Err(RhombusError::UnsupportedDatabaseOperation("revoke_session"))
+    }
+
+    async fn revoke_user_sessions(&self, _user_id: i64, _except: Option<&str>) -> Result<()> {
+        Err(RhombusError::UnsupportedDatabaseOperation(
+            "revoke_user_sessions",
+        ))
+    }
+
     async fn insert_track(
         &self,
         ip: IpAddr,
@@ -191,6 +442,10 @@ impl Database for Postgres {
         todo!()
     }
 
+    async fn set_user_admin(&self, _user_id: i64, _is_admin: bool) -> Result<()> {
+        Err(RhombusError::UnsupportedDatabaseOperation("set_user_admin"))
+    }
+
     async fn get_user_from_discord_id(&self, _discord_id: NonZeroU64) -> Result<User> {
         todo!()
     }
@@ -199,14 +454,100 @@ impl Database for Postgres {
         todo!()
     }
 
+    async fn transfer_team_ownership(
+        &self,
+        _team_id: i64,
+        _old_owner_user_id: i64,
+        _new_owner_user_id: i64,
+    ) -> Result<i64> {
+        Err(RhombusError::UnsupportedDatabaseOperation(
+            "transfer_team_ownership",
+        ))
+    }
+
+    async fn disband_team(&self, _team_id: i64) -> Result<Vec<(i64, i64)>> {
+        Err(RhombusError::UnsupportedDatabaseOperation("disband_team"))
+    }
+
     async fn kick_user(&self, _user_id: i64, _team_id: i64) -> Result<i64> {
         todo!()
     }
 
+    async fn get_team_discord(&self, _team_id: i64) -> Result<TeamDiscord> {
+        Err(RhombusError::UnsupportedDatabaseOperation(
+            "get_team_discord",
+        ))
+    }
+
+    async fn set_team_discord(&self, _team_id: i64, _team_discord: &TeamDiscord) -> Result<()> {
+        Err(RhombusError::UnsupportedDatabaseOperation(
+            "set_team_discord",
+        ))
+    }
+
+    async fn get_team_discords(&self) -> Result<Vec<(i64, TeamDiscord)>> {
+        Err(RhombusError::UnsupportedDatabaseOperation(
+            "get_team_discords",
+        ))
+    }
+
     async fn roll_invite_token(&self, _team_id: i64) -> Result<String> {
         todo!()
     }
 
+    async fn create_team_invite(
+        &self,
+        _team_id: i64,
+        _user_id: Option<i64>,
+        _email: Option<&str>,
+    ) -> Result<String> {
+        Err(RhombusError::UnsupportedDatabaseOperation(
+            "create_team_invite",
+        ))
+    }
+
+    async fn get_team_invite(&self, _code: &str) -> Result<Option<TeamInvite>> {
+        Err(RhombusError::UnsupportedDatabaseOperation(
+            "get_team_invite",
+        ))
+    }
+
+    async fn get_team_invites(&self, _team_id: i64) -> Result<Vec<TeamInvite>> {
+        Err(RhombusError::UnsupportedDatabaseOperation(
+            "get_team_invites",
+        ))
+    }
+
+    async fn get_user_team_invites(&self, _user_id: i64) -> Result<Vec<TeamInvite>> {
+        Err(RhombusError::UnsupportedDatabaseOperation(
+            "get_user_team_invites",
+        ))
+    }
+
+    async fn delete_team_invite(&self, _code: &str) -> Result<()> {
+        Err(RhombusError::UnsupportedDatabaseOperation(
+            "delete_team_invite",
+        ))
+    }
+
+    async fn create_team_join_request(&self, _team_id: i64, _user_id: i64) -> Result<()> {
+        Err(RhombusError::UnsupportedDatabaseOperation(
+            "create_team_join_request",
+        ))
+    }
+
+    async fn get_team_join_requests(&self, _team_id: i64) -> Result<Vec<TeamJoinRequest>> {
+        Err(RhombusError::UnsupportedDatabaseOperation(
+            "get_team_join_requests",
+        ))
+    }
+
+    async fn delete_team_join_request(&self, _team_id: i64, _user_id: i64) -> Result<bool> {
+        Err(RhombusError::UnsupportedDatabaseOperation(
+            "delete_team_join_request",
+        ))
+    }
+
     async fn roll_api_key(&self, _user_id: i64, _location_url: &str) -> Result<String> {
         todo!()
     }
@@ -269,12 +610,98 @@ impl Database for Postgres {
         _ticket_number: u64,
         _user_id: i64,
         _challenge_id: &str,
-        _discord_channel_id: NonZeroU64,
-        _panel_discord_message_id: NonZeroU64,
+        _author: &TicketMessageAuthor<'_>,
+        _content: &str,
+        _assignee_discord_id: Option<NonZeroU64>,
     ) -> Result<()> {
         todo!()
     }
 
+    async fn set_ticket_discord(
+        &self,
+        _ticket_number: u64,
+        _discord_channel_id: NonZeroU64,
+        _discord_panel_message_id: NonZeroU64,
+    ) -> Result<()> {
+        Err(RhombusError::UnsupportedDatabaseOperation(
+            "set_ticket_discord",
+        ))
+    }
+
+    async fn add_ticket_message(
+        &self,
+        _ticket_number: u64,
+        _author: &TicketMessageAuthor<'_>,
+        _content: &str,
+        _discord_message_id: Option<NonZeroU64>,
+        _is_staff: bool,
+    ) -> Result<()> {
+        Err(RhombusError::UnsupportedDatabaseOperation(
+            "add_ticket_message",
+        ))
+    }
+
+    async fn edit_ticket_message_by_discord_id(
+        &self,
+        _discord_message_id: NonZeroU64,
+        _content: &str,
+        _edited_at: DateTime<Utc>,
+    ) -> Result<Option<u64>> {
+        Err(RhombusError::UnsupportedDatabaseOperation(
+            "edit_ticket_message_by_discord_id",
+        ))
+    }
+
+    async fn get_ticket_messages(&self, _ticket_number: u64) -> Result<Vec<TicketMessage>> {
+        Err(RhombusError::UnsupportedDatabaseOperation(
+            "get_ticket_messages",
+        ))
+    }
+
+    async fn get_user_tickets(&self, _user_id: i64) -> Result<Vec<Ticket>> {
+        Err(RhombusError::UnsupportedDatabaseOperation(
+            "get_user_tickets",
+        ))
+    }
+
+    async fn get_tickets(&self, _include_closed: bool) -> Result<Vec<Ticket>> {
+        Err(RhombusError::UnsupportedDatabaseOperation("get_tickets"))
+    }
+
+    async fn get_challenge_tickets(&self, _challenge_id: &str) -> Result<Vec<Ticket>> {
+        Err(RhombusError::UnsupportedDatabaseOperation(
+            "get_challenge_tickets",
+        ))
+    }
+
+    async fn set_ticket_status(&self, _ticket_number: u64, _status: TicketStatus) -> Result<()> {
+        Err(RhombusError::UnsupportedDatabaseOperation(
+            "set_ticket_status",
+        ))
+    }
+
+    async fn set_ticket_assignee(
+        &self,
+        _ticket_number: u64,
+        _assignee_discord_id: Option<NonZeroU64>,
+    ) -> Result<()> {
+        Err(RhombusError::UnsupportedDatabaseOperation(
+            "set_ticket_assignee",
+        ))
+    }
+
+    async fn set_ticket_tags(&self, _ticket_number: u64, _tags: &[String]) -> Result<()> {
+        Err(RhombusError::UnsupportedDatabaseOperation(
+            "set_ticket_tags",
+        ))
+    }
+
+    async fn get_ticket_stats(&self) -> Result<TicketStats> {
+        Err(RhombusError::UnsupportedDatabaseOperation(
+            "get_ticket_stats",
+        ))
+    }
+
     async fn get_ticket_by_ticket_number(&self, _ticket_number: u64) -> Result<Ticket> {
         todo!()
     }
@@ -359,7 +786,7 @@ impl Database for Postgres {
         todo!()
     }
 
-    async fn verify_email_verification_callback_code(&self, _code: &str) -> Result<()> {
+    async fn verify_email_verification_callback_code(&self, _code: &str) -> Result<i64> {
         todo!()
     }
 
@@ -389,6 +816,7 @@ impl Database for Postgres {
         _old_division_id: &str,
         _new_division_id: &str,
         _now: DateTime<Utc>,
+        _admin_user_id: Option<i64>,
     ) -> Result<()> {
         todo!()
     }
@@ -397,6 +825,88 @@ impl Database for Postgres {
         todo!()
     }
 
+    async fn get_divisions(&self) -> Result<Vec<StoredDivision>> {
+        Err(RhombusError::UnsupportedDatabaseOperation("get_divisions"))
+    }
+
+    async fn create_division(
+        &self,
+        _division_id: &str,
+        _name: &str,
+        _description: &str,
+        _max_players: Option<&str>,
+    ) -> Result<bool> {
+        Err(RhombusError::UnsupportedDatabaseOperation(
+            "create_division",
+        ))
+    }
+
+    async fn update_division(
+        &self,
+        _division_id: &str,
+        _name: &str,
+        _description: &str,
+        _max_players: Option<&str>,
+    ) -> Result<()> {
+        Err(RhombusError::UnsupportedDatabaseOperation(
+            "update_division",
+        ))
+    }
+
+    async fn set_division_archived(&self, _division_id: &str, _archived: bool) -> Result<()> {
+        Err(RhombusError::UnsupportedDatabaseOperation(
+            "set_division_archived",
+        ))
+    }
+
+    async fn get_division_teams(&self, _division_id: &str) -> Result<Vec<TeamMeta>> {
+        Err(RhombusError::UnsupportedDatabaseOperation(
+            "get_division_teams",
+        ))
+    }
+
+    async fn get_division_history(&self, _division_id: &str) -> Result<Vec<TeamDivisionChange>> {
+        Err(RhombusError::UnsupportedDatabaseOperation(
+            "get_division_history",
+        ))
+    }
+
+    async fn request_division_approval(&self, _division_id: &str, _user_id: i64) -> Result<()> {
+        Err(RhombusError::UnsupportedDatabaseOperation(
+            "request_division_approval",
+        ))
+    }
+
+    async fn get_division_approval(
+        &self,
+        _division_id: &str,
+        _user_id: i64,
+    ) -> Result<Option<bool>> {
+        Err(RhombusError::UnsupportedDatabaseOperation(
+            "get_division_approval",
+        ))
+    }
+
+    async fn get_division_approvals(
+        &self,
+        _include_decided: bool,
+    ) -> Result<Vec<DivisionApproval>> {
+        Err(RhombusError::UnsupportedDatabaseOperation(
+            "get_division_approvals",
+        ))
+    }
+
+    async fn set_division_approval(
+        &self,
+        _division_id: &str,
+        _user_id: i64,
+        _approved: bool,
+    ) -> Result<()> {
+        Err(RhombusError::UnsupportedDatabaseOperation(
+            "set_division_approval",
+        ))
+    }
+
     async fn get_team_standing(&self, _team_id: i64) -> Result<Option<TeamStanding>> {
         todo!()
     }
@@ -405,8 +915,22 @@ impl Database for Postgres {
         todo!()
     }
 
-    async fn download_file(&self, _hash: &str) -> Result<(Bytes, String)> {
-        todo!()
+    async fn get_file_info(&self, _hash: &str) -> Result<(String, u64)> {
+        Err(RhombusError::UnsupportedDatabaseOperation("get_file_info"))
+    }
+
+    async fn download_file_chunk(&self, _hash: &str, _offset: u64, _length: u64) -> Result<Bytes> {
+        Err(RhombusError::UnsupportedDatabaseOperation(
+            "download_file_chunk",
+        ))
+    }
+
+    async fn list_files(&self) -> Result<Vec<(String, String)>> {
+        Err(RhombusError::UnsupportedDatabaseOperation("list_files"))
+    }
+
+    async fn delete_file(&self, _hash: &str) -> Result<()> {
+        Err(RhombusError::UnsupportedDatabaseOperation("delete_file"))
     }
 
     async fn get_site_statistics(&self) -> Result<SiteStatistics> {
@@ -416,6 +940,36 @@ impl Database for Postgres {
     async fn get_last_created_ticket_time(&self, _user_id: i64) -> Result<Option<DateTime<Utc>>> {
         todo!()
     }
+
+    async fn get_uploaded_attachment_urls(&self) -> Result<Vec<String>> {
+        Err(RhombusError::UnsupportedDatabaseOperation(
+            "get_uploaded_attachment_urls",
+        ))
+    }
+
+    async fn rewrite_attachment_urls(&self, _urls: &BTreeMap<String, String>) -> Result<()> {
+        Err(RhombusError::UnsupportedDatabaseOperation(
+            "rewrite_attachment_urls",
+        ))
+    }
+
+    async fn export_backup_tables(&self) -> Result<BTreeMap<String, BackupTable>> {
+        Err(RhombusError::UnsupportedDatabaseOperation(
+            "export_backup_tables",
+        ))
+    }
+
+    async fn check_backup_tables(&self, _tables: &BTreeMap<String, BackupTable>) -> Result<()> {
+        Err(RhombusError::UnsupportedDatabaseOperation(
+            "check_backup_tables",
+        ))
+    }
+
+    async fn import_backup_tables(&self, _tables: &BTreeMap<String, BackupTable>) -> Result<()> {
+        Err(RhombusError::UnsupportedDatabaseOperation(
+            "import_backup_tables",
+        ))
+    }
 }
 
 #[cfg(test)]
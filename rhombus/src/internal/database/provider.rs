@@ -7,12 +7,15 @@ use std::{
 
 use async_trait::async_trait;
 use chrono::{DateTime, Utc};
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use tokio_util::bytes::Bytes;
 
 use crate::{
-    internal::{auth::User, database::cache::Writeups, division::Division, settings::Settings},
+    internal::{
+        auth::User, backup::BackupTable, database::cache::Writeups, division::Division,
+        settings::Settings,
+    },
     Result,
 };
 
@@ -107,6 +110,7 @@ pub struct TeamInner {
     pub owner_user_id: i64,
     pub division_id: String,
     pub last_division_change: Option<DateTime<Utc>>,
+    pub ctftime_id: Option<i64>,
 }
 
 pub type Team = Arc<TeamInner>;
@@ -173,13 +177,80 @@ pub struct Ticket {
     pub ticket_number: u64,
     pub user_id: i64,
     pub challenge_id: String,
+    pub opened_at: DateTime<Utc>,
     pub closed_at: Option<DateTime<Utc>>,
-    pub discord_channel_id: NonZeroU64,
-    pub discord_panel_message_id: NonZeroU64,
+    pub status: TicketStatus,
+    pub assignee_discord_id: Option<NonZeroU64>,
+    pub first_response_at: Option<DateTime<Utc>>,
+    pub tags: Vec<String>,
+    pub discord_channel_id: Option<NonZeroU64>,
+    pub discord_panel_message_id: Option<NonZeroU64>,
     pub email_references: Vec<String>,
     pub email_in_reply_to: Option<String>,
 }
 
+/// Where a ticket is at. Replies move open tickets to waiting on the participant and back, while
+/// escalated tickets stay escalated until staff change it.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum TicketStatus {
+    Open,
+    Waiting,
+    Escalated,
+    Closed,
+}
+
+impl TicketStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TicketStatus::Open => "open",
+            TicketStatus::Waiting => "waiting",
+            TicketStatus::Escalated => "escalated",
+            TicketStatus::Closed => "closed",
+        }
+    }
+
+    pub fn parse(status: &str) -> Option<Self> {
+        match status {
+            "open" => Some(TicketStatus::Open),
+            "waiting" => Some(TicketStatus::Waiting),
+            "escalated" => Some(TicketStatus::Escalated),
+            "closed" => Some(TicketStatus::Closed),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Clone, Default)]
+pub struct TicketStats {
+    pub open: u64,
+    pub waiting: u64,
+    pub escalated: u64,
+    pub closed: u64,
+    pub average_first_response_seconds: Option<i64>,
+    pub average_resolution_seconds: Option<i64>,
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct TicketMessage {
+    pub id: i64,
+    pub user_id: Option<i64>,
+    pub author_name: String,
+    pub author_avatar: String,
+    pub content: String,
+    pub discord_message_id: Option<NonZeroU64>,
+    pub sent_at: DateTime<Utc>,
+    pub edited_at: Option<DateTime<Utc>>,
+}
+
+/// Who sent a ticket message. Messages sent from Discord by someone without an account on the
+/// site have no `user_id`
+pub struct TicketMessageAuthor<'a> {
+    pub user_id: Option<i64>,
+    pub name: &'a str,
+    pub avatar: &'a str,
+}
+
 #[derive(Debug, Serialize, Clone, PartialEq, PartialOrd, Eq, Ord)]
 pub struct StatisticsCategory {
     pub num: u64,
@@ -216,10 +287,161 @@ pub enum DiscordUpsertError {
     AlreadyInUse,
 }
 
+pub enum IdentityUpsertError {
+    /// The identity is already linked to a different user
+    AlreadyInUse,
+}
+
+/// An account on an external identity provider which can sign in as a user
+#[derive(Debug, Serialize, Clone)]
+pub struct UserIdentity {
+    pub provider: String,
+    pub subject: String,
+}
+
+/// Which of the built in sign in methods are linked to a user
+#[derive(Debug, Serialize, Clone)]
+pub struct UserSignInMethods {
+    pub discord: bool,
+    pub ctftime: bool,
+    pub password: bool,
+    pub passkey: bool,
+}
+
+/// A sign in method which can be unlinked from a user. Emails are unlinked with
+/// [`Database::delete_email`].
+pub enum SignInMethod<'a> {
+    Discord,
+    CTFtime,
+    Password,
+    Identity { provider: &'a str },
+}
+
+/// A TOTP secret of a user, which is pending until enabled
+#[derive(Debug, Serialize, Clone)]
+pub struct UserTotp {
+    pub secret: String,
+    pub enabled: bool,
+    pub recovery_codes: i64,
+}
+
+/// A WebAuthn credential of a user, as listed on the account page
+#[derive(Debug, Serialize, Clone)]
+pub struct UserPasskey {
+    pub id: String,
+    pub name: String,
+    pub created_at: DateTime<Utc>,
+    pub last_used_at: Option<DateTime<Utc>>,
+}
+
+/// The stored public key of a WebAuthn credential, used to verify sign in assertions
+#[derive(Debug, Clone)]
+pub struct PasskeyCredential {
+    pub user_id: i64,
+    pub public_key: Vec<u8>,
+    pub algorithm: i64,
+    pub sign_count: i64,
+}
+
+/// A signed in session of a user
+#[derive(Debug, Serialize, Clone)]
+pub struct UserSession {
+    pub id: String,
+    pub ip: Option<IpAddr>,
+    pub user_agent: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub last_seen_at: DateTime<Utc>,
+}
+
+/// An invitation for a user or email address to join a team
+#[derive(Debug, Serialize, Clone)]
+pub struct TeamInvite {
+    pub code: String,
+    pub team_id: i64,
+    pub team_name: String,
+    pub user_id: Option<i64>,
+    pub user_name: Option<String>,
+    pub email: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// A request from a user to join a team
+#[derive(Debug, Serialize, Clone)]
+pub struct TeamJoinRequest {
+    pub user_id: i64,
+    pub user_name: String,
+    pub avatar_url: String,
+    pub created_at: DateTime<Utc>,
+}
+
+/// The Discord role and private channels created for a team
+#[derive(Debug, Serialize, Clone, Default, PartialEq, Eq)]
+pub struct TeamDiscord {
+    pub role_id: Option<NonZeroU64>,
+    pub text_channel_id: Option<NonZeroU64>,
+    pub voice_channel_id: Option<NonZeroU64>,
+}
+
+/// A division as stored in the database
+#[derive(Debug, Serialize, Clone)]
+pub struct StoredDivision {
+    pub id: String,
+    pub name: String,
+    pub description: String,
+    pub max_players: Option<String>,
+    pub archived: bool,
+    /// Created by an admin at runtime instead of in the config
+    pub admin_managed: bool,
+}
+
+/// A change of the division a team is in
+#[derive(Debug, Serialize, Clone)]
+pub struct TeamDivisionChange {
+    pub team_id: i64,
+    pub team_name: String,
+    pub old_division_id: String,
+    pub new_division_id: String,
+    /// The admin who moved the team regardless of eligibility
+    pub admin_user_id: Option<i64>,
+    pub admin_user_name: Option<String>,
+    pub at: DateTime<Utc>,
+}
+
+/// A user's request to be approved for a division which requires manual approval
+#[derive(Debug, Serialize, Clone)]
+pub struct DivisionApproval {
+    pub division_id: String,
+    pub user_id: i64,
+    pub user_name: String,
+    /// None while the request is pending
+    pub approved: Option<bool>,
+    pub requested_at: DateTime<Utc>,
+}
+
+pub enum RegisterError {
+    /// Another user already has this name
+    NameTaken,
+    /// The email is already added to another user
+    EmailInUse,
+}
+
+pub enum SetPasswordError {
+    /// Another user with the same name already signs in with a password
+    NameInUse,
+    /// The user already has a password, and the current password did not match it
+    IncorrectPassword,
+}
+
+/// A password reset code for the user with a verified email
+pub struct PasswordReset {
+    pub user_name: String,
+    pub code: String,
+}
+
 pub struct ToBeClosedTicket {
     pub ticket_number: u64,
-    pub discord_channel_id: NonZeroU64,
-    pub discord_panel_message_id: NonZeroU64,
+    pub discord_channel_id: Option<NonZeroU64>,
+    pub discord_panel_message_id: Option<NonZeroU64>,
 }
 
 #[async_trait]
@@ -255,6 +477,21 @@ pub trait Database {
         password: &str,
         location_url: &str,
     ) -> Result<Option<(i64, i64)>>;
+    async fn login_user_by_credentials(
+        &self,
+        username: &str,
+        password: &str,
+    ) -> Result<Option<(i64, i64)>>;
+    /// Creates a user which signs in with a password, returning the verification code of
+    /// `email` if one is given
+    async fn register_user_by_credentials(
+        &self,
+        username: &str,
+        avatar: &str,
+        password: &str,
+        email: Option<&str>,
+        location_url: &str,
+    ) -> Result<std::result::Result<(i64, i64, Option<String>), RegisterError>>;
     #[allow(clippy::too_many_arguments)]
     async fn upsert_user_by_ctftime(
         &self,
@@ -266,6 +503,102 @@ pub trait Database {
         team_name: &str,
         location_url: &str,
     ) -> Result<(i64, i64, Option<String>)>;
+    /// Sign in with an OpenID Connect `subject`, or link it to `user_id`. The `email` is
+    /// trusted as verified, so an existing user with the same verified email is signed in
+    /// and linked.
+    async fn upsert_user_by_oidc(
+        &self,
+        name: &str,
+        email: Option<&str>,
+        avatar: &str,
+        subject: &str,
+        user_id: Option<i64>,
+        location_url: &str,
+    ) -> Result<std::result::Result<(i64, i64), IdentityUpsertError>>;
+    async fn upsert_user_by_github(
+        &self,
+        name: &str,
+        email: Option<&str>,
+        avatar: &str,
+        github_id: i64,
+        user_id: Option<i64>,
+        location_url: &str,
+    ) -> Result<std::result::Result<(i64, i64), IdentityUpsertError>>;
+    async fn upsert_user_by_google(
+        &self,
+        name: &str,
+        email: Option<&str>,
+        avatar: &str,
+        google_id: &str,
+        user_id: Option<i64>,
+        location_url: &str,
+    ) -> Result<std::result::Result<(i64, i64), IdentityUpsertError>>;
+    /// Sign in with the DN of an LDAP entry. The `email` is trusted as verified, like
+    /// [`Database::upsert_user_by_oidc`].
+    async fn upsert_user_by_ldap(
+        &self,
+        name: &str,
+        email: Option<&str>,
+        avatar: &str,
+        dn: &str,
+        location_url: &str,
+    ) -> Result<std::result::Result<(i64, i64), IdentityUpsertError>>;
+    async fn get_user_identities(&self, user_id: i64) -> Result<Vec<UserIdentity>>;
+    async fn get_user_sign_in_methods(&self, user_id: i64) -> Result<UserSignInMethods>;
+    async fn link_user_ctftime(
+        &self,
+        user_id: i64,
+        ctftime_user_id: i64,
+    ) -> Result<std::result::Result<(), IdentityUpsertError>>;
+    async fn set_user_password(
+        &self,
+        user_id: i64,
+        current_password: Option<&str>,
+        password: &str,
+    ) -> Result<std::result::Result<(), SetPasswordError>>;
+    async fn create_password_reset_code(&self, email: &str) -> Result<Option<PasswordReset>>;
+    async fn get_password_reset_by_callback_code(&self, code: &str) -> Result<String>;
+    async fn reset_password_by_callback_code(&self, code: &str, password: &str) -> Result<i64>;
+    async fn unlink_sign_in_method(&self, user_id: i64, method: SignInMethod<'_>) -> Result<()>;
+    async fn get_user_totp(&self, user_id: i64) -> Result<Option<UserTotp>>;
+    /// Stores a new secret to enroll with, unless two-factor authentication is already enabled
+    async fn set_user_totp_pending(&self, user_id: i64, secret: &str) -> Result<()>;
+    /// Enables two-factor authentication, replacing all recovery codes
+    async fn enable_user_totp(&self, user_id: i64, recovery_code_hashes: &[String]) -> Result<()>;
+    async fn disable_user_totp(&self, user_id: i64) -> Result<()>;
+    /// Marks a time step as used so a code cannot be replayed, returning whether it was unused
+    async fn use_user_totp_step(&self, user_id: i64, step: i64) -> Result<bool>;
+    async fn use_user_recovery_code(&self, user_id: i64, code_hash: &str) -> Result<bool>;
+    async fn add_user_passkey(
+        &self,
+        user_id: i64,
+        id: &str,
+        name: &str,
+        public_key: &[u8],
+        algorithm: i64,
+        sign_count: i64,
+    ) -> Result<()>;
+    async fn get_user_passkeys(&self, user_id: i64) -> Result<Vec<UserPasskey>>;
+    async fn get_passkey_credential(&self, id: &str) -> Result<Option<PasskeyCredential>>;
+    /// Records a sign in with a passkey, returning false if the signature counter went backwards,
+    /// which indicates a cloned authenticator
+    async fn use_passkey(&self, id: &str, sign_count: i64) -> Result<bool>;
+    async fn delete_user_passkey(&self, user_id: i64, id: &str) -> Result<()>;
+    async fn create_session(&self, id: &str, user_id: i64, expires: i64) -> Result<()>;
+    /// Returns the user of a session, unless it was revoked or has expired
+    async fn get_session_user_id(&self, id: &str) -> Result<Option<i64>>;
+    async fn get_user_sessions(&self, user_id: i64) -> Result<Vec<UserSession>>;
+    /// Records the latest request of a session
+    async fn touch_session(
+        &self,
+        id: &str,
+        ip: Option<IpAddr>,
+        user_agent: Option<&str>,
+        last_seen_at: i64,
+    ) -> Result<()>;
+    async fn revoke_session(&self, user_id: i64, id: &str) -> Result<()>;
+    /// Revokes all sessions of a user, except for the given one
+    async fn revoke_user_sessions(&self, user_id: i64, except: Option<&str>) -> Result<()>;
     async fn insert_track(
         &self,
         ip: IpAddr,
@@ -292,10 +625,41 @@ pub trait Database {
         now: DateTime<Utc>,
     ) -> Result<()>;
     async fn get_user_from_id(&self, user_id: i64) -> Result<User>;
+    async fn set_user_admin(&self, user_id: i64, is_admin: bool) -> Result<()>;
     async fn get_user_from_discord_id(&self, discord_id: NonZeroU64) -> Result<User>;
     async fn get_user_from_api_key(&self, api_key: &str) -> Result<User>;
     async fn kick_user(&self, user_id: i64, team_id: i64) -> Result<i64>;
+    async fn get_team_discord(&self, team_id: i64) -> Result<TeamDiscord>;
+    async fn set_team_discord(&self, team_id: i64, team_discord: &TeamDiscord) -> Result<()>;
+    /// Teams which have a Discord role or channels
+    async fn get_team_discords(&self) -> Result<Vec<(i64, TeamDiscord)>>;
+    /// Makes a member the owner of the team. The previous owner takes over the empty personal team
+    /// the new owner left behind, or gets a new one as on account creation, and its id is returned.
+    async fn transfer_team_ownership(
+        &self,
+        team_id: i64,
+        old_owner_user_id: i64,
+        new_owner_user_id: i64,
+    ) -> Result<i64>;
+    /// Returns every member except the owner to their personal team and rolls the invite token.
+    /// Returns the moved users along with their new team ids.
+    async fn disband_team(&self, team_id: i64) -> Result<Vec<(i64, i64)>>;
     async fn roll_invite_token(&self, team_id: i64) -> Result<String>;
+    /// Invites a user or an email address to a team, returning the code which accepts the invite
+    async fn create_team_invite(
+        &self,
+        team_id: i64,
+        user_id: Option<i64>,
+        email: Option<&str>,
+    ) -> Result<String>;
+    async fn get_team_invite(&self, code: &str) -> Result<Option<TeamInvite>>;
+    async fn get_team_invites(&self, team_id: i64) -> Result<Vec<TeamInvite>>;
+    async fn get_user_team_invites(&self, user_id: i64) -> Result<Vec<TeamInvite>>;
+    async fn delete_team_invite(&self, code: &str) -> Result<()>;
+    async fn create_team_join_request(&self, team_id: i64, user_id: i64) -> Result<()>;
+    async fn get_team_join_requests(&self, team_id: i64) -> Result<Vec<TeamJoinRequest>>;
+    /// Returns false if the user had not requested to join the team
+    async fn delete_team_join_request(&self, team_id: i64, user_id: i64) -> Result<bool>;
     async fn roll_api_key(&self, user_id: i64, location_url: &str) -> Result<String>;
     async fn set_team_name(
         &self,
@@ -325,9 +689,47 @@ pub trait Database {
         ticket_number: u64,
         user_id: i64,
         challenge_id: &str,
+        author: &TicketMessageAuthor<'_>,
+        content: &str,
+        assignee_discord_id: Option<NonZeroU64>,
+    ) -> Result<()>;
+    async fn set_ticket_discord(
+        &self,
+        ticket_number: u64,
         discord_channel_id: NonZeroU64,
-        panel_discord_message_id: NonZeroU64,
+        discord_panel_message_id: NonZeroU64,
     ) -> Result<()>;
+    /// Messages from staff record the first response and leave the ticket waiting on the
+    /// participant, while any other message reopens it
+    async fn add_ticket_message(
+        &self,
+        ticket_number: u64,
+        author: &TicketMessageAuthor<'_>,
+        content: &str,
+        discord_message_id: Option<NonZeroU64>,
+        is_staff: bool,
+    ) -> Result<()>;
+    /// Returns the number of the ticket the edited message belongs to, if it is known
+    async fn edit_ticket_message_by_discord_id(
+        &self,
+        discord_message_id: NonZeroU64,
+        content: &str,
+        edited_at: DateTime<Utc>,
+    ) -> Result<Option<u64>>;
+    async fn get_ticket_messages(&self, ticket_number: u64) -> Result<Vec<TicketMessage>>;
+    async fn get_user_tickets(&self, user_id: i64) -> Result<Vec<Ticket>>;
+    async fn get_tickets(&self, include_closed: bool) -> Result<Vec<Ticket>>;
+    /// Every ticket about a challenge, open or closed, oldest first
+    async fn get_challenge_tickets(&self, challenge_id: &str) -> Result<Vec<Ticket>>;
+    /// Sets the status of an open ticket. Use `close_ticket` and `reopen_ticket` for closing.
+    async fn set_ticket_status(&self, ticket_number: u64, status: TicketStatus) -> Result<()>;
+    async fn set_ticket_assignee(
+        &self,
+        ticket_number: u64,
+        assignee_discord_id: Option<NonZeroU64>,
+    ) -> Result<()>;
+    async fn set_ticket_tags(&self, ticket_number: u64, tags: &[String]) -> Result<()>;
+    async fn get_ticket_stats(&self) -> Result<TicketStats>;
     async fn get_ticket_by_ticket_number(&self, ticket_number: u64) -> Result<Ticket>;
     async fn get_ticket_by_discord_channel_id(
         &self,
@@ -365,7 +767,7 @@ pub trait Database {
         user_id: i64,
         email: &str,
     ) -> Result<String>;
-    async fn verify_email_verification_callback_code(&self, code: &str) -> Result<()>;
+    async fn verify_email_verification_callback_code(&self, code: &str) -> Result<i64>;
     async fn get_email_verification_by_callback_code(&self, code: &str) -> Result<String>;
     async fn create_email_signin_callback_code(&self, email: &str) -> Result<String>;
     async fn verify_email_signin_callback_code(&self, code: &str) -> Result<String>;
@@ -377,11 +779,52 @@ pub trait Database {
         old_division_id: &str,
         new_division_id: &str,
         now: DateTime<Utc>,
+        admin_user_id: Option<i64>,
     ) -> Result<()>;
     async fn insert_divisions(&self, divisions: &[Division]) -> Result<()>;
+    async fn get_divisions(&self) -> Result<Vec<StoredDivision>>;
+    /// Returns false if a division with this id or name already exists
+    async fn create_division(
+        &self,
+        division_id: &str,
+        name: &str,
+        description: &str,
+        max_players: Option<&str>,
+    ) -> Result<bool>;
+    async fn update_division(
+        &self,
+        division_id: &str,
+        name: &str,
+        description: &str,
+        max_players: Option<&str>,
+    ) -> Result<()>;
+    async fn set_division_archived(&self, division_id: &str, archived: bool) -> Result<()>;
+    async fn get_division_teams(&self, division_id: &str) -> Result<Vec<TeamMeta>>;
+    /// Teams which most recently moved into or out of the division
+    async fn get_division_history(&self, division_id: &str) -> Result<Vec<TeamDivisionChange>>;
+    /// Adds the user to the approval queue of the division, if they are not already in it
+    async fn request_division_approval(&self, division_id: &str, user_id: i64) -> Result<()>;
+    /// Returns None if the user has not been approved or denied yet
+    async fn get_division_approval(&self, division_id: &str, user_id: i64) -> Result<Option<bool>>;
+    async fn get_division_approvals(&self, include_decided: bool) -> Result<Vec<DivisionApproval>>;
+    async fn set_division_approval(
+        &self,
+        division_id: &str,
+        user_id: i64,
+        approved: bool,
+    ) -> Result<()>;
     async fn get_team_standing(&self, team_id: i64) -> Result<Option<TeamStanding>>;
     async fn upload_file(&self, hash: &str, filename: &str, bytes: &[u8]) -> Result<()>;
-    async fn download_file(&self, hash: &str) -> Result<(Bytes, String)>;
+    async fn get_file_info(&self, hash: &str) -> Result<(String, u64)>;
+    async fn download_file_chunk(&self, hash: &str, offset: u64, length: u64) -> Result<Bytes>;
+    async fn list_files(&self) -> Result<Vec<(String, String)>>;
+    async fn delete_file(&self, hash: &str) -> Result<()>;
     async fn get_site_statistics(&self) -> Result<SiteStatistics>;
     async fn get_last_created_ticket_time(&self, user_id: i64) -> Result<Option<DateTime<Utc>>>;
+    async fn get_uploaded_attachment_urls(&self) -> Result<Vec<String>>;
+    async fn rewrite_attachment_urls(&self, urls: &BTreeMap<String, String>) -> Result<()>;
+    async fn export_backup_tables(&self) -> Result<BTreeMap<String, BackupTable>>;
+    /// Check that `tables` could be imported, without changing anything
+    async fn check_backup_tables(&self, tables: &BTreeMap<String, BackupTable>) -> Result<()>;
+    async fn import_backup_tables(&self, tables: &BTreeMap<String, BackupTable>) -> Result<()>;
 }
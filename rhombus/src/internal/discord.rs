@@ -1,6 +1,5 @@
 use std::{
     borrow::Cow,
-    collections::{btree_map, BTreeMap},
     net::IpAddr,
     num::NonZeroU64,
     sync::{Arc, LazyLock},
@@ -12,31 +11,36 @@ use dashmap::DashMap;
 use futures::{future::join_all, StreamExt};
 use minijinja::context;
 use rand::{prelude::SliceRandom, thread_rng, Rng};
-use reqwest::header;
 use serde::{Deserialize, Serialize};
 use serde_json::json;
 use serenity::{
     all::{
         ButtonStyle, ChannelId, ChannelType, CreateActionRow, CreateAttachment, CreateButton,
-        CreateEmbed, CreateEmbedAuthor, CreateMessage, CreateThread, EditMessage, EditThread,
-        FullEvent, GatewayIntents, GetMessages, GuildId, Http, MessageFlags, MessageId, RoleId,
-        UserId,
+        CreateChannel, CreateEmbed, CreateEmbedAuthor, CreateMessage, CreateThread, EditChannel,
+        EditMessage, EditRole, EditThread, FullEvent, GatewayIntents, GuildId, Http, MessageFlags,
+        MessageId, PermissionOverwrite, PermissionOverwriteType, Permissions, RoleId, UserId,
     },
     Client,
 };
-use tokio::sync::{Mutex, RwLock};
+use tokio::sync::RwLock;
 
 use crate::{
     errors::RhombusError,
     internal::{
         auth::User,
         database::provider::{
-            Author, Challenge, ChallengeData, Connection, Team, Ticket, ToBeClosedTicket,
+            Author, Challenge, ChallengeData, Connection, Team, TeamDiscord, TeamInner, Ticket,
+            TicketMessageAuthor, ToBeClosedTicket,
         },
         division::Division,
         email::outbound_mailer::OutboundMailer,
         settings::Settings,
+        tickets::{
+            digest_ticket, is_ticket_staff, render_canned_responses, spawn_ticket_digest,
+            ticket_canned_responses,
+        },
     },
+    llm_provider::{LlmMessage, LlmProvider, LlmRole},
     Result,
 };
 
@@ -52,6 +56,7 @@ pub struct Data {
     outbound_mailer: Option<Arc<OutboundMailer>>,
     jinja: Arc<minijinja::Environment<'static>>,
     divisions: Arc<Vec<Division>>,
+    llm_provider: Option<Arc<dyn LlmProvider>>,
 }
 pub type DiscordError = Box<dyn std::error::Error + Send + Sync>;
 pub type Context<'a> = poise::Context<'a, Data, DiscordError>;
@@ -361,34 +366,6 @@ pub async fn verified(
     Ok(())
 }
 
-#[derive(Debug, Serialize)]
-struct ChatCompletionRequest {
-    model: String,
-    messages: Vec<ChatMessage>,
-    temperature: f32,
-}
-
-#[derive(Debug, Serialize)]
-struct ChatMessage {
-    role: String,
-    content: String,
-}
-
-#[derive(Debug, Deserialize)]
-struct ChatCompletionResponse {
-    choices: Vec<Choice>,
-}
-
-#[derive(Debug, Deserialize)]
-struct Choice {
-    message: ResponseMessage,
-}
-
-#[derive(Debug, Deserialize)]
-struct ResponseMessage {
-    content: String,
-}
-
 #[derive(Debug, Deserialize, Serialize)]
 struct AIMessage {
     content: String,
@@ -401,13 +378,14 @@ struct AIMessage {
 pub async fn ai(ctx: Context<'_>) -> std::result::Result<(), DiscordError> {
     ctx.defer_ephemeral().await.unwrap();
 
-    let (openai_api_key, author_discord_role_id) = {
-        let settings = ctx.data().settings.read().await;
-        (
-            settings.openai_api_key.clone(),
-            settings.discord.as_ref().and_then(|d| d.author_role_id),
-        )
-    };
+    let author_discord_role_id = ctx
+        .data()
+        .settings
+        .read()
+        .await
+        .discord
+        .as_ref()
+        .and_then(|d| d.author_role_id);
 
     let Some(author_discord_role_id) = author_discord_role_id else {
         ctx.reply("AI is not configured. Author role not set")
@@ -427,8 +405,8 @@ pub async fn ai(ctx: Context<'_>) -> std::result::Result<(), DiscordError> {
         return Ok(());
     }
 
-    let Some(openai_api_key) = openai_api_key else {
-        ctx.reply("OpenAI API key not set").await?;
+    let Some(llm_provider) = ctx.data().llm_provider.clone() else {
+        ctx.reply("AI is not configured").await?;
         return Ok(());
     };
 
@@ -494,6 +472,8 @@ pub async fn ai(ctx: Context<'_>) -> std::result::Result<(), DiscordError> {
             challenge,
             category,
             chats,
+            author_notes => challenge.metadata["private_context"]["author_notes"].as_str(),
+            solve_script => challenge.metadata["private_context"]["solve_script"].as_str(),
         })
         .unwrap();
 
@@ -508,51 +488,224 @@ pub async fn ai(ctx: Context<'_>) -> std::result::Result<(), DiscordError> {
         }
     }
 
-    let mut headers = header::HeaderMap::new();
-    headers.insert(
-        "Authorization",
-        header::HeaderValue::from_str(&format!("Bearer {}", openai_api_key))?,
-    );
-    headers.insert(
-        "Content-Type",
-        header::HeaderValue::from_static("application/json"),
-    );
-
-    let client = reqwest::Client::builder()
-        .default_headers(headers)
-        .build()?;
+    // the model never sees the flag, and anything it still manages to produce is withheld
+    let flag = &challenge.flag;
+    let prompt = redact_flag(&prompt, flag);
+    let last_message = redact_flag(&last_message, flag);
 
-    let request = ChatCompletionRequest {
-        model: "gpt-3.5-turbo".to_string(),
-        messages: vec![
-            ChatMessage {
-                role: "system".to_string(),
+    let reply = llm_provider
+        .complete(&[
+            LlmMessage {
+                role: LlmRole::System,
                 content: prompt,
             },
-            ChatMessage {
-                role: "user".to_string(),
+            LlmMessage {
+                role: LlmRole::User,
                 content: last_message,
             },
-        ],
-        temperature: 0.2,
-    };
+        ])
+        .await;
+
+    match reply {
+        Ok(reply) if contains_flag(&reply, flag) => {
+            tracing::warn!(
+                ticket_number = ticket.ticket_number,
+                "Withheld AI reply containing the flag"
+            );
+            ctx.reply("The suggested response contained the flag, so it was withheld")
+                .await?;
+        }
+        Ok(reply) => {
+            ctx.reply(reply).await?;
+        }
+        Err(error) => {
+            tracing::error!(?error, "Failed to get AI reply");
+            ctx.reply("Failed to get a suggested response").await?;
+        }
+    }
 
-    let response = client
-        .post("https://api.openai.com/v1/chat/completions")
-        .json(&request)
-        .send()
-        .await?;
+    Ok(())
+}
 
-    if response.status().is_success() {
-        let completion: ChatCompletionResponse = response.json().await?;
-        if let Some(choice) = completion.choices.first() {
-            ctx.reply(&choice.message.content).await?;
+/// The parts of a flag which should never be shown to a language model or be repeated by it: the
+/// whole flag, and what is inside its braces if that is long enough to be meaningful
+fn flag_secrets(flag: &str) -> Vec<&str> {
+    let flag = flag.trim();
+    let mut secrets = vec![];
+    if !flag.is_empty() {
+        secrets.push(flag);
+    }
+    if let (Some(start), Some(end)) = (flag.find('{'), flag.rfind('}')) {
+        if start < end && end - start - 1 > 4 {
+            secrets.push(&flag[start + 1..end]);
+        }
+    }
+    secrets
+}
+
+fn redact_flag(text: &str, flag: &str) -> String {
+    flag_secrets(flag)
+        .into_iter()
+        .fold(text.to_owned(), |text, secret| {
+            text.replace(secret, "[REDACTED]")
+        })
+}
+
+fn contains_flag(text: &str, flag: &str) -> bool {
+    let text = text.to_lowercase();
+    flag_secrets(flag)
+        .into_iter()
+        .any(|secret| text.contains(&secret.to_lowercase()))
+}
+
+/// Stores a message sent in a ticket thread, attributed to the sender's account on the site if
+/// they have linked one
+async fn store_discord_ticket_message(
+    db: &Connection,
+    ticket: &Ticket,
+    author: &serenity::all::User,
+    content: &str,
+    message_id: MessageId,
+) -> Result<()> {
+    let author_id = author.id;
+    let user = db.get_user_from_discord_id(author_id.into()).await.ok();
+    let face = author.face();
+    let author = if let Some(ref user) = user {
+        TicketMessageAuthor {
+            user_id: Some(user.id),
+            name: &user.name,
+            avatar: &user.avatar,
         }
     } else {
-        tracing::info!(response=?response);
-        ctx.reply(response.text().await?).await?;
+        TicketMessageAuthor {
+            user_id: None,
+            name: &author.name,
+            avatar: &face,
+        }
+    };
+
+    let challenge_data = db.get_challenges().await?;
+    let is_staff = is_ticket_staff(
+        &challenge_data,
+        ticket,
+        user.as_ref().is_some_and(|user| user.is_admin),
+        Some(author_id.into()),
+    );
+
+    db.add_ticket_message(
+        ticket.ticket_number,
+        &author,
+        content,
+        Some(message_id.into()),
+        is_staff,
+    )
+    .await?;
+
+    Ok(())
+}
+
+async fn autocomplete_canned_response<'a>(
+    ctx: Context<'_>,
+    partial: &'a str,
+) -> impl Iterator<Item = String> + 'a {
+    let mut names = vec![];
+    if let (Ok(ticket), Ok(challenge_data)) = (
+        ctx.data()
+            .db
+            .get_ticket_by_discord_channel_id(ctx.channel_id().into())
+            .await,
+        ctx.data().db.get_challenges().await,
+    ) {
+        let settings = ctx.data().settings.read().await;
+        names = ticket_canned_responses(
+            &settings,
+            challenge_data.challenges.get(&ticket.challenge_id),
+        )
+        .into_keys()
+        .collect();
+    }
+
+    names
+        .into_iter()
+        .filter(move |name| name.starts_with(partial))
+        .take(25)
+}
+
+/// Send a canned response to this ticket (author only)
+#[poise::command(slash_command, ephemeral)]
+pub async fn canned(
+    ctx: Context<'_>,
+    #[description = "Response to send"]
+    #[autocomplete = "autocomplete_canned_response"]
+    name: String,
+) -> std::result::Result<(), DiscordError> {
+    let author_discord_role_id = ctx
+        .data()
+        .settings
+        .read()
+        .await
+        .discord
+        .as_ref()
+        .and_then(|d| d.author_role_id);
+
+    let is_author = if let Some(author_discord_role_id) = author_discord_role_id {
+        ctx.author()
+            .has_role(ctx.http(), ctx.guild_id().unwrap(), author_discord_role_id)
+            .await
+            .unwrap_or(false)
+    } else {
+        false
+    };
+
+    let is_admin = ctx
+        .data()
+        .db
+        .get_user_from_discord_id(ctx.author().id.into())
+        .await
+        .map(|user| user.is_admin)
+        .unwrap_or(false);
+
+    if !is_author && !is_admin {
+        ctx.reply("You do not have permission to run this command")
+            .await?;
+        return Ok(());
     }
 
+    let Ok(ticket) = ctx
+        .data()
+        .db
+        .get_ticket_by_discord_channel_id(ctx.channel_id().into())
+        .await
+    else {
+        ctx.reply("Could not find associated ticket in the database")
+            .await?;
+        return Ok(());
+    };
+
+    let challenge_data = ctx.data().db.get_challenges().await?;
+    let settings = ctx.data().settings.read().await.clone();
+    let responses =
+        render_canned_responses(&ctx.data().db, &settings, &challenge_data, &ticket).await?;
+
+    let Some(content) = responses.get(&name) else {
+        ctx.reply(format!("No canned response named `{}`", name))
+            .await?;
+        return Ok(());
+    };
+
+    // the bot sends it so it shows up in the thread right away, but it is stored as the author's
+    let message = ctx.channel_id().say(ctx.http(), content).await?;
+    store_discord_ticket_message(&ctx.data().db, &ticket, ctx.author(), content, message.id)
+        .await?;
+
+    spawn_ticket_digest(
+        ctx.data().db.clone(),
+        ctx.data().outbound_mailer.clone(),
+        ticket.ticket_number,
+    );
+
+    ctx.reply(format!("Sent `{}`", name)).await?;
+
     Ok(())
 }
 
@@ -616,127 +769,6 @@ Default Ticket Template
     Ok(())
 }
 
-pub static DIGEST_DEBOUNCER: LazyLock<Mutex<BTreeMap<ChannelId, i64>>> =
-    LazyLock::new(Mutex::default);
-
-#[derive(Debug, Serialize, PartialEq, Eq, PartialOrd, Ord)]
-pub struct DigestAuthor {
-    pub name: String,
-    pub image_url: String,
-    pub discord_id: Option<UserId>,
-    pub rhombus_id: Option<i64>,
-}
-
-#[derive(Debug, Serialize, PartialEq, Eq, PartialOrd, Ord)]
-pub struct DigestMessage<'a> {
-    pub timestamp: DateTime<Utc>,
-    pub author: &'a DigestAuthor,
-    pub content: String,
-    pub edited_timestamp: Option<DateTime<Utc>>,
-}
-
-pub async fn digest_channel(
-    ctx: &serenity::all::Context,
-    data: &Data,
-    channel: ChannelId,
-    ticket: &Ticket,
-) -> Result<()> {
-    // dont send an email digest if we don't have a mailer
-    let outbound_mailer = if let Some(ref outbound_mailer) = data.outbound_mailer {
-        outbound_mailer
-    } else {
-        return Ok(());
-    };
-
-    // dont send a digest if the user who made the ticket has linked their discord
-    let ticket_creator_user = data.db.get_user_from_id(ticket.user_id).await?;
-    if ticket_creator_user.discord_id.is_some() {
-        return Ok(());
-    }
-
-    let now = chrono::Utc::now().timestamp();
-    {
-        _ = DIGEST_DEBOUNCER.lock().await.insert(channel, now);
-    }
-    tokio::time::sleep(std::time::Duration::from_secs(60)).await; // debouncing time
-    if let Some(timestamp) = DIGEST_DEBOUNCER.lock().await.remove(&channel) {
-        if timestamp != now {
-            return Ok(());
-        }
-    }
-
-    let messages = channel.messages(ctx, GetMessages::new().limit(100)).await?;
-
-    let rhombus_user_id = {
-        data.settings
-            .read()
-            .await
-            .discord
-            .as_ref()
-            .unwrap()
-            .client_id
-    };
-
-    let mut authors = BTreeMap::new();
-    for message in &messages {
-        match authors.entry(message.author.id) {
-            btree_map::Entry::Occupied(_) => {}
-            btree_map::Entry::Vacant(entry) => {
-                // Rhombus is acting as the intermediary for the email-only user,
-                // so messages sent by Rhombus should be attributed to the user
-                // who created the thread
-                if rhombus_user_id == message.author.id.into() {
-                    entry.insert(DigestAuthor {
-                        name: ticket_creator_user.name.clone(),
-                        image_url: ticket_creator_user.avatar.clone(),
-                        discord_id: ticket_creator_user.discord_id.map(|id| id.into()),
-                        rhombus_id: Some(ticket_creator_user.id),
-                    });
-                } else if let Ok(user) = data
-                    .db
-                    .get_user_from_discord_id(message.author.id.into())
-                    .await
-                {
-                    entry.insert(DigestAuthor {
-                        name: user.name.clone(),
-                        image_url: user.avatar.clone(),
-                        discord_id: user.discord_id.map(|id| id.into()),
-                        rhombus_id: Some(user.id),
-                    });
-                } else {
-                    entry.insert(DigestAuthor {
-                        name: message.author.name.clone(),
-                        image_url: message.author.avatar_url().unwrap_or_default(),
-                        discord_id: Some(message.author.id),
-                        rhombus_id: None,
-                    });
-                }
-            }
-        }
-    }
-
-    let mut messages = messages
-        .into_iter()
-        .filter(|message| {
-            matches!(
-                message.kind,
-                serenity::all::MessageType::Regular | serenity::all::MessageType::InlineReply
-            )
-        })
-        .map(|message| DigestMessage {
-            author: authors.get(&message.author.id).unwrap(),
-            content: message.content,
-            edited_timestamp: message.edited_timestamp.map(|t| t.to_utc()),
-            timestamp: message.timestamp.to_utc(),
-        })
-        .collect::<Vec<_>>();
-    messages.sort();
-
-    outbound_mailer.send_digest(ticket, &messages[1..]).await?;
-
-    Ok(())
-}
-
 async fn event_handler(
     ctx: &serenity::all::Context,
     event: &serenity::all::FullEvent,
@@ -882,7 +914,25 @@ async fn event_handler(
                                 .db
                                 .get_ticket_by_discord_channel_id(channel.id.into())
                                 .await?;
-                            digest_channel(ctx, data, channel.id, &ticket).await?;
+
+                            let mut content = new_message.content.clone();
+                            for attachment in &new_message.attachments {
+                                content.push_str("\n\n");
+                                content.push_str(&attachment.url);
+                            }
+                            store_discord_ticket_message(
+                                &data.db,
+                                &ticket,
+                                &new_message.author,
+                                &content,
+                                new_message.id,
+                            )
+                            .await?;
+
+                            if let Some(ref outbound_mailer) = data.outbound_mailer {
+                                digest_ticket(&data.db, outbound_mailer, ticket.ticket_number)
+                                    .await?;
+                            }
                         }
                     }
                 }
@@ -892,15 +942,21 @@ async fn event_handler(
                 new: _,
                 event,
             } => {
-                if let Some(channel) = event.channel_id.to_channel(ctx).await?.guild() {
-                    if let Some(parent_id) = channel.parent_id {
-                        if parent_id == support_channel {
-                            let ticket = data
-                                .db
-                                .get_ticket_by_discord_channel_id(channel.id.into())
-                                .await?;
-                            digest_channel(ctx, data, channel.id, &ticket).await?;
-                        }
+                let Some(ref content) = event.content else {
+                    return Ok(());
+                };
+
+                let edited_at = event
+                    .edited_timestamp
+                    .map(|t| t.to_utc())
+                    .unwrap_or_else(chrono::Utc::now);
+                if let Some(ticket_number) = data
+                    .db
+                    .edit_ticket_message_by_discord_id(event.id.into(), content, edited_at)
+                    .await?
+                {
+                    if let Some(ref outbound_mailer) = data.outbound_mailer {
+                        digest_ticket(&data.db, outbound_mailer, ticket_number).await?;
                     }
                 }
             }
@@ -985,6 +1041,7 @@ impl Bot {
         outbound_mailer: Option<Arc<OutboundMailer>>,
         jinja: Arc<minijinja::Environment<'static>>,
         divisions: Arc<Vec<Division>>,
+        llm_provider: Option<Arc<dyn LlmProvider>>,
     ) -> Self {
         let bot_token = {
             settings
@@ -1002,7 +1059,7 @@ impl Bot {
 
         let framework = poise::Framework::builder()
             .options(poise::FrameworkOptions {
-                commands: vec![admin(), whois(), ai()],
+                commands: vec![admin(), whois(), ai(), canned()],
                 event_handler: |ctx, event, framework, data| {
                     Box::pin(event_handler(ctx, event, framework, data))
                 },
@@ -1017,6 +1074,7 @@ impl Bot {
                         outbound_mailer,
                         jinja,
                         divisions,
+                        llm_provider,
                     })
                 })
             })
@@ -1122,8 +1180,10 @@ impl Bot {
         Ok(invite_url)
     }
 
+    /// Mirrors a new ticket to a private thread in the support channel, if one is configured
     pub async fn create_support_thread(
         &self,
+        ticket_number: u64,
         user: &User,
         team: &Team,
         challenge: &Challenge,
@@ -1141,8 +1201,6 @@ impl Bot {
             return Ok(());
         }
 
-        let ticket_number = self.db.get_next_ticket_number().await?;
-
         let thread = ChannelId::from(support_channel_id.unwrap())
             .create_thread(
                 &self.http,
@@ -1214,13 +1272,7 @@ impl Bot {
             .await?;
 
         self.db
-            .create_ticket(
-                ticket_number,
-                user.id,
-                &challenge.id,
-                thread.id.into(),
-                panel_message.id.into(),
-            )
+            .set_ticket_discord(ticket_number, thread.id.into(), panel_message.id.into())
             .await?;
 
         thread
@@ -1249,46 +1301,94 @@ impl Bot {
         ];
 
         for to_be_closed_ticket in to_be_closed_tickets {
-            let thread = ChannelId::from(to_be_closed_ticket.discord_channel_id);
-
-            let mut panel_message = thread
-                .message(
-                    &self.http,
-                    MessageId::from(to_be_closed_ticket.discord_panel_message_id),
-                )
-                .await
-                .unwrap();
-            panel_message
-                .edit(
-                    &self.http,
-                    EditMessage::new().components(vec![CreateActionRow::Buttons(vec![
-                        CreateButton::new(format!(
-                            "reopen-ticket-{}",
-                            to_be_closed_ticket.ticket_number
-                        ))
-                        .style(ButtonStyle::Primary)
-                        .label("Reopen Ticket")
-                        .emoji('🔓'),
-                    ])]),
-                )
-                .await
-                .unwrap();
+            let (Some(discord_channel_id), Some(discord_panel_message_id)) = (
+                to_be_closed_ticket.discord_channel_id,
+                to_be_closed_ticket.discord_panel_message_id,
+            ) else {
+                continue;
+            };
 
             let message = celebration_messages
                 .choose(&mut thread_rng())
                 .unwrap()
                 .to_string();
-            thread
+            ChannelId::from(discord_channel_id)
                 .send_message(&self.http, CreateMessage::new().content(message))
                 .await?;
-            thread
-                .edit_thread(&self.http, EditThread::new().archived(true))
-                .await?;
+
+            self.set_ticket_thread_closed(
+                to_be_closed_ticket.ticket_number,
+                discord_channel_id,
+                discord_panel_message_id,
+                true,
+            )
+            .await?;
         }
 
         Ok(())
     }
 
+    /// Adds the new assignee of a ticket to its thread and lets the thread know
+    pub async fn assign_ticket_thread(
+        &self,
+        discord_channel_id: NonZeroU64,
+        assignee_discord_id: NonZeroU64,
+    ) -> Result<()> {
+        let thread = ChannelId::from(discord_channel_id);
+
+        self.http
+            .add_thread_channel_member(thread, UserId::from(assignee_discord_id))
+            .await?;
+
+        thread
+            .send_message(
+                &self.http,
+                CreateMessage::new()
+                    .content(format!(":bookmark: Assigned to <@{}>", assignee_discord_id)),
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    /// Swaps the close and reopen buttons on the panel of a ticket thread, and archives or
+    /// unarchives the thread
+    pub async fn set_ticket_thread_closed(
+        &self,
+        ticket_number: u64,
+        discord_channel_id: NonZeroU64,
+        discord_panel_message_id: NonZeroU64,
+        closed: bool,
+    ) -> Result<()> {
+        let thread = ChannelId::from(discord_channel_id);
+
+        let button = if closed {
+            CreateButton::new(format!("reopen-ticket-{}", ticket_number))
+                .style(ButtonStyle::Primary)
+                .label("Reopen Ticket")
+                .emoji('🔓')
+        } else {
+            CreateButton::new(format!("close-ticket-{}", ticket_number))
+                .style(ButtonStyle::Primary)
+                .label("Close Ticket")
+                .emoji('🔒')
+        };
+
+        thread
+            .edit_message(
+                &self.http,
+                MessageId::from(discord_panel_message_id),
+                EditMessage::new().components(vec![CreateActionRow::Buttons(vec![button])]),
+            )
+            .await?;
+
+        thread
+            .edit_thread(&self.http, EditThread::new().archived(closed))
+            .await?;
+
+        Ok(())
+    }
+
     pub async fn send_first_blood(
         &self,
         user: &User,
@@ -1420,6 +1520,21 @@ impl Bot {
         Ok(())
     }
 
+    /// Whether a member of the guild has the given role. Fails if the user is not in the guild.
+    pub async fn user_has_role(&self, discord_id: NonZeroU64, role_id: NonZeroU64) -> Result<bool> {
+        let guild_id = {
+            let settings = self.settings.read().await;
+            settings.discord.as_ref().unwrap().guild_id
+        };
+
+        let member = self
+            .http
+            .get_member(guild_id.into(), discord_id.into())
+            .await?;
+
+        Ok(member.roles.contains(&RoleId::from(role_id)))
+    }
+
     pub async fn send_external_ticket_message(
         &self,
         channel_id: NonZeroU64,
@@ -1427,7 +1542,7 @@ impl Bot {
         from: Option<&str>,
         message: &str,
         attachments: &[DiscordAttachment<'_>],
-    ) -> Result<()> {
+    ) -> Result<NonZeroU64> {
         let channel_id = ChannelId::from(channel_id);
 
         let attachments = attachments
@@ -1451,7 +1566,7 @@ impl Bot {
             embed
         };
 
-        channel_id
+        let message = channel_id
             .send_message(
                 &self.http,
                 CreateMessage::new()
@@ -1461,7 +1576,7 @@ impl Bot {
             )
             .await?;
 
-        Ok(())
+        Ok(message.id.into())
     }
 
     pub async fn give_role_to_users(&self, discord_user_ids: &[NonZeroU64], role_id: NonZeroU64) {
@@ -1594,6 +1709,268 @@ impl Bot {
             }
         }
     }
+
+    /// Whether teams get their own Discord role and channels
+    pub async fn team_roles_enabled(&self) -> bool {
+        self.settings
+            .read()
+            .await
+            .discord
+            .as_ref()
+            .is_some_and(|discord| discord.team_roles.unwrap_or(false))
+    }
+
+    /// Creates the Discord role and private channels of a team if they do not exist yet, names
+    /// them after the team, and gives the role to every member of the team on Discord. Teams
+    /// smaller than [`TEAM_DISCORD_MIN_PLAYERS`] have theirs deleted instead.
+    pub async fn sync_team_discord(&self, team: &TeamInner) -> Result<()> {
+        let (guild_id, team_roles, category_id, voice_channels, ended) = {
+            let settings = self.settings.read().await;
+            let discord = settings.discord.as_ref().unwrap();
+            (
+                GuildId::from(discord.guild_id),
+                discord.team_roles.unwrap_or(false),
+                discord.team_category_id,
+                discord.team_voice_channels.unwrap_or(false),
+                settings
+                    .end_time
+                    .is_some_and(|end_time| end_time < chrono::Utc::now()),
+            )
+        };
+
+        if !team_roles || ended {
+            return Ok(());
+        }
+
+        let mut team_discord = self.db.get_team_discord(team.id).await?;
+        let old_team_discord = team_discord.clone();
+
+        if team.users.len() < TEAM_DISCORD_MIN_PLAYERS {
+            if team_discord != TeamDiscord::default() {
+                self.delete_team_discord_inner(team.id, &team_discord)
+                    .await?;
+            }
+            return Ok(());
+        }
+
+        // don't spend any of the guild's 250 roles on teams nobody on Discord is part of
+        let user_discord_ids = team
+            .users
+            .values()
+            .filter_map(|user| user.discord_id)
+            .collect::<Vec<_>>();
+        if user_discord_ids.is_empty() && team_discord.role_id.is_none() {
+            return Ok(());
+        }
+
+        let role_id = match team_discord.role_id {
+            Some(role_id)
+                if guild_id
+                    .edit_role(&self.http, role_id, EditRole::new().name(&team.name))
+                    .await
+                    .is_ok() =>
+            {
+                RoleId::from(role_id)
+            }
+            _ => {
+                let role = guild_id
+                    .create_role(
+                        &self.http,
+                        EditRole::new()
+                            .name(&team.name)
+                            .audit_log_reason("Rhombus team role"),
+                    )
+                    .await?;
+                team_discord.role_id = NonZeroU64::new(role.id.get());
+                role.id
+            }
+        };
+
+        if let Some(category_id) = category_id {
+            let permissions = vec![
+                PermissionOverwrite {
+                    allow: Permissions::empty(),
+                    deny: Permissions::VIEW_CHANNEL,
+                    kind: PermissionOverwriteType::Role(RoleId::new(guild_id.get())),
+                },
+                PermissionOverwrite {
+                    allow: Permissions::VIEW_CHANNEL
+                        | Permissions::SEND_MESSAGES
+                        | Permissions::READ_MESSAGE_HISTORY
+                        | Permissions::CONNECT
+                        | Permissions::SPEAK,
+                    deny: Permissions::empty(),
+                    kind: PermissionOverwriteType::Role(role_id),
+                },
+            ];
+
+            team_discord.text_channel_id = self
+                .sync_team_channel(
+                    guild_id,
+                    category_id,
+                    team_discord.text_channel_id,
+                    &team_channel_name(&team.name),
+                    ChannelType::Text,
+                    &permissions,
+                )
+                .await?;
+
+            if voice_channels {
+                team_discord.voice_channel_id = self
+                    .sync_team_channel(
+                        guild_id,
+                        category_id,
+                        team_discord.voice_channel_id,
+                        &team.name,
+                        ChannelType::Voice,
+                        &permissions,
+                    )
+                    .await?;
+            }
+        }
+
+        if team_discord.role_id != old_team_discord.role_id
+            || team_discord.text_channel_id != old_team_discord.text_channel_id
+            || team_discord.voice_channel_id != old_team_discord.voice_channel_id
+        {
+            self.db.set_team_discord(team.id, &team_discord).await?;
+        }
+
+        self.give_role_to_users(&user_discord_ids, NonZeroU64::new(role_id.get()).unwrap())
+            .await;
+
+        Ok(())
+    }
+
+    /// Renames the channel of a team, or creates it if it does not exist
+    async fn sync_team_channel(
+        &self,
+        guild_id: GuildId,
+        category_id: NonZeroU64,
+        channel_id: Option<NonZeroU64>,
+        name: &str,
+        kind: ChannelType,
+        permissions: &[PermissionOverwrite],
+    ) -> Result<Option<NonZeroU64>> {
+        if let Some(channel_id) = channel_id {
+            if ChannelId::from(channel_id)
+                .edit(&self.http, EditChannel::new().name(name))
+                .await
+                .is_ok()
+            {
+                return Ok(Some(channel_id));
+            }
+        }
+
+        let channel = guild_id
+            .create_channel(
+                &self.http,
+                CreateChannel::new(name)
+                    .kind(kind)
+                    .category(ChannelId::from(category_id))
+                    .permissions(permissions.to_vec())
+                    .audit_log_reason("Rhombus team channel"),
+            )
+            .await?;
+
+        Ok(NonZeroU64::new(channel.id.get()))
+    }
+
+    /// Removes the role of a team from a user who is no longer on it
+    pub async fn remove_user_from_team_discord(
+        &self,
+        discord_id: NonZeroU64,
+        team_id: i64,
+    ) -> Result<()> {
+        if !self.team_roles_enabled().await {
+            return Ok(());
+        }
+
+        let team_discord = self.db.get_team_discord(team_id).await?;
+        if let Some(role_id) = team_discord.role_id {
+            self.remove_role_from_users(&[discord_id], role_id).await;
+        }
+        Ok(())
+    }
+
+    /// Deletes the Discord role and private channels of a team
+    pub async fn delete_team_discord(&self, team_id: i64) -> Result<()> {
+        if !self.team_roles_enabled().await {
+            return Ok(());
+        }
+
+        let team_discord = self.db.get_team_discord(team_id).await?;
+        if team_discord == TeamDiscord::default() {
+            return Ok(());
+        }
+        self.delete_team_discord_inner(team_id, &team_discord).await
+    }
+
+    /// Deletes the Discord roles and private channels of every team, once the event is over
+    pub async fn delete_team_discords(&self) -> Result<()> {
+        for (team_id, team_discord) in self.db.get_team_discords().await? {
+            if let Err(error) = self.delete_team_discord_inner(team_id, &team_discord).await {
+                tracing::error!(
+                    ?error,
+                    team_id,
+                    "Failed to delete team Discord role and channels"
+                );
+            }
+        }
+        Ok(())
+    }
+
+    async fn delete_team_discord_inner(
+        &self,
+        team_id: i64,
+        team_discord: &TeamDiscord,
+    ) -> Result<()> {
+        let guild_id = {
+            let settings = self.settings.read().await;
+            GuildId::from(settings.discord.as_ref().unwrap().guild_id)
+        };
+
+        // these may have already been deleted by hand, which is fine
+        for channel_id in [team_discord.text_channel_id, team_discord.voice_channel_id]
+            .into_iter()
+            .flatten()
+        {
+            if let Err(error) = ChannelId::from(channel_id).delete(&self.http).await {
+                tracing::warn!(
+                    ?error,
+                    team_id,
+                    ?channel_id,
+                    "Failed to delete team channel"
+                );
+            }
+        }
+        if let Some(role_id) = team_discord.role_id {
+            if let Err(error) = guild_id.delete_role(&self.http, role_id).await {
+                tracing::warn!(?error, team_id, ?role_id, "Failed to delete team role");
+            }
+        }
+
+        self.db
+            .set_team_discord(team_id, &TeamDiscord::default())
+            .await?;
+
+        tracing::info!(team_id, "Deleted team Discord role and channels");
+
+        Ok(())
+    }
+}
+
+/// Teams need this many players before they get a Discord role and channels, so that players on
+/// their own don't use up the guild's 250 roles
+pub const TEAM_DISCORD_MIN_PLAYERS: usize = 2;
+
+/// Discord text channel names are lowercase without spaces
+fn team_channel_name(team_name: &str) -> String {
+    team_name
+        .to_lowercase()
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join("-")
 }
 
 pub struct DiscordAttachment<'a> {
@@ -1627,3 +2004,54 @@ fn escape_discord_link(input: &str) -> Cow<str> {
         input.into()
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn redact_full_flag() {
+        let flag = "flag{s3cr3t_v4lu3}";
+        assert_eq!(
+            flag_secrets(flag),
+            vec!["flag{s3cr3t_v4lu3}", "s3cr3t_v4lu3"]
+        );
+        assert_eq!(
+            redact_flag("the flag is flag{s3cr3t_v4lu3}!", flag),
+            "the flag is [REDACTED]!"
+        );
+        assert!(contains_flag("try flag{s3cr3t_v4lu3}", flag));
+    }
+
+    #[test]
+    fn redact_inner_body() {
+        let flag = "flag{s3cr3t_v4lu3}";
+        assert_eq!(
+            redact_flag("it starts with s3cr3t_v4lu3", flag),
+            "it starts with [REDACTED]"
+        );
+        assert!(contains_flag("maybe s3cr3t_v4lu3?", flag));
+        assert!(!contains_flag("nothing to see here", flag));
+    }
+
+    #[test]
+    fn detect_flag_case_insensitive() {
+        let flag = "flag{s3cr3t_v4lu3}";
+        assert!(contains_flag("FLAG{S3CR3T_V4LU3}", flag));
+        assert!(contains_flag("S3cR3t_V4lU3", flag));
+    }
+
+    #[test]
+    fn keep_short_inner_body() {
+        for flag in ["flag{abcd}", "flag{}", "a{b}"] {
+            assert_eq!(flag_secrets(flag), vec![flag]);
+        }
+        assert_eq!(
+            redact_flag("abcd is not flag{abcd}", "flag{abcd}"),
+            "abcd is not [REDACTED]"
+        );
+        assert!(!contains_flag("abcd", "flag{abcd}"));
+        assert_eq!(flag_secrets("flag{abcde}"), vec!["flag{abcde}", "abcde"]);
+        assert!(flag_secrets("  ").is_empty());
+    }
+}
@@ -1,19 +1,41 @@
 use std::{
+    collections::BTreeSet,
     num::{NonZeroU32, NonZeroU64},
-    sync::Arc,
+    sync::{Arc, LazyLock, OnceLock, Weak},
 };
 
 use async_trait::async_trait;
+use dashmap::DashMap;
 use fancy_regex::Regex;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 
-use crate::internal::database::provider::Connection;
+use crate::internal::{
+    database::{
+        cache::TimedCache,
+        provider::{Connection, TeamInner},
+    },
+    discord::Bot,
+};
 
 pub type DivisionEligibilityProvider = Arc<dyn DivisionEligible + Send + Sync>;
 
 #[async_trait]
 pub trait DivisionEligible {
     async fn is_user_eligible(&self, user_id: i64) -> std::result::Result<bool, String>;
+
+    /// Checks the team as a whole, after each of its members passed `is_user_eligible`
+    async fn is_team_eligible(&self, _team: &TeamInner) -> std::result::Result<bool, String> {
+        Ok(true)
+    }
+
+    /// Called when a team tries to join the division while the user is not eligible, so rules
+    /// which are granted on request (like manual approval) can record it
+    async fn request_eligibility(&self, _user_id: i64) {}
+
+    /// Whether `request_eligibility` can make an ineligible user eligible
+    fn is_requestable(&self) -> bool {
+        false
+    }
 }
 
 pub struct EmailDivisionEligibilityProvider {
@@ -59,6 +81,287 @@ impl DivisionEligible for OpenDivisionEligibilityProvider {
     }
 }
 
+/// The Discord bot, which is created after the divisions it is used by
+pub type DivisionBot = Arc<OnceLock<Weak<Bot>>>;
+
+static DISCORD_ROLE_CACHE: LazyLock<DashMap<(NonZeroU64, NonZeroU64), TimedCache<bool>>> =
+    LazyLock::new(DashMap::new);
+
+pub struct DiscordRoleDivisionEligibilityProvider {
+    pub db: Connection,
+    pub bot: DivisionBot,
+    pub role_id: NonZeroU64,
+    pub requirement: String,
+}
+
+impl DiscordRoleDivisionEligibilityProvider {
+    pub fn new(
+        db: Connection,
+        bot: DivisionBot,
+        role_id: NonZeroU64,
+        requirement: Option<String>,
+    ) -> Self {
+        Self {
+            db,
+            bot,
+            role_id,
+            requirement: requirement
+                .unwrap_or("Must have the required role in the Discord server".to_owned()),
+        }
+    }
+}
+
+#[async_trait]
+impl DivisionEligible for DiscordRoleDivisionEligibilityProvider {
+    async fn is_user_eligible(&self, user_id: i64) -> std::result::Result<bool, String> {
+        let discord_id = match self.db.get_user_from_id(user_id).await {
+            Ok(user) => user.discord_id,
+            Err(error) => {
+                tracing::error!(?error, user_id, "Failed to get user");
+                None
+            }
+        };
+        let Some(discord_id) = discord_id else {
+            return Err(self.requirement.clone());
+        };
+
+        let now = chrono::Utc::now().timestamp();
+        if let Some(has_role) = DISCORD_ROLE_CACHE
+            .get(&(discord_id, self.role_id))
+            .filter(|cached| cached.insert_timestamp > now - 60)
+        {
+            return if has_role.value {
+                Ok(true)
+            } else {
+                Err(self.requirement.clone())
+            };
+        }
+
+        let Some(bot) = self.bot.get().and_then(|bot| bot.upgrade()) else {
+            tracing::error!("Division requires a Discord role but the Discord bot is not running");
+            return Err(self.requirement.clone());
+        };
+
+        let has_role = bot
+            .user_has_role(discord_id, self.role_id)
+            .await
+            .unwrap_or_else(|error| {
+                tracing::trace!(?error, user_id, "Failed to get Discord member");
+                false
+            });
+        DISCORD_ROLE_CACHE.insert((discord_id, self.role_id), TimedCache::new(has_role));
+
+        if has_role {
+            Ok(true)
+        } else {
+            Err(self.requirement.clone())
+        }
+    }
+}
+
+static CTFTIME_TEAM_COUNTRY_CACHE: LazyLock<DashMap<i64, TimedCache<Option<String>>>> =
+    LazyLock::new(DashMap::new);
+
+#[derive(Deserialize)]
+struct CTFtimeTeam {
+    country: Option<String>,
+}
+
+/// Requires the team to be linked to a CTFtime team, optionally from one of the given countries
+pub struct CTFtimeDivisionEligibilityProvider {
+    pub countries: Option<Vec<String>>,
+    pub requirement: String,
+}
+
+impl CTFtimeDivisionEligibilityProvider {
+    pub fn new(countries: Option<Vec<String>>, requirement: Option<String>) -> Self {
+        let requirement = requirement.unwrap_or_else(|| match &countries {
+            Some(countries) => format!(
+                "Team must be linked to a CTFtime team from {}",
+                countries.join(", ")
+            ),
+            None => "Team must be linked to a CTFtime team".to_owned(),
+        });
+
+        Self {
+            countries: countries.map(|countries| {
+                countries
+                    .iter()
+                    .map(|country| country.to_uppercase())
+                    .collect()
+            }),
+            requirement,
+        }
+    }
+
+    async fn get_country(ctftime_team_id: i64) -> Option<String> {
+        let now = chrono::Utc::now().timestamp();
+        if let Some(country) = CTFTIME_TEAM_COUNTRY_CACHE
+            .get(&ctftime_team_id)
+            .filter(|cached| cached.insert_timestamp > now - 60 * 60)
+        {
+            return country.value.clone();
+        }
+
+        let team = reqwest::get(format!(
+            "https://ctftime.org/api/v1/teams/{}/",
+            ctftime_team_id
+        ))
+        .await;
+        let country = match team {
+            Ok(response) => match response.json::<CTFtimeTeam>().await {
+                Ok(team) => team.country.map(|country| country.to_uppercase()),
+                Err(error) => {
+                    tracing::error!(?error, ctftime_team_id, "Failed to parse CTFtime team");
+                    return None;
+                }
+            },
+            Err(error) => {
+                tracing::error!(?error, ctftime_team_id, "Failed to get CTFtime team");
+                return None;
+            }
+        };
+
+        CTFTIME_TEAM_COUNTRY_CACHE.insert(ctftime_team_id, TimedCache::new(country.clone()));
+        country
+    }
+}
+
+#[async_trait]
+impl DivisionEligible for CTFtimeDivisionEligibilityProvider {
+    async fn is_user_eligible(&self, _user_id: i64) -> std::result::Result<bool, String> {
+        Ok(true)
+    }
+
+    async fn is_team_eligible(&self, team: &TeamInner) -> std::result::Result<bool, String> {
+        let Some(ctftime_team_id) = team.ctftime_id else {
+            return Err(self.requirement.clone());
+        };
+
+        if let Some(countries) = &self.countries {
+            let country = Self::get_country(ctftime_team_id).await;
+            if !country.is_some_and(|country| countries.contains(&country)) {
+                return Err(self.requirement.clone());
+            }
+        }
+
+        Ok(true)
+    }
+}
+
+/// Requires an admin to approve each member. Users who are not yet approved are added to the
+/// approval queue when their team tries to join the division.
+pub struct ManualDivisionEligibilityProvider {
+    pub db: Connection,
+    pub division_id: String,
+    pub requirement: String,
+}
+
+impl ManualDivisionEligibilityProvider {
+    pub fn new(db: Connection, division_id: &str, requirement: Option<String>) -> Self {
+        Self {
+            db,
+            division_id: division_id.to_owned(),
+            requirement: requirement.unwrap_or("Must be approved by an admin".to_owned()),
+        }
+    }
+}
+
+#[async_trait]
+impl DivisionEligible for ManualDivisionEligibilityProvider {
+    async fn is_user_eligible(&self, user_id: i64) -> std::result::Result<bool, String> {
+        match self
+            .db
+            .get_division_approval(&self.division_id, user_id)
+            .await
+        {
+            Ok(Some(true)) => Ok(true),
+            Ok(_) => Err(self.requirement.clone()),
+            Err(error) => {
+                tracing::error!(?error, user_id, "Failed to get division approval");
+                Err(self.requirement.clone())
+            }
+        }
+    }
+
+    async fn request_eligibility(&self, user_id: i64) {
+        if let Err(error) = self
+            .db
+            .request_division_approval(&self.division_id, user_id)
+            .await
+        {
+            tracing::error!(?error, user_id, "Failed to request division approval");
+        }
+    }
+
+    fn is_requestable(&self) -> bool {
+        true
+    }
+}
+
+pub struct UserListDivisionEligibilityProvider {
+    pub user_ids: BTreeSet<i64>,
+    pub requirement: String,
+}
+
+impl UserListDivisionEligibilityProvider {
+    pub fn new(user_ids: &[i64], requirement: Option<String>) -> Self {
+        Self {
+            user_ids: user_ids.iter().copied().collect(),
+            requirement: requirement.unwrap_or("Must be on the list of allowed users".to_owned()),
+        }
+    }
+}
+
+#[async_trait]
+impl DivisionEligible for UserListDivisionEligibilityProvider {
+    async fn is_user_eligible(&self, user_id: i64) -> std::result::Result<bool, String> {
+        if self.user_ids.contains(&user_id) {
+            Ok(true)
+        } else {
+            Err(self.requirement.clone())
+        }
+    }
+}
+
+/// Requires every one of the given rules
+pub struct AllDivisionEligibilityProvider {
+    pub providers: Vec<DivisionEligibilityProvider>,
+}
+
+#[async_trait]
+impl DivisionEligible for AllDivisionEligibilityProvider {
+    async fn is_user_eligible(&self, user_id: i64) -> std::result::Result<bool, String> {
+        for provider in self.providers.iter() {
+            if !provider.is_user_eligible(user_id).await? {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    async fn is_team_eligible(&self, team: &TeamInner) -> std::result::Result<bool, String> {
+        for provider in self.providers.iter() {
+            if !provider.is_team_eligible(team).await? {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    async fn request_eligibility(&self, user_id: i64) {
+        for provider in self.providers.iter() {
+            provider.request_eligibility(user_id).await;
+        }
+    }
+
+    fn is_requestable(&self) -> bool {
+        self.providers
+            .iter()
+            .any(|provider| provider.is_requestable())
+    }
+}
+
 #[derive(Serialize, Clone)]
 pub struct Division {
     pub id: String,
@@ -67,13 +370,163 @@ pub struct Division {
     pub max_players: MaxDivisionPlayers,
     pub is_default: bool,
     pub discord_role_id: Option<NonZeroU64>,
+    /// Archived divisions cannot be joined, but keep the teams already in them
+    pub archived: bool,
+    /// Created by an admin at runtime instead of in the config
+    pub admin_managed: bool,
 
     #[serde(skip)]
     pub division_eligibility: DivisionEligibilityProvider,
 }
 
+impl Division {
+    /// Checks every member of a team, and a user about to join it, against the eligibility rules
+    /// of the division. Returns the requirement which is not met.
+    pub async fn is_team_eligible(
+        &self,
+        team: &TeamInner,
+        joining_user_id: Option<i64>,
+    ) -> std::result::Result<(), String> {
+        let ineligible = || format!("Not eligible for {}", self.name);
+
+        for user_id in team.users.keys().copied().chain(joining_user_id) {
+            if !self.division_eligibility.is_user_eligible(user_id).await? {
+                return Err(ineligible());
+            }
+        }
+
+        if !self.division_eligibility.is_team_eligible(team).await? {
+            return Err(ineligible());
+        }
+
+        Ok(())
+    }
+
+    /// Records that the members of a team want to join the division, for rules which are
+    /// granted on request
+    pub async fn request_team_eligibility(&self, team: &TeamInner) {
+        for user_id in team.users.keys() {
+            if self
+                .division_eligibility
+                .is_user_eligible(*user_id)
+                .await
+                .is_err()
+            {
+                self.division_eligibility
+                    .request_eligibility(*user_id)
+                    .await;
+            }
+        }
+    }
+}
+
 #[derive(Debug, Serialize, Clone)]
 pub enum MaxDivisionPlayers {
     Unlimited,
     Limited(NonZeroU32),
 }
+
+impl MaxDivisionPlayers {
+    pub fn parse(max_players: &str) -> Option<Self> {
+        match max_players {
+            "unlimited" | "infinity" | "infinite" | "any" => Some(MaxDivisionPlayers::Unlimited),
+            _ => max_players
+                .parse::<NonZeroU32>()
+                .ok()
+                .map(MaxDivisionPlayers::Limited),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{collections::BTreeMap, sync::Arc};
+
+    use crate::internal::database::provider::{TeamInner, TeamUser};
+
+    use super::{
+        AllDivisionEligibilityProvider, Division, DivisionEligible, MaxDivisionPlayers,
+        OpenDivisionEligibilityProvider, UserListDivisionEligibilityProvider,
+    };
+
+    fn team(user_ids: &[i64]) -> TeamInner {
+        TeamInner {
+            id: 1,
+            name: "Team 1".to_owned(),
+            invite_token: "".to_owned(),
+            users: user_ids
+                .iter()
+                .map(|&id| {
+                    (
+                        id,
+                        TeamUser {
+                            name: format!("user {}", id),
+                            avatar_url: "".to_owned(),
+                            is_team_owner: id == user_ids[0],
+                            discord_id: None,
+                        },
+                    )
+                })
+                .collect(),
+            solves: BTreeMap::new(),
+            writeups: BTreeMap::new(),
+            owner_user_id: user_ids[0],
+            division_id: "invited".to_owned(),
+            last_division_change: None,
+            ctftime_id: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn user_list() {
+        let provider = UserListDivisionEligibilityProvider::new(&[1, 2], None);
+        assert_eq!(provider.is_user_eligible(1).await, Ok(true));
+        assert_eq!(
+            provider.is_user_eligible(3).await,
+            Err("Must be on the list of allowed users".to_owned())
+        );
+
+        let provider =
+            UserListDivisionEligibilityProvider::new(&[], Some("Invited only".to_owned()));
+        assert_eq!(
+            provider.is_user_eligible(1).await,
+            Err("Invited only".to_owned())
+        );
+    }
+
+    #[tokio::test]
+    async fn team_eligible_with_joining_user() {
+        let division = Division {
+            id: "invited".to_owned(),
+            name: "Invited".to_owned(),
+            description: "".to_owned(),
+            max_players: MaxDivisionPlayers::Unlimited,
+            is_default: false,
+            discord_role_id: None,
+            archived: false,
+            admin_managed: false,
+            division_eligibility: Arc::new(AllDivisionEligibilityProvider {
+                providers: vec![
+                    Arc::new(OpenDivisionEligibilityProvider {}),
+                    Arc::new(UserListDivisionEligibilityProvider::new(&[1, 2], None)),
+                ],
+            }),
+        };
+
+        assert_eq!(division.is_team_eligible(&team(&[1]), None).await, Ok(()));
+        assert_eq!(
+            division.is_team_eligible(&team(&[1]), Some(2)).await,
+            Ok(())
+        );
+
+        // the team itself is eligible, but the user joining it is not
+        assert_eq!(
+            division.is_team_eligible(&team(&[1, 2]), Some(3)).await,
+            Err("Must be on the list of allowed users".to_owned())
+        );
+        assert_eq!(
+            division.is_team_eligible(&team(&[1, 3]), None).await,
+            Err("Must be on the list of allowed users".to_owned())
+        );
+    }
+}
@@ -21,18 +21,23 @@ use crate::{
         discord::{Bot, DiscordAttachment},
         email::{provider::InboundEmail, reply_parser},
         settings::Settings,
+        tickets::add_ticket_message,
     },
     Result,
 };
 
 pub struct ImapEmailReciever {
     pub settings: Weak<RwLock<Settings>>,
-    pub bot: Weak<Bot>,
+    pub bot: Option<Weak<Bot>>,
     pub db: WeakConnection,
 }
 
 impl ImapEmailReciever {
-    pub fn new(settings: Weak<RwLock<Settings>>, bot: Weak<Bot>, db: WeakConnection) -> Self {
+    pub fn new(
+        settings: Weak<RwLock<Settings>>,
+        bot: Option<Weak<Bot>>,
+        db: WeakConnection,
+    ) -> Self {
         ImapEmailReciever { settings, bot, db }
     }
 }
@@ -45,11 +50,16 @@ impl InboundEmail for ImapEmailReciever {
 
         tokio::task::spawn(async move {
             loop {
-                let (Some(db), Some(bot), Some(settings)) =
-                    (db.upgrade(), bot.upgrade(), settings.upgrade())
-                else {
+                let (Some(db), Some(settings)) = (db.upgrade(), settings.upgrade()) else {
                     break;
                 };
+                let bot = match bot {
+                    Some(ref bot) => match bot.upgrade() {
+                        Some(bot) => Some(bot),
+                        None => break,
+                    },
+                    None => None,
+                };
 
                 let poll_interval = Duration::from_secs(
                     settings
@@ -65,7 +75,7 @@ impl InboundEmail for ImapEmailReciever {
                         .unwrap_or(30),
                 );
 
-                if let Err(e) = receive_emails(bot.clone(), db.clone(), settings.clone()).await {
+                if let Err(e) = receive_emails(bot, db.clone(), settings.clone()).await {
                     tracing::error!(error = ?e, "Failed to receive emails");
                 }
 
@@ -90,7 +100,7 @@ pub enum ImapError {
 }
 
 async fn receive_emails(
-    bot: Arc<Bot>,
+    bot: Option<Arc<Bot>>,
     db: Connection,
     settings: Arc<RwLock<Settings>>,
 ) -> std::result::Result<(), ImapError> {
@@ -213,18 +223,22 @@ async fn receive_emails(
                 })
                 .collect::<Vec<_>>();
 
-            if bot
-                .send_external_ticket_message(
-                    ticket.discord_channel_id,
-                    &user,
-                    from.as_deref(),
-                    &main_message,
-                    &attachments,
-                )
-                .await
-                .is_err()
+            if let Err(error) = add_ticket_message(
+                &db,
+                bot.as_deref(),
+                &ticket,
+                &user,
+                from.as_deref(),
+                &main_message,
+                &attachments,
+            )
+            .await
             {
-                tracing::error!("Failed to send external ticket message");
+                tracing::error!(
+                    ?error,
+                    ticket_number,
+                    "Failed to add ticket message from email"
+                );
             }
         }
     }
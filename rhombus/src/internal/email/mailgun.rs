@@ -22,6 +22,7 @@ use crate::{
         local_upload_provider::slice_to_hex_string,
         router::RouterState,
         settings::Settings,
+        tickets::add_ticket_message,
     },
 };
 
@@ -44,10 +45,6 @@ pub async fn route_mailgun_receive_email(
 ) -> impl IntoResponse {
     tracing::info!("recieving mailgun");
 
-    let Some(ref bot) = state.bot else {
-        return mailgun_error("Discord bot not configured");
-    };
-
     let Some(content_type) = headers.get("content-type") else {
         return mailgun_error("Content-Type not found");
     };
@@ -181,24 +178,28 @@ pub async fn route_mailgun_receive_email(
         return mailgun_error("Internal error");
     };
 
-    if bot
-        .send_external_ticket_message(
-            ticket.discord_channel_id,
-            &user,
-            from.as_deref(),
-            &main_message,
-            &attachments
-                .iter()
-                .map(|a| DiscordAttachment {
-                    data: &a.data,
-                    filename: &a.filename,
-                })
-                .collect::<Vec<_>>(),
-        )
-        .await
-        .is_err()
+    if let Err(error) = add_ticket_message(
+        &state.db,
+        state.bot.as_deref(),
+        &ticket,
+        &user,
+        from.as_deref(),
+        &main_message,
+        &attachments
+            .iter()
+            .map(|a| DiscordAttachment {
+                data: &a.data,
+                filename: &a.filename,
+            })
+            .collect::<Vec<_>>(),
+    )
+    .await
     {
-        tracing::error!("Failed to send external ticket message");
+        tracing::error!(
+            ?error,
+            ticket_number,
+            "Failed to add ticket message from email"
+        );
     }
 
     Response::builder()
@@ -3,9 +3,9 @@ use std::{fmt::Write, sync::Arc};
 use crate::{
     internal::{
         database::provider::{Connection, Ticket},
-        discord::DigestMessage,
         email::provider::OutboundEmailProvider,
         settings::Settings,
+        tickets::DigestMessage,
     },
     Result,
 };
@@ -43,6 +43,34 @@ impl OutboundMailer {
         ip: Option<&str>,
         to: &str,
         code: &str,
+    ) -> Result<()> {
+        self.send_verify(username, ip, to, &format!("/account/verify?code={}", code))
+            .await
+    }
+
+    /// Confirms the email of a newly registered credentials account, which signs them in
+    pub async fn send_registration_confirmation(
+        &self,
+        username: &str,
+        ip: Option<&str>,
+        to: &str,
+        code: &str,
+    ) -> Result<()> {
+        self.send_verify(
+            username,
+            ip,
+            to,
+            &format!("/signin/register/confirm?code={}", code),
+        )
+        .await
+    }
+
+    async fn send_verify(
+        &self,
+        username: &str,
+        ip: Option<&str>,
+        to: &str,
+        verify_path: &str,
     ) -> Result<()> {
         let (title, contact_email, location_url) = {
             let settings = self.settings.read().await;
@@ -59,7 +87,7 @@ impl OutboundMailer {
             username,
             ip,
             email => to,
-            verify_url => format!("{}/account/verify?code={}", location_url, code),
+            verify_url => format!("{}{}", location_url, verify_path),
             logo => format!("{}/{}", location_url, self.logo_path),
         };
 
@@ -138,6 +166,114 @@ impl OutboundMailer {
         Ok(())
     }
 
+    pub async fn send_password_reset(
+        &self,
+        username: &str,
+        ip: Option<&str>,
+        to: &str,
+        code: &str,
+    ) -> Result<()> {
+        let (title, contact_email, location_url) = {
+            let settings = self.settings.read().await;
+            (
+                settings.title.clone(),
+                settings.contact_email.clone(),
+                settings.location_url.clone(),
+            )
+        };
+
+        let context = context! {
+            title,
+            contact_email,
+            username,
+            ip,
+            email => to,
+            reset_url => format!("{}/signin/reset/confirm?code={}", location_url, code),
+            logo => format!("{}/{}", location_url, self.logo_path),
+        };
+
+        let plaintext = self
+            .jinja
+            .get_template("emails/password-reset.txt")
+            .unwrap()
+            .render(&context)
+            .unwrap();
+
+        let html = self
+            .jinja
+            .get_template("emails/password-reset.html")
+            .unwrap()
+            .render(&context)
+            .unwrap();
+
+        self.inner
+            .send_email(
+                to,
+                &format!("{} Password Reset", title),
+                &plaintext,
+                &html,
+                None,
+                &[],
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn send_team_invite(
+        &self,
+        inviter: &str,
+        team_name: &str,
+        to: &str,
+        code: &str,
+    ) -> Result<()> {
+        let (title, contact_email, location_url) = {
+            let settings = self.settings.read().await;
+            (
+                settings.title.clone(),
+                settings.contact_email.clone(),
+                settings.location_url.clone(),
+            )
+        };
+
+        let context = context! {
+            title,
+            contact_email,
+            inviter,
+            team_name,
+            email => to,
+            invite_url => format!("{}/team/invite/{}", location_url, code),
+            logo => format!("{}/{}", location_url, self.logo_path),
+        };
+
+        let plaintext = self
+            .jinja
+            .get_template("emails/team-invite.txt")
+            .unwrap()
+            .render(&context)
+            .unwrap();
+
+        let html = self
+            .jinja
+            .get_template("emails/team-invite.html")
+            .unwrap()
+            .render(&context)
+            .unwrap();
+
+        self.inner
+            .send_email(
+                to,
+                &format!("Join {} on {}", team_name, title),
+                &plaintext,
+                &html,
+                None,
+                &[],
+            )
+            .await?;
+
+        Ok(())
+    }
+
     pub async fn send_digest(&self, ticket: &Ticket, messages: &[DigestMessage<'_>]) -> Result<()> {
         let user_emails = self.db.get_emails_for_user_id(ticket.user_id).await?;
         let to = if let Some(email) = user_emails.iter().find(|e| e.verified) {
@@ -1,21 +1,41 @@
+use std::{pin::Pin, sync::Arc};
+
+use axum::body::Bytes;
+use futures::{Stream, StreamExt, TryStreamExt};
+use tokio::sync::RwLock;
+use tokio_util::io::StreamReader;
+use tonic::metadata::MetadataMap;
+
+use crate::errors::RhombusError;
 use crate::grpc::proto::rhombus_server::{Rhombus, RhombusServer};
+use crate::grpc::proto::upload_provider_selection::Provider;
 use crate::grpc::proto::whoami_reply::Whoami;
-use crate::grpc::proto::{self, User, WhoamiReply, FILE_DESCRIPTOR_SET};
+use crate::grpc::proto::{
+    self, BackupReply, CollectUploadGarbageReply, DivisionApproval, ListDivisionApprovalsReply,
+    MigrateUploadsReply, RestoreReply, SetDivisionApprovalReply, UploadProviderSelection, User,
+    WhoamiReply, FILE_DESCRIPTOR_SET,
+};
+use crate::internal::backup::{backup_stream, restore_backup};
 use crate::internal::database::provider::Connection;
+use crate::internal::settings::Settings;
+use crate::internal::upload_provider::{collect_garbage, migrate_uploads};
 use crate::plugin::RunContext;
+use crate::upload_provider::ErasedUploadProvider;
+use crate::{database_upload_provider::DatabaseUploadProvider, LocalUploadProvider};
 
 struct RhombusImpl {
     db: Connection,
     root_key: Option<String>,
+    settings: Arc<RwLock<Settings>>,
+    upload_provider: Arc<dyn ErasedUploadProvider>,
+    division_ids: Vec<String>,
 }
 
-#[tonic::async_trait]
-impl Rhombus for RhombusImpl {
-    async fn whoami(
+impl RhombusImpl {
+    async fn authenticate(
         &self,
-        request: tonic::Request<proto::WhoamiRequest>,
-    ) -> std::result::Result<tonic::Response<WhoamiReply>, tonic::Status> {
-        let (metadata, _, _) = request.into_parts();
+        metadata: &MetadataMap,
+    ) -> std::result::Result<Whoami, tonic::Status> {
         let key = metadata
             .get("authorization")
             .and_then(|s| s.to_str().ok())
@@ -28,9 +48,7 @@ impl Rhombus for RhombusImpl {
             .as_ref()
             .is_some_and(|root_key| key == root_key)
         {
-            return Ok(tonic::Response::new(WhoamiReply {
-                whoami: Some(Whoami::Root(())),
-            }));
+            return Ok(Whoami::Root(()));
         }
 
         let user = self
@@ -39,20 +57,282 @@ impl Rhombus for RhombusImpl {
             .await
             .map_err(|_| tonic::Status::unauthenticated("Invalid api key"))?;
 
+        Ok(Whoami::User(User {
+            id: user.id,
+            name: user.name.clone(),
+            is_admin: user.is_admin,
+        }))
+    }
+
+    async fn select_upload_provider(
+        &self,
+        selection: Option<UploadProviderSelection>,
+    ) -> std::result::Result<Arc<dyn ErasedUploadProvider>, tonic::Status> {
+        let Some(provider) = selection.and_then(|selection| selection.provider) else {
+            return Ok(self.upload_provider.clone());
+        };
+
+        Ok(match provider {
+            Provider::Local(folder) => Arc::new(LocalUploadProvider::new(folder.into())),
+            Provider::Database(()) => Arc::new(DatabaseUploadProvider::new(self.db.clone()).await),
+            Provider::S3(()) => {
+                #[cfg(feature = "s3")]
+                {
+                    let s3 = self
+                        .settings
+                        .read()
+                        .await
+                        .uploads
+                        .as_ref()
+                        .and_then(|uploads| uploads.s3.clone())
+                        .ok_or_else(|| {
+                            tonic::Status::failed_precondition("S3 is not configured")
+                        })?;
+                    let s3_upload_provider = crate::s3_upload_provider::S3UploadProvider::new(&s3)
+                        .await
+                        .map_err(|e| {
+                            tracing::error!(error = ?e, "Failed to create S3 upload provider");
+                            tonic::Status::internal("Failed to create S3 upload provider")
+                        })?;
+                    Arc::new(s3_upload_provider)
+                }
+
+                #[cfg(not(feature = "s3"))]
+                {
+                    _ = &self.settings;
+                    return Err(tonic::Status::failed_precondition(
+                        "S3 requires the `s3` feature to be enabled",
+                    ));
+                }
+            }
+        })
+    }
+}
+
+#[tonic::async_trait]
+impl Rhombus for RhombusImpl {
+    async fn whoami(
+        &self,
+        request: tonic::Request<proto::WhoamiRequest>,
+    ) -> std::result::Result<tonic::Response<WhoamiReply>, tonic::Status> {
+        let whoami = self.authenticate(request.metadata()).await?;
+
         Ok(tonic::Response::new(WhoamiReply {
-            whoami: Some(Whoami::User(User {
-                id: user.id,
-                name: user.name.clone(),
-                is_admin: user.is_admin,
-            })),
+            whoami: Some(whoami),
         }))
     }
+
+    type BackupStream =
+        Pin<Box<dyn Stream<Item = std::result::Result<BackupReply, tonic::Status>> + Send>>;
+
+    async fn backup(
+        &self,
+        request: tonic::Request<proto::BackupRequest>,
+    ) -> std::result::Result<tonic::Response<Self::BackupStream>, tonic::Status> {
+        match self.authenticate(request.metadata()).await? {
+            Whoami::Root(()) => {}
+            Whoami::User(user) if user.is_admin => {}
+            Whoami::User(_) => return Err(tonic::Status::permission_denied("Must be an admin")),
+        }
+
+        let stream = backup_stream(&self.db, self.upload_provider.clone())
+            .await
+            .map_err(|e| {
+                tracing::error!(error = ?e, "Failed to create backup");
+                backup_status(e, "Failed to create backup")
+            })?
+            .map(|chunk| {
+                chunk
+                    .map(|chunk| BackupReply {
+                        chunk: chunk.to_vec(),
+                    })
+                    .map_err(|e| {
+                        tracing::error!(error = ?e, "Failed to create backup");
+                        tonic::Status::internal("Failed to create backup")
+                    })
+            });
+
+        Ok(tonic::Response::new(Box::pin(stream)))
+    }
+
+    async fn restore(
+        &self,
+        request: tonic::Request<tonic::Streaming<proto::RestoreRequest>>,
+    ) -> std::result::Result<tonic::Response<RestoreReply>, tonic::Status> {
+        if !matches!(
+            self.authenticate(request.metadata()).await?,
+            Whoami::Root(())
+        ) {
+            return Err(tonic::Status::permission_denied(
+                "Restoring a backup requires the root api key",
+            ));
+        }
+
+        let chunks = request
+            .into_inner()
+            .map_ok(|request| Bytes::from(request.chunk))
+            .map_err(std::io::Error::other);
+
+        restore_backup(&self.db, &self.upload_provider, StreamReader::new(chunks))
+            .await
+            .map_err(|e| {
+                tracing::error!(error = ?e, "Failed to restore backup");
+                backup_status(e, "Failed to restore backup")
+            })?;
+
+        Ok(tonic::Response::new(RestoreReply {}))
+    }
+
+    async fn migrate_uploads(
+        &self,
+        request: tonic::Request<proto::MigrateUploadsRequest>,
+    ) -> std::result::Result<tonic::Response<MigrateUploadsReply>, tonic::Status> {
+        if !matches!(
+            self.authenticate(request.metadata()).await?,
+            Whoami::Root(())
+        ) {
+            return Err(tonic::Status::permission_denied(
+                "Migrating uploads requires the root api key",
+            ));
+        }
+
+        let request = request.into_inner();
+        if request
+            .from
+            .as_ref()
+            .and_then(|from| from.provider.as_ref())
+            .is_none()
+        {
+            return Err(tonic::Status::invalid_argument(
+                "Missing upload provider to migrate from",
+            ));
+        }
+
+        let from = self.select_upload_provider(request.from).await?;
+        let to = self.select_upload_provider(request.to).await?;
+
+        let migrated = migrate_uploads(&self.db, &from, &to).await.map_err(|e| {
+            tracing::error!(error = ?e, "Failed to migrate uploads");
+            tonic::Status::internal("Failed to migrate uploads, no attachments were changed")
+        })?;
+
+        Ok(tonic::Response::new(MigrateUploadsReply {
+            migrated: migrated as u64,
+        }))
+    }
+
+    async fn collect_upload_garbage(
+        &self,
+        request: tonic::Request<proto::CollectUploadGarbageRequest>,
+    ) -> std::result::Result<tonic::Response<CollectUploadGarbageReply>, tonic::Status> {
+        if !matches!(
+            self.authenticate(request.metadata()).await?,
+            Whoami::Root(())
+        ) {
+            return Err(tonic::Status::permission_denied(
+                "Collecting upload garbage requires the root api key",
+            ));
+        }
+
+        let orphaned = collect_garbage(&self.db, &self.upload_provider, request.get_ref().dry_run)
+            .await
+            .map_err(|e| {
+                tracing::error!(error = ?e, "Failed to collect upload garbage");
+                tonic::Status::internal("Failed to collect upload garbage")
+            })?;
+
+        Ok(tonic::Response::new(CollectUploadGarbageReply { orphaned }))
+    }
+
+    async fn list_division_approvals(
+        &self,
+        request: tonic::Request<proto::ListDivisionApprovalsRequest>,
+    ) -> std::result::Result<tonic::Response<ListDivisionApprovalsReply>, tonic::Status> {
+        match self.authenticate(request.metadata()).await? {
+            Whoami::Root(()) => {}
+            Whoami::User(user) if user.is_admin => {}
+            Whoami::User(_) => return Err(tonic::Status::permission_denied("Must be an admin")),
+        }
+
+        let approvals = self
+            .db
+            .get_division_approvals(request.get_ref().include_decided)
+            .await
+            .map_err(|e| {
+                tracing::error!(error = ?e, "Failed to get division approvals");
+                tonic::Status::internal("Failed to get division approvals")
+            })?
+            .into_iter()
+            .map(|approval| DivisionApproval {
+                division_id: approval.division_id,
+                user_id: approval.user_id,
+                user_name: approval.user_name,
+                approved: approval.approved,
+                requested_at: approval.requested_at.timestamp(),
+            })
+            .collect();
+
+        Ok(tonic::Response::new(ListDivisionApprovalsReply {
+            approvals,
+        }))
+    }
+
+    async fn set_division_approval(
+        &self,
+        request: tonic::Request<proto::SetDivisionApprovalRequest>,
+    ) -> std::result::Result<tonic::Response<SetDivisionApprovalReply>, tonic::Status> {
+        match self.authenticate(request.metadata()).await? {
+            Whoami::Root(()) => {}
+            Whoami::User(user) if user.is_admin => {}
+            Whoami::User(_) => return Err(tonic::Status::permission_denied("Must be an admin")),
+        }
+
+        let request = request.into_inner();
+        if !self.division_ids.contains(&request.division_id) {
+            return Err(tonic::Status::not_found("Division not found"));
+        }
+
+        self.db
+            .set_division_approval(&request.division_id, request.user_id, request.approved)
+            .await
+            .map_err(|e| {
+                tracing::error!(error = ?e, "Failed to set division approval");
+                tonic::Status::internal("Failed to set division approval")
+            })?;
+
+        tracing::info!(
+            division_id = request.division_id,
+            user_id = request.user_id,
+            approved = request.approved,
+            "Set division approval"
+        );
+
+        Ok(tonic::Response::new(SetDivisionApprovalReply {}))
+    }
+}
+
+fn backup_status(error: RhombusError, message: &str) -> tonic::Status {
+    match error {
+        RhombusError::UnsupportedDatabaseOperation(_) => {
+            tonic::Status::unimplemented("Backups are only supported with the libSQL database")
+        }
+        RhombusError::Backup(message) => tonic::Status::invalid_argument(message),
+        RhombusError::Json(_) => tonic::Status::invalid_argument("Invalid or unsupported archive"),
+        _ => tonic::Status::internal(message),
+    }
 }
 
 pub async fn init_grpc<'a>(run_context: &mut RunContext<'a>) {
     let service = RhombusImpl {
         db: run_context.db.clone(),
         root_key: run_context.settings.read().await.root_api_key.clone(),
+        settings: run_context.settings.clone(),
+        upload_provider: run_context.upload_provider.clone(),
+        division_ids: run_context
+            .divisions
+            .iter()
+            .map(|division| division.id.clone())
+            .collect(),
     };
     run_context
         .grpc_builder
@@ -14,7 +14,11 @@ use std::{
 };
 use tower_governor::{key_extractor::KeyExtractor, GovernorError};
 
-use crate::internal::{auth::MaybeUser, database::provider::Connection, router::RouterState};
+use crate::internal::{
+    auth::{MaybeTokenClaims, MaybeUser},
+    database::provider::Connection,
+    router::RouterState,
+};
 
 pub fn track_flusher(db: Connection) {
     tokio::task::spawn(async move {
@@ -40,6 +44,25 @@ pub fn track_flusher(db: Connection) {
             if total_count > 0 {
                 tracing::trace!(count = total_count, "Flushed tracks");
             }
+
+            let session_ids = SESSION_TRACK_CACHE
+                .iter()
+                .map(|session| session.key().clone())
+                .collect::<Vec<_>>();
+            for session_id in session_ids {
+                let Some((session_id, (ip, user_agent, last_seen_at))) =
+                    SESSION_TRACK_CACHE.remove(&session_id)
+                else {
+                    continue;
+                };
+                if let Err(e) = db
+                    .touch_session(&session_id, ip, user_agent.as_deref(), last_seen_at)
+                    .await
+                {
+                    tracing::error!(error = ?e, "Failed to flush session");
+                }
+            }
+            SESSION_TRACK_CACHE.shrink_to_fit();
         }
     });
 }
@@ -48,22 +71,39 @@ pub type TrackKey = (IpAddr, Option<String>, Option<i64>);
 
 pub static TRACK_CACHE: LazyLock<DashMap<TrackKey, u64>> = LazyLock::new(DashMap::new);
 
+/// The latest IP, user agent and timestamp of a session
+pub type SessionTrack = (Option<IpAddr>, Option<String>, i64);
+
+/// Sessions with requests since the last flush
+pub static SESSION_TRACK_CACHE: LazyLock<DashMap<String, SessionTrack>> =
+    LazyLock::new(DashMap::new);
+
 /// Middleware to log the IP and user agent of the client in the database as track.
 /// Associates the track with the user if the user is logged in. Runs asynchronously,
 /// so it does not block the request and passes on to the next middleware immediately.
 pub async fn track_middleware(
     Extension(ip): Extension<Option<IpAddr>>,
     Extension(user): Extension<MaybeUser>,
+    Extension(token_claims): Extension<MaybeTokenClaims>,
     uri: Uri,
     req: Request<Body>,
     next: Next,
 ) -> impl IntoResponse {
+    let user_agent = req
+        .headers()
+        .get(&USER_AGENT)
+        .and_then(|header| header.to_str().ok())
+        .map(|user_agent| truncate_to_256_chars(user_agent).to_string());
+
+    if let Some(token_claims) = token_claims {
+        SESSION_TRACK_CACHE.insert(
+            token_claims.sid,
+            (ip, user_agent.clone(), chrono::Utc::now().timestamp()),
+        );
+    }
+
     if let Some(ip) = ip {
         let user_id = user.as_ref().map(|u| u.id);
-        let user_agent = req
-            .headers()
-            .get(&USER_AGENT)
-            .map(|header| truncate_to_256_chars(header.to_str().unwrap()).to_string());
 
         tracing::trace!(user_id, uri = uri.to_string(), "Request");
         tokio::task::spawn(async move {
@@ -198,6 +238,41 @@ pub fn canonicalize_ip(ip: IpAddr) -> IpAddr {
     }
 }
 
+/// Describes the browser and operating system of a user agent for listing sessions, such as
+/// "Firefox on Linux"
+pub fn describe_user_agent(user_agent: &str) -> Option<String> {
+    const BROWSERS: &[(&str, &str)] = &[
+        ("Edg/", "Edge"),
+        ("OPR/", "Opera"),
+        ("Firefox/", "Firefox"),
+        ("Chrome/", "Chrome"),
+        ("Safari/", "Safari"),
+        ("curl/", "curl"),
+    ];
+    const SYSTEMS: &[(&str, &str)] = &[
+        ("Windows", "Windows"),
+        ("Android", "Android"),
+        ("iPhone", "iOS"),
+        ("iPad", "iPadOS"),
+        ("Mac OS X", "macOS"),
+        ("CrOS", "ChromeOS"),
+        ("Linux", "Linux"),
+    ];
+
+    let find = |names: &[(&str, &'static str)]| {
+        names
+            .iter()
+            .find(|(needle, _)| user_agent.contains(needle))
+            .map(|(_, name)| *name)
+    };
+
+    match (find(BROWSERS), find(SYSTEMS)) {
+        (Some(browser), Some(system)) => Some(format!("{} on {}", browser, system)),
+        (Some(name), None) | (None, Some(name)) => Some(name.to_owned()),
+        (None, None) => None,
+    }
+}
+
 #[cfg(test)]
 mod test {
     use std::net::IpAddr;
@@ -205,7 +280,7 @@ mod test {
     use axum::{body::Body, http::Request};
 
     use crate::internal::ip::{
-        canonicalize_ip, maybe_cf_connecting_ip, maybe_fly_client_ip,
+        canonicalize_ip, describe_user_agent, maybe_cf_connecting_ip, maybe_fly_client_ip,
         maybe_rightmost_x_forwarded_for, maybe_true_client_ip, maybe_x_real_ip,
     };
 
@@ -325,4 +400,25 @@ mod test {
         let result = canonicalize_ip(ip);
         assert_eq!(ip_want, result);
     }
+
+    #[test]
+    fn user_agent_description() {
+        assert_eq!(
+            describe_user_agent(
+                "Mozilla/5.0 (X11; Linux x86_64; rv:131.0) Gecko/20100101 Firefox/131.0"
+            )
+            .as_deref(),
+            Some("Firefox on Linux")
+        );
+        assert_eq!(
+            describe_user_agent("Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/130.0.0.0 Safari/537.36 Edg/130.0.0.0").as_deref(),
+            Some("Edge on Windows")
+        );
+        assert_eq!(
+            describe_user_agent("Mozilla/5.0 (iPhone; CPU iPhone OS 18_0 like Mac OS X) AppleWebKit/605.1.15 (KHTML, like Gecko) Version/18.0 Mobile/15E148 Safari/604.1").as_deref(),
+            Some("Safari on iOS")
+        );
+        assert_eq!(describe_user_agent("curl/8.5.0").as_deref(), Some("curl"));
+        assert_eq!(describe_user_agent("something"), None);
+    }
 }
@@ -0,0 +1,278 @@
+//! Sign in against an LDAP directory. Users are found with a search, as the configured service
+//! account or anonymously, and then authenticated by binding as their own entry.
+
+use std::time::Duration;
+
+use ldap3::{ldap_escape, Ldap, LdapConnAsync, LdapConnSettings, Scope, SearchEntry};
+
+use crate::{internal::settings::LdapSettings, Result};
+
+/// A user whose password was accepted by the directory
+#[derive(Debug)]
+pub struct LdapUser {
+    pub dn: String,
+    pub name: Option<String>,
+    pub email: Option<String>,
+    /// DNs of the groups the user is a member of
+    pub groups: Vec<String>,
+}
+
+impl LdapUser {
+    pub fn is_member(&self, group: &str) -> bool {
+        self.groups
+            .iter()
+            .any(|dn| dn.trim().eq_ignore_ascii_case(group.trim()))
+    }
+}
+
+/// Replaces the `{name}` placeholders of a search filter in a single pass, so values can never
+/// introduce placeholders of their own
+fn fill_filter(filter: &str, values: &[(&str, &str)]) -> String {
+    let mut filled = String::with_capacity(filter.len());
+    let mut rest = filter;
+    while let Some(start) = rest.find('{') {
+        filled.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        let value = rest.find('}').and_then(|end| {
+            values
+                .iter()
+                .find(|(name, _)| *name == &rest[1..end])
+                .map(|(_, value)| (ldap_escape(*value), end))
+        });
+
+        match value {
+            Some((value, end)) => {
+                filled.push_str(&value);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                filled.push('{');
+                rest = &rest[1..];
+            }
+        }
+    }
+    filled.push_str(rest);
+    filled
+}
+
+/// Reads the values of an attribute, whose name servers may return in a different case
+fn attribute_values<'a>(entry: &'a SearchEntry, attribute: &str) -> Option<&'a Vec<String>> {
+    entry
+        .attrs
+        .iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case(attribute))
+        .map(|(_, values)| values)
+}
+
+async fn bind_service_account(ldap: &mut Ldap, settings: &LdapSettings) -> Result<()> {
+    if let Some(bind_dn) = &settings.bind_dn {
+        ldap.simple_bind(
+            bind_dn,
+            settings.bind_password.as_deref().unwrap_or_default(),
+        )
+        .await?
+        .success()?;
+    }
+    Ok(())
+}
+
+/// Checks the username and password against the directory, returning the user if they are valid
+pub async fn authenticate(
+    settings: &LdapSettings,
+    username: &str,
+    password: &str,
+) -> Result<Option<LdapUser>> {
+    // binding with an empty password is an unauthenticated bind, which most servers accept
+    if username.is_empty() || password.is_empty() {
+        return Ok(None);
+    }
+
+    let connection_settings = LdapConnSettings::new()
+        .set_conn_timeout(Duration::from_secs(10))
+        .set_starttls(settings.starttls.unwrap_or(false));
+    let (connection, mut ldap) =
+        LdapConnAsync::with_settings(connection_settings, &settings.url).await?;
+    ldap3::drive!(connection);
+
+    let result = search_and_bind(&mut ldap, settings, username, password).await;
+    _ = ldap.unbind().await;
+    result
+}
+
+async fn search_and_bind(
+    ldap: &mut Ldap,
+    settings: &LdapSettings,
+    username: &str,
+    password: &str,
+) -> Result<Option<LdapUser>> {
+    bind_service_account(ldap, settings).await?;
+
+    let name_attribute = settings.name_attribute.as_deref().unwrap_or("cn");
+    let email_attribute = settings.email_attribute.as_deref().unwrap_or("mail");
+    let filter = fill_filter(
+        settings
+            .user_filter
+            .as_deref()
+            .unwrap_or("(uid={username})"),
+        &[("username", username)],
+    );
+
+    let (entries, _) = ldap
+        .search(
+            &settings.base_dn,
+            Scope::Subtree,
+            &filter,
+            vec![name_attribute, email_attribute, "memberOf"],
+        )
+        .await?
+        .success()?;
+
+    // unknown and ambiguous usernames can not sign in
+    let mut entries = entries.into_iter();
+    let (Some(entry), None) = (entries.next(), entries.next()) else {
+        return Ok(None);
+    };
+    let entry = SearchEntry::construct(entry);
+
+    if ldap
+        .simple_bind(&entry.dn, password)
+        .await?
+        .success()
+        .is_err()
+    {
+        return Ok(None);
+    }
+
+    let groups = if let Some(group_base_dn) = &settings.group_base_dn {
+        // the user may not be allowed to read groups
+        bind_service_account(ldap, settings).await?;
+
+        let filter = fill_filter(
+            settings.group_filter.as_deref().unwrap_or("(member={dn})"),
+            &[("dn", &entry.dn), ("username", username)],
+        );
+        let (groups, _) = ldap
+            .search(group_base_dn, Scope::Subtree, &filter, vec!["1.1"])
+            .await?
+            .success()?;
+        groups
+            .into_iter()
+            .map(|group| SearchEntry::construct(group).dn)
+            .collect()
+    } else {
+        attribute_values(&entry, "memberOf")
+            .cloned()
+            .unwrap_or_default()
+    };
+
+    let first_value = |attribute: &str| {
+        attribute_values(&entry, attribute)
+            .and_then(|values| values.first())
+            .map(|value| value.trim().to_owned())
+            .filter(|value| !value.is_empty())
+    };
+
+    Ok(Some(LdapUser {
+        name: first_value(name_attribute),
+        email: first_value(email_attribute),
+        dn: entry.dn.clone(),
+        groups,
+    }))
+}
+
+/// The id of the division mapped to the first group the user is a member of
+pub fn mapped_division<'a>(settings: &'a LdapSettings, user: &LdapUser) -> Option<&'a str> {
+    settings
+        .division_groups
+        .iter()
+        .flatten()
+        .find(|division_group| user.is_member(&division_group.group))
+        .map(|division_group| division_group.division.as_str())
+}
+
+#[cfg(test)]
+mod test {
+    use testcontainers::runners::AsyncRunner;
+    use testcontainers_modules::openldap::OpenLDAP;
+
+    use super::*;
+
+    #[test]
+    fn filter_placeholders() {
+        assert_eq!(
+            fill_filter("(uid={username})", &[("username", "bob")]),
+            "(uid=bob)"
+        );
+        assert_eq!(
+            fill_filter("(uid={username})", &[("username", "*)(uid=*")]),
+            "(uid=\\2a\\29\\28uid=\\2a)"
+        );
+        assert_eq!(
+            fill_filter(
+                "(|(member={dn})(memberUid={username}))",
+                &[("dn", "cn={username},dc=org"), ("username", "bob")]
+            ),
+            "(|(member=cn={username},dc=org)(memberUid=bob))"
+        );
+        assert_eq!(fill_filter("(cn={other}", &[]), "(cn={other}");
+    }
+
+    #[test]
+    fn group_membership() {
+        let user = LdapUser {
+            dn: "cn=bob,ou=users,dc=example,dc=org".to_owned(),
+            name: None,
+            email: None,
+            groups: vec!["cn=Admins,ou=groups,dc=example,dc=org".to_owned()],
+        };
+        assert!(user.is_member("cn=admins,ou=groups,dc=example,dc=org"));
+        assert!(!user.is_member("cn=players,ou=groups,dc=example,dc=org"));
+    }
+
+    #[cfg_attr(not(feature = "testcontainers"), ignore)]
+    #[tokio::test]
+    async fn openldap_sign_in() {
+        let openldap_instance = OpenLDAP::default()
+            .with_user("bob", "password")
+            .start()
+            .await
+            .unwrap();
+        let settings = LdapSettings {
+            url: format!(
+                "ldap://127.0.0.1:{}",
+                openldap_instance.get_host_port_ipv4(1389).await.unwrap()
+            ),
+            starttls: None,
+            bind_dn: Some("cn=admin,dc=example,dc=org".to_owned()),
+            bind_password: Some("adminpassword".to_owned()),
+            base_dn: "ou=users,dc=example,dc=org".to_owned(),
+            user_filter: Some("(cn={username})".to_owned()),
+            name_attribute: None,
+            email_attribute: None,
+            group_base_dn: Some("dc=example,dc=org".to_owned()),
+            group_filter: None,
+            admin_group: None,
+            division_groups: None,
+            display_name: None,
+        };
+
+        let user = authenticate(&settings, "bob", "password")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(user.dn, "cn=bob,ou=users,dc=example,dc=org");
+        assert_eq!(user.name.as_deref(), Some("bob"));
+        assert!(user.is_member("cn=readers,ou=users,dc=example,dc=org"));
+
+        assert!(authenticate(&settings, "bob", "wrong")
+            .await
+            .unwrap()
+            .is_none());
+        assert!(authenticate(&settings, "bob", "").await.unwrap().is_none());
+        assert!(authenticate(&settings, "alice", "password")
+            .await
+            .unwrap()
+            .is_none());
+    }
+}
@@ -3,6 +3,7 @@ use std::{fmt::Write, sync::Arc, task::Poll};
 use axum::{
     body::Body,
     extract::{Request, State},
+    http::header,
     response::IntoResponse,
     Extension,
 };
@@ -12,7 +13,10 @@ use tokio::io::AsyncRead;
 use tower_http::services::ServeFile;
 
 use crate::{
-    internal::{auth::MaybeUser, upload_provider::validate_simple_filename},
+    internal::{
+        auth::MaybeUser,
+        upload_provider::{if_none_match, upload_etag, validate_simple_filename},
+    },
     LocalUploadProvider,
 };
 
@@ -28,7 +32,7 @@ pub async fn route_local_download(
         return (StatusCode::BAD_REQUEST, "Invalid path".to_owned()).into_response();
     }
 
-    let (_, filename) = if let Some(parts) = path.split_once('-') {
+    let (hash, filename) = if let Some(parts) = path.split_once('-') {
         parts
     } else {
         return (StatusCode::BAD_REQUEST, "Invalid path".to_owned()).into_response();
@@ -43,13 +47,22 @@ pub async fn route_local_download(
         }
     };
 
+    let etag = upload_etag(hash);
+    if if_none_match(req.headers(), &etag) {
+        return (StatusCode::NOT_MODIFIED, [(header::ETAG, etag)]).into_response();
+    }
+
     tracing::info!(
         path = filepath.to_str().unwrap(),
         user_id = maybe_user.map(|u| u.id),
         "Downloading from local file"
     );
 
+    // ServeFile takes care of range requests
     let mut response = ServeFile::new(&filepath).try_call(req).await.unwrap();
+    response
+        .headers_mut()
+        .insert(header::ETAG, etag.parse().unwrap());
     response.headers_mut().insert(
         "Content-Disposition",
         format!("attachment; filename={}", &filename)
@@ -1,4 +1,5 @@
 pub mod auth;
+pub mod backup;
 pub mod command_palette;
 pub mod database;
 pub mod discord;
@@ -8,12 +9,18 @@ pub mod errors;
 pub mod grpc;
 pub mod health;
 pub mod ip;
+#[cfg(feature = "ldap")]
+pub mod ldap;
 pub mod local_upload_provider;
 pub mod locales;
 pub mod open_graph;
+pub mod proof_of_work;
 pub mod router;
 pub mod routes;
 pub mod settings;
 pub mod static_serve;
 pub mod templates;
+pub mod tickets;
+pub mod totp;
 pub mod upload_provider;
+pub mod webauthn;
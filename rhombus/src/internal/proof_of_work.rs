@@ -0,0 +1,257 @@
+//! A hashcash style proof of work which can be required before submitting forms, to slow down
+//! automated sign ups, sign in email spam and submissions without relying on an external service.
+//!
+//! The browser fetches a signed challenge and searches for a nonce such that
+//! `sha256("{challenge}:{nonce}")` starts with at least `difficulty` zero bits.
+
+use std::sync::LazyLock;
+
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    response::IntoResponse,
+    Json,
+};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use dashmap::DashMap;
+use jsonwebtoken::{decode, encode, DecodingKey, EncodingKey, Header, Validation};
+use rand::{thread_rng, RngCore};
+use ring::digest;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::internal::{router::RouterState, settings::ProofOfWorkSettings};
+
+/// Difficulties above this would take far too long to solve in a browser
+const MAX_DIFFICULTY: u32 = 32;
+
+/// The routes a proof of work can be required on
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ProofOfWorkRoute {
+    Credentials,
+    Register,
+    Email,
+    Ticket,
+    Flag,
+}
+
+impl ProofOfWorkRoute {
+    /// The configured difficulty for this route, or none if it does not require a proof of work
+    pub fn difficulty(self, settings: &ProofOfWorkSettings) -> Option<u32> {
+        match self {
+            ProofOfWorkRoute::Credentials => settings.credentials,
+            ProofOfWorkRoute::Register => settings.register,
+            ProofOfWorkRoute::Email => settings.email,
+            ProofOfWorkRoute::Ticket => settings.ticket,
+            ProofOfWorkRoute::Flag => settings.flag,
+        }
+        .filter(|&difficulty| difficulty > 0)
+        .map(|difficulty| difficulty.min(MAX_DIFFICULTY))
+    }
+}
+
+/// Claims of the token handed to the browser with a challenge
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ProofOfWorkClaims {
+    pub challenge: String,
+    pub route: ProofOfWorkRoute,
+    pub difficulty: u32,
+    pub exp: i64,
+}
+
+/// The solution fields submitted along with a protected form
+#[derive(Debug, Deserialize, Default)]
+pub struct ProofOfWorkSolution {
+    pub proof_of_work_token: Option<String>,
+    pub proof_of_work_nonce: Option<String>,
+}
+
+/// Challenge tokens are signed with a derived key, so they are never accepted as a `rhombus-token`
+fn challenge_key(jwt_secret: &str) -> Vec<u8> {
+    format!("{}:proof-of-work", jwt_secret).into_bytes()
+}
+
+/// Creates a new random challenge, returning it along with a token to verify it later
+pub fn create_challenge(
+    jwt_secret: &str,
+    route: ProofOfWorkRoute,
+    difficulty: u32,
+) -> (String, String) {
+    let mut challenge = [0u8; 16];
+    thread_rng().fill_bytes(&mut challenge);
+    let challenge = URL_SAFE_NO_PAD.encode(challenge);
+
+    let claims = ProofOfWorkClaims {
+        challenge: challenge.clone(),
+        route,
+        difficulty,
+        exp: (chrono::Utc::now() + chrono::Duration::minutes(10)).timestamp(),
+    };
+    let token = encode(
+        &Header::default(),
+        &claims,
+        &EncodingKey::from_secret(&challenge_key(jwt_secret)),
+    )
+    .unwrap();
+
+    (challenge, token)
+}
+
+pub fn decode_challenge(jwt_secret: &str, token: &str) -> Option<ProofOfWorkClaims> {
+    decode::<ProofOfWorkClaims>(
+        token,
+        &DecodingKey::from_secret(&challenge_key(jwt_secret)),
+        &Validation::default(),
+    )
+    .ok()
+    .map(|data| data.claims)
+}
+
+fn leading_zero_bits(hash: &[u8]) -> u32 {
+    let mut bits = 0;
+    for byte in hash {
+        bits += byte.leading_zeros();
+        if *byte != 0 {
+            break;
+        }
+    }
+    bits
+}
+
+/// Checks the nonce solves the challenge at the given difficulty
+pub fn verify_solution(challenge: &str, nonce: &str, difficulty: u32) -> bool {
+    if nonce.is_empty() || nonce.len() > 32 {
+        return false;
+    }
+
+    let hash = digest::digest(
+        &digest::SHA256,
+        format!("{}:{}", challenge, nonce).as_bytes(),
+    );
+    leading_zero_bits(hash.as_ref()) >= difficulty
+}
+
+/// Challenges which have already been redeemed, mapped to when their token expires
+static REDEEMED_CHALLENGES: LazyLock<DashMap<String, i64>> = LazyLock::new(DashMap::new);
+
+/// Verifies the submitted solution if the route requires a proof of work. Each challenge can only
+/// be redeemed once.
+pub async fn verify_proof_of_work(
+    state: &RouterState,
+    route: ProofOfWorkRoute,
+    solution: &ProofOfWorkSolution,
+) -> bool {
+    let (difficulty, jwt_secret) = {
+        let settings = state.settings.read().await;
+        let Some(difficulty) = settings
+            .proof_of_work
+            .as_ref()
+            .and_then(|proof_of_work| route.difficulty(proof_of_work))
+        else {
+            return true;
+        };
+        (difficulty, settings.jwt_secret.clone())
+    };
+
+    let (Some(token), Some(nonce)) = (&solution.proof_of_work_token, &solution.proof_of_work_nonce)
+    else {
+        return false;
+    };
+
+    let Some(claims) = decode_challenge(&jwt_secret, token) else {
+        return false;
+    };
+
+    if claims.route != route
+        || claims.difficulty < difficulty
+        || !verify_solution(&claims.challenge, nonce, claims.difficulty)
+    {
+        return false;
+    }
+
+    let now = chrono::Utc::now().timestamp();
+    REDEEMED_CHALLENGES.retain(|_, exp| *exp > now);
+    REDEEMED_CHALLENGES
+        .insert(claims.challenge, claims.exp)
+        .is_none()
+}
+
+#[derive(Deserialize)]
+pub struct ProofOfWorkParams {
+    route: ProofOfWorkRoute,
+}
+
+pub async fn route_proof_of_work_challenge(
+    State(state): State<RouterState>,
+    Query(params): Query<ProofOfWorkParams>,
+) -> impl IntoResponse {
+    let (difficulty, jwt_secret) = {
+        let settings = state.settings.read().await;
+        let Some(difficulty) = settings
+            .proof_of_work
+            .as_ref()
+            .and_then(|proof_of_work| params.route.difficulty(proof_of_work))
+        else {
+            return StatusCode::NOT_FOUND.into_response();
+        };
+        (difficulty, settings.jwt_secret.clone())
+    };
+
+    let (challenge, token) = create_challenge(&jwt_secret, params.route, difficulty);
+
+    Json(json!({
+        "token": token,
+        "challenge": challenge,
+        "difficulty": difficulty,
+    }))
+    .into_response()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn zero_bits() {
+        assert_eq!(leading_zero_bits(&[0, 0, 0xff]), 16);
+        assert_eq!(leading_zero_bits(&[0, 0x10, 0]), 11);
+        assert_eq!(leading_zero_bits(&[0x80]), 0);
+        assert_eq!(leading_zero_bits(&[0, 0]), 16);
+    }
+
+    #[test]
+    fn solve_challenge() {
+        let (challenge, token) = create_challenge("secret", ProofOfWorkRoute::Flag, 8);
+        let claims = decode_challenge("secret", &token).unwrap();
+        assert_eq!(claims.challenge, challenge);
+        assert_eq!(claims.route, ProofOfWorkRoute::Flag);
+        assert!(decode_challenge("other secret", &token).is_none());
+
+        let nonce = (0u64..)
+            .map(|nonce| nonce.to_string())
+            .find(|nonce| verify_solution(&challenge, nonce, 8))
+            .unwrap();
+        let hash = digest::digest(&digest::SHA256, format!("{challenge}:{nonce}").as_bytes());
+        assert_eq!(hash.as_ref()[0], 0);
+        assert!(!verify_solution(&challenge, "", 0));
+    }
+
+    #[test]
+    fn route_difficulty() {
+        let settings = ProofOfWorkSettings {
+            credentials: Some(16),
+            register: Some(0),
+            email: None,
+            ticket: Some(64),
+            flag: None,
+        };
+        assert_eq!(
+            ProofOfWorkRoute::Credentials.difficulty(&settings),
+            Some(16)
+        );
+        assert_eq!(ProofOfWorkRoute::Register.difficulty(&settings), None);
+        assert_eq!(ProofOfWorkRoute::Email.difficulty(&settings), None);
+        assert_eq!(ProofOfWorkRoute::Ticket.difficulty(&settings), Some(32));
+    }
+}
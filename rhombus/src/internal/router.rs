@@ -16,18 +16,21 @@ use axum::{
 use tokio::sync::{Mutex, RwLock};
 use tower::{make::Shared, Service, ServiceExt};
 
-use crate::internal::{
-    database::provider::Connection,
-    discord::Bot,
-    division::Division,
-    email::outbound_mailer::OutboundMailer,
-    ip::IpExtractorFn,
-    locales::Localizations,
-    routes::{
-        challenges::{ChallengeFlag, ChallengePoints},
-        meta::GlobalPageMeta,
+use crate::{
+    internal::{
+        database::provider::Connection,
+        discord::Bot,
+        division::Division,
+        email::outbound_mailer::OutboundMailer,
+        ip::IpExtractorFn,
+        locales::Localizations,
+        routes::{
+            challenges::{ChallengeFlag, ChallengePoints},
+            meta::GlobalPageMeta,
+        },
+        settings::Settings,
     },
-    settings::Settings,
+    upload_provider::ErasedUploadProvider,
 };
 
 pub type RouterState = Arc<RouterStateInner>;
@@ -41,6 +44,7 @@ pub struct RouterStateInner {
     pub ip_extractor: IpExtractorFn,
     pub outbound_mailer: Option<Arc<OutboundMailer>>,
     pub divisions: Arc<Vec<Division>>,
+    pub upload_provider: Arc<dyn ErasedUploadProvider>,
     pub router: Arc<Router>,
     pub global_page_meta: Arc<GlobalPageMeta>,
     pub score_type_map: Arc<Mutex<BTreeMap<String, Box<dyn ChallengePoints + Send + Sync>>>>,
@@ -122,7 +126,14 @@ impl Router {
     }
 }
 
-pub fn rebuild_router(builder: crate::Builder, rr: Arc<crate::internal::router::Router>) {
+/// Builds a new router in the background. The returned receiver resolves once the new router
+/// is serving requests.
+pub fn rebuild_router(
+    builder: crate::Builder,
+    rr: Arc<crate::internal::router::Router>,
+) -> tokio::sync::oneshot::Receiver<()> {
+    let (tx, rx) = tokio::sync::oneshot::channel();
+
     let rt = tokio::runtime::Builder::new_current_thread()
         .enable_all()
         .build()
@@ -135,6 +146,7 @@ pub fn rebuild_router(builder: crate::Builder, rr: Arc<crate::internal::router::
             tokio::task::spawn_local(async move {
                 let router = builder.build_axum_router(rr.clone()).await.unwrap();
                 rr.update(router);
+                _ = tx.send(());
             })
             .await
             .unwrap();
@@ -142,6 +154,8 @@ pub fn rebuild_router(builder: crate::Builder, rr: Arc<crate::internal::router::
 
         rt.block_on(local);
     });
+
+    rx
 }
 
 pub type BuilderExtension = Extension<Arc<Mutex<Option<crate::Builder>>>>;
@@ -152,6 +166,6 @@ pub async fn route_reload(
 ) -> impl IntoResponse {
     if let Some(builder) = builder.lock().await.take() {
         let builder = builder.config_override("auth", vec!["discord"]);
-        rebuild_router(builder, state.router.clone());
+        _ = rebuild_router(builder, state.router.clone());
     }
 }
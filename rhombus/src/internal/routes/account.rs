@@ -4,8 +4,9 @@ use axum::{
     extract::{Query, State},
     http::Extensions,
     response::{Html, IntoResponse, Redirect, Response},
-    Extension, Form,
+    Extension, Form, Json,
 };
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
 use dashmap::DashMap;
 use minijinja::context;
 use rand::{
@@ -14,20 +15,27 @@ use rand::{
 };
 use reqwest::{Client, StatusCode};
 use serde::{Deserialize, Serialize};
+use serde_json::json;
 use unicode_segmentation::UnicodeSegmentation;
 
 use crate::{
     errors::RhombusError,
     internal::{
-        auth::User,
+        auth::{TokenClaims, User},
         database::{
             cache::TimedCache,
-            provider::{Email, SetAccountNameError},
+            provider::{
+                Email, SetAccountNameError, SetPasswordError, SignInMethod, UserIdentity,
+                UserSession, UserSignInMethods, UserTotp,
+            },
         },
         errors::{htmx_error_status_code, IntoErrorResponse},
+        ip::describe_user_agent,
         router::RouterState,
         routes::meta::PageMeta,
+        settings::{AuthProvider, Settings},
         templates::{toast_header, ToastKind},
+        totp, webauthn,
     },
 };
 
@@ -77,6 +85,7 @@ async fn is_in_server(
 pub async fn route_account(
     State(state): State<RouterState>,
     Extension(user): Extension<User>,
+    Extension(token_claims): Extension<TokenClaims>,
     Extension(page): Extension<PageMeta>,
     extensions: Extensions,
 ) -> Result<impl IntoResponse, Response> {
@@ -132,8 +141,38 @@ pub async fn route_account(
     let challenge_data = state.db.get_challenges();
     let team = state.db.get_team_from_id(user.team_id);
     let emails = state.db.get_emails_for_user_id(user.id);
-    let (challenge_data, team, emails) = tokio::try_join!(challenge_data, team, emails)
-        .map_err_page(&extensions, "Failed to get data")?;
+    let identities = state.db.get_user_identities(user.id);
+    let user_sign_in_methods = state.db.get_user_sign_in_methods(user.id);
+    let two_factor = two_factor_context(&state, &user);
+    let passkeys = state.db.get_user_passkeys(user.id);
+    let sessions = state.db.get_user_sessions(user.id);
+    let (
+        challenge_data,
+        team,
+        emails,
+        identities,
+        user_sign_in_methods,
+        two_factor,
+        passkeys,
+        sessions,
+    ) = tokio::try_join!(
+        challenge_data,
+        team,
+        emails,
+        identities,
+        user_sign_in_methods,
+        two_factor,
+        passkeys,
+        sessions
+    )
+    .map_err_page(&extensions, "Failed to get data")?;
+
+    let sign_in_methods = sign_in_methods(
+        &*state.settings.read().await,
+        &user_sign_in_methods,
+        &emails,
+        &identities,
+    );
 
     Ok(Html(
         state
@@ -153,11 +192,910 @@ pub async fn route_account(
                 challenges => challenge_data.challenges,
                 categories => challenge_data.categories,
                 emails,
+                sign_in_methods,
+                two_factor,
+                passkeys_enabled => passkeys_enabled(&state).await,
+                passkeys,
+                sessions => session_entries(sessions, &token_claims.sid),
             })
             .unwrap()
     ))
 }
 
+/// A sign in method enabled in the settings, and whether it is linked to the user
+#[derive(Serialize)]
+pub struct SignInMethodEntry {
+    method: &'static str,
+    name: String,
+    linked: bool,
+}
+
+fn sign_in_methods(
+    settings: &Settings,
+    user_sign_in_methods: &UserSignInMethods,
+    emails: &[Email],
+    identities: &[UserIdentity],
+) -> Vec<SignInMethodEntry> {
+    let has_identity = |provider: &str| {
+        identities
+            .iter()
+            .any(|identity| identity.provider == provider)
+    };
+
+    settings
+        .auth
+        .iter()
+        .map(|auth| match auth {
+            AuthProvider::Discord => (
+                "discord",
+                "Discord".to_owned(),
+                user_sign_in_methods.discord,
+            ),
+            AuthProvider::Email => (
+                "email",
+                "Email".to_owned(),
+                emails.iter().any(|email| email.verified),
+            ),
+            AuthProvider::CTFtime => (
+                "ctftime",
+                "CTFtime".to_owned(),
+                user_sign_in_methods.ctftime,
+            ),
+            AuthProvider::GitHub => ("github", "GitHub".to_owned(), has_identity("github")),
+            AuthProvider::Google => ("google", "Google".to_owned(), has_identity("google")),
+            AuthProvider::Oidc => (
+                "oidc",
+                settings
+                    .oidc
+                    .as_ref()
+                    .and_then(|oidc| oidc.display_name.clone())
+                    .unwrap_or_else(|| "Single Sign-On".to_owned()),
+                has_identity("oidc"),
+            ),
+            AuthProvider::Credentials => (
+                "credentials",
+                "Password".to_owned(),
+                user_sign_in_methods.password,
+            ),
+            AuthProvider::Passkey => (
+                "passkey",
+                "Passkey".to_owned(),
+                user_sign_in_methods.passkey,
+            ),
+            AuthProvider::Ldap => (
+                "ldap",
+                settings
+                    .ldap
+                    .as_ref()
+                    .and_then(|ldap| ldap.display_name.clone())
+                    .unwrap_or_else(|| "LDAP".to_owned()),
+                has_identity("ldap"),
+            ),
+        })
+        .map(|(method, name, linked)| SignInMethodEntry {
+            method,
+            name,
+            linked,
+        })
+        .collect()
+}
+
+fn error_toast(state: &RouterState, page: &PageMeta, key: &str) -> Response {
+    (
+        StatusCode::BAD_REQUEST,
+        [(
+            "HX-Trigger",
+            toast_header(
+                ToastKind::Error,
+                &state.localizer.localize(&page.lang, key, None).unwrap(),
+            ),
+        )],
+    )
+        .into_response()
+}
+
+#[derive(Deserialize)]
+pub struct SignInMethodRemove {
+    method: String,
+}
+
+pub async fn route_account_unlink_sign_in_method(
+    State(state): State<RouterState>,
+    Extension(user): Extension<User>,
+    Extension(page): Extension<PageMeta>,
+    Query(query): Query<SignInMethodRemove>,
+) -> Result<impl IntoResponse, Response> {
+    let emails = state.db.get_emails_for_user_id(user.id);
+    let identities = state.db.get_user_identities(user.id);
+    let user_sign_in_methods = state.db.get_user_sign_in_methods(user.id);
+    let (emails, identities, user_sign_in_methods) =
+        tokio::try_join!(emails, identities, user_sign_in_methods).map_err(|e| {
+            tracing::error!(user_id = user.id, error = ?e, "Failed to get sign in methods");
+            htmx_error_status_code().into_response()
+        })?;
+
+    let mut sign_in_methods = sign_in_methods(
+        &*state.settings.read().await,
+        &user_sign_in_methods,
+        &emails,
+        &identities,
+    );
+
+    let method = match query.method.as_str() {
+        "discord" => SignInMethod::Discord,
+        "ctftime" => SignInMethod::CTFtime,
+        "credentials" => SignInMethod::Password,
+        "github" | "google" | "oidc" | "ldap" => SignInMethod::Identity {
+            provider: &query.method,
+        },
+        _ => return Err(StatusCode::BAD_REQUEST.into_response()),
+    };
+
+    if !sign_in_methods
+        .iter()
+        .any(|entry| entry.linked && entry.method == query.method)
+    {
+        return Err(StatusCode::BAD_REQUEST.into_response());
+    }
+
+    if sign_in_methods.iter().filter(|entry| entry.linked).count() <= 1 {
+        return Err(error_toast(
+            &state,
+            &page,
+            "account-error-last-sign-in-method",
+        ));
+    }
+
+    state
+        .db
+        .unlink_sign_in_method(user.id, method)
+        .await
+        .map_err(|e| {
+            tracing::error!(user_id = user.id, method = query.method, error = ?e, "Failed to unlink sign in method");
+            htmx_error_status_code().into_response()
+        })?;
+
+    for entry in sign_in_methods.iter_mut() {
+        if entry.method == query.method {
+            entry.linked = false;
+        }
+    }
+
+    Ok(Html(
+        state
+            .jinja
+            .get_template("account/account-sign-in-methods.html")
+            .unwrap()
+            .render(context! {
+                page,
+                sign_in_methods,
+            })
+            .unwrap(),
+    ))
+}
+
+#[derive(Deserialize)]
+pub struct SetPassword {
+    current_password: Option<String>,
+    password: String,
+}
+
+pub async fn route_account_set_password(
+    State(state): State<RouterState>,
+    Extension(user): Extension<User>,
+    Extension(token_claims): Extension<TokenClaims>,
+    Extension(page): Extension<PageMeta>,
+    Form(form): Form<SetPassword>,
+) -> Result<impl IntoResponse, Response> {
+    if !state
+        .settings
+        .read()
+        .await
+        .auth
+        .contains(&AuthProvider::Credentials)
+    {
+        return Err(StatusCode::NOT_FOUND.into_response());
+    }
+
+    let password_graphemes = form.password.graphemes(true).count();
+    if !(8..=256).contains(&password_graphemes) || !(0..=256).contains(&form.password.len()) {
+        return Err(error_toast(&state, &page, "account-error-password-length"));
+    }
+
+    let result = state
+        .db
+        .set_user_password(user.id, form.current_password.as_deref(), &form.password)
+        .await
+        .map_err(|e| {
+            tracing::error!(user_id = user.id, error = ?e, "Failed to set password");
+            htmx_error_status_code().into_response()
+        })?;
+    match result {
+        Ok(()) => {}
+        Err(SetPasswordError::NameInUse) => {
+            return Err(error_toast(
+                &state,
+                &page,
+                "account-error-password-name-taken",
+            ));
+        }
+        Err(SetPasswordError::IncorrectPassword) => {
+            return Err(error_toast(
+                &state,
+                &page,
+                "account-error-incorrect-password",
+            ));
+        }
+    }
+
+    if form.current_password.is_some() {
+        // sign out other sessions which may have been signed in with the old password
+        if let Err(e) = state
+            .db
+            .revoke_user_sessions(user.id, Some(&token_claims.sid))
+            .await
+        {
+            tracing::error!(user_id = user.id, error = ?e, "Failed to revoke sessions");
+        }
+    }
+
+    let emails = state.db.get_emails_for_user_id(user.id);
+    let identities = state.db.get_user_identities(user.id);
+    let user_sign_in_methods = state.db.get_user_sign_in_methods(user.id);
+    let (emails, identities, user_sign_in_methods) =
+        tokio::try_join!(emails, identities, user_sign_in_methods).map_err(|e| {
+            tracing::error!(user_id = user.id, error = ?e, "Failed to get sign in methods");
+            htmx_error_status_code().into_response()
+        })?;
+
+    let sign_in_methods = sign_in_methods(
+        &*state.settings.read().await,
+        &user_sign_in_methods,
+        &emails,
+        &identities,
+    );
+
+    Ok((
+        [(
+            "HX-Trigger",
+            toast_header(
+                ToastKind::Success,
+                &state
+                    .localizer
+                    .localize(
+                        &page.lang,
+                        if form.current_password.is_some() {
+                            "account-success-change-password"
+                        } else {
+                            "account-success-set-password"
+                        },
+                        None,
+                    )
+                    .unwrap(),
+            ),
+        )],
+        Html(
+            state
+                .jinja
+                .get_template("account/account-sign-in-methods.html")
+                .unwrap()
+                .render(context! {
+                    page,
+                    sign_in_methods,
+                })
+                .unwrap(),
+        ),
+    ))
+}
+
+/// The two-factor authentication state shown on the account page
+async fn two_factor_context(state: &RouterState, user: &User) -> crate::Result<minijinja::Value> {
+    let totp = state.db.get_user_totp(user.id).await?;
+    let required = user.is_admin
+        && state
+            .settings
+            .read()
+            .await
+            .two_factor
+            .as_ref()
+            .and_then(|two_factor| two_factor.require_for_admins)
+            .unwrap_or(false);
+
+    Ok(match totp {
+        Some(totp) if totp.enabled => context! {
+            enabled => true,
+            recovery_codes => totp.recovery_codes,
+            required,
+        },
+        _ => context! {
+            enabled => false,
+            required,
+        },
+    })
+}
+
+async fn render_two_factor(
+    state: &RouterState,
+    page: &PageMeta,
+    user: &User,
+    enrollment: Option<minijinja::Value>,
+    recovery_codes: Option<Vec<String>>,
+    success: Option<&str>,
+) -> Result<Response, Response> {
+    let two_factor = two_factor_context(state, user).await.map_err(|e| {
+        tracing::error!(user_id = user.id, error = ?e, "Failed to get two-factor authentication");
+        htmx_error_status_code().into_response()
+    })?;
+
+    let html = state
+        .jinja
+        .get_template("account/account-two-factor.html")
+        .unwrap()
+        .render(context! {
+            page,
+            two_factor,
+            enrollment,
+            recovery_codes,
+        })
+        .unwrap();
+
+    Ok(match success {
+        Some(key) => (
+            [(
+                "HX-Trigger",
+                toast_header(
+                    ToastKind::Success,
+                    &state.localizer.localize(&page.lang, key, None).unwrap(),
+                ),
+            )],
+            Html(html),
+        )
+            .into_response(),
+        None => Html(html).into_response(),
+    })
+}
+
+/// Generates and stores new recovery codes, returning them so they can be shown once
+async fn reset_recovery_codes(state: &RouterState, user_id: i64) -> crate::Result<Vec<String>> {
+    let recovery_codes = totp::generate_recovery_codes();
+    let hashes = recovery_codes
+        .iter()
+        .map(|code| totp::hash_recovery_code(code))
+        .collect::<Vec<_>>();
+    state.db.enable_user_totp(user_id, &hashes).await?;
+    Ok(recovery_codes)
+}
+
+async fn get_user_totp(state: &RouterState, user_id: i64) -> Result<Option<UserTotp>, Response> {
+    state.db.get_user_totp(user_id).await.map_err(|e| {
+        tracing::error!(user_id, error = ?e, "Failed to get two-factor authentication");
+        htmx_error_status_code().into_response()
+    })
+}
+
+async fn verify_two_factor_code(
+    state: &RouterState,
+    page: &PageMeta,
+    user_id: i64,
+    totp: &UserTotp,
+    code: &str,
+    allow_recovery: bool,
+) -> Result<(), Response> {
+    match totp::verify_user_code(&state.db, user_id, totp, code, allow_recovery).await {
+        Ok(true) => Ok(()),
+        Ok(false) => Err(error_toast(state, page, "two-factor-error-invalid-code")),
+        Err(e) => {
+            tracing::error!(user_id, error = ?e, "Failed to verify two-factor code");
+            Err(htmx_error_status_code().into_response())
+        }
+    }
+}
+
+pub async fn route_account_two_factor_setup(
+    State(state): State<RouterState>,
+    Extension(user): Extension<User>,
+    Extension(page): Extension<PageMeta>,
+) -> Result<Response, Response> {
+    let secret = match get_user_totp(&state, user.id).await? {
+        Some(totp) if totp.enabled => {
+            return Err(error_toast(
+                &state,
+                &page,
+                "two-factor-error-already-enabled",
+            ));
+        }
+        Some(totp) => totp.secret,
+        None => {
+            let secret = totp::generate_secret();
+            state
+                .db
+                .set_user_totp_pending(user.id, &secret)
+                .await
+                .map_err(|e| {
+                    tracing::error!(user_id = user.id, error = ?e, "Failed to set up two-factor authentication");
+                    htmx_error_status_code().into_response()
+                })?;
+            secret
+        }
+    };
+
+    let qr_code = totp::qr_code_data_url(&totp::otpauth_url(
+        &state.global_page_meta.title,
+        &user.name,
+        &secret,
+    ));
+
+    render_two_factor(
+        &state,
+        &page,
+        &user,
+        Some(context! { secret, qr_code }),
+        None,
+        None,
+    )
+    .await
+}
+
+#[derive(Deserialize)]
+pub struct TwoFactorCode {
+    code: String,
+}
+
+pub async fn route_account_two_factor_enable(
+    State(state): State<RouterState>,
+    Extension(user): Extension<User>,
+    Extension(page): Extension<PageMeta>,
+    Form(form): Form<TwoFactorCode>,
+) -> Result<Response, Response> {
+    let Some(totp) = get_user_totp(&state, user.id)
+        .await?
+        .filter(|totp| !totp.enabled)
+    else {
+        return Err(error_toast(&state, &page, "two-factor-error-not-set-up"));
+    };
+
+    verify_two_factor_code(&state, &page, user.id, &totp, &form.code, false).await?;
+
+    let recovery_codes = reset_recovery_codes(&state, user.id).await.map_err(|e| {
+        tracing::error!(user_id = user.id, error = ?e, "Failed to enable two-factor authentication");
+        htmx_error_status_code().into_response()
+    })?;
+
+    render_two_factor(
+        &state,
+        &page,
+        &user,
+        None,
+        Some(recovery_codes),
+        Some("two-factor-success-enabled"),
+    )
+    .await
+}
+
+pub async fn route_account_two_factor_recovery_codes(
+    State(state): State<RouterState>,
+    Extension(user): Extension<User>,
+    Extension(page): Extension<PageMeta>,
+    Form(form): Form<TwoFactorCode>,
+) -> Result<Response, Response> {
+    let Some(totp) = get_user_totp(&state, user.id)
+        .await?
+        .filter(|totp| totp.enabled)
+    else {
+        return Err(error_toast(&state, &page, "two-factor-error-not-set-up"));
+    };
+
+    verify_two_factor_code(&state, &page, user.id, &totp, &form.code, false).await?;
+
+    let recovery_codes = reset_recovery_codes(&state, user.id).await.map_err(|e| {
+        tracing::error!(user_id = user.id, error = ?e, "Failed to reset recovery codes");
+        htmx_error_status_code().into_response()
+    })?;
+
+    render_two_factor(&state, &page, &user, None, Some(recovery_codes), None).await
+}
+
+pub async fn route_account_two_factor_disable(
+    State(state): State<RouterState>,
+    Extension(user): Extension<User>,
+    Extension(page): Extension<PageMeta>,
+    Form(form): Form<TwoFactorCode>,
+) -> Result<Response, Response> {
+    let Some(totp) = get_user_totp(&state, user.id)
+        .await?
+        .filter(|totp| totp.enabled)
+    else {
+        return Err(error_toast(&state, &page, "two-factor-error-not-set-up"));
+    };
+
+    let required = user.is_admin
+        && state
+            .settings
+            .read()
+            .await
+            .two_factor
+            .as_ref()
+            .and_then(|two_factor| two_factor.require_for_admins)
+            .unwrap_or(false);
+    if required {
+        return Err(error_toast(&state, &page, "two-factor-error-required"));
+    }
+
+    verify_two_factor_code(&state, &page, user.id, &totp, &form.code, true).await?;
+
+    state.db.disable_user_totp(user.id).await.map_err(|e| {
+        tracing::error!(user_id = user.id, error = ?e, "Failed to disable two-factor authentication");
+        htmx_error_status_code().into_response()
+    })?;
+
+    render_two_factor(
+        &state,
+        &page,
+        &user,
+        None,
+        None,
+        Some("two-factor-success-disabled"),
+    )
+    .await
+}
+
+async fn passkeys_enabled(state: &RouterState) -> bool {
+    state
+        .settings
+        .read()
+        .await
+        .auth
+        .contains(&AuthProvider::Passkey)
+}
+
+/// Renders the passkey list along with the sign in methods, which depend on it
+async fn render_passkeys(
+    state: &RouterState,
+    page: &PageMeta,
+    user: &User,
+    success: &str,
+) -> Result<Response, Response> {
+    let emails = state.db.get_emails_for_user_id(user.id);
+    let identities = state.db.get_user_identities(user.id);
+    let user_sign_in_methods = state.db.get_user_sign_in_methods(user.id);
+    let passkeys = state.db.get_user_passkeys(user.id);
+    let (emails, identities, user_sign_in_methods, passkeys) =
+        tokio::try_join!(emails, identities, user_sign_in_methods, passkeys).map_err(|e| {
+            tracing::error!(user_id = user.id, error = ?e, "Failed to get sign in methods");
+            htmx_error_status_code().into_response()
+        })?;
+
+    let sign_in_methods = sign_in_methods(
+        &*state.settings.read().await,
+        &user_sign_in_methods,
+        &emails,
+        &identities,
+    );
+
+    let sign_in_methods_html = state
+        .jinja
+        .get_template("account/account-sign-in-methods.html")
+        .unwrap()
+        .render(context! {
+            page,
+            sign_in_methods,
+        })
+        .unwrap();
+    let passkeys_html = state
+        .jinja
+        .get_template("account/account-passkeys.html")
+        .unwrap()
+        .render(context! {
+            page,
+            passkeys,
+            now => chrono::Utc::now(),
+        })
+        .unwrap();
+
+    Ok((
+        [(
+            "HX-Trigger",
+            toast_header(
+                ToastKind::Success,
+                &state.localizer.localize(&page.lang, success, None).unwrap(),
+            ),
+        )],
+        Html(sign_in_methods_html + &passkeys_html),
+    )
+        .into_response())
+}
+
+pub async fn route_account_passkey_options(
+    State(state): State<RouterState>,
+    Extension(user): Extension<User>,
+) -> Result<impl IntoResponse, Response> {
+    if !passkeys_enabled(&state).await {
+        return Err(StatusCode::NOT_FOUND.into_response());
+    }
+
+    let Some(relying_party) =
+        webauthn::RelyingParty::from_location_url(&state.global_page_meta.location_url)
+    else {
+        tracing::error!(
+            location_url = state.global_page_meta.location_url,
+            "Location url is not a valid passkey origin"
+        );
+        return Err(StatusCode::INTERNAL_SERVER_ERROR.into_response());
+    };
+
+    let passkeys = state.db.get_user_passkeys(user.id).await.map_err(|e| {
+        tracing::error!(user_id = user.id, error = ?e, "Failed to get passkeys");
+        StatusCode::INTERNAL_SERVER_ERROR.into_response()
+    })?;
+
+    let jwt_secret = state.settings.read().await.jwt_secret.clone();
+    let (challenge, token) = webauthn::create_challenge(&jwt_secret, Some(user.id));
+
+    Ok(Json(json!({
+        "token": token,
+        "options": {
+            "challenge": challenge,
+            "rp": {
+                "id": relying_party.id,
+                "name": state.global_page_meta.title,
+            },
+            "user": {
+                "id": URL_SAFE_NO_PAD.encode(user.id.to_string()),
+                "name": user.name,
+                "displayName": user.name,
+            },
+            "pubKeyCredParams": webauthn::SUPPORTED_ALGORITHMS
+                .iter()
+                .map(|algorithm| json!({ "type": "public-key", "alg": algorithm }))
+                .collect::<Vec<_>>(),
+            "excludeCredentials": passkeys
+                .iter()
+                .map(|passkey| json!({ "type": "public-key", "id": passkey.id }))
+                .collect::<Vec<_>>(),
+            "authenticatorSelection": {
+                "residentKey": "required",
+                "userVerification": "preferred",
+            },
+            "attestation": "none",
+            "timeout": 300000,
+        },
+    })))
+}
+
+#[derive(Deserialize)]
+pub struct PasskeySubmit {
+    token: String,
+    name: String,
+    id: String,
+    client_data_json: String,
+    authenticator_data: String,
+    public_key: String,
+    algorithm: i64,
+}
+
+pub async fn route_account_add_passkey(
+    State(state): State<RouterState>,
+    Extension(user): Extension<User>,
+    Extension(page): Extension<PageMeta>,
+    Form(form): Form<PasskeySubmit>,
+) -> Result<Response, Response> {
+    if !passkeys_enabled(&state).await {
+        return Err(StatusCode::NOT_FOUND.into_response());
+    }
+
+    let jwt_secret = state.settings.read().await.jwt_secret.clone();
+    let Some(claims) = webauthn::decode_challenge(&jwt_secret, &form.token)
+        .filter(|claims| claims.sub == Some(user.id))
+    else {
+        return Err(error_toast(&state, &page, "passkey-error"));
+    };
+
+    let relying_party =
+        webauthn::RelyingParty::from_location_url(&state.global_page_meta.location_url)
+            .ok_or_else(|| StatusCode::INTERNAL_SERVER_ERROR.into_response())?;
+
+    let (Ok(client_data_json), Ok(authenticator_data), Ok(spki), Ok(credential_id)) = (
+        URL_SAFE_NO_PAD.decode(&form.client_data_json),
+        URL_SAFE_NO_PAD.decode(&form.authenticator_data),
+        URL_SAFE_NO_PAD.decode(&form.public_key),
+        URL_SAFE_NO_PAD.decode(&form.id),
+    ) else {
+        return Err(StatusCode::BAD_REQUEST.into_response());
+    };
+
+    if credential_id.is_empty() || credential_id.len() > 1023 {
+        return Err(StatusCode::BAD_REQUEST.into_response());
+    }
+
+    if !webauthn::verify_client_data(
+        &client_data_json,
+        "webauthn.create",
+        &claims.challenge,
+        &relying_party,
+    ) {
+        return Err(error_toast(&state, &page, "passkey-error"));
+    }
+
+    let Some(sign_count) = webauthn::verify_authenticator_data(&authenticator_data, &relying_party)
+    else {
+        return Err(error_toast(&state, &page, "passkey-error"));
+    };
+
+    if !webauthn::redeem_challenge(&claims) {
+        return Err(error_toast(&state, &page, "passkey-error"));
+    }
+
+    let Some(public_key) = webauthn::public_key_from_spki(&spki)
+        .filter(|_| webauthn::SUPPORTED_ALGORITHMS.contains(&form.algorithm))
+    else {
+        return Err(error_toast(&state, &page, "passkey-error-unsupported"));
+    };
+
+    let name = form.name.trim();
+    let name = if name.is_empty() { "Passkey" } else { name };
+    if name.graphemes(true).count() > 64 {
+        return Err(error_toast(&state, &page, "account-error-passkey-name"));
+    }
+
+    state
+        .db
+        .add_user_passkey(
+            user.id,
+            &form.id,
+            name,
+            &public_key,
+            form.algorithm,
+            sign_count,
+        )
+        .await
+        .map_err(|e| {
+            tracing::error!(user_id = user.id, error = ?e, "Failed to add passkey");
+            error_toast(&state, &page, "passkey-error")
+        })?;
+
+    render_passkeys(&state, &page, &user, "account-success-add-passkey").await
+}
+
+#[derive(Deserialize)]
+pub struct PasskeyRemove {
+    id: String,
+}
+
+pub async fn route_account_delete_passkey(
+    State(state): State<RouterState>,
+    Extension(user): Extension<User>,
+    Extension(page): Extension<PageMeta>,
+    Query(query): Query<PasskeyRemove>,
+) -> Result<Response, Response> {
+    let emails = state.db.get_emails_for_user_id(user.id);
+    let identities = state.db.get_user_identities(user.id);
+    let user_sign_in_methods = state.db.get_user_sign_in_methods(user.id);
+    let passkeys = state.db.get_user_passkeys(user.id);
+    let (emails, identities, user_sign_in_methods, passkeys) =
+        tokio::try_join!(emails, identities, user_sign_in_methods, passkeys).map_err(|e| {
+            tracing::error!(user_id = user.id, error = ?e, "Failed to get sign in methods");
+            htmx_error_status_code().into_response()
+        })?;
+
+    if !passkeys.iter().any(|passkey| passkey.id == query.id) {
+        return Err(StatusCode::BAD_REQUEST.into_response());
+    }
+
+    // removing the only passkey unlinks the method, which must not be the last one
+    let linked = sign_in_methods(
+        &*state.settings.read().await,
+        &user_sign_in_methods,
+        &emails,
+        &identities,
+    )
+    .iter()
+    .filter(|entry| entry.linked)
+    .count();
+    if passkeys.len() == 1 && linked <= 1 {
+        return Err(error_toast(
+            &state,
+            &page,
+            "account-error-last-sign-in-method",
+        ));
+    }
+
+    state
+        .db
+        .delete_user_passkey(user.id, &query.id)
+        .await
+        .map_err(|e| {
+            tracing::error!(user_id = user.id, error = ?e, "Failed to delete passkey");
+            htmx_error_status_code().into_response()
+        })?;
+
+    render_passkeys(&state, &page, &user, "account-success-delete-passkey").await
+}
+
+/// A session as listed on the account page
+#[derive(Serialize)]
+pub struct SessionEntry {
+    id: String,
+    device: Option<String>,
+    ip: Option<IpAddr>,
+    user_agent: Option<String>,
+    last_seen_at: chrono::DateTime<chrono::Utc>,
+    current: bool,
+}
+
+fn session_entries(sessions: Vec<UserSession>, current_session_id: &str) -> Vec<SessionEntry> {
+    sessions
+        .into_iter()
+        .map(|session| SessionEntry {
+            device: session.user_agent.as_deref().and_then(describe_user_agent),
+            current: session.id == current_session_id,
+            id: session.id,
+            ip: session.ip,
+            user_agent: session.user_agent,
+            last_seen_at: session.last_seen_at,
+        })
+        .collect()
+}
+
+#[derive(Deserialize)]
+pub struct SessionRevoke {
+    id: String,
+}
+
+pub async fn route_account_revoke_session(
+    State(state): State<RouterState>,
+    Extension(user): Extension<User>,
+    Extension(token_claims): Extension<TokenClaims>,
+    Extension(page): Extension<PageMeta>,
+    Query(query): Query<SessionRevoke>,
+) -> Result<impl IntoResponse, Response> {
+    if query.id == token_claims.sid {
+        return Err(StatusCode::BAD_REQUEST.into_response());
+    }
+
+    state
+        .db
+        .revoke_session(user.id, &query.id)
+        .await
+        .map_err(|e| {
+            tracing::error!(user_id = user.id, error = ?e, "Failed to revoke session");
+            htmx_error_status_code().into_response()
+        })?;
+
+    let sessions = state.db.get_user_sessions(user.id).await.map_err(|e| {
+        tracing::error!(user_id = user.id, error = ?e, "Failed to get sessions");
+        htmx_error_status_code().into_response()
+    })?;
+
+    Ok(Html(
+        state
+            .jinja
+            .get_template("account/account-sessions.html")
+            .unwrap()
+            .render(context! {
+                page,
+                sessions => session_entries(sessions, &token_claims.sid),
+                now => chrono::Utc::now(),
+            })
+            .unwrap(),
+    ))
+}
+
+pub async fn route_account_revoke_all_sessions(
+    State(state): State<RouterState>,
+    Extension(user): Extension<User>,
+) -> Result<impl IntoResponse, Response> {
+    state
+        .db
+        .revoke_user_sessions(user.id, None)
+        .await
+        .map_err(|e| {
+            tracing::error!(user_id = user.id, error = ?e, "Failed to revoke sessions");
+            htmx_error_status_code().into_response()
+        })?;
+
+    Ok([("HX-Redirect", "/signin")])
+}
+
 #[derive(Deserialize)]
 pub struct EmailSubmit {
     email: String,
@@ -388,6 +1326,41 @@ pub async fn route_account_delete_email(
         return StatusCode::BAD_REQUEST.into_response();
     }
 
+    let (identities, user_sign_in_methods) = match tokio::try_join!(
+        state.db.get_user_identities(user.id),
+        state.db.get_user_sign_in_methods(user.id)
+    ) {
+        Ok(result) => result,
+        Err(e) => {
+            tracing::error!(
+                user_id = user.id,
+                error = ?e,
+                "Failed to get sign in methods while deleting email"
+            );
+            return htmx_error_status_code().into_response();
+        }
+    };
+
+    let mut remaining_emails = emails.clone();
+    remaining_emails.retain(|email| email.address != query.email);
+
+    let settings = state.settings.read().await;
+    let linked_before = sign_in_methods(&settings, &user_sign_in_methods, &emails, &identities)
+        .iter()
+        .any(|entry| entry.linked);
+    let sign_in_methods = sign_in_methods(
+        &settings,
+        &user_sign_in_methods,
+        &remaining_emails,
+        &identities,
+    );
+    drop(settings);
+
+    // removing the last verified email must not lock the user out
+    if linked_before && !sign_in_methods.iter().any(|entry| entry.linked) {
+        return error_toast(&state, &page, "account-error-last-sign-in-method");
+    }
+
     if let Err(e) = state.db.delete_email(user.id, &query.email).await {
         tracing::error!(
             user_id = user.id,
@@ -400,18 +1373,26 @@ pub async fn route_account_delete_email(
 
     emails.retain(|email| email.address != query.email);
 
-    Html(
-        state
-            .jinja
-            .get_template("account/account-emails.html")
-            .unwrap()
-            .render(context! {
-                page,
-                emails,
-            })
-            .unwrap(),
-    )
-    .into_response()
+    let emails_html = state
+        .jinja
+        .get_template("account/account-emails.html")
+        .unwrap()
+        .render(context! {
+            page,
+            emails,
+        })
+        .unwrap();
+    let sign_in_methods_html = state
+        .jinja
+        .get_template("account/account-sign-in-methods.html")
+        .unwrap()
+        .render(context! {
+            page,
+            sign_in_methods,
+        })
+        .unwrap();
+
+    Html(emails_html + &sign_in_methods_html).into_response()
 }
 
 pub fn discord_cache_evictor() {
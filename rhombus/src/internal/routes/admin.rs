@@ -0,0 +1,716 @@
+use std::{collections::BTreeMap, num::NonZeroU64};
+
+use axum::{
+    body::Body,
+    extract::{Path, Query, State},
+    http::{header, Extensions, StatusCode},
+    response::{Html, IntoResponse, Response},
+    Extension, Form,
+};
+use futures::TryStreamExt;
+use minijinja::context;
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::{
+    errors::RhombusError,
+    internal::{
+        auth::User,
+        backup::backup_stream,
+        database::provider::TicketStatus,
+        division::MaxDivisionPlayers,
+        errors::{htmx_error_status_code, IntoErrorResponse},
+        router::{rebuild_router, BuilderExtension, RouterState},
+        routes::{meta::PageMeta, team::move_team_division},
+        templates::{toast_header, ToastKind},
+        tickets::{
+            format_duration, get_ticket_transcript, set_ticket_closed, ticket_assignees,
+            TranscriptFormat,
+        },
+    },
+};
+
+pub async fn route_admin_backup(
+    State(state): State<RouterState>,
+    Extension(user): Extension<User>,
+    extensions: Extensions,
+) -> std::result::Result<impl IntoResponse, Response> {
+    let stream = match backup_stream(&state.db, state.upload_provider.clone()).await {
+        Err(e @ RhombusError::UnsupportedDatabaseOperation(_)) => Err(e).map_err_page_code(
+            &extensions,
+            StatusCode::NOT_IMPLEMENTED,
+            "Backups are only supported with the libSQL database",
+        ),
+        result => result.map_err_page(&extensions, "Failed to create backup"),
+    }?
+    .inspect_err(|e| {
+        tracing::error!(error = ?e, "Failed to create backup");
+    });
+
+    tracing::info!(user_id = user.id, "Downloading backup");
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, "application/x-tar".to_owned()),
+            (
+                header::CONTENT_DISPOSITION,
+                format!(
+                    "attachment; filename=rhombus-backup-{}.tar",
+                    chrono::Utc::now().format("%Y%m%d%H%M%S")
+                ),
+            ),
+        ],
+        Body::from_stream(stream),
+    ))
+}
+
+fn admin_toast(kind: ToastKind, message: &str) -> Response {
+    ([("HX-Trigger", toast_header(kind, message))]).into_response()
+}
+
+/// Swaps the screen with the page at `path`
+fn screen_location(path: &str) -> String {
+    format!(r##"{{"path":"{path}","select":"#screen","target":"#screen","swap":"outerHTML"}}"##)
+}
+
+/// Rebuilds the router so changes to divisions are picked up, and navigates to `location` once
+/// the new router is serving requests
+async fn reload_divisions(
+    state: &RouterState,
+    builder: BuilderExtension,
+    message: &str,
+    location: &str,
+) -> Response {
+    let Some(builder) = builder.lock().await.take() else {
+        return admin_toast(ToastKind::Error, "Already reloading, try again shortly");
+    };
+
+    if rebuild_router(builder, state.router.clone()).await.is_err() {
+        tracing::error!("Failed to reload router after changing divisions");
+        return htmx_error_status_code().into_response();
+    }
+
+    ([
+        ("HX-Trigger", toast_header(ToastKind::Success, message)),
+        ("HX-Location", screen_location(location)),
+    ])
+    .into_response()
+}
+
+pub async fn route_admin_divisions(
+    State(state): State<RouterState>,
+    Extension(user): Extension<User>,
+    Extension(page): Extension<PageMeta>,
+    extensions: Extensions,
+) -> std::result::Result<impl IntoResponse, Response> {
+    let mut team_counts = vec![];
+    for division in state.divisions.iter() {
+        let teams = state
+            .db
+            .get_division_teams(&division.id)
+            .await
+            .map_err_page(&extensions, "Failed to get division teams")?;
+        team_counts.push(teams.len());
+    }
+
+    Ok(Html(
+        state
+            .jinja
+            .get_template("admin/divisions.html")
+            .unwrap()
+            .render(context! {
+                global => state.global_page_meta,
+                page,
+                title => format!("Divisions | Admin | {}", state.global_page_meta.title),
+                user,
+                divisions => state.divisions,
+                team_counts,
+            })
+            .unwrap(),
+    ))
+}
+
+pub async fn route_admin_division(
+    State(state): State<RouterState>,
+    Extension(user): Extension<User>,
+    Extension(page): Extension<PageMeta>,
+    Path(division_id): Path<String>,
+    extensions: Extensions,
+) -> std::result::Result<impl IntoResponse, Response> {
+    let Some(division) = state.divisions.iter().find(|d| d.id == division_id) else {
+        return Err(StatusCode::NOT_FOUND.into_response());
+    };
+
+    let teams = state.db.get_division_teams(&division.id);
+    let history = state.db.get_division_history(&division.id);
+    let (teams, history) = tokio::try_join!(teams, history)
+        .map_err_page(&extensions, "Failed to get division teams")?;
+
+    Ok(Html(
+        state
+            .jinja
+            .get_template("admin/division.html")
+            .unwrap()
+            .render(context! {
+                global => state.global_page_meta,
+                page,
+                title => format!("{} | Admin | {}", division.name, state.global_page_meta.title),
+                user,
+                division,
+                divisions => state.divisions,
+                teams,
+                history,
+            })
+            .unwrap(),
+    ))
+}
+
+#[derive(Deserialize)]
+pub struct AdminDivisionForm {
+    name: String,
+    description: String,
+    max_players: String,
+}
+
+impl AdminDivisionForm {
+    /// Validates the form, returning the trimmed name, description and max players
+    fn validate(&self) -> std::result::Result<(&str, &str, Option<&str>), &'static str> {
+        let name = self.name.trim();
+        if name.is_empty() || name.len() > 30 {
+            return Err("Division name must be between 1 and 30 characters");
+        }
+
+        let description = self.description.trim();
+        if description.is_empty() || description.len() > 200 {
+            return Err("Division description must be between 1 and 200 characters");
+        }
+
+        let max_players = match self.max_players.trim() {
+            "" => None,
+            max_players => {
+                if MaxDivisionPlayers::parse(max_players).is_none() {
+                    return Err("Max players must be a positive number or unlimited");
+                }
+                Some(max_players)
+            }
+        };
+
+        Ok((name, description, max_players))
+    }
+}
+
+/// The id of a division created from the admin panel: the lowercase ASCII letters and numbers of
+/// its name, with every other run of characters replaced by a single dash
+fn division_slug(name: &str) -> String {
+    name.to_lowercase()
+        .split(|c: char| !c.is_ascii_alphanumeric())
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+pub async fn route_admin_division_create(
+    State(state): State<RouterState>,
+    Extension(user): Extension<User>,
+    builder: BuilderExtension,
+    Form(form): Form<AdminDivisionForm>,
+) -> Response {
+    let (name, description, max_players) = match form.validate() {
+        Ok(fields) => fields,
+        Err(message) => return admin_toast(ToastKind::Error, message),
+    };
+
+    let division_id = division_slug(name);
+    if division_id.is_empty() {
+        return admin_toast(
+            ToastKind::Error,
+            "Division name must contain at least one ASCII letter or number",
+        );
+    }
+
+    if state
+        .divisions
+        .iter()
+        .any(|d| d.id == division_id || d.name == name)
+    {
+        return admin_toast(ToastKind::Error, "A division with this name already exists");
+    }
+
+    match state
+        .db
+        .create_division(&division_id, name, description, max_players)
+        .await
+    {
+        Ok(true) => {}
+        Ok(false) => {
+            return admin_toast(ToastKind::Error, "A division with this name already exists");
+        }
+        Err(e) => {
+            tracing::error!(error = ?e, user_id = user.id, division_id, "Failed to create division");
+            return htmx_error_status_code().into_response();
+        }
+    }
+
+    tracing::info!(user_id = user.id, division_id, "Created division");
+
+    reload_divisions(
+        &state,
+        builder,
+        "Created division",
+        &format!("/admin/divisions/{}", division_id),
+    )
+    .await
+}
+
+pub async fn route_admin_division_update(
+    State(state): State<RouterState>,
+    Extension(user): Extension<User>,
+    Path(division_id): Path<String>,
+    builder: BuilderExtension,
+    Form(form): Form<AdminDivisionForm>,
+) -> Response {
+    let Some(division) = state.divisions.iter().find(|d| d.id == division_id) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+
+    if !division.admin_managed {
+        return admin_toast(
+            ToastKind::Error,
+            "Divisions from the config must be edited in the config",
+        );
+    }
+
+    let (name, description, max_players) = match form.validate() {
+        Ok(fields) => fields,
+        Err(message) => return admin_toast(ToastKind::Error, message),
+    };
+
+    if state
+        .divisions
+        .iter()
+        .any(|d| d.id != division_id && d.name == name)
+    {
+        return admin_toast(ToastKind::Error, "A division with this name already exists");
+    }
+
+    match state
+        .db
+        .update_division(&division_id, name, description, max_players)
+        .await
+    {
+        Ok(()) => {}
+        Err(RhombusError::DatabaseReturnedNoRows) => {
+            return StatusCode::NOT_FOUND.into_response();
+        }
+        Err(e) => {
+            tracing::error!(error = ?e, user_id = user.id, division_id, "Failed to update division");
+            return htmx_error_status_code().into_response();
+        }
+    }
+
+    tracing::info!(user_id = user.id, division_id, "Updated division");
+
+    reload_divisions(
+        &state,
+        builder,
+        "Updated division",
+        &format!("/admin/divisions/{}", division_id),
+    )
+    .await
+}
+
+#[derive(Deserialize)]
+pub struct AdminDivisionArchiveForm {
+    archived: bool,
+}
+
+pub async fn route_admin_division_archive(
+    State(state): State<RouterState>,
+    Extension(user): Extension<User>,
+    Path(division_id): Path<String>,
+    builder: BuilderExtension,
+    Form(form): Form<AdminDivisionArchiveForm>,
+) -> Response {
+    let Some(division) = state.divisions.iter().find(|d| d.id == division_id) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+
+    // new teams are put in the default division, so it must stay joinable
+    if division.is_default && form.archived {
+        return admin_toast(ToastKind::Error, "The default division cannot be archived");
+    }
+
+    if let Err(e) = state
+        .db
+        .set_division_archived(&division_id, form.archived)
+        .await
+    {
+        tracing::error!(error = ?e, user_id = user.id, division_id, "Failed to archive division");
+        return htmx_error_status_code().into_response();
+    }
+
+    tracing::info!(
+        user_id = user.id,
+        division_id,
+        archived = form.archived,
+        "Set division archived"
+    );
+
+    reload_divisions(
+        &state,
+        builder,
+        if form.archived {
+            "Archived division"
+        } else {
+            "Restored division"
+        },
+        &format!("/admin/divisions/{}", division_id),
+    )
+    .await
+}
+
+#[derive(Deserialize)]
+pub struct AdminTeamDivisionForm {
+    division_id: String,
+}
+
+/// Moves a team to a division regardless of its eligibility and the division change cooldown
+pub async fn route_admin_team_division(
+    State(state): State<RouterState>,
+    Extension(user): Extension<User>,
+    Path(team_id): Path<i64>,
+    Form(form): Form<AdminTeamDivisionForm>,
+) -> Response {
+    if !state.divisions.iter().any(|d| d.id == form.division_id) {
+        return StatusCode::NOT_FOUND.into_response();
+    }
+
+    let team = match state.db.get_team_from_id(team_id).await {
+        Ok(team) => team,
+        Err(RhombusError::DatabaseReturnedNoRows) => return StatusCode::NOT_FOUND.into_response(),
+        Err(e) => {
+            tracing::error!(error = ?e, user_id = user.id, team_id, "Failed to get team");
+            return htmx_error_status_code().into_response();
+        }
+    };
+
+    if team.division_id == form.division_id {
+        return admin_toast(ToastKind::Error, "Team is already in this division");
+    }
+
+    if let Err(e) = move_team_division(
+        &state,
+        &team,
+        &form.division_id,
+        chrono::Utc::now(),
+        Some(user.id),
+    )
+    .await
+    {
+        tracing::error!(error = ?e, user_id = user.id, team_id, "Failed to move team division");
+        return htmx_error_status_code().into_response();
+    }
+
+    tracing::info!(
+        user_id = user.id,
+        team_id,
+        old_division_id = team.division_id,
+        new_division_id = form.division_id,
+        "Admin moved team division"
+    );
+
+    ([
+        ("HX-Trigger", toast_header(ToastKind::Success, "Moved team")),
+        (
+            "HX-Location",
+            screen_location(&format!("/admin/divisions/{}", team.division_id)),
+        ),
+    ])
+    .into_response()
+}
+
+#[derive(Deserialize)]
+pub struct AdminTicketsQuery {
+    #[serde(default)]
+    closed: bool,
+    status: Option<String>,
+    assignee: Option<NonZeroU64>,
+    tag: Option<String>,
+}
+
+pub async fn route_admin_tickets(
+    State(state): State<RouterState>,
+    Extension(user): Extension<User>,
+    Extension(page): Extension<PageMeta>,
+    Query(query): Query<AdminTicketsQuery>,
+    extensions: Extensions,
+) -> std::result::Result<impl IntoResponse, Response> {
+    let tickets = state.db.get_tickets(query.closed);
+    let challenge_data = state.db.get_challenges();
+    let stats = state.db.get_ticket_stats();
+    let (tickets, challenge_data, stats) = tokio::try_join!(tickets, challenge_data, stats)
+        .map_err_page(&extensions, "Failed to get tickets")?;
+
+    let status = query.status.as_deref().and_then(TicketStatus::parse);
+    let tickets = tickets
+        .into_iter()
+        .filter(|ticket| status.map_or(true, |status| ticket.status == status))
+        .filter(|ticket| {
+            query.assignee.map_or(true, |assignee| {
+                ticket.assignee_discord_id == Some(assignee)
+            })
+        })
+        .filter(|ticket| {
+            query
+                .tag
+                .as_ref()
+                .map_or(true, |tag| ticket.tags.contains(tag))
+        })
+        .collect::<Vec<_>>();
+
+    let mut creators = vec![];
+    for ticket in &tickets {
+        let creator = state
+            .db
+            .get_user_from_id(ticket.user_id)
+            .await
+            .map_err_page(&extensions, "Failed to get ticket creator")?;
+        creators.push(creator);
+    }
+
+    // open tickets by challenge, with the most tickets first and then the oldest ticket first
+    let mut by_challenge = BTreeMap::<&str, (usize, chrono::DateTime<chrono::Utc>)>::new();
+    for ticket in tickets.iter().filter(|ticket| ticket.closed_at.is_none()) {
+        by_challenge
+            .entry(&ticket.challenge_id)
+            .and_modify(|(count, oldest)| {
+                *count += 1;
+                *oldest = (*oldest).min(ticket.opened_at);
+            })
+            .or_insert((1, ticket.opened_at));
+    }
+    let mut by_challenge = by_challenge
+        .into_iter()
+        .map(|(challenge_id, (count, oldest))| {
+            json!({
+                "challenge_id": challenge_id,
+                "count": count,
+                "oldest": oldest,
+            })
+        })
+        .collect::<Vec<_>>();
+    by_challenge.sort_by(|a, b| {
+        b["count"]
+            .as_u64()
+            .cmp(&a["count"].as_u64())
+            .then_with(|| a["oldest"].as_str().cmp(&b["oldest"].as_str()))
+    });
+
+    let average_first_response = stats.average_first_response_seconds.map(format_duration);
+    let average_resolution = stats.average_resolution_seconds.map(format_duration);
+
+    Ok(Html(
+        state
+            .jinja
+            .get_template("admin/tickets.html")
+            .unwrap()
+            .render(context! {
+                global => state.global_page_meta,
+                page,
+                title => format!("Tickets | Admin | {}", state.global_page_meta.title),
+                user,
+                tickets,
+                creators,
+                by_challenge,
+                stats,
+                average_first_response,
+                average_resolution,
+                assignees => ticket_assignees(&challenge_data),
+                challenges => challenge_data.challenges,
+                closed => query.closed,
+                filtered => status.is_some() || query.assignee.is_some() || query.tag.is_some(),
+                now => chrono::Utc::now(),
+            })
+            .unwrap(),
+    ))
+}
+
+#[derive(Deserialize)]
+pub struct AdminTicketForm {
+    status: String,
+    assignee: String,
+    tags: String,
+}
+
+pub async fn route_admin_ticket_update(
+    State(state): State<RouterState>,
+    Extension(user): Extension<User>,
+    Path(ticket_number): Path<u64>,
+    Form(form): Form<AdminTicketForm>,
+) -> Response {
+    let Ok(ticket) = state.db.get_ticket_by_ticket_number(ticket_number).await else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+
+    let Some(status) = TicketStatus::parse(&form.status) else {
+        return admin_toast(ToastKind::Error, "Unknown ticket status");
+    };
+
+    let assignee_discord_id = match form.assignee.trim() {
+        "" => None,
+        assignee => match assignee.parse::<NonZeroU64>() {
+            Ok(assignee) => Some(assignee),
+            Err(_) => return admin_toast(ToastKind::Error, "Invalid assignee"),
+        },
+    };
+
+    let mut tags = vec![];
+    for tag in form.tags.split(',') {
+        let tag = tag.trim().to_lowercase();
+        if tag.is_empty() {
+            continue;
+        }
+        if tag.len() > 20 || !tag.chars().all(|c| c.is_ascii_alphanumeric() || c == '-') {
+            return admin_toast(
+                ToastKind::Error,
+                "Tags must be up to 20 letters, numbers or dashes",
+            );
+        }
+        if !tags.contains(&tag) {
+            tags.push(tag);
+        }
+    }
+    if tags.len() > 5 {
+        return admin_toast(ToastKind::Error, "Tickets can have up to 5 tags");
+    }
+
+    let bot = state.bot.as_deref();
+
+    let result = async {
+        if (status == TicketStatus::Closed) != (ticket.status == TicketStatus::Closed) {
+            set_ticket_closed(&state.db, bot, &ticket, status == TicketStatus::Closed).await?;
+        }
+        if status != TicketStatus::Closed {
+            state.db.set_ticket_status(ticket_number, status).await?;
+        }
+
+        if assignee_discord_id != ticket.assignee_discord_id {
+            state
+                .db
+                .set_ticket_assignee(ticket_number, assignee_discord_id)
+                .await?;
+
+            if let (Some(bot), Some(discord_channel_id), Some(assignee_discord_id)) =
+                (bot, ticket.discord_channel_id, assignee_discord_id)
+            {
+                if let Err(error) = bot
+                    .assign_ticket_thread(discord_channel_id, assignee_discord_id)
+                    .await
+                {
+                    tracing::error!(
+                        ?error,
+                        ticket_number,
+                        "Failed to add assignee to ticket thread"
+                    );
+                }
+            }
+        }
+
+        state.db.set_ticket_tags(ticket_number, &tags).await
+    }
+    .await;
+
+    if let Err(error) = result {
+        tracing::error!(?error, ticket_number, "Failed to update ticket");
+        return htmx_error_status_code().into_response();
+    }
+
+    tracing::info!(
+        user_id = user.id,
+        ticket_number,
+        status = status.as_str(),
+        ?assignee_discord_id,
+        ?tags,
+        "Updated ticket"
+    );
+
+    admin_toast(ToastKind::Success, "Ticket updated")
+}
+
+#[derive(Deserialize)]
+pub struct AdminTicketsExportQuery {
+    challenge_id: String,
+    #[serde(default)]
+    format: TranscriptFormat,
+}
+
+pub async fn route_admin_tickets_export(
+    State(state): State<RouterState>,
+    Extension(user): Extension<User>,
+    Query(query): Query<AdminTicketsExportQuery>,
+    extensions: Extensions,
+) -> std::result::Result<impl IntoResponse, Response> {
+    let tickets = state.db.get_challenge_tickets(&query.challenge_id);
+    let challenge_data = state.db.get_challenges();
+    let (tickets, challenge_data) = tokio::try_join!(tickets, challenge_data)
+        .map_err_page(&extensions, "Failed to get tickets")?;
+
+    let mut transcripts = Vec::with_capacity(tickets.len());
+    for ticket in tickets {
+        let transcript = get_ticket_transcript(&state.db, &challenge_data, ticket)
+            .await
+            .map_err_page(&extensions, "Failed to get ticket transcript")?;
+        transcripts.push(transcript);
+    }
+
+    let body = match query.format {
+        TranscriptFormat::Markdown => transcripts
+            .iter()
+            .map(|transcript| transcript.to_markdown())
+            .collect::<Vec<_>>()
+            .join("\n---\n\n"),
+        TranscriptFormat::Json => serde_json::to_string_pretty(&transcripts).unwrap(),
+    };
+
+    tracing::info!(
+        user_id = user.id,
+        challenge_id = query.challenge_id,
+        tickets = transcripts.len(),
+        "Exported tickets"
+    );
+
+    // challenge ids are slugs, but keep the filename safe to put in a header regardless
+    let filename = query
+        .challenge_id
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric() || *c == '-' || *c == '_')
+        .collect::<String>();
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, query.format.content_type().to_owned()),
+            (
+                header::CONTENT_DISPOSITION,
+                format!(
+                    "attachment; filename=tickets-{}.{}",
+                    filename,
+                    query.format.extension()
+                ),
+            ),
+        ],
+        body,
+    ))
+}
+
+#[cfg(test)]
+mod test {
+    use super::division_slug;
+
+    #[test]
+    fn division_slugs() {
+        assert_eq!(division_slug("Open"), "open");
+        assert_eq!(division_slug("  High School  (US) "), "high-school-us");
+        assert_eq!(division_slug("Étudiants 2024"), "tudiants-2024");
+        assert_eq!(division_slug("Студенты"), "");
+    }
+}
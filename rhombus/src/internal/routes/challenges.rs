@@ -1,4 +1,4 @@
-use std::{cmp::max, sync::LazyLock, time::Duration};
+use std::{cmp::max, collections::BTreeMap, sync::LazyLock, time::Duration};
 
 use axum::{
     extract::{Path, State},
@@ -15,11 +15,13 @@ use serde_json::{json, Value};
 
 use crate::internal::{
     auth::User,
-    database::provider::{Challenge, Team},
+    database::provider::{Challenge, Team, TicketMessageAuthor},
     errors::IntoErrorResponse,
+    proof_of_work::{verify_proof_of_work, ProofOfWorkRoute, ProofOfWorkSolution},
     router::RouterState,
     routes::meta::PageMeta,
     templates::{base64_encode, toast_header, ToastKind},
+    upload_provider::sign_attachments,
 };
 
 pub async fn route_challenges(
@@ -52,14 +54,15 @@ pub async fn route_challenges(
     let (challenge_data, team) =
         tokio::try_join!(challenge_data, team).map_err_page(&extensions, "Failed to get data")?;
 
-    let ticket_enabled = {
-        let settings = state.settings.read().await;
-        settings
-            .discord
-            .as_ref()
-            .and_then(|d| d.support_channel_id)
-            .is_some()
-    };
+    let ticket_enabled = state.settings.read().await.tickets_enabled();
+
+    let mut attachments = BTreeMap::new();
+    for challenge in challenge_data.challenges.values() {
+        attachments.insert(
+            &challenge.id,
+            sign_attachments(&state, &challenge.attachments, team.id).await,
+        );
+    }
 
     let challenge_json = json!({
         "division_id": team.division_id,
@@ -83,7 +86,7 @@ pub async fn route_challenges(
                 "division_id": division_id,
                 "solves": solves,
             })).collect::<serde_json::Value>(),
-            "attachments": challenge.attachments.iter().map(|attachment| json!({
+            "attachments": attachments[&challenge.id].iter().map(|attachment| json!({
                 "name": attachment.name,
                 "url": attachment.url,
             })).collect::<serde_json::Value>(),
@@ -254,6 +257,16 @@ pub async fn route_challenge_view(
         .get(&challenge.category_id)
         .unwrap();
 
+    let mut challenge = challenge.clone();
+    challenge.attachments = sign_attachments(&state, &challenge.attachments, team.id).await;
+    let proof_of_work = state
+        .settings
+        .read()
+        .await
+        .proof_of_work
+        .clone()
+        .unwrap_or_default();
+
     Ok(Html(
         state
             .jinja
@@ -268,6 +281,7 @@ pub async fn route_challenge_view(
                 team,
                 divisions => challenge_data.divisions,
                 user_writeups,
+                proof_of_work,
             })
             .unwrap(),
     )
@@ -329,6 +343,14 @@ pub async fn route_ticket_view(
         .get(&challenge.category_id)
         .unwrap();
 
+    let proof_of_work = state
+        .settings
+        .read()
+        .await
+        .proof_of_work
+        .clone()
+        .unwrap_or_default();
+
     let ticket_template = if let Some(ticket_template) = &challenge.ticket_template {
         ticket_template
     } else {
@@ -348,6 +370,7 @@ pub async fn route_ticket_view(
                 category,
                 team,
                 ticket_template,
+                proof_of_work,
             })
             .unwrap(),
     ))
@@ -356,6 +379,8 @@ pub async fn route_ticket_view(
 #[derive(Deserialize)]
 pub struct TicketSubmit {
     content: String,
+    #[serde(flatten)]
+    proof_of_work: ProofOfWorkSolution,
 }
 
 pub async fn route_ticket_submit(
@@ -372,16 +397,9 @@ pub async fn route_ticket_submit(
         }
     }
 
-    let ticket_enabled = {
-        let settings = state.settings.read().await;
-        settings
-            .discord
-            .as_ref()
-            .and_then(|d| d.support_channel_id)
-            .is_some()
-    };
+    let ticket_enabled = state.settings.read().await.tickets_enabled();
 
-    if !ticket_enabled || state.bot.is_none() {
+    if !ticket_enabled {
         return Err(([("HX-Trigger", "closeModal")]).into_response());
     }
 
@@ -411,6 +429,21 @@ pub async fn route_ticket_submit(
             .into_response());
     }
 
+    if !verify_proof_of_work(&state, ProofOfWorkRoute::Ticket, &form.proof_of_work).await {
+        let html = state
+            .jinja
+            .get_template("challenges/challenge-submit.html")
+            .unwrap()
+            .render(context! {
+                page,
+                user,
+                error => state.localizer.localize(&page.lang, "proof-of-work-error", None),
+            })
+            .unwrap();
+
+        return Ok(Html(html).into_response());
+    }
+
     let content = form.content;
 
     if content.len() > 1000 {
@@ -440,13 +473,44 @@ pub async fn route_ticket_submit(
 
     let author = challenge_data.authors.get(&challenge.author_id).unwrap();
 
+    let ticket_number = state
+        .db
+        .get_next_ticket_number()
+        .await
+        .map_err_htmx(&extensions, "Failed to get ticket number")?;
+
     state
-        .bot
-        .as_ref()
-        .unwrap()
-        .create_support_thread(&user, &team, challenge, author, content.as_str())
+        .db
+        .create_ticket(
+            ticket_number,
+            user.id,
+            &challenge.id,
+            &TicketMessageAuthor {
+                user_id: Some(user.id),
+                name: &user.name,
+                avatar: &user.avatar,
+            },
+            &content,
+            Some(author.discord_id),
+        )
         .await
-        .map_err_htmx(&extensions, "Failed to create ticket in Discord")?;
+        .map_err_htmx(&extensions, "Failed to create ticket")?;
+
+    if let Some(ref bot) = state.bot {
+        if let Err(error) = bot
+            .create_support_thread(ticket_number, &user, &team, challenge, author, &content)
+            .await
+        {
+            tracing::error!(?error, ticket_number, "Failed to mirror ticket to Discord");
+        }
+    }
+
+    tracing::info!(
+        user_id = user.id,
+        ticket_number,
+        challenge_id = challenge.id,
+        "Opened ticket"
+    );
 
     Ok(([(
         "HX-Trigger",
@@ -467,6 +531,8 @@ pub static TEAM_BURSTED_POINTS: LazyLock<DashMap<i64, i64>> = LazyLock::new(Dash
 #[derive(Deserialize)]
 pub struct SubmitChallenge {
     flag: String,
+    #[serde(flatten)]
+    proof_of_work: ProofOfWorkSolution,
 }
 
 pub async fn route_challenge_submit(
@@ -483,6 +549,19 @@ pub async fn route_challenge_submit(
         }
     }
 
+    if !verify_proof_of_work(&state, ProofOfWorkRoute::Flag, &form.proof_of_work).await {
+        let html = state
+            .jinja
+            .get_template("challenges/challenge-submit.html")
+            .unwrap()
+            .render(context! {
+                page,
+                error => state.localizer.localize(&page.lang, "proof-of-work-error", None),
+            })
+            .unwrap();
+        return Html(html).into_response();
+    }
+
     let challenge_data = match state.db.get_challenges().await {
         Ok(challenge_data) => challenge_data,
         Err(e) => {
@@ -645,18 +724,18 @@ pub async fn route_challenge_submit(
         .and_modify(|v| *v += 1)
         .or_insert(1);
 
-    if let Some(ref bot) = state.bot {
-        {
-            let bot = bot.clone();
-            let db = state.db.clone();
-            let user_id = user.id;
-            let challenge_id = challenge.id.clone();
-            tokio::task::spawn(async move {
-                match db
-                    .close_tickets_for_challenge(user_id, &challenge_id, now)
-                    .await
-                {
-                    Ok(to_be_closed_tickets) => {
+    {
+        let bot = state.bot.clone();
+        let db = state.db.clone();
+        let user_id = user.id;
+        let challenge_id = challenge.id.clone();
+        tokio::task::spawn(async move {
+            match db
+                .close_tickets_for_challenge(user_id, &challenge_id, now)
+                .await
+            {
+                Ok(to_be_closed_tickets) => {
+                    if let Some(ref bot) = bot {
                         if let Err(e) = bot.close_tickets(&to_be_closed_tickets).await {
                             tracing::error!(
                                 user_id,
@@ -666,20 +745,24 @@ pub async fn route_challenge_submit(
                             );
                         }
                     }
-                    Err(e) => {
-                        tracing::error!(
-                            user_id,
-                            challenge_id,
-                            error = ?e,
-                            "Failed to close tickets on solve"
-                        );
-                    }
-                };
+                }
+                Err(e) => {
+                    tracing::error!(
+                        user_id,
+                        challenge_id,
+                        error = ?e,
+                        "Failed to close tickets on solve"
+                    );
+                }
+            };
 
+            if let Some(bot) = bot {
                 bot.sync_top10_discord_role().await;
-            });
-        }
+            }
+        });
+    }
 
+    if let Some(ref bot) = state.bot {
         let first_blood_enabled = {
             let settings = state.settings.read().await;
             settings
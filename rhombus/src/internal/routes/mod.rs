@@ -1,4 +1,5 @@
 pub mod account;
+pub mod admin;
 pub mod challenges;
 pub mod home;
 pub mod meta;
@@ -6,3 +7,4 @@ pub mod public;
 pub mod scoreboard;
 pub mod team;
 pub mod terms;
+pub mod tickets;
@@ -1,7 +1,9 @@
+use std::num::{NonZeroU32, NonZeroU64};
+
 use axum::{
     extract::{Path, State},
     http::Extensions,
-    response::{Html, IntoResponse, Response},
+    response::{Html, IntoResponse, Redirect, Response},
     Extension, Form,
 };
 use minijinja::context;
@@ -13,14 +15,18 @@ use reqwest::StatusCode;
 use serde::{Deserialize, Serialize};
 use unicode_segmentation::UnicodeSegmentation;
 
-use crate::internal::{
-    auth::User,
-    database::provider::SetTeamNameError,
-    division::MaxDivisionPlayers,
-    errors::{htmx_error_status_code, IntoErrorResponse},
-    router::RouterState,
-    routes::meta::PageMeta,
-    templates::{toast_header, ToastKind},
+use crate::{
+    errors::RhombusError,
+    internal::{
+        auth::User,
+        database::provider::{Email, SetTeamNameError, Team, TeamInner},
+        discord::{Bot, TEAM_DISCORD_MIN_PLAYERS},
+        division::{Division, MaxDivisionPlayers},
+        errors::{error_page, htmx_error_status_code, IntoErrorResponse},
+        router::RouterState,
+        routes::meta::PageMeta,
+        templates::{toast_header, ToastKind},
+    },
 };
 
 pub fn create_team_invite_token() -> String {
@@ -34,10 +40,46 @@ pub struct TeamDivision<'a> {
     pub description: &'a str,
     pub eligible: bool,
     pub requirement: Option<String>,
+    /// The team is not eligible, but may join to request eligibility (like manual approval)
+    pub requestable: bool,
     pub joined: bool,
     pub max_players: MaxDivisionPlayers,
 }
 
+/// Checks the eligibility of the team for each division
+async fn team_divisions<'a>(state: &'a RouterState, team: &Team) -> Vec<TeamDivision<'a>> {
+    let mut divisions = vec![];
+    for division in state.divisions.iter() {
+        let joined = team.division_id == division.id;
+        if division.archived && !joined {
+            continue;
+        }
+
+        let eligible = division.is_team_eligible(team, None).await;
+
+        let oversized = match division.max_players {
+            MaxDivisionPlayers::Unlimited => true,
+            MaxDivisionPlayers::Limited(max_players) => {
+                team.users.len() <= max_players.get() as usize
+            }
+        };
+
+        divisions.push(TeamDivision {
+            id: division.id.clone(),
+            name: &division.name,
+            description: &division.description,
+            eligible: eligible.is_ok() && oversized,
+            requestable: eligible.is_err()
+                && oversized
+                && division.division_eligibility.is_requestable(),
+            requirement: eligible.err(),
+            joined,
+            max_players: division.max_players.clone(),
+        })
+    }
+    divisions
+}
+
 pub async fn route_team(
     State(state): State<RouterState>,
     Extension(user): Extension<User>,
@@ -55,36 +97,24 @@ pub async fn route_team(
         .await
         .map_err_page(&extensions, "Failed to get team standing")?;
 
+    let incoming_invites = state.db.get_user_team_invites(user.id);
+    let (team_invites, join_requests) = if user.is_team_owner {
+        let team_invites = state.db.get_team_invites(user.team_id);
+        let join_requests = state.db.get_team_join_requests(user.team_id);
+        tokio::try_join!(team_invites, join_requests)
+            .map_err_page(&extensions, "Failed to get team invites")?
+    } else {
+        (vec![], vec![])
+    };
+    let incoming_invites = incoming_invites
+        .await
+        .map_err_page(&extensions, "Failed to get invites")?;
+
     let location_url = state.settings.read().await.location_url.clone();
 
     let team_invite_url = format!("{}/signin?token={}", location_url, team.invite_token);
 
-    let mut divisions = vec![];
-    for division in state.divisions.iter() {
-        let eligible = division
-            .division_eligibility
-            .is_user_eligible(team.owner_user_id)
-            .await;
-
-        let oversized = match division.max_players {
-            MaxDivisionPlayers::Unlimited => true,
-            MaxDivisionPlayers::Limited(max_players) => {
-                team.users.len() <= max_players.get() as usize
-            }
-        };
-
-        let joined = team.division_id == division.id;
-
-        divisions.push(TeamDivision {
-            id: division.id.clone(),
-            name: &division.name,
-            description: &division.description,
-            eligible: eligible.is_ok() && oversized,
-            requirement: eligible.err(),
-            joined,
-            max_players: division.max_players.clone(),
-        })
-    }
+    let divisions = team_divisions(&state, &team).await;
 
     let max_players = divisions
         .iter()
@@ -120,6 +150,10 @@ pub async fn route_team(
                 divisions,
                 standing,
                 division_id => team.division_id,
+                team_invites,
+                join_requests,
+                incoming_invites,
+                email_invites => state.outbound_mailer.is_some(),
             })
             .map_err_page(&extensions, "Failed to render template team/team.html")?,
     ))
@@ -211,6 +245,23 @@ pub async fn route_team_set_name(
         }
     }
 
+    if errors.is_empty() {
+        if let Some(bot) = state.bot.as_ref() {
+            if bot.team_roles_enabled().await {
+                match state.db.get_team_from_id(user.team_id).await {
+                    Ok(team) => {
+                        if let Err(e) = bot.sync_team_discord(&team).await {
+                            tracing::error!(error = ?e, team_id = user.team_id, "Failed to rename team Discord role and channels");
+                        }
+                    }
+                    Err(e) => {
+                        tracing::error!(error = ?e, team_id = user.team_id, "Failed to get team");
+                    }
+                }
+            }
+        }
+    }
+
     let team_name_template = state.jinja.get_template("team/team-set-name.html").unwrap();
 
     let ctx = if errors.is_empty() {
@@ -230,6 +281,272 @@ pub async fn route_team_set_name(
     Ok(Html(html))
 }
 
+fn toast(state: &RouterState, page: &PageMeta, kind: ToastKind, key: &str) -> Response {
+    ([(
+        "HX-Trigger",
+        toast_header(
+            kind,
+            &state
+                .localizer
+                .localize(&page.lang, key, None)
+                .unwrap_or_default(),
+        ),
+    )],)
+        .into_response()
+}
+
+/// Why a user cannot join a team
+pub enum JoinTeamError {
+    /// The team has no members, because its owner has moved to a different team
+    Abandoned,
+    /// The user's current team has other members on it
+    ExistingTeam,
+    /// The team is at the player limit of its division
+    Full(NonZeroU32),
+    /// The user is not eligible for the team's division, with the requirement they do not meet
+    Ineligible(String),
+}
+
+/// Checks that a user on `old_team` may move onto `new_team`. If the user is not eligible for the
+/// division of `new_team`, they are queued for any eligibility granted on request.
+pub async fn check_join_team(
+    divisions: &[Division],
+    user_id: i64,
+    old_team: &Team,
+    new_team: &Team,
+) -> std::result::Result<(), JoinTeamError> {
+    if new_team.users.is_empty() {
+        return Err(JoinTeamError::Abandoned);
+    }
+
+    if old_team.users.len() > 1 {
+        return Err(JoinTeamError::ExistingTeam);
+    }
+
+    let division = divisions
+        .iter()
+        .find(|division| division.id == new_team.division_id)
+        .unwrap();
+
+    if let MaxDivisionPlayers::Limited(max_players) = &division.max_players {
+        if new_team.users.len() >= max_players.get() as usize {
+            return Err(JoinTeamError::Full(*max_players));
+        }
+    }
+
+    if let Err(requirement) = division.is_team_eligible(new_team, Some(user_id)).await {
+        division
+            .division_eligibility
+            .request_eligibility(user_id)
+            .await;
+        return Err(JoinTeamError::Ineligible(requirement));
+    }
+
+    Ok(())
+}
+
+/// Whether one of `emails` is verified and matches `address`, so an email invite to it can be
+/// accepted
+pub fn has_verified_email(emails: &[Email], address: &str) -> bool {
+    emails
+        .iter()
+        .any(|email| email.verified && email.address.eq_ignore_ascii_case(address))
+}
+
+/// Renders the page explaining why a user cannot join a team
+pub fn join_team_error_page(
+    state: &RouterState,
+    page: &PageMeta,
+    user: &User,
+    error: JoinTeamError,
+    old_team: Team,
+    new_team: Team,
+) -> Response {
+    let html = match error {
+        JoinTeamError::Abandoned => {
+            return error_page(
+                StatusCode::NOT_FOUND,
+                "Team not found",
+                state,
+                &Some(user.clone()),
+                page,
+            )
+        }
+        JoinTeamError::ExistingTeam => state
+            .jinja
+            .get_template("team/join-error-existing-team.html")
+            .unwrap()
+            .render(context! {
+                global => state.global_page_meta,
+                page,
+                title => format!("Team Join Error | {}", state.global_page_meta.title),
+                user,
+                team => old_team,
+            })
+            .unwrap(),
+        JoinTeamError::Full(max_players) => state
+            .jinja
+            .get_template("team/join-error-max.html")
+            .unwrap()
+            .render(context! {
+                global => state.global_page_meta,
+                page,
+                title => format!("Team Join Error | {}", state.global_page_meta.title),
+                user,
+                team => new_team,
+                max_players,
+            })
+            .unwrap(),
+        JoinTeamError::Ineligible(requirement) => state
+            .jinja
+            .get_template("team/join-error-ineligible.html")
+            .unwrap()
+            .render(context! {
+                global => state.global_page_meta,
+                page,
+                title => format!("Team Join Error | {}", state.global_page_meta.title),
+                user,
+                team => new_team,
+                division_name => state
+                    .divisions
+                    .iter()
+                    .find(|division| division.id == new_team.division_id)
+                    .map(|division| division.name.as_str()),
+                requirement,
+            })
+            .unwrap(),
+    };
+
+    Html(html).into_response()
+}
+
+/// Moves a user onto a team and swaps their Discord roles
+pub async fn join_team(
+    state: &RouterState,
+    user: &User,
+    old_team: &Team,
+    new_team_id: i64,
+) -> crate::Result<()> {
+    state
+        .db
+        .add_user_to_team(user.id, new_team_id, Some(old_team.id))
+        .await?;
+
+    if let Some(user_discord_id) = user.discord_id {
+        move_user_discord_roles(state, user_discord_id, old_team, new_team_id).await?;
+    }
+
+    tracing::info!(user_id = user.id, team_id = new_team_id, "Joined team");
+
+    Ok(())
+}
+
+/// Swaps the Discord team, division and top 10 roles of a user who was moved off `old_team` onto
+/// the team with id `new_team_id`
+/// Moves a player between the Discord roles of their old and new team. The old team's role and
+/// channels go away once it is too small to have them.
+async fn move_user_team_discord(
+    state: &RouterState,
+    bot: &Bot,
+    user_discord_id: NonZeroU64,
+    old_team_id: i64,
+    new_team: &TeamInner,
+) -> crate::Result<()> {
+    let old_team = state.db.get_team_from_id(old_team_id).await?;
+    if old_team.users.len() < TEAM_DISCORD_MIN_PLAYERS {
+        bot.delete_team_discord(old_team_id).await?;
+    } else {
+        bot.remove_user_from_team_discord(user_discord_id, old_team_id)
+            .await?;
+    }
+    bot.sync_team_discord(new_team).await
+}
+
+async fn move_user_discord_roles(
+    state: &RouterState,
+    user_discord_id: NonZeroU64,
+    old_team: &Team,
+    new_team_id: i64,
+) -> crate::Result<()> {
+    let Some(bot) = state.bot.as_ref() else {
+        return Ok(());
+    };
+
+    let new_team = state.db.get_team_from_id(new_team_id).await?;
+
+    // a problem with team roles must not stop players from changing teams
+    if bot.team_roles_enabled().await {
+        if let Err(error) =
+            move_user_team_discord(state, bot, user_discord_id, old_team.id, &new_team).await
+        {
+            tracing::error!(
+                ?error,
+                old_team_id = old_team.id,
+                new_team_id,
+                "Failed to update team Discord roles and channels"
+            );
+        }
+    }
+
+    let old_division = state
+        .divisions
+        .iter()
+        .find(|d| d.id == old_team.division_id)
+        .unwrap();
+
+    let new_division = state
+        .divisions
+        .iter()
+        .find(|d| d.id == new_team.division_id)
+        .unwrap();
+
+    if old_division.discord_role_id != new_division.discord_role_id {
+        if let Some(discord_role_id) = old_division.discord_role_id {
+            bot.remove_role_from_users(&[user_discord_id], discord_role_id)
+                .await;
+        }
+
+        if let Some(discord_role_id) = new_division.discord_role_id {
+            bot.give_role_to_users(&[user_discord_id], discord_role_id)
+                .await;
+        }
+    }
+
+    if let Some(top10_role_id) = state
+        .settings
+        .read()
+        .await
+        .discord
+        .as_ref()
+        .and_then(|discord| discord.top10_role_id)
+    {
+        let old_team_standing = state.db.get_team_standing(old_team.id);
+        let new_team_standing = state.db.get_team_standing(new_team.id);
+        let (old_team_standing, new_team_standing) =
+            tokio::try_join!(old_team_standing, new_team_standing)?;
+
+        let old_team_top_10 = old_team_standing
+            .map(|standing| standing.rank <= 10)
+            .unwrap_or(false);
+
+        let new_team_top_10 = new_team_standing
+            .map(|standing| standing.rank <= 10)
+            .unwrap_or(false);
+
+        if old_team_top_10 != new_team_top_10 {
+            if new_team_top_10 {
+                bot.give_role_to_users(&[user_discord_id], top10_role_id)
+                    .await;
+            } else {
+                bot.remove_role_from_users(&[user_discord_id], top10_role_id)
+                    .await;
+            }
+        }
+    }
+
+    Ok(())
+}
+
 pub async fn route_user_kick(
     State(state): State<RouterState>,
     Extension(user): Extension<User>,
@@ -244,7 +561,7 @@ pub async fn route_user_kick(
             }
         };
 
-        if let (Some(bot), Some(user_discord_id)) = (state.bot.as_ref(), user.discord_id) {
+        if let (Some(_), Some(user_discord_id)) = (state.bot.as_ref(), user.discord_id) {
             let old_team = match state.db.get_team_from_id(user.team_id).await {
                 Ok(team) => team,
                 Err(e) => {
@@ -253,73 +570,11 @@ pub async fn route_user_kick(
                 }
             };
 
-            let old_division = state
-                .divisions
-                .iter()
-                .find(|d| d.id == old_team.division_id)
-                .unwrap();
-
-            let new_team = match state.db.get_team_from_id(new_team_id).await {
-                Ok(team) => team,
-                Err(e) => {
-                    tracing::error!(error = ?e, who_user_id = user.id, team_id = user.team_id, to_kick_user_id=user_id, "Failed to get new team");
-                    return Err(htmx_error_status_code());
-                }
-            };
-
-            let new_division = state
-                .divisions
-                .iter()
-                .find(|d| d.id == new_team.division_id)
-                .unwrap();
-
-            if old_division.discord_role_id != new_division.discord_role_id {
-                if let Some(discord_role_id) = old_division.discord_role_id {
-                    bot.remove_role_from_users(&[user_discord_id], discord_role_id)
-                        .await;
-                }
-
-                if let Some(discord_role_id) = new_division.discord_role_id {
-                    bot.give_role_to_users(&[user_discord_id], discord_role_id)
-                        .await;
-                }
-            }
-
-            if let Some(top10_role_id) = state
-                .settings
-                .read()
-                .await
-                .discord
-                .as_ref()
-                .and_then(|discord| discord.top10_role_id)
+            if let Err(e) =
+                move_user_discord_roles(&state, user_discord_id, &old_team, new_team_id).await
             {
-                let old_team_standing = state.db.get_team_standing(old_team.id);
-                let new_team_standing = state.db.get_team_standing(new_team.id);
-
-                let (old_team_standing, new_team_standing) =
-                    tokio::try_join!(old_team_standing, new_team_standing)
-                        .map_err(|e| {
-                            tracing::error!(error = ?e, who_user_id = user.id, team_id = user.team_id, to_kick_user_id=user_id, "Failed to get team standings");
-                            htmx_error_status_code()
-                        })?;
-
-                let old_team_top_10 = old_team_standing
-                    .map(|standing| standing.rank <= 10)
-                    .unwrap_or(false);
-
-                let new_team_top_10 = new_team_standing
-                    .map(|standing| standing.rank <= 10)
-                    .unwrap_or(false);
-
-                if old_team_top_10 != new_team_top_10 {
-                    if new_team_top_10 {
-                        bot.give_role_to_users(&[user_discord_id], top10_role_id)
-                            .await;
-                    } else {
-                        bot.remove_role_from_users(&[user_discord_id], top10_role_id)
-                            .await;
-                    }
-                }
+                tracing::error!(error = ?e, who_user_id = user.id, team_id = user.team_id, to_kick_user_id=user_id, "Failed to update Discord roles");
+                return Err(htmx_error_status_code());
             }
         }
 
@@ -349,82 +604,22 @@ pub async fn route_user_kick(
         }
     };
 
-    if let (Some(bot), Some(user_discord_id)) = (state.bot.as_ref(), user_in_team.discord_id) {
-        let old_division = state
-            .divisions
-            .iter()
-            .find(|d| d.id == team.division_id)
-            .unwrap();
-
-        let new_team = match state.db.get_team_from_id(new_team_id).await {
-            Ok(team) => team,
-            Err(e) => {
-                tracing::error!(error = ?e, who_user_id = user.id, team_id = user.team_id, to_kick_user_id=user_id, "Failed to get new team");
-                return Err(htmx_error_status_code());
-            }
-        };
-        let new_division = state
-            .divisions
-            .iter()
-            .find(|d| d.id == new_team.division_id)
-            .unwrap();
-
-        if old_division.discord_role_id != new_division.discord_role_id {
-            if let Some(discord_role_id) = old_division.discord_role_id {
-                bot.remove_role_from_users(&[user_discord_id], discord_role_id)
-                    .await;
-            }
-
-            if let Some(discord_role_id) = new_division.discord_role_id {
-                bot.give_role_to_users(&[user_discord_id], discord_role_id)
-                    .await;
-            }
-        }
-
-        if let Some(top10_role_id) = state
-            .settings
-            .read()
-            .await
-            .discord
-            .as_ref()
-            .and_then(|discord| discord.top10_role_id)
-        {
-            let old_team_standing = state.db.get_team_standing(team.id);
-            let new_team_standing = state.db.get_team_standing(new_team.id);
-            let (old_team_standing, new_team_standing) =
-                tokio::try_join!(old_team_standing, new_team_standing)
-                    .map_err(|e| {
-                        tracing::error!(error = ?e, who_user_id = user.id, team_id = user.team_id, to_kick_user_id=user_id, "Failed to get team standings");
-                        htmx_error_status_code()
-                    })?;
-            let old_team_top_10 = old_team_standing
-                .map(|standing| standing.rank <= 10)
-                .unwrap_or(false);
-            let new_team_top_10 = new_team_standing
-                .map(|standing| standing.rank <= 10)
-                .unwrap_or(false);
-            if old_team_top_10 != new_team_top_10 {
-                if new_team_top_10 {
-                    bot.give_role_to_users(&[user_discord_id], top10_role_id)
-                        .await;
-                } else {
-                    bot.remove_role_from_users(&[user_discord_id], top10_role_id)
-                        .await;
-                }
-            }
+    if let Some(user_discord_id) = user_in_team.discord_id {
+        if let Err(e) = move_user_discord_roles(&state, user_discord_id, &team, new_team_id).await {
+            tracing::error!(error = ?e, who_user_id = user.id, team_id = user.team_id, to_kick_user_id=user_id, "Failed to update Discord roles");
+            return Err(htmx_error_status_code());
         }
     }
 
     Ok(([("HX-Trigger", "pageRefresh")]).into_response())
 }
 
-pub async fn route_team_set_division(
+pub async fn route_team_transfer_ownership(
     State(state): State<RouterState>,
     Extension(user): Extension<User>,
-    Extension(page): Extension<PageMeta>,
-    Path(division_id): Path<String>,
+    Path(user_id): Path<i64>,
 ) -> std::result::Result<impl IntoResponse, Response> {
-    if !user.is_team_owner {
+    if !user.is_team_owner || user_id == user.id {
         return Err(StatusCode::UNAUTHORIZED.into_response());
     }
 
@@ -432,88 +627,114 @@ pub async fn route_team_set_division(
         return Err(StatusCode::FORBIDDEN.into_response());
     }
 
-    let team = state.db.get_team_from_id(user.team_id).await.unwrap();
-
-    let next_allowed =
-        team.last_division_change.unwrap_or_default() + chrono::Duration::minutes(60);
-
-    let now = chrono::Utc::now();
-    if next_allowed > now {
-        let resets_in = next_allowed - now;
-
-        return Err(([
-            (
-                "HX-Trigger",
-                toast_header(
-                    ToastKind::Error,
-                    &format!(
-                        "You can change this division status again in {} minutes",
-                        resets_in.num_minutes() + 1
-                    ),
-                ),
-            ),
-            (
-                "HX-Location",
-                r##"{{"path":"/team","select":"#screen","target":"#screen","swap":"outerHTML"}}"##
-                    .to_string(),
-            ),
-        ])
-        .into_response());
-    }
-
-    let division = state.divisions.iter().find(|d| d.id == division_id);
-    let Some(division) = division else {
-        return Err(StatusCode::NOT_FOUND.into_response());
-    };
-
-    match division.max_players {
-        MaxDivisionPlayers::Limited(max_players) => {
-            if team.users.len() > max_players.get() as usize {
-                return Err(StatusCode::FORBIDDEN.into_response());
-            }
+    let team = match state.db.get_team_from_id(user.team_id).await {
+        Ok(team) => team,
+        Err(e) => {
+            tracing::error!(error = ?e, who_user_id = user.id, team_id = user.team_id, new_owner_user_id = user_id, "Failed to get team");
+            return Err(htmx_error_status_code().into_response());
         }
-        MaxDivisionPlayers::Unlimited => {}
+    };
+    if !team.users.contains_key(&user_id) {
+        return Err(StatusCode::NOT_FOUND.into_response());
     }
 
-    let eligible = division
-        .division_eligibility
-        .is_user_eligible(user.id)
+    if let Err(e) = state
+        .db
+        .transfer_team_ownership(team.id, user.id, user_id)
         .await
-        .is_ok();
-
-    if !eligible {
-        return Err(StatusCode::FORBIDDEN.into_response());
+    {
+        tracing::error!(error = ?e, who_user_id = user.id, team_id = user.team_id, new_owner_user_id = user_id, "Failed to transfer team ownership");
+        return Err(htmx_error_status_code().into_response());
     }
 
-    state
-        .db
-        .set_team_division(team.id, &team.division_id, &division_id, now)
-        .await
-        .unwrap();
+    tracing::info!(
+        team_id = team.id,
+        old_owner_user_id = user.id,
+        new_owner_user_id = user_id,
+        "Transferred team ownership"
+    );
 
-    if let Some(bot) = state.bot.as_ref() {
-        let user_discord_ids = team
-            .users
-            .values()
-            .filter_map(|u| u.discord_id)
-            .collect::<Vec<_>>();
+    Ok(([("HX-Trigger", "pageRefresh")]).into_response())
+}
 
-        let old_division = state
-            .divisions
-            .iter()
-            .find(|d| d.id == team.division_id)
-            .unwrap();
-        if let Some(discord_role_id) = old_division.discord_role_id {
+pub async fn route_team_disband(
+    State(state): State<RouterState>,
+    Extension(user): Extension<User>,
+) -> std::result::Result<impl IntoResponse, StatusCode> {
+    if !user.is_team_owner {
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+
+    if user.disabled {
+        return Err(StatusCode::FORBIDDEN);
+    }
+
+    let team = match state.db.get_team_from_id(user.team_id).await {
+        Ok(team) => team,
+        Err(e) => {
+            tracing::error!(error = ?e, who_user_id = user.id, team_id = user.team_id, "Failed to get team");
+            return Err(htmx_error_status_code());
+        }
+    };
+
+    let moved = match state.db.disband_team(team.id).await {
+        Ok(moved) => moved,
+        Err(e) => {
+            tracing::error!(error = ?e, who_user_id = user.id, team_id = user.team_id, "Failed to disband team");
+            return Err(htmx_error_status_code());
+        }
+    };
+
+    for (moved_user_id, new_team_id) in moved {
+        let Some(user_discord_id) = team.users.get(&moved_user_id).and_then(|u| u.discord_id)
+        else {
+            continue;
+        };
+
+        if let Err(e) = move_user_discord_roles(&state, user_discord_id, &team, new_team_id).await {
+            tracing::error!(error = ?e, team_id = team.id, moved_user_id, "Failed to update Discord roles");
+        }
+    }
+
+    if let Some(bot) = state.bot.as_ref() {
+        if let Err(e) = bot.delete_team_discord(team.id).await {
+            tracing::error!(error = ?e, team_id = team.id, "Failed to delete team Discord role and channels");
+        }
+    }
+
+    tracing::info!(team_id = team.id, who_user_id = user.id, "Disbanded team");
+
+    Ok(([("HX-Trigger", "pageRefresh")]).into_response())
+}
+
+/// Moves a team to another division and swaps the Discord division roles of its members
+pub async fn move_team_division(
+    state: &RouterState,
+    team: &Team,
+    division_id: &str,
+    now: chrono::DateTime<chrono::Utc>,
+    admin_user_id: Option<i64>,
+) -> crate::Result<()> {
+    state
+        .db
+        .set_team_division(team.id, &team.division_id, division_id, now, admin_user_id)
+        .await?;
+
+    if let Some(bot) = state.bot.as_ref() {
+        let user_discord_ids = team
+            .users
+            .values()
+            .filter_map(|u| u.discord_id)
+            .collect::<Vec<_>>();
+
+        let old_division = state.divisions.iter().find(|d| d.id == team.division_id);
+        if let Some(discord_role_id) = old_division.and_then(|d| d.discord_role_id) {
             bot.remove_role_from_users(&user_discord_ids, discord_role_id)
                 .await;
         }
 
-        let new_division = state
-            .divisions
-            .iter()
-            .find(|d| d.id == division_id)
-            .unwrap();
-        if let Some(discord_role_id) = new_division.discord_role_id {
+        let new_division = state.divisions.iter().find(|d| d.id == division_id);
+        if let Some(discord_role_id) = new_division.and_then(|d| d.discord_role_id) {
             bot.give_role_to_users(&user_discord_ids, discord_role_id)
                 .await;
         }
@@ -521,35 +742,110 @@ pub async fn route_team_set_division(
 
     tracing::trace!(team_id = team.id, division_id, "Set division");
 
-    let standing = state.db.get_team_standing(user.team_id).await.unwrap();
+    Ok(())
+}
 
-    let mut divisions = vec![];
-    for division in state.divisions.iter() {
-        let eligible = division
-            .division_eligibility
-            .is_user_eligible(team.owner_user_id)
-            .await;
+pub async fn route_team_set_division(
+    State(state): State<RouterState>,
+    Extension(user): Extension<User>,
+    Extension(page): Extension<PageMeta>,
+    Path(division_id): Path<String>,
+) -> std::result::Result<impl IntoResponse, Response> {
+    if !user.is_team_owner {
+        return Err(StatusCode::UNAUTHORIZED.into_response());
+    }
 
-        let oversized = match division.max_players {
-            MaxDivisionPlayers::Unlimited => true,
-            MaxDivisionPlayers::Limited(max_players) => {
-                team.users.len() <= max_players.get() as usize
+    if user.disabled {
+        return Err(StatusCode::FORBIDDEN.into_response());
+    }
+
+    let team = state.db.get_team_from_id(user.team_id).await.unwrap();
+
+    let next_allowed =
+        team.last_division_change.unwrap_or_default() + chrono::Duration::minutes(60);
+
+    let now = chrono::Utc::now();
+    if next_allowed > now {
+        let resets_in = next_allowed - now;
+
+        return Err(([
+            (
+                "HX-Trigger",
+                toast_header(
+                    ToastKind::Error,
+                    &format!(
+                        "You can change this division status again in {} minutes",
+                        resets_in.num_minutes() + 1
+                    ),
+                ),
+            ),
+            (
+                "HX-Location",
+                r##"{{"path":"/team","select":"#screen","target":"#screen","swap":"outerHTML"}}"##
+                    .to_string(),
+            ),
+        ])
+        .into_response());
+    }
+
+    let division = state
+        .divisions
+        .iter()
+        .find(|d| d.id == division_id && !d.archived);
+    let Some(division) = division else {
+        return Err(StatusCode::NOT_FOUND.into_response());
+    };
+
+    match division.max_players {
+        MaxDivisionPlayers::Limited(max_players) => {
+            if team.users.len() > max_players.get() as usize {
+                return Err(StatusCode::FORBIDDEN.into_response());
             }
-        };
+        }
+        MaxDivisionPlayers::Unlimited => {}
+    }
 
-        let joined = division_id == division.id;
+    // every member of the team must be eligible for the division
+    if let Err(requirement) = division.is_team_eligible(&team, None).await {
+        if !division.division_eligibility.is_requestable() {
+            return Err(StatusCode::FORBIDDEN.into_response());
+        }
 
-        divisions.push(TeamDivision {
-            id: division.id.clone(),
-            name: &division.name,
-            description: &division.description,
-            eligible: eligible.is_ok() && oversized,
-            requirement: eligible.err(),
-            joined,
-            max_players: division.max_players.clone(),
-        })
+        division.request_team_eligibility(&team).await;
+
+        return Err(([
+            (
+                "HX-Trigger",
+                toast_header(
+                    ToastKind::Error,
+                    &format!(
+                        "{} {}",
+                        requirement,
+                        state
+                            .localizer
+                            .localize(&page.lang, "team-division-eligibility-requested", None)
+                            .unwrap_or_default()
+                    ),
+                ),
+            ),
+            (
+                "HX-Location",
+                r##"{{"path":"/team","select":"#screen","target":"#screen","swap":"outerHTML"}}"##
+                    .to_string(),
+            ),
+        ])
+        .into_response());
     }
 
+    move_team_division(&state, &team, &division_id, now, None)
+        .await
+        .unwrap();
+
+    let standing = state.db.get_team_standing(user.team_id).await.unwrap();
+
+    let team = state.db.get_team_from_id(user.team_id).await.unwrap();
+    let divisions = team_divisions(&state, &team).await;
+
     let max_players = divisions
         .iter()
         .filter(|division| division.joined)
@@ -580,3 +876,357 @@ pub async fn route_team_set_division(
 
     Ok(Html(html))
 }
+
+#[derive(Deserialize)]
+pub struct TeamInviteEmail {
+    email: String,
+}
+
+pub async fn route_team_invite_email(
+    State(state): State<RouterState>,
+    Extension(user): Extension<User>,
+    Extension(page): Extension<PageMeta>,
+    Form(form): Form<TeamInviteEmail>,
+) -> std::result::Result<impl IntoResponse, Response> {
+    if !user.is_team_owner {
+        return Err(StatusCode::UNAUTHORIZED.into_response());
+    }
+
+    if user.disabled {
+        return Err(StatusCode::FORBIDDEN.into_response());
+    }
+
+    let Some(outbound_mailer) = state.outbound_mailer.as_ref() else {
+        return Err(StatusCode::NOT_FOUND.into_response());
+    };
+
+    let email = form.email.trim();
+    if !(1..=255).contains(&email.len()) || !email.contains('@') {
+        return Err(toast(
+            &state,
+            &page,
+            ToastKind::Error,
+            "team-error-invite-email",
+        ));
+    }
+
+    let team = match state.db.get_team_from_id(user.team_id).await {
+        Ok(team) => team,
+        Err(e) => {
+            tracing::error!(error = ?e, user_id = user.id, team_id = user.team_id, "Failed to get team");
+            return Err(htmx_error_status_code().into_response());
+        }
+    };
+
+    let code = match state
+        .db
+        .create_team_invite(team.id, None, Some(email))
+        .await
+    {
+        Ok(code) => code,
+        Err(e) => {
+            tracing::error!(error = ?e, user_id = user.id, team_id = team.id, "Failed to create team invite");
+            return Err(htmx_error_status_code().into_response());
+        }
+    };
+
+    if let Err(e) = outbound_mailer
+        .send_team_invite(&user.name, &team.name, email, &code)
+        .await
+    {
+        tracing::error!(error = ?e, user_id = user.id, team_id = team.id, "Failed to send team invite");
+        _ = state.db.delete_team_invite(&code).await;
+        return Err(htmx_error_status_code().into_response());
+    }
+
+    Ok(([("HX-Trigger", "pageRefresh")]).into_response())
+}
+
+pub async fn route_team_invite_user(
+    State(state): State<RouterState>,
+    Extension(user): Extension<User>,
+    Extension(page): Extension<PageMeta>,
+    Path(user_id): Path<i64>,
+) -> std::result::Result<impl IntoResponse, Response> {
+    if !user.is_team_owner {
+        return Err(StatusCode::UNAUTHORIZED.into_response());
+    }
+
+    if user.disabled {
+        return Err(StatusCode::FORBIDDEN.into_response());
+    }
+
+    let invited_user = match state.db.get_user_from_id(user_id).await {
+        Ok(invited_user) => invited_user,
+        Err(RhombusError::DatabaseReturnedNoRows) => {
+            return Err(StatusCode::NOT_FOUND.into_response());
+        }
+        Err(e) => {
+            tracing::error!(error = ?e, user_id = user.id, invited_user_id = user_id, "Failed to get invited user");
+            return Err(htmx_error_status_code().into_response());
+        }
+    };
+
+    if invited_user.team_id == user.team_id {
+        return Err(StatusCode::BAD_REQUEST.into_response());
+    }
+
+    let already_invited = match state.db.get_user_team_invites(user_id).await {
+        Ok(invites) => invites.iter().any(|invite| invite.team_id == user.team_id),
+        Err(e) => {
+            tracing::error!(error = ?e, user_id = user.id, invited_user_id = user_id, "Failed to get invites");
+            return Err(htmx_error_status_code().into_response());
+        }
+    };
+
+    if !already_invited {
+        if let Err(e) = state
+            .db
+            .create_team_invite(user.team_id, Some(user_id), None)
+            .await
+        {
+            tracing::error!(error = ?e, user_id = user.id, invited_user_id = user_id, "Failed to create team invite");
+            return Err(htmx_error_status_code().into_response());
+        }
+    }
+
+    Ok(toast(&state, &page, ToastKind::Success, "team-invite-sent"))
+}
+
+/// Revokes an invite as the team owner, or declines it as the invited user
+pub async fn route_team_invite_delete(
+    State(state): State<RouterState>,
+    Extension(user): Extension<User>,
+    Path(code): Path<String>,
+) -> std::result::Result<impl IntoResponse, StatusCode> {
+    let invite = match state.db.get_team_invite(&code).await {
+        Ok(Some(invite)) => invite,
+        Ok(None) => return Err(StatusCode::NOT_FOUND),
+        Err(e) => {
+            tracing::error!(error = ?e, user_id = user.id, "Failed to get team invite");
+            return Err(htmx_error_status_code());
+        }
+    };
+
+    let is_owner = user.is_team_owner && invite.team_id == user.team_id;
+    if !is_owner && invite.user_id != Some(user.id) {
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+
+    if let Err(e) = state.db.delete_team_invite(&code).await {
+        tracing::error!(error = ?e, user_id = user.id, "Failed to delete team invite");
+        return Err(htmx_error_status_code());
+    }
+
+    Ok(([("HX-Trigger", "pageRefresh")]).into_response())
+}
+
+pub async fn route_team_invite_accept(
+    State(state): State<RouterState>,
+    Extension(user): Extension<User>,
+    Extension(page): Extension<PageMeta>,
+    Path(code): Path<String>,
+    extensions: Extensions,
+) -> std::result::Result<impl IntoResponse, Response> {
+    let invite = state
+        .db
+        .get_team_invite(&code)
+        .await
+        .map_err_page(&extensions, "Failed to get team invite")?;
+
+    // invites to a user can only be accepted by them, and email invites by someone who has
+    // verified that email
+    let Some(invite) = invite.filter(|invite| invite.user_id.map_or(true, |id| id == user.id))
+    else {
+        return Err(error_page(
+            StatusCode::NOT_FOUND,
+            "Invite not found",
+            &state,
+            &Some(user),
+            &page,
+        ));
+    };
+
+    if let Some(ref invite_email) = invite.email {
+        let emails = state
+            .db
+            .get_emails_for_user_id(user.id)
+            .await
+            .map_err_page(&extensions, "Failed to get emails")?;
+
+        if !has_verified_email(&emails, invite_email) {
+            return Err(error_page(
+                StatusCode::FORBIDDEN,
+                "This invite was sent to an email you have not verified",
+                &state,
+                &Some(user),
+                &page,
+            ));
+        }
+    }
+
+    if invite.team_id == user.team_id {
+        _ = state.db.delete_team_invite(&code).await;
+        return Ok(Redirect::to("/team"));
+    }
+
+    let old_team = state.db.get_team_from_id(user.team_id);
+    let new_team = state.db.get_team_from_id(invite.team_id);
+    let (old_team, new_team) = tokio::try_join!(old_team, new_team)
+        .map_err_page(&extensions, "Failed to get team data")?;
+
+    if let Err(error) = check_join_team(&state.divisions, user.id, &old_team, &new_team).await {
+        return Err(join_team_error_page(
+            &state, &page, &user, error, old_team, new_team,
+        ));
+    }
+
+    join_team(&state, &user, &old_team, new_team.id)
+        .await
+        .map_err_page(&extensions, "Failed to join team")?;
+
+    state
+        .db
+        .delete_team_invite(&code)
+        .await
+        .map_err_page(&extensions, "Failed to delete team invite")?;
+
+    Ok(Redirect::to("/team"))
+}
+
+pub async fn route_team_join_request(
+    State(state): State<RouterState>,
+    Extension(user): Extension<User>,
+    Extension(page): Extension<PageMeta>,
+    Path(team_id): Path<i64>,
+) -> std::result::Result<impl IntoResponse, Response> {
+    if user.team_id == team_id {
+        return Err(StatusCode::BAD_REQUEST.into_response());
+    }
+
+    let team = match state.db.get_team_from_id(team_id).await {
+        Ok(team) => team,
+        Err(RhombusError::DatabaseReturnedNoRows) => {
+            return Err(StatusCode::NOT_FOUND.into_response());
+        }
+        Err(e) => {
+            tracing::error!(error = ?e, user_id = user.id, team_id, "Failed to get team");
+            return Err(htmx_error_status_code().into_response());
+        }
+    };
+
+    if team.users.is_empty() {
+        return Err(StatusCode::NOT_FOUND.into_response());
+    }
+
+    if let Err(e) = state.db.create_team_join_request(team_id, user.id).await {
+        tracing::error!(error = ?e, user_id = user.id, team_id, "Failed to create join request");
+        return Err(htmx_error_status_code().into_response());
+    }
+
+    Ok(toast(
+        &state,
+        &page,
+        ToastKind::Success,
+        "team-join-requested",
+    ))
+}
+
+pub async fn route_team_join_request_approve(
+    State(state): State<RouterState>,
+    Extension(user): Extension<User>,
+    Extension(page): Extension<PageMeta>,
+    Path(user_id): Path<i64>,
+) -> std::result::Result<impl IntoResponse, Response> {
+    if !user.is_team_owner {
+        return Err(StatusCode::UNAUTHORIZED.into_response());
+    }
+
+    let requester = match state.db.get_user_from_id(user_id).await {
+        Ok(requester) => requester,
+        Err(e) => {
+            tracing::error!(error = ?e, user_id = user.id, requester_user_id = user_id, "Failed to get requesting user");
+            return Err(htmx_error_status_code().into_response());
+        }
+    };
+
+    let old_team = state.db.get_team_from_id(requester.team_id);
+    let new_team = state.db.get_team_from_id(user.team_id);
+    let (old_team, new_team) = match tokio::try_join!(old_team, new_team) {
+        Ok(teams) => teams,
+        Err(e) => {
+            tracing::error!(error = ?e, user_id = user.id, requester_user_id = user_id, "Failed to get team data");
+            return Err(htmx_error_status_code().into_response());
+        }
+    };
+
+    match check_join_team(&state.divisions, requester.id, &old_team, &new_team).await {
+        Ok(()) => {}
+        Err(JoinTeamError::Abandoned) => return Err(StatusCode::NOT_FOUND.into_response()),
+        Err(JoinTeamError::ExistingTeam) => {
+            return Err(toast(
+                &state,
+                &page,
+                ToastKind::Error,
+                "team-error-join-request-existing-team",
+            ));
+        }
+        Err(JoinTeamError::Full(_)) => {
+            return Err(toast(
+                &state,
+                &page,
+                ToastKind::Error,
+                "team-error-join-request-full",
+            ));
+        }
+        Err(JoinTeamError::Ineligible(_)) => {
+            return Err(toast(
+                &state,
+                &page,
+                ToastKind::Error,
+                "team-error-join-request-ineligible",
+            ));
+        }
+    }
+
+    match state
+        .db
+        .delete_team_join_request(user.team_id, user_id)
+        .await
+    {
+        Ok(true) => {}
+        Ok(false) => return Err(StatusCode::NOT_FOUND.into_response()),
+        Err(e) => {
+            tracing::error!(error = ?e, user_id = user.id, requester_user_id = user_id, "Failed to delete join request");
+            return Err(htmx_error_status_code().into_response());
+        }
+    }
+
+    if let Err(e) = join_team(&state, &requester, &old_team, new_team.id).await {
+        tracing::error!(error = ?e, user_id = user.id, requester_user_id = user_id, "Failed to add user to team");
+        return Err(htmx_error_status_code().into_response());
+    }
+
+    Ok(([("HX-Trigger", "pageRefresh")]).into_response())
+}
+
+pub async fn route_team_join_request_deny(
+    State(state): State<RouterState>,
+    Extension(user): Extension<User>,
+    Path(user_id): Path<i64>,
+) -> std::result::Result<impl IntoResponse, StatusCode> {
+    if !user.is_team_owner {
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+
+    if let Err(e) = state
+        .db
+        .delete_team_join_request(user.team_id, user_id)
+        .await
+    {
+        tracing::error!(error = ?e, user_id = user.id, requester_user_id = user_id, "Failed to delete join request");
+        return Err(htmx_error_status_code());
+    }
+
+    Ok(([("HX-Trigger", "pageRefresh")]).into_response())
+}
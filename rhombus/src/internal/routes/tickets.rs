@@ -0,0 +1,294 @@
+use axum::{
+    extract::{Path, Query, State},
+    http::{header, Extensions, StatusCode},
+    response::{Html, IntoResponse, Response},
+    Extension, Form,
+};
+use minijinja::context;
+use serde::Deserialize;
+
+use crate::internal::{
+    auth::User,
+    database::provider::Ticket,
+    errors::IntoErrorResponse,
+    router::RouterState,
+    routes::meta::PageMeta,
+    templates::{toast_header, ToastKind},
+    tickets::{
+        add_ticket_message, format_duration, get_ticket_transcript, is_ticket_staff,
+        render_canned_responses, set_ticket_closed, spawn_ticket_digest, ticket_assignees,
+        TranscriptFormat,
+    },
+};
+
+/// Gets a ticket the user opened or answers as staff. Admins can get any ticket, and authors the
+/// tickets about their challenges.
+async fn get_ticket_for_user(
+    state: &RouterState,
+    user: &User,
+    ticket_number: u64,
+) -> std::result::Result<Ticket, StatusCode> {
+    let ticket = state
+        .db
+        .get_ticket_by_ticket_number(ticket_number)
+        .await
+        .map_err(|_| StatusCode::NOT_FOUND)?;
+
+    if ticket.user_id == user.id {
+        return Ok(ticket);
+    }
+
+    let challenge_data = state
+        .db
+        .get_challenges()
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    if !is_ticket_staff(&challenge_data, &ticket, user.is_admin, user.discord_id) {
+        return Err(StatusCode::NOT_FOUND);
+    }
+
+    Ok(ticket)
+}
+
+pub async fn route_tickets(
+    State(state): State<RouterState>,
+    Extension(user): Extension<User>,
+    Extension(page): Extension<PageMeta>,
+    extensions: Extensions,
+) -> std::result::Result<impl IntoResponse, Response> {
+    let tickets = state.db.get_user_tickets(user.id);
+    let challenge_data = state.db.get_challenges();
+    let (tickets, challenge_data) = tokio::try_join!(tickets, challenge_data)
+        .map_err_page(&extensions, "Failed to get tickets")?;
+
+    Ok(Html(
+        state
+            .jinja
+            .get_template("tickets/tickets.html")
+            .unwrap()
+            .render(context! {
+                global => state.global_page_meta,
+                page,
+                title => format!("Tickets | {}", state.global_page_meta.title),
+                user,
+                tickets,
+                challenges => challenge_data.challenges,
+                now => chrono::Utc::now(),
+            })
+            .unwrap(),
+    ))
+}
+
+pub async fn route_ticket(
+    State(state): State<RouterState>,
+    Extension(user): Extension<User>,
+    Extension(page): Extension<PageMeta>,
+    Path(ticket_number): Path<u64>,
+    extensions: Extensions,
+) -> std::result::Result<impl IntoResponse, Response> {
+    let ticket = get_ticket_for_user(&state, &user, ticket_number)
+        .await
+        .map_err(|status| status.into_response())?;
+
+    let messages = state.db.get_ticket_messages(ticket_number);
+    let challenge_data = state.db.get_challenges();
+    let creator = state.db.get_user_from_id(ticket.user_id);
+    let (messages, challenge_data, creator) = tokio::try_join!(messages, challenge_data, creator)
+        .map_err_page(&extensions, "Failed to get ticket")?;
+
+    let challenge = challenge_data.challenges.get(&ticket.challenge_id);
+    let assignees = user.is_admin.then(|| ticket_assignees(&challenge_data));
+    let canned_responses =
+        if is_ticket_staff(&challenge_data, &ticket, user.is_admin, user.discord_id) {
+            let settings = state.settings.read().await;
+            Some(
+                render_canned_responses(&state.db, &settings, &challenge_data, &ticket)
+                    .await
+                    .map_err_page(&extensions, "Failed to render canned responses")?,
+            )
+        } else {
+            None
+        };
+    let first_response = ticket.first_response_at.map(|first_response_at| {
+        format_duration((first_response_at - ticket.opened_at).num_seconds())
+    });
+
+    Ok(Html(
+        state
+            .jinja
+            .get_template("tickets/ticket.html")
+            .unwrap()
+            .render(context! {
+                global => state.global_page_meta,
+                page,
+                title => format!("Ticket #{} | {}", ticket.ticket_number, state.global_page_meta.title),
+                user,
+                ticket,
+                messages,
+                challenge,
+                creator,
+                assignees,
+                canned_responses,
+                first_response,
+                now => chrono::Utc::now(),
+            })
+            .unwrap(),
+    ))
+}
+
+#[derive(Deserialize)]
+pub struct TicketReply {
+    content: String,
+}
+
+pub async fn route_ticket_reply(
+    State(state): State<RouterState>,
+    Extension(user): Extension<User>,
+    Extension(page): Extension<PageMeta>,
+    Path(ticket_number): Path<u64>,
+    Form(form): Form<TicketReply>,
+) -> std::result::Result<impl IntoResponse, Response> {
+    let ticket = get_ticket_for_user(&state, &user, ticket_number)
+        .await
+        .map_err(|status| status.into_response())?;
+
+    let content = form.content.trim();
+    if content.is_empty() {
+        return Ok(StatusCode::NO_CONTENT.into_response());
+    }
+    if content.len() > 1000 {
+        return Ok(([(
+            "HX-Trigger",
+            toast_header(
+                ToastKind::Error,
+                &state
+                    .localizer
+                    .localize(&page.lang, "challenges-error-ticket-too-long", None)
+                    .unwrap(),
+            ),
+        )])
+        .into_response());
+    }
+
+    // replying to a closed ticket picks the conversation back up
+    if ticket.closed_at.is_some() {
+        if let Err(error) = set_ticket_closed(&state.db, state.bot.as_deref(), &ticket, false).await
+        {
+            tracing::error!(?error, ticket_number, "Failed to reopen ticket");
+            return Err(StatusCode::INTERNAL_SERVER_ERROR.into_response());
+        }
+    }
+
+    if let Err(error) = add_ticket_message(
+        &state.db,
+        state.bot.as_deref(),
+        &ticket,
+        &user,
+        None,
+        content,
+        &[],
+    )
+    .await
+    {
+        tracing::error!(
+            ?error,
+            ticket_number,
+            user_id = user.id,
+            "Failed to add ticket message"
+        );
+        return Err(StatusCode::INTERNAL_SERVER_ERROR.into_response());
+    }
+
+    if user.id != ticket.user_id {
+        spawn_ticket_digest(
+            state.db.clone(),
+            state.outbound_mailer.clone(),
+            ticket_number,
+        );
+    }
+
+    Ok(([("HX-Trigger", "pageRefresh")]).into_response())
+}
+
+async fn set_closed(
+    state: &RouterState,
+    user: &User,
+    ticket_number: u64,
+    closed: bool,
+) -> std::result::Result<Response, Response> {
+    let ticket = get_ticket_for_user(state, user, ticket_number)
+        .await
+        .map_err(|status| status.into_response())?;
+
+    if let Err(error) = set_ticket_closed(&state.db, state.bot.as_deref(), &ticket, closed).await {
+        tracing::error!(?error, ticket_number, closed, "Failed to update ticket");
+        return Err(StatusCode::INTERNAL_SERVER_ERROR.into_response());
+    }
+
+    tracing::info!(ticket_number, user_id = user.id, closed, "Updated ticket");
+
+    Ok(([("HX-Trigger", "pageRefresh")]).into_response())
+}
+
+pub async fn route_ticket_close(
+    State(state): State<RouterState>,
+    Extension(user): Extension<User>,
+    Path(ticket_number): Path<u64>,
+) -> std::result::Result<Response, Response> {
+    set_closed(&state, &user, ticket_number, true).await
+}
+
+pub async fn route_ticket_reopen(
+    State(state): State<RouterState>,
+    Extension(user): Extension<User>,
+    Path(ticket_number): Path<u64>,
+) -> std::result::Result<Response, Response> {
+    set_closed(&state, &user, ticket_number, false).await
+}
+
+#[derive(Deserialize)]
+pub struct TranscriptQuery {
+    #[serde(default)]
+    format: TranscriptFormat,
+}
+
+pub async fn route_ticket_transcript(
+    State(state): State<RouterState>,
+    Extension(user): Extension<User>,
+    Path(ticket_number): Path<u64>,
+    Query(query): Query<TranscriptQuery>,
+    extensions: Extensions,
+) -> std::result::Result<impl IntoResponse, Response> {
+    let ticket = get_ticket_for_user(&state, &user, ticket_number)
+        .await
+        .map_err(|status| status.into_response())?;
+
+    let challenge_data = state
+        .db
+        .get_challenges()
+        .await
+        .map_err_page(&extensions, "Failed to get challenges")?;
+    let transcript = get_ticket_transcript(&state.db, &challenge_data, ticket)
+        .await
+        .map_err_page(&extensions, "Failed to get ticket transcript")?;
+
+    let body = match query.format {
+        TranscriptFormat::Markdown => transcript.to_markdown(),
+        TranscriptFormat::Json => serde_json::to_string_pretty(&transcript).unwrap(),
+    };
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, query.format.content_type().to_owned()),
+            (
+                header::CONTENT_DISPOSITION,
+                format!(
+                    "attachment; filename=ticket-{}.{}",
+                    ticket_number,
+                    query.format.extension()
+                ),
+            ),
+        ],
+        body,
+    ))
+}
@@ -1,4 +1,4 @@
-use std::{num::NonZeroU64, sync::Arc};
+use std::{collections::BTreeMap, num::NonZeroU64, sync::Arc};
 
 use serde::{Deserialize, Serialize};
 
@@ -15,6 +15,9 @@ pub struct DiscordSettings {
     pub verified_role_id: Option<NonZeroU64>,
     pub top10_role_id: Option<NonZeroU64>,
     pub invite_url: Option<String>,
+    pub team_roles: Option<bool>,
+    pub team_category_id: Option<NonZeroU64>,
+    pub team_voice_channels: Option<bool>,
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
@@ -80,6 +83,16 @@ pub struct UploadProviderSettings {
     pub local: Option<LocalUploadProviderSettings>,
     pub s3: Option<S3UploadProviderSettings>,
     pub database: Option<bool>,
+    pub signed_urls: Option<SignedUrlSettings>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct SignedUrlSettings {
+    /// Seconds until a download link expires, defaults to one hour
+    pub expiry: Option<u32>,
+    /// Only allow the team which a link was created for to use it. With S3, files are
+    /// then proxied through Rhombus instead of presigned.
+    pub bind_team: Option<bool>,
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
@@ -118,6 +131,15 @@ pub struct Division {
     pub email_regex: Option<String>,
     pub max_players: Option<String>,
     pub discord_role_id: Option<NonZeroU64>,
+    pub required_discord_role_id: Option<NonZeroU64>,
+    pub ctftime: Option<DivisionCTFtimeSettings>,
+    pub manual_approval: Option<bool>,
+    pub user_ids: Option<Vec<i64>>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct DivisionCTFtimeSettings {
+    pub countries: Option<Vec<String>>,
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
@@ -130,6 +152,16 @@ pub enum AuthProvider {
     CTFtime,
     #[serde(rename = "credentials")]
     Credentials,
+    #[serde(rename = "oidc")]
+    Oidc,
+    #[serde(rename = "github")]
+    GitHub,
+    #[serde(rename = "google")]
+    Google,
+    #[serde(rename = "passkey")]
+    Passkey,
+    #[serde(rename = "ldap")]
+    Ldap,
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
@@ -143,6 +175,127 @@ pub struct CTFtimeSettings {
     pub client_secret: Option<String>,
 }
 
+/// An OpenAI compatible chat completions API for the `/ai` ticket assistant
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct AiSettings {
+    /// Defaults to `https://api.openai.com/v1`
+    pub base_url: Option<String>,
+    /// Defaults to `gpt-3.5-turbo`
+    pub model: Option<String>,
+    /// Not needed for local servers. Defaults to `openai_api_key`
+    pub api_key: Option<String>,
+    /// Defaults to `0.2`
+    pub temperature: Option<f32>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct GitHubSettings {
+    pub client_id: String,
+    pub client_secret: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct GoogleSettings {
+    pub client_id: String,
+    pub client_secret: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+pub struct CredentialsSettings {
+    /// Allow anyone to register, defaults to `true`. When disabled, only players with a team
+    /// invite link can register
+    pub registration: Option<bool>,
+    /// Require an email when registering, which must be confirmed before signing in
+    pub require_email: Option<bool>,
+    /// Defaults to 3
+    pub username_min_length: Option<usize>,
+    /// Defaults to 30
+    pub username_max_length: Option<usize>,
+    /// Regex which usernames must match when registering, e.g. `^[a-zA-Z0-9_]+$`
+    pub username_regex: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct TwoFactorSettings {
+    /// Require admins to enroll in two-factor authentication before they can sign in
+    pub require_for_admins: Option<bool>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct LdapDivisionGroup {
+    /// DN of the group
+    pub group: String,
+    /// Id of the division, its `stable_id` or name
+    pub division: String,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct LdapSettings {
+    /// Url of the directory, e.g. `ldap://localhost:389` or `ldaps://ldap.example.com`
+    pub url: String,
+    /// Upgrade the `ldap://` connection with StartTLS
+    pub starttls: Option<bool>,
+    /// Service account used to search for users, searches anonymously if unset
+    pub bind_dn: Option<String>,
+    pub bind_password: Option<String>,
+    /// Base DN to search for users under
+    pub base_dn: String,
+    /// Filter which finds a user, `{username}` is replaced with the escaped username.
+    /// Defaults to `(uid={username})`
+    pub user_filter: Option<String>,
+    /// Attribute of the user's display name. Defaults to `cn`
+    pub name_attribute: Option<String>,
+    /// Attribute of the user's email. Defaults to `mail`
+    pub email_attribute: Option<String>,
+    /// Base DN to search for the groups of a user under. If unset, groups are read from the
+    /// `memberOf` attribute of the user.
+    pub group_base_dn: Option<String>,
+    /// Filter which finds the groups of a user, `{dn}` is replaced with the escaped DN of the user
+    /// and `{username}` with the escaped username. Defaults to `(member={dn})`
+    pub group_filter: Option<String>,
+    /// If set, users are admins exactly when they are a member of this group
+    pub admin_group: Option<String>,
+    /// Teams owned by members of a group are moved into its division. The first matching group wins.
+    pub division_groups: Option<Vec<LdapDivisionGroup>>,
+    /// Name shown on the sign in page. Defaults to `LDAP`
+    pub display_name: Option<String>,
+}
+
+/// Difficulty of the proof of work required on each route, as the number of leading zero bits of
+/// the hash. Each extra bit doubles the expected work, `16` takes around a second in a browser.
+/// Routes without a difficulty do not require a proof of work.
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+pub struct ProofOfWorkSettings {
+    /// Signing in with credentials or LDAP
+    pub credentials: Option<u32>,
+    /// Registering a credentials account
+    pub register: Option<u32>,
+    /// Requesting a sign in email
+    pub email: Option<u32>,
+    /// Creating a ticket
+    pub ticket: Option<u32>,
+    /// Submitting a flag
+    pub flag: Option<u32>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct OidcSettings {
+    /// Issuer url, the discovery document is fetched from `{issuer}/.well-known/openid-configuration`
+    pub issuer: String,
+    pub client_id: String,
+    pub client_secret: Option<String>,
+    /// Label of the sign in button, defaults to `Single Sign-On`
+    pub display_name: Option<String>,
+    /// Defaults to `openid profile email`
+    pub scopes: Option<Vec<String>>,
+    /// Claim to use as the user's name, defaults to `preferred_username` and then `name`
+    pub name_claim: Option<String>,
+    /// Defaults to `email`
+    pub email_claim: Option<String>,
+    /// Defaults to `picture`
+    pub avatar_claim: Option<String>,
+}
+
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct Settings {
     pub title: String,
@@ -162,6 +315,12 @@ pub struct Settings {
     pub ip_preset: Option<IpPreset>,
     pub live_reload: bool,
     pub default_ticket_template: String,
+    /// Whether players can open tickets from challenges. Defaults to whether a Discord support
+    /// channel is configured
+    pub tickets: Option<bool>,
+    /// Replies authors can send to any ticket by name, rendered with minijinja. Challenges can
+    /// add their own under `canned_responses`
+    pub canned_responses: Option<BTreeMap<String, String>>,
     pub immutable_config: bool,
     pub contact_email: Option<String>,
     pub divisions: Option<Vec<Division>>,
@@ -169,7 +328,15 @@ pub struct Settings {
     pub uploads: Option<UploadProviderSettings>,
     pub auth: Vec<AuthProvider>,
     pub ctftime: Option<CTFtimeSettings>,
+    pub oidc: Option<OidcSettings>,
+    pub github: Option<GitHubSettings>,
+    pub google: Option<GoogleSettings>,
+    pub ldap: Option<LdapSettings>,
+    pub credentials: Option<CredentialsSettings>,
+    pub two_factor: Option<TwoFactorSettings>,
+    pub proof_of_work: Option<ProofOfWorkSettings>,
     pub openai_api_key: Option<String>,
+    pub ai: Option<AiSettings>,
 
     /// A pre-shared key for rhombus-cli with admin access.
     pub root_api_key: Option<String>,
@@ -187,6 +354,17 @@ pub struct Settings {
     pub turso: Option<Turso>,
 }
 
+impl Settings {
+    pub fn tickets_enabled(&self) -> bool {
+        self.tickets.unwrap_or_else(|| {
+            self.discord
+                .as_ref()
+                .and_then(|d| d.support_channel_id)
+                .is_some()
+        })
+    }
+}
+
 pub enum DbConfig {
     #[cfg(feature = "postgres")]
     RawPostgres(sqlx::PgPool),
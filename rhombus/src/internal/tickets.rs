@@ -0,0 +1,559 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    num::NonZeroU64,
+    sync::{Arc, LazyLock},
+};
+
+use chrono::{DateTime, Utc};
+use minijinja::context;
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+
+use crate::{
+    internal::{
+        auth::User,
+        database::provider::{
+            Challenge, ChallengeData, Connection, Ticket, TicketMessage, TicketMessageAuthor,
+            TicketStatus,
+        },
+        discord::{Bot, DiscordAttachment},
+        email::outbound_mailer::OutboundMailer,
+        settings::Settings,
+    },
+    Result,
+};
+
+pub static DIGEST_DEBOUNCER: LazyLock<Mutex<BTreeMap<u64, i64>>> = LazyLock::new(Mutex::default);
+
+#[derive(Debug, Serialize, PartialEq, Eq, PartialOrd, Ord)]
+pub struct DigestAuthor {
+    pub name: String,
+    pub image_url: String,
+    pub rhombus_id: Option<i64>,
+}
+
+#[derive(Debug, Serialize, PartialEq, Eq, PartialOrd, Ord)]
+pub struct DigestMessage<'a> {
+    pub timestamp: DateTime<Utc>,
+    pub author: &'a DigestAuthor,
+    pub content: String,
+    pub edited_timestamp: Option<DateTime<Utc>>,
+}
+
+/// The people tickets can be assigned to: every challenge author, by Discord id
+pub fn ticket_assignees(challenge_data: &ChallengeData) -> BTreeMap<NonZeroU64, String> {
+    challenge_data
+        .authors
+        .values()
+        .map(|author| (author.discord_id, author.name.clone()))
+        .collect()
+}
+
+/// Whether someone answers tickets about the ticket's challenge: an admin, or the challenge's
+/// author by their linked Discord account
+pub fn is_ticket_staff(
+    challenge_data: &ChallengeData,
+    ticket: &Ticket,
+    is_admin: bool,
+    discord_id: Option<NonZeroU64>,
+) -> bool {
+    is_admin
+        || challenge_data
+            .challenges
+            .get(&ticket.challenge_id)
+            .and_then(|challenge| challenge_data.authors.get(&challenge.author_id))
+            .is_some_and(|author| Some(author.discord_id) == discord_id)
+}
+
+/// Formats a number of seconds like `2h 5m`
+pub fn format_duration(seconds: i64) -> String {
+    let minutes = seconds / 60;
+    match (minutes / 60, minutes % 60) {
+        (0, minutes) => format!("{}m", minutes),
+        (hours, minutes) => format!("{}h {}m", hours, minutes),
+    }
+}
+
+/// The canned responses that can be sent to a ticket about `challenge`, by name. Responses from
+/// the challenge's own `canned_responses` replace global ones with the same name.
+pub fn ticket_canned_responses(
+    settings: &Settings,
+    challenge: Option<&Challenge>,
+) -> BTreeMap<String, String> {
+    let mut responses = settings.canned_responses.clone().unwrap_or_default();
+    if let Some(challenge_responses) =
+        challenge.and_then(|challenge| challenge.metadata["canned_responses"].as_object())
+    {
+        for (name, content) in challenge_responses {
+            if let Some(content) = content.as_str() {
+                responses.insert(name.clone(), content.to_owned());
+            }
+        }
+    }
+    responses
+}
+
+/// Renders every canned response for a ticket. Templates only get the names of the participant,
+/// their team and the challenge, so a response can never leak something like the flag. Responses
+/// which fail to render are logged and left out.
+pub async fn render_canned_responses(
+    db: &Connection,
+    settings: &Settings,
+    challenge_data: &ChallengeData,
+    ticket: &Ticket,
+) -> Result<BTreeMap<String, String>> {
+    let challenge = challenge_data.challenges.get(&ticket.challenge_id);
+    let responses = ticket_canned_responses(settings, challenge);
+    if responses.is_empty() {
+        return Ok(responses);
+    }
+
+    let participant = db.get_user_from_id(ticket.user_id).await?;
+    let team = db.get_team_from_id(participant.team_id).await?;
+
+    let ctx = context! {
+        participant => context! {
+            id => participant.id,
+            name => &participant.name,
+        },
+        team => context! {
+            id => team.id,
+            name => &team.name,
+        },
+        challenge => context! {
+            id => &ticket.challenge_id,
+            name => challenge.map(|challenge| challenge.name.as_str()),
+        },
+        ticket_number => ticket.ticket_number,
+        location_url => &settings.location_url,
+    };
+
+    let env = minijinja::Environment::new();
+    let mut rendered = BTreeMap::new();
+    for (name, template) in responses {
+        match env.render_str(&template, &ctx) {
+            Ok(content) => {
+                rendered.insert(name, content);
+            }
+            Err(error) => {
+                tracing::error!(?error, name, "Failed to render canned response");
+            }
+        }
+    }
+
+    Ok(rendered)
+}
+
+/// Adds a message from `user` to a ticket, mirroring it to the ticket's Discord thread if it has
+/// one. The database is the source of truth, so failing to mirror the message is only logged.
+pub async fn add_ticket_message(
+    db: &Connection,
+    bot: Option<&Bot>,
+    ticket: &Ticket,
+    user: &User,
+    from: Option<&str>,
+    content: &str,
+    attachments: &[DiscordAttachment<'_>],
+) -> Result<()> {
+    // attachments only live on Discord, so keep a note of them in the transcript
+    let mut stored_content = content.to_owned();
+    for attachment in attachments {
+        _ = write!(stored_content, "\n\nAttachment: {}", attachment.filename);
+    }
+
+    let mut discord_message_id = None;
+    if let (Some(bot), Some(discord_channel_id)) = (bot, ticket.discord_channel_id) {
+        match bot
+            .send_external_ticket_message(discord_channel_id, user, from, content, attachments)
+            .await
+        {
+            Ok(message_id) => discord_message_id = Some(message_id),
+            Err(error) => {
+                tracing::error!(
+                    ?error,
+                    ticket_number = ticket.ticket_number,
+                    "Failed to mirror ticket message to Discord"
+                );
+            }
+        }
+    }
+
+    let challenge_data = db.get_challenges().await?;
+    db.add_ticket_message(
+        ticket.ticket_number,
+        &TicketMessageAuthor {
+            user_id: Some(user.id),
+            name: &user.name,
+            avatar: &user.avatar,
+        },
+        &stored_content,
+        discord_message_id,
+        is_ticket_staff(&challenge_data, ticket, user.is_admin, user.discord_id),
+    )
+    .await?;
+
+    Ok(())
+}
+
+/// Closes or reopens a ticket, and its Discord thread if it has one
+pub async fn set_ticket_closed(
+    db: &Connection,
+    bot: Option<&Bot>,
+    ticket: &Ticket,
+    closed: bool,
+) -> Result<()> {
+    if closed {
+        db.close_ticket(ticket.ticket_number, chrono::Utc::now())
+            .await?;
+    } else {
+        db.reopen_ticket(ticket.ticket_number).await?;
+    }
+
+    if let (Some(bot), Some(discord_channel_id), Some(discord_panel_message_id)) = (
+        bot,
+        ticket.discord_channel_id,
+        ticket.discord_panel_message_id,
+    ) {
+        if let Err(error) = bot
+            .set_ticket_thread_closed(
+                ticket.ticket_number,
+                discord_channel_id,
+                discord_panel_message_id,
+                closed,
+            )
+            .await
+        {
+            tracing::error!(
+                ?error,
+                ticket_number = ticket.ticket_number,
+                closed,
+                "Failed to update ticket thread on Discord"
+            );
+        }
+    }
+
+    Ok(())
+}
+
+/// Emails the creator of a ticket every reply to it, once nobody has written anything for a
+/// minute. Creators who linked their Discord account already follow the ticket's thread there.
+pub async fn digest_ticket(
+    db: &Connection,
+    outbound_mailer: &OutboundMailer,
+    ticket_number: u64,
+) -> Result<()> {
+    let now = chrono::Utc::now().timestamp();
+    {
+        _ = DIGEST_DEBOUNCER.lock().await.insert(ticket_number, now);
+    }
+    tokio::time::sleep(std::time::Duration::from_secs(60)).await; // debouncing time
+    if let Some(timestamp) = DIGEST_DEBOUNCER.lock().await.remove(&ticket_number) {
+        if timestamp != now {
+            return Ok(());
+        }
+    }
+
+    let ticket = db.get_ticket_by_ticket_number(ticket_number).await?;
+    let ticket_creator_user = db.get_user_from_id(ticket.user_id).await?;
+    if ticket_creator_user.discord_id.is_some() && ticket.discord_channel_id.is_some() {
+        return Ok(());
+    }
+
+    let messages = db.get_ticket_messages(ticket_number).await?;
+
+    let authors = messages
+        .iter()
+        .map(|message| DigestAuthor {
+            name: message.author_name.clone(),
+            image_url: message.author_avatar.clone(),
+            rhombus_id: message.user_id,
+        })
+        .collect::<Vec<_>>();
+
+    // the first message is the one the creator opened the ticket with
+    let messages = messages
+        .iter()
+        .zip(authors.iter())
+        .skip(1)
+        .map(|(message, author)| DigestMessage {
+            author,
+            content: message.content.clone(),
+            edited_timestamp: message.edited_at,
+            timestamp: message.sent_at,
+        })
+        .collect::<Vec<_>>();
+
+    if messages.is_empty() {
+        return Ok(());
+    }
+
+    outbound_mailer.send_digest(&ticket, &messages).await?;
+
+    Ok(())
+}
+
+/// Runs [`digest_ticket`] in the background, if email is configured
+pub fn spawn_ticket_digest(
+    db: Connection,
+    outbound_mailer: Option<Arc<OutboundMailer>>,
+    ticket_number: u64,
+) {
+    let Some(outbound_mailer) = outbound_mailer else {
+        return;
+    };
+
+    tokio::task::spawn(async move {
+        if let Err(error) = digest_ticket(&db, &outbound_mailer, ticket_number).await {
+            tracing::error!(?error, ticket_number, "Failed to send ticket digest");
+        }
+    });
+}
+
+/// A ticket and everything said in it, as exported for keeping after the event
+#[derive(Debug, Serialize)]
+pub struct TicketTranscript {
+    pub ticket_number: u64,
+    pub challenge_id: String,
+    pub challenge_name: Option<String>,
+    pub creator_id: i64,
+    pub creator_name: String,
+    pub status: TicketStatus,
+    pub opened_at: DateTime<Utc>,
+    pub closed_at: Option<DateTime<Utc>>,
+    pub tags: Vec<String>,
+    pub messages: Vec<TicketMessage>,
+}
+
+impl TicketTranscript {
+    pub fn to_markdown(&self) -> String {
+        let mut markdown = String::new();
+        _ = writeln!(
+            markdown,
+            "# Ticket #{}: {}\n",
+            self.ticket_number,
+            self.challenge_name.as_deref().unwrap_or(&self.challenge_id)
+        );
+        _ = writeln!(markdown, "- Opened by: {}", self.creator_name);
+        _ = writeln!(markdown, "- Opened at: {}", self.opened_at.to_rfc3339());
+        if let Some(closed_at) = self.closed_at {
+            _ = writeln!(markdown, "- Closed at: {}", closed_at.to_rfc3339());
+        }
+        _ = writeln!(markdown, "- Status: {}", self.status.as_str());
+        if !self.tags.is_empty() {
+            _ = writeln!(markdown, "- Tags: {}", self.tags.join(", "));
+        }
+
+        for message in &self.messages {
+            _ = write!(
+                markdown,
+                "\n## {} ({})",
+                message.author_name,
+                message.sent_at.to_rfc3339()
+            );
+            if let Some(edited_at) = message.edited_at {
+                _ = write!(markdown, ", edited {}", edited_at.to_rfc3339());
+            }
+            _ = writeln!(markdown, "\n\n{}", message.content.trim_end());
+        }
+
+        markdown
+    }
+}
+
+/// Loads the full transcript of a ticket
+pub async fn get_ticket_transcript(
+    db: &Connection,
+    challenge_data: &ChallengeData,
+    ticket: Ticket,
+) -> Result<TicketTranscript> {
+    let messages = db.get_ticket_messages(ticket.ticket_number);
+    let creator = db.get_user_from_id(ticket.user_id);
+    let (messages, creator) = tokio::try_join!(messages, creator)?;
+
+    Ok(TicketTranscript {
+        ticket_number: ticket.ticket_number,
+        challenge_name: challenge_data
+            .challenges
+            .get(&ticket.challenge_id)
+            .map(|challenge| challenge.name.clone()),
+        challenge_id: ticket.challenge_id,
+        creator_id: creator.id,
+        creator_name: creator.name.clone(),
+        status: ticket.status,
+        opened_at: ticket.opened_at,
+        closed_at: ticket.closed_at,
+        tags: ticket.tags,
+        messages,
+    })
+}
+
+#[derive(Debug, Deserialize, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum TranscriptFormat {
+    #[default]
+    Markdown,
+    Json,
+}
+
+impl TranscriptFormat {
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            TranscriptFormat::Markdown => "text/markdown; charset=utf-8",
+            TranscriptFormat::Json => "application/json",
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            TranscriptFormat::Markdown => "md",
+            TranscriptFormat::Json => "json",
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::TimeZone;
+    use serde_json::json;
+
+    use super::*;
+
+    fn test_settings(canned_responses: serde_json::Value) -> Settings {
+        serde_json::from_value(json!({
+            "title": "CTF",
+            "location_url": "http://localhost:3000",
+            "jwt_secret": "secret",
+            "live_reload": false,
+            "default_ticket_template": "",
+            "immutable_config": false,
+            "auth": [],
+            "in_memory_cache": "false",
+            "canned_responses": canned_responses,
+        }))
+        .unwrap()
+    }
+
+    fn test_challenge(metadata: serde_json::Value) -> Challenge {
+        Challenge {
+            id: "web-1".to_owned(),
+            name: "Web 1".to_owned(),
+            description: String::new(),
+            flag: "flag{test}".to_owned(),
+            category_id: "web".to_owned(),
+            author_id: "alice".to_owned(),
+            ticket_template: None,
+            healthscript: None,
+            healthy: None,
+            last_healthcheck: None,
+            score_type: "dynamic".to_owned(),
+            metadata,
+            points: 100,
+            attachments: vec![],
+            division_solves: BTreeMap::new(),
+        }
+    }
+
+    #[test]
+    fn duration() {
+        assert_eq!(format_duration(0), "0m");
+        assert_eq!(format_duration(59), "0m");
+        assert_eq!(format_duration(45 * 60), "45m");
+        assert_eq!(format_duration(60 * 60), "1h 0m");
+        assert_eq!(format_duration(26 * 60 * 60 + 5 * 60 + 30), "26h 5m");
+    }
+
+    #[test]
+    fn canned_responses_override_global() {
+        let settings = test_settings(json!({
+            "remote": "The remote is up",
+            "format": "Flags look like flag{...}",
+        }));
+
+        let global = ticket_canned_responses(&settings, None);
+        assert_eq!(global.len(), 2);
+        assert_eq!(global["remote"], "The remote is up");
+
+        let challenge = test_challenge(json!({
+            "canned_responses": {
+                "remote": "Web 1 has no remote",
+                "hint": "Look at the cookies",
+                "ignored": 5,
+            },
+        }));
+        let responses = ticket_canned_responses(&settings, Some(&challenge));
+        assert_eq!(
+            responses.into_iter().collect::<Vec<_>>(),
+            vec![
+                ("format".to_owned(), "Flags look like flag{...}".to_owned()),
+                ("hint".to_owned(), "Look at the cookies".to_owned()),
+                ("remote".to_owned(), "Web 1 has no remote".to_owned()),
+            ]
+        );
+
+        let settings = test_settings(serde_json::Value::Null);
+        assert!(ticket_canned_responses(&settings, None).is_empty());
+        assert_eq!(
+            ticket_canned_responses(&settings, Some(&challenge)).len(),
+            2
+        );
+    }
+
+    #[test]
+    fn transcript_markdown() {
+        let at = |seconds: i64| Utc.timestamp_opt(1_700_000_000 + seconds, 0).unwrap();
+        let transcript = TicketTranscript {
+            ticket_number: 7,
+            challenge_id: "web-1".to_owned(),
+            challenge_name: None,
+            creator_id: 1,
+            creator_name: "alice".to_owned(),
+            status: TicketStatus::Closed,
+            opened_at: at(0),
+            closed_at: Some(at(600)),
+            tags: vec!["bug".to_owned(), "infra".to_owned()],
+            messages: vec![
+                TicketMessage {
+                    id: 1,
+                    user_id: Some(1),
+                    author_name: "alice".to_owned(),
+                    author_avatar: String::new(),
+                    content: "The remote is down\n\n".to_owned(),
+                    discord_message_id: None,
+                    sent_at: at(0),
+                    edited_at: None,
+                },
+                TicketMessage {
+                    id: 2,
+                    user_id: None,
+                    author_name: "bob".to_owned(),
+                    author_avatar: String::new(),
+                    content: "Fixed".to_owned(),
+                    discord_message_id: NonZeroU64::new(42),
+                    sent_at: at(300),
+                    edited_at: Some(at(360)),
+                },
+            ],
+        };
+
+        assert_eq!(
+            transcript.to_markdown(),
+            "# Ticket #7: web-1
+
+- Opened by: alice
+- Opened at: 2023-11-14T22:13:20+00:00
+- Closed at: 2023-11-14T22:23:20+00:00
+- Status: closed
+- Tags: bug, infra
+
+## alice (2023-11-14T22:13:20+00:00)
+
+The remote is down
+
+## bob (2023-11-14T22:18:20+00:00), edited 2023-11-14T22:19:20+00:00
+
+Fixed
+"
+        );
+    }
+}
@@ -0,0 +1,149 @@
+//! Time-based one-time passwords ([RFC 6238](https://datatracker.ietf.org/doc/html/rfc6238))
+//! for two-factor authentication
+
+use base64::{
+    engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD},
+    Engine,
+};
+use qrcode::{render::svg, QrCode};
+use rand::{
+    distributions::{Alphanumeric, DistString},
+    thread_rng, RngCore,
+};
+use ring::{digest, hmac};
+
+use crate::{
+    internal::database::provider::{Connection, UserTotp},
+    Result,
+};
+
+const STEP_SECONDS: i64 = 30;
+const DIGITS: u32 = 6;
+const RECOVERY_CODE_COUNT: usize = 10;
+const SECRET_ALPHABET: base32::Alphabet = base32::Alphabet::Rfc4648 { padding: false };
+
+/// Generates a new base32 encoded secret for an authenticator app
+pub fn generate_secret() -> String {
+    let mut secret = [0u8; 20];
+    thread_rng().fill_bytes(&mut secret);
+    base32::encode(SECRET_ALPHABET, &secret)
+}
+
+fn hotp(secret: &[u8], counter: u64) -> u32 {
+    let key = hmac::Key::new(hmac::HMAC_SHA1_FOR_LEGACY_USE_ONLY, secret);
+    let tag = hmac::sign(&key, &counter.to_be_bytes());
+    let hash = tag.as_ref();
+    let offset = (hash[hash.len() - 1] & 0x0f) as usize;
+    let binary = u32::from_be_bytes([
+        hash[offset] & 0x7f,
+        hash[offset + 1],
+        hash[offset + 2],
+        hash[offset + 3],
+    ]);
+    binary % 10u32.pow(DIGITS)
+}
+
+/// Returns the time step a code is valid for, allowing one step of clock drift either way
+pub fn verify_code(secret: &str, code: &str, now: i64) -> Option<i64> {
+    let code = code.trim();
+    if code.len() != DIGITS as usize || !code.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let code = code.parse::<u32>().ok()?;
+    let secret = base32::decode(SECRET_ALPHABET, secret)?;
+
+    let step = now / STEP_SECONDS;
+    (step - 1..=step + 1).find(|&step| step >= 0 && hotp(&secret, step as u64) == code)
+}
+
+pub fn otpauth_url(issuer: &str, account: &str, secret: &str) -> String {
+    format!(
+        "otpauth://totp/{}:{}?secret={}&issuer={}&digits={}&period={}",
+        urlencoding::encode(issuer),
+        urlencoding::encode(account),
+        secret,
+        urlencoding::encode(issuer),
+        DIGITS,
+        STEP_SECONDS,
+    )
+}
+
+/// Renders `data` as a QR code in an svg data url, to be used as an image source
+pub fn qr_code_data_url(data: &str) -> Option<String> {
+    let svg = QrCode::new(data.as_bytes())
+        .ok()?
+        .render::<svg::Color>()
+        .min_dimensions(200, 200)
+        .dark_color(svg::Color("#000000"))
+        .light_color(svg::Color("#ffffff"))
+        .build();
+    Some(format!(
+        "data:image/svg+xml;base64,{}",
+        STANDARD.encode(svg)
+    ))
+}
+
+pub fn generate_recovery_codes() -> Vec<String> {
+    (0..RECOVERY_CODE_COUNT)
+        .map(|_| {
+            let code = Alphanumeric
+                .sample_string(&mut thread_rng(), 10)
+                .to_lowercase();
+            format!("{}-{}", &code[..5], &code[5..])
+        })
+        .collect()
+}
+
+/// Recovery codes are random, so a plain hash is enough to store them
+pub fn hash_recovery_code(code: &str) -> String {
+    let normalized = code.trim().to_lowercase().replace('-', "");
+    URL_SAFE_NO_PAD.encode(digest::digest(&digest::SHA256, normalized.as_bytes()))
+}
+
+/// Checks a code from the authenticator app, or a recovery code if `allow_recovery` is set.
+/// Each code can only be used once.
+pub async fn verify_user_code(
+    db: &Connection,
+    user_id: i64,
+    totp: &UserTotp,
+    code: &str,
+    allow_recovery: bool,
+) -> Result<bool> {
+    if let Some(step) = verify_code(&totp.secret, code, chrono::Utc::now().timestamp()) {
+        return db.use_user_totp_step(user_id, step).await;
+    }
+
+    if allow_recovery && totp.enabled {
+        return db
+            .use_user_recovery_code(user_id, &hash_recovery_code(code))
+            .await;
+    }
+
+    Ok(false)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn rfc_6238_vectors() {
+        // the RFC 6238 SHA1 secret is the ascii string "12345678901234567890"
+        let secret = base32::encode(SECRET_ALPHABET, b"12345678901234567890");
+        assert_eq!(verify_code(&secret, "287082", 59), Some(1));
+        assert_eq!(verify_code(&secret, "081804", 1111111109), Some(37037036));
+        assert_eq!(verify_code(&secret, "050471", 1111111111), Some(37037037));
+        assert_eq!(verify_code(&secret, "287083", 59), None);
+        assert_eq!(verify_code(&secret, "28708", 59), None);
+    }
+
+    #[test]
+    fn recovery_codes_normalize() {
+        let codes = generate_recovery_codes();
+        assert_eq!(codes.len(), RECOVERY_CODE_COUNT);
+        assert_eq!(
+            hash_recovery_code(&codes[0]),
+            hash_recovery_code(&format!(" {} ", codes[0].to_uppercase().replace('-', "")))
+        );
+    }
+}
@@ -1,13 +1,27 @@
-use std::sync::Arc;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    sync::{Arc, LazyLock},
+};
 
 use axum::{
     extract::{Path, Request, State},
-    response::IntoResponse,
+    http::{header, HeaderMap},
+    middleware::Next,
+    response::{IntoResponse, Response},
     Extension,
 };
 use reqwest::StatusCode;
+use tokio::sync::RwLock;
 
-use crate::{internal::auth::MaybeUser, UploadProvider};
+use crate::{
+    internal::{
+        auth::MaybeUser,
+        database::provider::{ChallengeAttachment, Connection},
+        router::RouterState,
+    },
+    upload_provider::{ErasedUploadProvider, UrlSigner},
+    Result, UploadProvider,
+};
 
 pub async fn route_upload_file<U: UploadProvider>(
     State(upload_provider): State<Arc<U>>,
@@ -47,3 +61,262 @@ pub fn validate_simple_filename(path: &str) -> bool {
 
     components.count() == 1
 }
+
+/// Extract the content hash and filename from a url returned by one of the builtin upload providers.
+///
+/// Local and database uploads are served from `/uploads/{hash}-{filename}`, while
+/// S3 objects are stored at `{prefix}{hash}/{filename}`.
+pub fn parse_upload_url(url: &str) -> Option<(String, String)> {
+    let path = url.split(['?', '#']).next()?;
+    let mut segments = path.rsplit('/');
+    let last = segments.next()?;
+
+    let is_hash = |s: &str| s.len() == 64 && s.bytes().all(|b| b.is_ascii_hexdigit());
+
+    if let Some(previous) = segments.next() {
+        if is_hash(previous) && validate_simple_filename(last) {
+            return Some((previous.to_owned(), last.to_owned()));
+        }
+    }
+
+    let (hash, filename) = last.split_once('-')?;
+    if is_hash(hash) && validate_simple_filename(filename) {
+        Some((hash.to_owned(), filename.to_owned()))
+    } else {
+        None
+    }
+}
+
+/// Copy every uploaded attachment from one upload provider to another, then point all
+/// attachments at their new urls in a single transaction. If any file fails to copy,
+/// no urls are rewritten and the old provider remains the source of truth.
+pub async fn migrate_uploads(
+    db: &Connection,
+    from: &Arc<dyn ErasedUploadProvider>,
+    to: &Arc<dyn ErasedUploadProvider>,
+) -> Result<usize> {
+    let mut new_urls = BTreeMap::new();
+    for url in db.get_uploaded_attachment_urls().await? {
+        let Some((_, filename)) = parse_upload_url(&url) else {
+            tracing::warn!(url, "Skipping attachment with unrecognized url");
+            continue;
+        };
+
+        let stream = from.download(&url).await?;
+        let new_url = to.upload(&filename, stream).await?;
+
+        tracing::info!(url, new_url, "Migrated upload");
+        new_urls.insert(url, new_url);
+    }
+
+    db.rewrite_attachment_urls(&new_urls).await?;
+
+    Ok(new_urls.len())
+}
+
+/// Held shared by the challenge loader from uploading attachments until they are
+/// committed, and exclusively by [`collect_garbage`], so a freshly uploaded file is
+/// never mistaken for an orphan.
+pub static ATTACHMENT_LOCK: LazyLock<RwLock<()>> = LazyLock::new(RwLock::default);
+
+/// Delete every file stored by the upload provider which is no longer referenced by
+/// a challenge attachment, returning the urls of the orphaned files. With `dry_run`
+/// nothing is deleted.
+pub async fn collect_garbage(
+    db: &Connection,
+    upload_provider: &Arc<dyn ErasedUploadProvider>,
+    dry_run: bool,
+) -> Result<Vec<String>> {
+    let _guard = ATTACHMENT_LOCK.write().await;
+
+    let referenced = db
+        .get_uploaded_attachment_urls()
+        .await?
+        .iter()
+        .filter_map(|url| parse_upload_url(url))
+        .collect::<BTreeSet<_>>();
+
+    let mut orphaned = vec![];
+    for url in upload_provider.list().await? {
+        let Some(key) = parse_upload_url(&url) else {
+            continue;
+        };
+
+        if referenced.contains(&key) {
+            continue;
+        }
+
+        if !dry_run {
+            upload_provider.delete(&url).await?;
+            tracing::info!(url, "Deleted orphaned upload");
+        }
+        orphaned.push(url);
+    }
+
+    Ok(orphaned)
+}
+
+/// Uploads are content addressed, so the hash in the filename is a strong validator
+pub fn upload_etag(hash: &str) -> String {
+    format!("\"{}\"", hash)
+}
+
+/// Whether the `If-None-Match` header of a request matches the etag of the file
+pub fn if_none_match(headers: &HeaderMap, etag: &str) -> bool {
+    headers
+        .get_all(header::IF_NONE_MATCH)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|tag| tag.trim())
+        .any(|tag| tag == "*" || tag.trim_start_matches("W/") == etag)
+}
+
+/// Parse a single `Range` header into an inclusive byte range of a file with `size` bytes.
+///
+/// Returns `None` if the whole file should be served, and `Some(Err(()))` if the
+/// range can not be satisfied.
+pub fn parse_range(headers: &HeaderMap, size: u64) -> Option<std::result::Result<(u64, u64), ()>> {
+    let range = headers.get(header::RANGE)?.to_str().ok()?;
+    let range = range.trim().strip_prefix("bytes=")?;
+
+    // multiple ranges are allowed to be ignored, so just serve the whole file
+    if range.contains(',') {
+        return None;
+    }
+
+    let (start, end) = range.split_once('-')?;
+    let (start, end) = match (start.trim(), end.trim()) {
+        ("", suffix) => {
+            let suffix = suffix.parse::<u64>().ok()?;
+            if suffix == 0 {
+                return Some(Err(()));
+            }
+            (size.saturating_sub(suffix), size.saturating_sub(1))
+        }
+        (start, "") => (start.parse::<u64>().ok()?, size.saturating_sub(1)),
+        (start, end) => {
+            let start = start.parse::<u64>().ok()?;
+            let end = end.parse::<u64>().ok()?;
+            if end < start {
+                return None;
+            }
+            (start, end.min(size.saturating_sub(1)))
+        }
+    };
+
+    if start >= size {
+        return Some(Err(()));
+    }
+
+    Some(Ok((start, end)))
+}
+
+/// Reject downloads from `/uploads/` without a valid signature when signed urls are
+/// enabled. Admins can always download files.
+pub async fn enforce_signed_upload_middleware(
+    State(state): State<RouterState>,
+    Extension(maybe_user): Extension<MaybeUser>,
+    req: Request,
+    next: Next,
+) -> Response {
+    if !req.uri().path().starts_with("/uploads/") {
+        return next.run(req).await;
+    }
+
+    let Some(signer) = UrlSigner::from_settings(&*state.settings.read().await) else {
+        return next.run(req).await;
+    };
+
+    if maybe_user.as_ref().is_some_and(|user| user.is_admin)
+        || signer.verify(
+            req.uri().path(),
+            req.uri().query(),
+            maybe_user.map(|user| user.team_id),
+            chrono::Utc::now().timestamp(),
+        )
+    {
+        return next.run(req).await;
+    }
+
+    (
+        StatusCode::FORBIDDEN,
+        "Download link is invalid or has expired",
+    )
+        .into_response()
+}
+
+/// Sign the urls of `attachments` for `team_id` if signed urls are enabled. Attachments
+/// which fail to sign keep their original url.
+pub async fn sign_attachments(
+    state: &RouterState,
+    attachments: &[ChallengeAttachment],
+    team_id: i64,
+) -> Vec<ChallengeAttachment> {
+    let Some(signer) = UrlSigner::from_settings(&*state.settings.read().await) else {
+        return attachments.to_vec();
+    };
+
+    let mut signed = Vec::with_capacity(attachments.len());
+    for attachment in attachments {
+        let url = match state
+            .upload_provider
+            .sign(&attachment.url, &signer, team_id)
+            .await
+        {
+            Ok(url) => url,
+            Err(error) => {
+                tracing::error!(
+                    url = attachment.url,
+                    ?error,
+                    "Failed to sign attachment url"
+                );
+                attachment.url.clone()
+            }
+        };
+
+        signed.push(ChallengeAttachment {
+            name: attachment.name.clone(),
+            url,
+        });
+    }
+
+    signed
+}
+
+#[cfg(test)]
+mod test {
+    use axum::http::{header, HeaderMap, HeaderValue};
+
+    use super::{if_none_match, parse_range};
+
+    fn range(value: &'static str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::RANGE, HeaderValue::from_static(value));
+        headers
+    }
+
+    #[test]
+    fn ranges() {
+        assert_eq!(parse_range(&HeaderMap::new(), 100), None);
+        assert_eq!(parse_range(&range("bytes=0-9"), 100), Some(Ok((0, 9))));
+        assert_eq!(parse_range(&range("bytes=90-"), 100), Some(Ok((90, 99))));
+        assert_eq!(parse_range(&range("bytes=-10"), 100), Some(Ok((90, 99))));
+        assert_eq!(parse_range(&range("bytes=50-500"), 100), Some(Ok((50, 99))));
+        assert_eq!(parse_range(&range("bytes=100-"), 100), Some(Err(())));
+        assert_eq!(parse_range(&range("bytes=0-1,5-6"), 100), None);
+        assert_eq!(parse_range(&range("items=0-1"), 100), None);
+    }
+
+    #[test]
+    fn etags() {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            header::IF_NONE_MATCH,
+            HeaderValue::from_static("\"abc\", W/\"def\""),
+        );
+        assert!(if_none_match(&headers, "\"abc\""));
+        assert!(if_none_match(&headers, "\"def\""));
+        assert!(!if_none_match(&headers, "\"ghi\""));
+    }
+}
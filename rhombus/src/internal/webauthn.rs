@@ -0,0 +1,333 @@
+//! A minimal [WebAuthn](https://www.w3.org/TR/webauthn-2/) relying party for passkey sign in.
+//!
+//! Registration requests `none` attestation, so the public key reported by the browser is trusted
+//! as is. Sign in verifies the assertion signature against the stored key.
+
+use std::sync::LazyLock;
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use dashmap::DashMap;
+use jsonwebtoken::{decode, encode, DecodingKey, EncodingKey, Header, Validation};
+use rand::{thread_rng, RngCore};
+use ring::{digest, signature};
+use serde::{Deserialize, Serialize};
+
+/// COSE algorithm identifiers of the supported credential types
+pub const ES256: i64 = -7;
+pub const EDDSA: i64 = -8;
+pub const RS256: i64 = -257;
+pub const SUPPORTED_ALGORITHMS: [i64; 3] = [ES256, EDDSA, RS256];
+
+const FLAG_USER_PRESENT: u8 = 0x01;
+
+/// The relying party id (the host) and origin passkeys are scoped to
+pub struct RelyingParty {
+    pub id: String,
+    pub origin: String,
+}
+
+impl RelyingParty {
+    pub fn from_location_url(location_url: &str) -> Option<Self> {
+        let url = reqwest::Url::parse(location_url).ok()?;
+        Some(Self {
+            id: url.host_str()?.to_owned(),
+            origin: url.origin().ascii_serialization(),
+        })
+    }
+}
+
+/// Claims of the token handed to the browser with the challenge of a ceremony
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ChallengeClaims {
+    pub challenge: String,
+    /// The user registering a passkey, or none when signing in
+    pub sub: Option<i64>,
+    pub exp: i64,
+}
+
+/// Challenge tokens are signed with a derived key, so they are never accepted as a `rhombus-token`
+fn challenge_key(jwt_secret: &str) -> Vec<u8> {
+    format!("{}:webauthn", jwt_secret).into_bytes()
+}
+
+/// Creates a new random challenge, returning it along with a token to verify it later
+pub fn create_challenge(jwt_secret: &str, user_id: Option<i64>) -> (String, String) {
+    let mut challenge = [0u8; 32];
+    thread_rng().fill_bytes(&mut challenge);
+    let challenge = URL_SAFE_NO_PAD.encode(challenge);
+
+    let claims = ChallengeClaims {
+        challenge: challenge.clone(),
+        sub: user_id,
+        exp: (chrono::Utc::now() + chrono::Duration::minutes(5)).timestamp(),
+    };
+    let token = encode(
+        &Header::default(),
+        &claims,
+        &EncodingKey::from_secret(&challenge_key(jwt_secret)),
+    )
+    .unwrap();
+
+    (challenge, token)
+}
+
+pub fn decode_challenge(jwt_secret: &str, token: &str) -> Option<ChallengeClaims> {
+    decode::<ChallengeClaims>(
+        token,
+        &DecodingKey::from_secret(&challenge_key(jwt_secret)),
+        &Validation::default(),
+    )
+    .ok()
+    .map(|data| data.claims)
+}
+
+/// Challenges which have already been redeemed, mapped to when their token expires
+static REDEEMED_CHALLENGES: LazyLock<DashMap<String, i64>> = LazyLock::new(DashMap::new);
+
+/// Marks the challenge of a verified ceremony as used, returning false if it already was, so a
+/// response can never be replayed
+pub fn redeem_challenge(claims: &ChallengeClaims) -> bool {
+    let now = chrono::Utc::now().timestamp();
+    REDEEMED_CHALLENGES.retain(|_, exp| *exp > now);
+    REDEEMED_CHALLENGES
+        .insert(claims.challenge.clone(), claims.exp)
+        .is_none()
+}
+
+#[derive(Deserialize)]
+struct ClientData {
+    #[serde(rename = "type")]
+    kind: String,
+    challenge: String,
+    origin: String,
+}
+
+/// Checks the client data collected by the browser belongs to this ceremony
+pub fn verify_client_data(
+    client_data_json: &[u8],
+    kind: &str,
+    challenge: &str,
+    relying_party: &RelyingParty,
+) -> bool {
+    serde_json::from_slice::<ClientData>(client_data_json).is_ok_and(|client_data| {
+        client_data.kind == kind
+            && client_data.challenge == challenge
+            && client_data.origin == relying_party.origin
+    })
+}
+
+/// Checks the authenticator data is scoped to this relying party and the user was present,
+/// returning the signature counter
+pub fn verify_authenticator_data(
+    authenticator_data: &[u8],
+    relying_party: &RelyingParty,
+) -> Option<i64> {
+    if authenticator_data.len() < 37 {
+        return None;
+    }
+
+    let rp_id_hash = digest::digest(&digest::SHA256, relying_party.id.as_bytes());
+    if authenticator_data[..32] != *rp_id_hash.as_ref()
+        || authenticator_data[32] & FLAG_USER_PRESENT == 0
+    {
+        return None;
+    }
+
+    Some(u32::from_be_bytes(authenticator_data[33..37].try_into().unwrap()) as i64)
+}
+
+/// Splits a DER element into its tag, contents and the remaining input
+fn der_element(input: &[u8]) -> Option<(u8, &[u8], &[u8])> {
+    let (&tag, input) = input.split_first()?;
+    let (&length, mut input) = input.split_first()?;
+
+    let length = if length & 0x80 == 0 {
+        length as usize
+    } else {
+        let octets = (length & 0x7f) as usize;
+        if octets == 0 || octets > 4 || input.len() < octets {
+            return None;
+        }
+        let length = input[..octets]
+            .iter()
+            .fold(0usize, |length, &b| length << 8 | b as usize);
+        input = &input[octets..];
+        length
+    };
+
+    if input.len() < length {
+        return None;
+    }
+    Some((tag, &input[..length], &input[length..]))
+}
+
+/// Extracts the public key from a DER encoded SubjectPublicKeyInfo, as returned by
+/// `AuthenticatorAttestationResponse.getPublicKey()` in the browser
+pub fn public_key_from_spki(spki: &[u8]) -> Option<Vec<u8>> {
+    let (0x30, spki, _) = der_element(spki)? else {
+        return None;
+    };
+    let (0x30, _algorithm, spki) = der_element(spki)? else {
+        return None;
+    };
+    let (0x03, bits, _) = der_element(spki)? else {
+        return None;
+    };
+    let (0, key) = bits.split_first()? else {
+        return None;
+    };
+    Some(key.to_vec())
+}
+
+/// Verifies an assertion signature over the authenticator data and the hash of the client data
+pub fn verify_signature(
+    algorithm: i64,
+    public_key: &[u8],
+    authenticator_data: &[u8],
+    client_data_json: &[u8],
+    signature: &[u8],
+) -> bool {
+    let algorithm: &dyn signature::VerificationAlgorithm = match algorithm {
+        ES256 => &signature::ECDSA_P256_SHA256_ASN1,
+        EDDSA => &signature::ED25519,
+        RS256 => &signature::RSA_PKCS1_2048_8192_SHA256,
+        _ => return false,
+    };
+
+    let mut message = authenticator_data.to_vec();
+    message.extend_from_slice(digest::digest(&digest::SHA256, client_data_json).as_ref());
+
+    signature::UnparsedPublicKey::new(algorithm, public_key)
+        .verify(&message, signature)
+        .is_ok()
+}
+
+#[cfg(test)]
+mod test {
+    use ring::{
+        rand::SystemRandom,
+        signature::{EcdsaKeyPair, KeyPair, ECDSA_P256_SHA256_ASN1_SIGNING},
+    };
+
+    use super::*;
+
+    fn relying_party() -> RelyingParty {
+        RelyingParty::from_location_url("https://ctf.example.com").unwrap()
+    }
+
+    fn authenticator_data(rp_id: &str, flags: u8, sign_count: u32) -> Vec<u8> {
+        let mut data = digest::digest(&digest::SHA256, rp_id.as_bytes())
+            .as_ref()
+            .to_vec();
+        data.push(flags);
+        data.extend_from_slice(&sign_count.to_be_bytes());
+        data
+    }
+
+    #[test]
+    fn es256_assertion() {
+        let rng = SystemRandom::new();
+        let pkcs8 = EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_ASN1_SIGNING, &rng).unwrap();
+        let key_pair =
+            EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_ASN1_SIGNING, pkcs8.as_ref(), &rng)
+                .unwrap();
+
+        // SubjectPublicKeyInfo header for an uncompressed P-256 point
+        let mut spki = vec![
+            0x30, 0x59, 0x30, 0x13, 0x06, 0x07, 0x2a, 0x86, 0x48, 0xce, 0x3d, 0x02, 0x01, 0x06,
+            0x08, 0x2a, 0x86, 0x48, 0xce, 0x3d, 0x03, 0x01, 0x07, 0x03, 0x42, 0x00,
+        ];
+        spki.extend_from_slice(key_pair.public_key().as_ref());
+        let public_key = public_key_from_spki(&spki).unwrap();
+        assert_eq!(public_key, key_pair.public_key().as_ref());
+
+        let relying_party = relying_party();
+        let client_data_json = format!(
+            r#"{{"type":"webauthn.get","challenge":"abc","origin":"{}"}}"#,
+            relying_party.origin
+        );
+        assert!(verify_client_data(
+            client_data_json.as_bytes(),
+            "webauthn.get",
+            "abc",
+            &relying_party
+        ));
+        assert!(!verify_client_data(
+            client_data_json.as_bytes(),
+            "webauthn.create",
+            "abc",
+            &relying_party
+        ));
+        assert!(!verify_client_data(
+            client_data_json.as_bytes(),
+            "webauthn.get",
+            "abd",
+            &relying_party
+        ));
+
+        let authenticator_data = authenticator_data("ctf.example.com", FLAG_USER_PRESENT, 7);
+        assert_eq!(
+            verify_authenticator_data(&authenticator_data, &relying_party),
+            Some(7)
+        );
+
+        let mut message = authenticator_data.clone();
+        message.extend_from_slice(
+            digest::digest(&digest::SHA256, client_data_json.as_bytes()).as_ref(),
+        );
+        let signature = key_pair.sign(&rng, &message).unwrap();
+
+        assert!(verify_signature(
+            ES256,
+            &public_key,
+            &authenticator_data,
+            client_data_json.as_bytes(),
+            signature.as_ref()
+        ));
+        assert!(!verify_signature(
+            ES256,
+            &public_key,
+            &authenticator_data,
+            b"{}",
+            signature.as_ref()
+        ));
+    }
+
+    #[test]
+    fn authenticator_data_scope() {
+        let relying_party = relying_party();
+        assert!(verify_authenticator_data(
+            &authenticator_data("evil.example.com", FLAG_USER_PRESENT, 0),
+            &relying_party
+        )
+        .is_none());
+        assert!(verify_authenticator_data(
+            &authenticator_data("ctf.example.com", 0, 0),
+            &relying_party
+        )
+        .is_none());
+        assert!(verify_authenticator_data(&[0; 10], &relying_party).is_none());
+    }
+
+    #[test]
+    fn challenge_token() {
+        let (challenge, token) = create_challenge("secret", Some(1));
+        let claims = decode_challenge("secret", &token).unwrap();
+        assert_eq!(claims.challenge, challenge);
+        assert_eq!(claims.sub, Some(1));
+        assert!(decode_challenge("other secret", &token).is_none());
+    }
+
+    #[test]
+    fn challenge_single_use() {
+        let (_, token) = create_challenge("secret", None);
+        let claims = decode_challenge("secret", &token).unwrap();
+        assert!(redeem_challenge(&claims));
+        assert!(!redeem_challenge(&claims));
+
+        let (_, token) = create_challenge("secret", None);
+        assert!(redeem_challenge(
+            &decode_challenge("secret", &token).unwrap()
+        ));
+    }
+}
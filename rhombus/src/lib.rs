@@ -24,6 +24,7 @@ pub mod challenge_loader_plugin;
 
 pub mod database_upload_provider;
 pub mod grpc;
+pub mod llm_provider;
 mod local_upload_provider;
 pub mod plugin;
 pub mod upload_provider;
@@ -53,6 +54,9 @@ pub use local_upload_provider::LocalUploadProvider;
 #[doc(inline)]
 pub use upload_provider::UploadProvider;
 
+#[doc(inline)]
+pub use llm_provider::LlmProvider;
+
 #[cfg(feature = "systemfd")]
 pub use systemfd::serve_systemfd;
 
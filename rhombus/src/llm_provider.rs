@@ -0,0 +1,145 @@
+use reqwest::header;
+use serde::{Deserialize, Serialize};
+
+use crate::{errors::RhombusError, internal::settings::Settings, Result};
+
+#[derive(Debug, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum LlmRole {
+    System,
+    User,
+    Assistant,
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct LlmMessage {
+    pub role: LlmRole,
+    pub content: String,
+}
+
+/// A large language model which the `/ai` ticket assistant asks for suggested replies
+#[async_trait::async_trait]
+pub trait LlmProvider: Send + Sync {
+    /// Continue the conversation in `messages`, returning the model's reply
+    async fn complete(&self, messages: &[LlmMessage]) -> Result<String>;
+}
+
+/// Talks to any server implementing OpenAI's chat completions API, including local ones such as
+/// llama.cpp, Ollama or vLLM
+pub struct OpenAiCompatibleProvider {
+    client: reqwest::Client,
+    base_url: String,
+    model: String,
+    temperature: f32,
+}
+
+impl OpenAiCompatibleProvider {
+    pub const DEFAULT_BASE_URL: &'static str = "https://api.openai.com/v1";
+    pub const DEFAULT_MODEL: &'static str = "gpt-3.5-turbo";
+
+    pub fn new(
+        base_url: impl Into<String>,
+        model: impl Into<String>,
+        api_key: Option<&str>,
+        temperature: f32,
+    ) -> Result<Self> {
+        let mut headers = header::HeaderMap::new();
+        if let Some(api_key) = api_key {
+            headers.insert(
+                header::AUTHORIZATION,
+                header::HeaderValue::from_str(&format!("Bearer {}", api_key))
+                    .map_err(|_| RhombusError::Llm("Invalid API key".to_owned()))?,
+            );
+        }
+
+        let client = reqwest::Client::builder()
+            .default_headers(headers)
+            .build()?;
+
+        Ok(Self {
+            client,
+            base_url: base_url.into().trim_end_matches('/').to_owned(),
+            model: model.into(),
+            temperature,
+        })
+    }
+
+    /// Configure from the `ai` settings, falling back to OpenAI with `openai_api_key`. Returns
+    /// `None` if neither is set.
+    pub fn from_settings(settings: &Settings) -> Result<Option<Self>> {
+        let Some(ai) = &settings.ai else {
+            return settings
+                .openai_api_key
+                .as_deref()
+                .map(|api_key| {
+                    Self::new(
+                        Self::DEFAULT_BASE_URL,
+                        Self::DEFAULT_MODEL,
+                        Some(api_key),
+                        0.2,
+                    )
+                })
+                .transpose();
+        };
+
+        Self::new(
+            ai.base_url.as_deref().unwrap_or(Self::DEFAULT_BASE_URL),
+            ai.model.as_deref().unwrap_or(Self::DEFAULT_MODEL),
+            ai.api_key.as_deref().or(settings.openai_api_key.as_deref()),
+            ai.temperature.unwrap_or(0.2),
+        )
+        .map(Some)
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct ChatCompletionRequest<'a> {
+    model: &'a str,
+    messages: &'a [LlmMessage],
+    temperature: f32,
+}
+
+#[derive(Debug, Deserialize)]
+struct ChatCompletionResponse {
+    choices: Vec<Choice>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Choice {
+    message: ResponseMessage,
+}
+
+#[derive(Debug, Deserialize)]
+struct ResponseMessage {
+    content: String,
+}
+
+#[async_trait::async_trait]
+impl LlmProvider for OpenAiCompatibleProvider {
+    async fn complete(&self, messages: &[LlmMessage]) -> Result<String> {
+        let response = self
+            .client
+            .post(format!("{}/chat/completions", self.base_url))
+            .json(&ChatCompletionRequest {
+                model: &self.model,
+                messages,
+                temperature: self.temperature,
+            })
+            .send()
+            .await?;
+
+        if !response.status().is_success() {
+            let status = response.status();
+            let body = response.text().await.unwrap_or_default();
+            return Err(RhombusError::Llm(format!("{}: {}", status, body)));
+        }
+
+        let completion = response.json::<ChatCompletionResponse>().await?;
+        completion
+            .choices
+            .into_iter()
+            .next()
+            .map(|choice| choice.message.content)
+            .ok_or_else(|| RhombusError::Llm("No choices returned".to_owned()))
+    }
+}
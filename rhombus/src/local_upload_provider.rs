@@ -11,15 +11,15 @@ use rand::{
     thread_rng,
 };
 use tokio::{fs::File, io::BufWriter};
-use tokio_util::io::StreamReader;
+use tokio_util::io::{ReaderStream, StreamReader};
 
 use crate::{
     errors::RhombusError,
     internal::{
         local_upload_provider::{route_local_download, slice_to_hex_string, HashRead},
-        upload_provider::route_upload_file,
+        upload_provider::{parse_upload_url, route_upload_file},
     },
-    upload_provider::UploadProvider,
+    upload_provider::{ByteStream, UploadProvider},
     Result,
 };
 
@@ -79,4 +79,55 @@ impl UploadProvider for LocalUploadProvider {
         .await
         .map_err(|_| RhombusError::Unknown)
     }
+
+    async fn download(&self, url: &str) -> Result<ByteStream> {
+        let (hash, filename) = parse_upload_url(url).ok_or(RhombusError::Unknown)?;
+        let filepath = self.base_path.join(format!("{}-{}", hash, filename));
+
+        let file = File::open(&filepath).await?;
+        let stream = ReaderStream::new(file).map_err(|err| err.into());
+
+        Ok(Box::pin(stream))
+    }
+
+    async fn list(&self) -> Result<Vec<String>> {
+        let mut urls = vec![];
+
+        let mut entries = match tokio::fs::read_dir(&self.base_path).await {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(urls),
+            Err(err) => return Err(err.into()),
+        };
+
+        while let Some(entry) = entries.next_entry().await? {
+            if !entry.file_type().await?.is_file() {
+                continue;
+            }
+
+            let Some(name) = entry.file_name().to_str().map(|name| name.to_owned()) else {
+                continue;
+            };
+
+            // skip partially written uploads
+            if name.ends_with(".tmp") {
+                continue;
+            }
+
+            let url = format!("/uploads/{}", name);
+            if parse_upload_url(&url).is_some() {
+                urls.push(url);
+            }
+        }
+
+        Ok(urls)
+    }
+
+    async fn delete(&self, url: &str) -> Result<()> {
+        let (hash, filename) = parse_upload_url(url).ok_or(RhombusError::Unknown)?;
+        let filepath = self.base_path.join(format!("{}-{}", hash, filename));
+
+        tokio::fs::remove_file(&filepath).await?;
+
+        Ok(())
+    }
 }
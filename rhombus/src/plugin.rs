@@ -24,7 +24,7 @@ pub struct RunContext<'a> {
     /// The selected upload provider which can be used to upload files with.
     /// For a plugin to provide a custom upload provider, implement the [upload_provider](Plugin::upload_provider)
     /// function of the [Plugin] trait.
-    pub upload_provider: Box<dyn ErasedUploadProvider>,
+    pub upload_provider: Arc<dyn ErasedUploadProvider>,
 
     pub templates: &'a mut Templates,
 
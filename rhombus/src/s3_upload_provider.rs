@@ -1,18 +1,29 @@
 use std::{io, str::FromStr, sync::Arc};
 
-use axum::{body::Bytes, routing::post, Router};
+use axum::{
+    body::{Body, Bytes},
+    extract::State,
+    http::header,
+    response::IntoResponse,
+    routing::{get, post},
+    Extension, Router,
+};
 use futures::{Stream, TryStreamExt};
+use reqwest::StatusCode;
 
 use s3::{creds::Credentials, Bucket};
 use tokio::io::AsyncReadExt;
 use tokio_util::{bytes::BytesMut, io::StreamReader};
 
 use crate::{
+    errors::RhombusError,
     internal::{
-        local_upload_provider::slice_to_hex_string, settings::S3UploadProviderSettings,
-        upload_provider::route_upload_file,
+        auth::MaybeUser,
+        local_upload_provider::slice_to_hex_string,
+        settings::S3UploadProviderSettings,
+        upload_provider::{parse_upload_url, route_upload_file, validate_simple_filename},
     },
-    upload_provider::UploadProvider,
+    upload_provider::{ByteStream, UploadProvider, UrlSigner},
     Result,
 };
 
@@ -71,6 +82,7 @@ impl UploadProvider for S3UploadProvider {
     fn routes(&self) -> Result<Router> {
         let provider_state = Arc::new(self.clone());
         let router = Router::new()
+            .route("/uploads/:hash_filename", get(route_s3_download))
             .route("/upload/:path", post(route_upload_file::<Self>))
             .with_state(provider_state);
         Ok(router)
@@ -112,4 +124,92 @@ impl UploadProvider for S3UploadProvider {
 
         Ok(url)
     }
+
+    async fn download(&self, url: &str) -> Result<ByteStream> {
+        let (hash, filename) = parse_upload_url(url).ok_or(RhombusError::Unknown)?;
+        let s3_path = format!("{}{}/{}", self.prefix, hash, filename);
+
+        let response = self.bucket.get_object_stream(&s3_path).await?;
+        let stream = response.bytes.map_err(|err| err.into());
+
+        Ok(Box::pin(stream))
+    }
+
+    async fn list(&self) -> Result<Vec<String>> {
+        let urls = self
+            .bucket
+            .list(self.prefix.clone(), None)
+            .await?
+            .into_iter()
+            .flat_map(|result| result.contents)
+            .map(|object| format!("{}/{}", self.bucket.url(), object.key))
+            .filter(|url| parse_upload_url(url).is_some())
+            .collect();
+
+        Ok(urls)
+    }
+
+    async fn delete(&self, url: &str) -> Result<()> {
+        let (hash, filename) = parse_upload_url(url).ok_or(RhombusError::Unknown)?;
+        let s3_path = format!("{}{}/{}", self.prefix, hash, filename);
+
+        _ = self.bucket.delete_object(&s3_path).await?;
+
+        Ok(())
+    }
+
+    /// S3 serves files directly, so links are presigned with the signer's expiry
+    /// instead. S3 cannot check the team of a presigned link, so links bound to a
+    /// team are signed for `/uploads/` and proxied through Rhombus.
+    async fn sign(&self, url: &str, signer: &UrlSigner, team_id: i64) -> Result<String> {
+        let Some((hash, filename)) = parse_upload_url(url) else {
+            return Ok(url.to_owned());
+        };
+
+        if signer.bind_team {
+            return Ok(signer.sign(&format!("/uploads/{}-{}", hash, filename), team_id));
+        }
+
+        let s3_path = format!("{}{}/{}", self.prefix, hash, filename);
+        let url = self
+            .bucket
+            .presign_get(&s3_path, signer.expiry.as_secs() as u32, None)
+            .await?;
+
+        Ok(url)
+    }
+}
+
+pub async fn route_s3_download(
+    state: State<Arc<S3UploadProvider>>,
+    Extension(maybe_user): Extension<MaybeUser>,
+    axum::extract::Path(path): axum::extract::Path<String>,
+) -> impl IntoResponse {
+    if !validate_simple_filename(&path) {
+        return (StatusCode::BAD_REQUEST, "Invalid path".to_owned()).into_response();
+    }
+
+    let url = format!("/uploads/{}", path);
+    let Some((_, filename)) = parse_upload_url(&url) else {
+        return (StatusCode::BAD_REQUEST, "Invalid path".to_owned()).into_response();
+    };
+
+    let Ok(stream) = state.download(&url).await else {
+        return (StatusCode::NOT_FOUND, "Not Found").into_response();
+    };
+
+    tracing::info!(
+        path = filename,
+        user_id = maybe_user.map(|u| u.id),
+        "Downloading from s3"
+    );
+
+    (
+        [(
+            header::CONTENT_DISPOSITION,
+            format!("attachment; filename={}", &filename),
+        )],
+        Body::from_stream(stream),
+    )
+        .into_response()
 }
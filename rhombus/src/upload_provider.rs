@@ -1,8 +1,18 @@
 use axum::{body::Bytes, Router};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
 use futures::{Stream, StreamExt};
-use std::pin::Pin;
+use serde::Deserialize;
+use std::{pin::Pin, time::Duration};
 
-use crate::Result;
+use crate::{internal::settings::Settings, Result};
+
+/// A boxed stream of file contents, as returned by [UploadProvider::download]
+pub type ByteStream = Pin<
+    Box<
+        dyn Stream<Item = std::result::Result<Bytes, Box<dyn std::error::Error + Send + Sync>>>
+            + Send,
+    >,
+>;
 
 #[async_trait::async_trait]
 pub trait ErasedUploadProvider: Send + Sync {
@@ -20,6 +30,14 @@ pub trait ErasedUploadProvider: Send + Sync {
             >,
         >,
     ) -> Result<String>;
+
+    async fn download_erased(&self, url: &str) -> Result<ByteStream>;
+
+    async fn list_erased(&self) -> Result<Vec<String>>;
+
+    async fn delete_erased(&self, url: &str) -> Result<()>;
+
+    async fn sign_erased(&self, url: &str, signer: &UrlSigner, team_id: i64) -> Result<String>;
 }
 
 #[async_trait::async_trait]
@@ -30,6 +48,28 @@ pub trait UploadProvider: Send + Sync {
     where
         S: Stream<Item = std::result::Result<Bytes, E>> + Send + 'async_trait,
         E: Into<axum::BoxError>;
+
+    /// Stream the contents of a file previously uploaded with this provider.
+    /// The `url` is the one which was returned by [upload](UploadProvider::upload).
+    async fn download(&self, url: &str) -> Result<ByteStream>;
+
+    /// List the urls of every file stored by this provider.
+    async fn list(&self) -> Result<Vec<String>>;
+
+    /// Delete a file previously uploaded with this provider.
+    async fn delete(&self, url: &str) -> Result<()>;
+
+    /// Create a temporary link to a file previously uploaded with this provider.
+    /// By default, files served by Rhombus under `/uploads/` are signed with `signer`
+    /// and checked on download. Providers which serve files from elsewhere should
+    /// override this, for example with a presigned url.
+    async fn sign(&self, url: &str, signer: &UrlSigner, team_id: i64) -> Result<String> {
+        if url.starts_with("/uploads/") {
+            Ok(signer.sign(url, team_id))
+        } else {
+            Ok(url.to_owned())
+        }
+    }
 }
 
 #[async_trait::async_trait]
@@ -48,6 +88,22 @@ impl UploadProvider for dyn ErasedUploadProvider {
 
         self.upload_erased(filename, boxed_stream).await
     }
+
+    async fn download(&self, url: &str) -> Result<ByteStream> {
+        self.download_erased(url).await
+    }
+
+    async fn list(&self) -> Result<Vec<String>> {
+        self.list_erased().await
+    }
+
+    async fn delete(&self, url: &str) -> Result<()> {
+        self.delete_erased(url).await
+    }
+
+    async fn sign(&self, url: &str, signer: &UrlSigner, team_id: i64) -> Result<String> {
+        self.sign_erased(url, signer, team_id).await
+    }
 }
 
 #[async_trait::async_trait]
@@ -73,4 +129,161 @@ where
     ) -> Result<String> {
         self.upload(filename, stream).await
     }
+
+    async fn download_erased(&self, url: &str) -> Result<ByteStream> {
+        self.download(url).await
+    }
+
+    async fn list_erased(&self) -> Result<Vec<String>> {
+        self.list().await
+    }
+
+    async fn delete_erased(&self, url: &str) -> Result<()> {
+        self.delete(url).await
+    }
+
+    async fn sign_erased(&self, url: &str, signer: &UrlSigner, team_id: i64) -> Result<String> {
+        self.sign(url, signer, team_id).await
+    }
+}
+
+/// Signs links to uploaded files so they can only be downloaded for a limited time,
+/// and optionally only by members of a single team
+pub struct UrlSigner {
+    key: ring::hmac::Key,
+    pub expiry: Duration,
+    pub bind_team: bool,
+}
+
+#[derive(Debug, Deserialize)]
+struct SignatureQuery {
+    expires: i64,
+    team: Option<i64>,
+    signature: String,
+}
+
+impl UrlSigner {
+    pub fn new(secret: &str, expiry: Duration, bind_team: bool) -> UrlSigner {
+        UrlSigner {
+            key: ring::hmac::Key::new(ring::hmac::HMAC_SHA256, secret.as_bytes()),
+            expiry,
+            bind_team,
+        }
+    }
+
+    /// Returns `None` unless signed urls are enabled in the upload settings
+    pub fn from_settings(settings: &Settings) -> Option<UrlSigner> {
+        let signed_urls = settings.uploads.as_ref()?.signed_urls.as_ref()?;
+
+        Some(UrlSigner::new(
+            &settings.jwt_secret,
+            Duration::from_secs(signed_urls.expiry.unwrap_or(3600) as u64),
+            signed_urls.bind_team.unwrap_or(false),
+        ))
+    }
+
+    fn message(path: &str, expires: i64, team_id: Option<i64>) -> String {
+        format!(
+            "{}\n{}\n{}",
+            path,
+            expires,
+            team_id.map(|id| id.to_string()).unwrap_or_default()
+        )
+    }
+
+    /// Append an expiry, the team (if bound) and a signature to the query of `url`
+    pub fn sign(&self, url: &str, team_id: i64) -> String {
+        let expires = chrono::Utc::now().timestamp() + self.expiry.as_secs() as i64;
+        self.sign_until(url, team_id, expires)
+    }
+
+    fn sign_until(&self, url: &str, team_id: i64, expires: i64) -> String {
+        let path = url.split(['?', '#']).next().unwrap_or(url);
+        let team_id = self.bind_team.then_some(team_id);
+
+        let tag = ring::hmac::sign(&self.key, Self::message(path, expires, team_id).as_bytes());
+        let signature = URL_SAFE_NO_PAD.encode(tag.as_ref());
+
+        let mut signed = format!("{}?expires={}", path, expires);
+        if let Some(team_id) = team_id {
+            signed.push_str(&format!("&team={}", team_id));
+        }
+        signed.push_str(&format!("&signature={}", signature));
+        signed
+    }
+
+    /// Check the signature in the `query` of a download request for `path`. The
+    /// `team_id` is the team of the user making the request, if they are signed in.
+    pub fn verify(&self, path: &str, query: Option<&str>, team_id: Option<i64>, now: i64) -> bool {
+        let Some(query) = query else {
+            return false;
+        };
+
+        let Ok(query) = axum::extract::Query::<SignatureQuery>::try_from_uri(
+            &format!("/?{}", query).parse().unwrap_or_default(),
+        ) else {
+            return false;
+        };
+
+        if query.expires < now {
+            return false;
+        }
+
+        if self.bind_team && (query.team.is_none() || query.team != team_id) {
+            return false;
+        }
+
+        let Ok(signature) = URL_SAFE_NO_PAD.decode(&query.signature) else {
+            return false;
+        };
+
+        ring::hmac::verify(
+            &self.key,
+            Self::message(path, query.expires, query.team).as_bytes(),
+            &signature,
+        )
+        .is_ok()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::UrlSigner;
+
+    #[test]
+    fn signed_urls() {
+        let url = "/uploads/abc-flag.txt";
+        let signer = UrlSigner::new("secret", Duration::from_secs(60), false);
+
+        let signed = signer.sign_until(url, 1, 1000);
+        let (path, query) = signed.split_once('?').unwrap();
+        assert_eq!(path, url);
+        assert!(signer.verify(path, Some(query), None, 1000));
+        assert!(!signer.verify(path, Some(query), None, 1001));
+        assert!(!signer.verify("/uploads/abc-other.txt", Some(query), None, 1000));
+        assert!(!signer.verify(path, None, None, 1000));
+
+        let tampered = query.replace("expires=1000", "expires=2000");
+        assert!(!signer.verify(path, Some(&tampered), None, 1000));
+
+        let other_secret = UrlSigner::new("other", Duration::from_secs(60), false);
+        assert!(!other_secret.verify(path, Some(query), None, 1000));
+    }
+
+    #[test]
+    fn team_bound_urls() {
+        let url = "/uploads/abc-flag.txt";
+        let signer = UrlSigner::new("secret", Duration::from_secs(60), true);
+
+        let signed = signer.sign_until(url, 1, 1000);
+        let (path, query) = signed.split_once('?').unwrap();
+        assert!(signer.verify(path, Some(query), Some(1), 1000));
+        assert!(!signer.verify(path, Some(query), Some(2), 1000));
+        assert!(!signer.verify(path, Some(query), None, 1000));
+
+        let tampered = query.replace("team=1", "team=2");
+        assert!(!signer.verify(path, Some(&tampered), Some(2), 1000));
+    }
 }
@@ -0,0 +1,99 @@
+(function () {
+  const toBase64Url = (buffer) =>
+    btoa(String.fromCharCode(...new Uint8Array(buffer)))
+      .replace(/\+/g, "-")
+      .replace(/\//g, "_")
+      .replace(/=+$/, "");
+
+  const fromBase64Url = (value) =>
+    Uint8Array.from(atob(value.replace(/-/g, "+").replace(/_/g, "/")), (c) =>
+      c.charCodeAt(0),
+    );
+
+  const supported = (element) => {
+    if (!window.PublicKeyCredential) {
+      rhombus.toast.error(element.dataset.unsupported);
+      return false;
+    }
+    return true;
+  };
+
+  const fail = (element, error) => {
+    // the user dismissing the browser prompt is not an error
+    if (error.name !== "NotAllowedError") {
+      console.error(error);
+      rhombus.toast.error(element.dataset.error);
+    }
+  };
+
+  window.rhombusPasskey = {
+    signIn: async (button) => {
+      if (!supported(button)) return;
+      try {
+        const options = await fetch("/signin/passkey").then((res) => res.json());
+        const credential = await navigator.credentials.get({
+          publicKey: {
+            challenge: fromBase64Url(options.challenge),
+            rpId: options.rp_id,
+            userVerification: "preferred",
+          },
+        });
+        htmx.ajax("POST", "/signin/passkey", {
+          swap: "none",
+          values: {
+            token: options.token,
+            id: credential.id,
+            client_data_json: toBase64Url(credential.response.clientDataJSON),
+            authenticator_data: toBase64Url(
+              credential.response.authenticatorData,
+            ),
+            signature: toBase64Url(credential.response.signature),
+          },
+        });
+      } catch (error) {
+        fail(button, error);
+      }
+    },
+
+    register: async (form) => {
+      if (!supported(form)) return;
+      try {
+        const { token, options } = await fetch("/account/passkey/options", {
+          method: "POST",
+        }).then((res) => res.json());
+        options.challenge = fromBase64Url(options.challenge);
+        options.user.id = fromBase64Url(options.user.id);
+        options.excludeCredentials = options.excludeCredentials.map(
+          (credential) => ({ ...credential, id: fromBase64Url(credential.id) }),
+        );
+
+        const credential = await navigator.credentials.create({
+          publicKey: options,
+        });
+        const publicKey = credential.response.getPublicKey();
+        if (!publicKey) {
+          rhombus.toast.error(form.dataset.unsupported);
+          return;
+        }
+
+        await htmx.ajax("POST", "/account/passkey", {
+          swap: "none",
+          values: {
+            token,
+            name: form.elements.name.value,
+            id: credential.id,
+            client_data_json: toBase64Url(credential.response.clientDataJSON),
+            authenticator_data: toBase64Url(
+              credential.response.getAuthenticatorData(),
+            ),
+            public_key: toBase64Url(publicKey),
+            algorithm: credential.response.getPublicKeyAlgorithm(),
+          },
+        });
+        form.reset();
+      } catch (error) {
+        fail(form, error);
+      }
+    },
+  };
+})();
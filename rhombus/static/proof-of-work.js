@@ -0,0 +1,64 @@
+(function () {
+  const encoder = new TextEncoder();
+
+  const leadingZeroBits = (hash) => {
+    let bits = 0;
+    for (const byte of new Uint8Array(hash)) {
+      if (byte !== 0) return bits + Math.clz32(byte) - 24;
+      bits += 8;
+    }
+    return bits;
+  };
+
+  // hashes nonces in batches so the page stays responsive while solving
+  const solve = async (challenge, difficulty) => {
+    const batch = 256;
+    for (let start = 0; ; start += batch) {
+      const hashes = await Promise.all(
+        Array.from({ length: batch }, (_, i) =>
+          crypto.subtle.digest(
+            "SHA-256",
+            encoder.encode(`${challenge}:${start + i}`),
+          ),
+        ),
+      );
+      const index = hashes.findIndex(
+        (hash) => leadingZeroBits(hash) >= difficulty,
+      );
+      if (index !== -1) return (start + index).toString();
+    }
+  };
+
+  // solve a challenge before any request from an element marked with
+  // data-proof-of-work, the server responds with an error if it is missing
+  document.addEventListener("htmx:confirm", (event) => {
+    const element = event.detail.elt;
+    const route = element.dataset.proofOfWork;
+    if (!route) return;
+
+    event.preventDefault();
+    if (element.getAttribute("aria-busy") === "true") return;
+    element.setAttribute("aria-busy", "true");
+
+    fetch(`/proof-of-work?route=${route}`)
+      .then((res) => res.json())
+      .then(async ({ token, challenge, difficulty }) => {
+        element.proofOfWork = {
+          proof_of_work_token: token,
+          proof_of_work_nonce: await solve(challenge, difficulty),
+        };
+      })
+      .catch(console.error)
+      .finally(() => {
+        element.removeAttribute("aria-busy");
+        event.detail.issueRequest(true);
+      });
+  });
+
+  document.addEventListener("htmx:configRequest", (event) => {
+    const solution = event.detail.elt.proofOfWork;
+    if (!solution) return;
+    delete event.detail.elt.proofOfWork;
+    Object.assign(event.detail.parameters, solution);
+  });
+})();